@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use rusqlite::Connection;
+
+use aw_models::Bucket;
+use aw_models::Event;
+use aw_models::EventProvenance;
+use aw_models::KeyValue;
+use aw_transform::MergePolicy;
+
+use crate::datastore::DatastoreInstance;
+use crate::DatastoreError;
+
+/// The storage operations the worker thread dispatches to. Implementing
+/// this trait is all it takes to put another database behind the
+/// datastore: the worker, commands and endpoints stay unchanged.
+///
+/// `begin`/`commit` bracket the worker's request batches; backends
+/// without transactions can implement them as no-ops.
+pub trait StorageBackend: Send {
+    fn begin(&mut self);
+    fn commit(&mut self);
+
+    fn create_bucket(&mut self, bucket: Bucket) -> Result<(), DatastoreError>;
+    fn delete_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError>;
+    fn soft_delete_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError>;
+    fn restore_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError>;
+    fn get_deleted_buckets(&mut self) -> Result<Vec<String>, DatastoreError>;
+    fn purge_deleted_buckets(&mut self, before: DateTime<Utc>) -> Result<i64, DatastoreError>;
+    fn get_bucket(&mut self, bucket_id: &str) -> Result<Bucket, DatastoreError>;
+    fn get_buckets(&mut self) -> HashMap<String, Bucket>;
+    /// Per-bucket `(event count, total duration in nanoseconds)` for all
+    /// buckets, cheap enough to serve on every listing that asks for it
+    fn get_bucket_rollups(&mut self) -> Result<HashMap<String, (i64, i64)>, DatastoreError>;
+
+    /// Replaces a bucket's metadata `data`
+    fn set_bucket_data(
+        &mut self,
+        bucket_id: &str,
+        data: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), DatastoreError>;
+
+    fn import(
+        &mut self,
+        data: HashMap<String, (Bucket, Vec<Event>)>,
+        provenance: Option<&EventProvenance>,
+    ) -> Result<(), DatastoreError>;
+    fn insert_events(
+        &mut self,
+        bucket_id: &str,
+        events: Vec<Event>,
+    ) -> Result<Vec<Event>, DatastoreError>;
+    /// Records ingest provenance for the given event rows
+    fn set_event_provenance(
+        &mut self,
+        event_ids: &[i64],
+        provenance: &EventProvenance,
+    ) -> Result<(), DatastoreError>;
+    /// Provenance for the given event rows; rows without recorded
+    /// provenance are absent from the result
+    fn get_event_provenance(
+        &mut self,
+        event_ids: &[i64],
+    ) -> Result<HashMap<i64, EventProvenance>, DatastoreError>;
+    /// Deletes all events from an import batch, returning how many were
+    /// removed
+    fn delete_events_by_batch(&mut self, batch: &str) -> Result<i64, DatastoreError>;
+    /// How many of a bucket's events end before `before`; the dry-run
+    /// counterpart of `delete_events_before`
+    fn count_events_before(
+        &self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError>;
+    /// Permanently deletes the bucket's events ending before `before`
+    /// (no history pre-image), returning how many were removed
+    fn delete_events_before(
+        &mut self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError>;
+    /// Merges runs of identical-data events ending before `before`, a
+    /// retroactive heartbeat pass; returns how many rows were merged away
+    fn compact_events_before(
+        &mut self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+        pulsetime: f64,
+    ) -> Result<i64, DatastoreError>;
+    /// Replaces the bucket's last event. If `event.id` is set the update
+    /// targets that exact row, only falling back to last-by-endtime when
+    /// the id is stale.
+    fn replace_last_event(&mut self, bucket_id: &str, event: &Event)
+        -> Result<(), DatastoreError>;
+    /// Merges the heartbeat into the bucket's last event if the data
+    /// matches and it falls within the pulsetime window, otherwise
+    /// inserts it as a new event. Returns the resulting event.
+    fn heartbeat(
+        &mut self,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Event, DatastoreError>;
+    /// Processes an ordered list of heartbeats sequentially through the
+    /// same merge logic as [`Self::heartbeat`]
+    fn heartbeat_batch(
+        &mut self,
+        bucket_id: &str,
+        heartbeats: Vec<Event>,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Vec<Event>, DatastoreError>;
+    fn get_events(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<Event>, DatastoreError>;
+    /// A single event by id, without loading the rest of the bucket
+    fn get_event_by_id(&mut self, bucket_id: &str, event_id: i64)
+        -> Result<Event, DatastoreError>;
+    /// Like `get_events`, but additionally filters on exact matches of
+    /// top-level `data` fields, applied before the limit
+    fn get_events_filtered(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        filters: &[(String, serde_json::Value)],
+    ) -> Result<Vec<Event>, DatastoreError>;
+    /// The bucket's events as they were at `as_of`: live events ingested
+    /// by then plus pre-images of events deleted after it
+    fn get_events_as_of(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        as_of: DateTime<Utc>,
+    ) -> Result<Vec<Event>, DatastoreError>;
+    /// `(event count, total duration in ns)` grouped by a top-level
+    /// `data` field, keyed by the field value's text representation.
+    /// Durations are clipped to the query range; events without the
+    /// field are excluded.
+    fn get_events_aggregate(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        field: &str,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError>;
+    /// Distinct values of a top-level `data` field starting with `prefix`
+    /// (case-insensitive), with occurrence counts, most frequent first
+    fn get_event_suggestions(
+        &mut self,
+        bucket_id: &str,
+        field: &str,
+        prefix: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<Vec<(String, i64)>, DatastoreError>;
+    /// Event count and total duration per UTC hour, keyed by the hour
+    /// start as an RFC3339 timestamp; events count towards the hour
+    /// their (clipped) start falls in
+    fn get_events_hourly_summary(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError>;
+    /// A page of events for streaming downloads, keyset paginated on
+    /// `(starttime ns, id)`; see the sqlite implementation for details
+    fn get_events_page(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        before: Option<(i64, i64)>,
+        limit: u64,
+    ) -> Result<Vec<Event>, DatastoreError>;
+    fn get_event_count(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<i64, DatastoreError>;
+    /// The most recent `limit` events of every bucket
+    fn get_last_events(&mut self, limit: u64)
+        -> Result<HashMap<String, Vec<Event>>, DatastoreError>;
+    /// Describes how the backend would execute the corresponding
+    /// `get_events` call, one line per plan step
+    fn explain_get_events(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<String>, DatastoreError>;
+    fn delete_events_by_id(
+        &mut self,
+        bucket_id: &str,
+        event_ids: Vec<i64>,
+    ) -> Result<(), DatastoreError>;
+    /// Updates a single event (timestamp, duration and data) in place;
+    /// fails if the bucket does not own the event
+    fn update_event(&mut self, bucket_id: &str, event: Event) -> Result<Event, DatastoreError>;
+
+    fn insert_key_value(&mut self, key: &str, data: &str) -> Result<(), DatastoreError>;
+    fn get_key_value(&mut self, key: &str) -> Result<KeyValue, DatastoreError>;
+    fn get_keys_starting(&mut self, pattern: &str) -> Result<Vec<String>, DatastoreError>;
+    fn delete_key_value(&mut self, key: &str) -> Result<(), DatastoreError>;
+}
+
+/// How long SQLite waits on a locked database before giving up with
+/// SQLITE_BUSY, e.g. when another process holds the write lock
+const BUSY_TIMEOUT_MS: i64 = 30_000;
+
+/// How many times transient transaction failures are retried before the
+/// worker gives up
+const TRANSIENT_RETRIES: u32 = 3;
+
+/// Delay between transaction retries
+const RETRY_DELAY_MS: u64 = 250;
+
+/// The default backend: a SQLite database (file or in-memory) owned by
+/// the worker thread
+pub struct SqliteBackend {
+    conn: Connection,
+    ds: DatastoreInstance,
+}
+
+impl SqliteBackend {
+    pub fn new(dbpath: &str, in_memory: bool) -> Self {
+        let conn = if in_memory {
+            Connection::open_in_memory().expect("Failed to create in-memory datastore")
+        } else {
+            Connection::open(dbpath).expect("Failed to open database")
+        };
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .expect("Failed to set journal_mode to WAL");
+        conn.pragma_update(None, "busy_timeout", BUSY_TIMEOUT_MS)
+            .expect("Failed to set busy_timeout");
+        let ds = match DatastoreInstance::new(&conn, true) {
+            Ok(ds) => ds,
+            Err(e) => {
+                error!("Failed to initialize datastore: {e}");
+                panic!("Failed to initialize datastore");
+            }
+        };
+        SqliteBackend { conn, ds }
+    }
+
+    /// Runs a transaction control statement, retrying transient failures
+    /// (e.g. the database staying locked past the busy timeout) so a
+    /// momentarily unavailable database doesn't take the worker down
+    fn execute_with_retry(&self, sql: &str) {
+        let mut attempts = 0;
+        loop {
+            match self.conn.execute_batch(sql) {
+                Ok(()) => return,
+                Err(err) if attempts < TRANSIENT_RETRIES => {
+                    attempts += 1;
+                    warn!("'{sql}' failed (attempt {attempts}/{TRANSIENT_RETRIES}): {err}");
+                    std::thread::sleep(std::time::Duration::from_millis(RETRY_DELAY_MS));
+                }
+                Err(err) => {
+                    panic!("'{sql}' failed after {TRANSIENT_RETRIES} retries: {err}")
+                }
+            }
+        }
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    fn begin(&mut self) {
+        self.execute_with_retry("BEGIN IMMEDIATE");
+    }
+
+    fn commit(&mut self) {
+        self.execute_with_retry("COMMIT");
+    }
+
+    fn create_bucket(&mut self, bucket: Bucket) -> Result<(), DatastoreError> {
+        self.ds.create_bucket(&self.conn, bucket)
+    }
+
+    fn delete_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError> {
+        self.ds.delete_bucket(&self.conn, bucket_id)
+    }
+
+    fn soft_delete_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError> {
+        self.ds.soft_delete_bucket(&self.conn, bucket_id)
+    }
+
+    fn restore_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError> {
+        self.ds.restore_bucket(&self.conn, bucket_id)
+    }
+
+    fn get_deleted_buckets(&mut self) -> Result<Vec<String>, DatastoreError> {
+        self.ds.get_deleted_buckets(&self.conn)
+    }
+
+    fn purge_deleted_buckets(&mut self, before: DateTime<Utc>) -> Result<i64, DatastoreError> {
+        self.ds.purge_deleted_buckets(&self.conn, before)
+    }
+
+    fn get_bucket(&mut self, bucket_id: &str) -> Result<Bucket, DatastoreError> {
+        self.ds.get_bucket(bucket_id)
+    }
+
+    fn get_buckets(&mut self) -> HashMap<String, Bucket> {
+        self.ds.get_buckets()
+    }
+
+    fn get_bucket_rollups(&mut self) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        self.ds.get_bucket_rollups(&self.conn)
+    }
+
+    fn set_bucket_data(
+        &mut self,
+        bucket_id: &str,
+        data: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), DatastoreError> {
+        self.ds.set_bucket_data(&self.conn, bucket_id, data)
+    }
+
+    fn import(
+        &mut self,
+        data: HashMap<String, (Bucket, Vec<Event>)>,
+        provenance: Option<&EventProvenance>,
+    ) -> Result<(), DatastoreError> {
+        self.ds.import(&self.conn, data, provenance)
+    }
+
+    fn insert_events(
+        &mut self,
+        bucket_id: &str,
+        events: Vec<Event>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        self.ds.insert_events(&self.conn, bucket_id, events)
+    }
+
+    fn set_event_provenance(
+        &mut self,
+        event_ids: &[i64],
+        provenance: &EventProvenance,
+    ) -> Result<(), DatastoreError> {
+        self.ds
+            .set_event_provenance(&self.conn, event_ids, provenance)
+    }
+
+    fn get_event_provenance(
+        &mut self,
+        event_ids: &[i64],
+    ) -> Result<HashMap<i64, EventProvenance>, DatastoreError> {
+        self.ds.get_event_provenance(&self.conn, event_ids)
+    }
+
+    fn delete_events_by_batch(&mut self, batch: &str) -> Result<i64, DatastoreError> {
+        self.ds.delete_events_by_batch(&self.conn, batch)
+    }
+
+    fn count_events_before(
+        &self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        self.ds.count_events_before(&self.conn, bucket_id, before)
+    }
+
+    fn delete_events_before(
+        &mut self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        self.ds.delete_events_before(&self.conn, bucket_id, before)
+    }
+
+    fn compact_events_before(
+        &mut self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+        pulsetime: f64,
+    ) -> Result<i64, DatastoreError> {
+        self.ds
+            .compact_events_before(&self.conn, bucket_id, before, pulsetime)
+    }
+
+    fn replace_last_event(
+        &mut self,
+        bucket_id: &str,
+        event: &Event,
+    ) -> Result<(), DatastoreError> {
+        self.ds.replace_last_event(&self.conn, bucket_id, event)
+    }
+
+    fn heartbeat(
+        &mut self,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Event, DatastoreError> {
+        self.ds
+            .heartbeat(&self.conn, bucket_id, heartbeat, pulsetime, &policy)
+    }
+
+    fn heartbeat_batch(
+        &mut self,
+        bucket_id: &str,
+        heartbeats: Vec<Event>,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        self.ds
+            .heartbeat_batch(&self.conn, bucket_id, heartbeats, pulsetime, &policy)
+    }
+
+    fn get_events(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        self.ds
+            .get_events(&self.conn, bucket_id, starttime_opt, endtime_opt, limit_opt)
+    }
+
+    fn get_event_by_id(
+        &mut self,
+        bucket_id: &str,
+        event_id: i64,
+    ) -> Result<Event, DatastoreError> {
+        self.ds.get_event_by_id(&self.conn, bucket_id, event_id)
+    }
+
+    fn get_events_filtered(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        filters: &[(String, serde_json::Value)],
+    ) -> Result<Vec<Event>, DatastoreError> {
+        self.ds.get_events_filtered(
+            &self.conn,
+            bucket_id,
+            starttime_opt,
+            endtime_opt,
+            limit_opt,
+            filters,
+        )
+    }
+
+    fn get_events_as_of(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        as_of: DateTime<Utc>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        self.ds.get_events_as_of(
+            &self.conn,
+            bucket_id,
+            starttime_opt,
+            endtime_opt,
+            limit_opt,
+            as_of,
+        )
+    }
+
+    fn get_events_aggregate(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        field: &str,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        self.ds
+            .get_events_aggregate(&self.conn, bucket_id, starttime_opt, endtime_opt, field)
+    }
+
+    fn get_events_hourly_summary(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        self.ds
+            .get_events_hourly_summary(&self.conn, bucket_id, starttime_opt, endtime_opt)
+    }
+
+    fn get_event_suggestions(
+        &mut self,
+        bucket_id: &str,
+        field: &str,
+        prefix: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<Vec<(String, i64)>, DatastoreError> {
+        self.ds.get_event_suggestions(
+            &self.conn,
+            bucket_id,
+            field,
+            prefix,
+            starttime_opt,
+            endtime_opt,
+            limit,
+        )
+    }
+
+    fn get_events_page(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        before: Option<(i64, i64)>,
+        limit: u64,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        self.ds.get_events_page(
+            &self.conn,
+            bucket_id,
+            starttime_opt,
+            endtime_opt,
+            before,
+            limit,
+        )
+    }
+
+    fn get_event_count(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<i64, DatastoreError> {
+        self.ds
+            .get_event_count(&self.conn, bucket_id, starttime_opt, endtime_opt)
+    }
+
+    fn explain_get_events(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<String>, DatastoreError> {
+        self.ds
+            .explain_get_events(&self.conn, bucket_id, starttime_opt, endtime_opt, limit_opt)
+    }
+
+    fn get_last_events(
+        &mut self,
+        limit: u64,
+    ) -> Result<HashMap<String, Vec<Event>>, DatastoreError> {
+        self.ds.get_last_events(&self.conn, limit)
+    }
+
+    fn delete_events_by_id(
+        &mut self,
+        bucket_id: &str,
+        event_ids: Vec<i64>,
+    ) -> Result<(), DatastoreError> {
+        self.ds.delete_events_by_id(&self.conn, bucket_id, event_ids)
+    }
+
+    fn update_event(&mut self, bucket_id: &str, event: Event) -> Result<Event, DatastoreError> {
+        self.ds.update_event(&self.conn, bucket_id, event)
+    }
+
+    fn insert_key_value(&mut self, key: &str, data: &str) -> Result<(), DatastoreError> {
+        self.ds.insert_key_value(&self.conn, key, data)
+    }
+
+    fn get_key_value(&mut self, key: &str) -> Result<KeyValue, DatastoreError> {
+        self.ds.get_key_value(&self.conn, key)
+    }
+
+    fn get_keys_starting(&mut self, pattern: &str) -> Result<Vec<String>, DatastoreError> {
+        self.ds.get_keys_starting(&self.conn, pattern)
+    }
+
+    fn delete_key_value(&mut self, key: &str) -> Result<(), DatastoreError> {
+        self.ds.delete_key_value(&self.conn, key)
+    }
+}
@@ -0,0 +1,2235 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use rusqlite::params;
+use rusqlite::types::ToSql;
+use rusqlite::Connection;
+
+use aw_models::Bucket;
+use aw_models::BucketMetadata;
+use aw_models::Event;
+use aw_models::EventProvenance;
+use aw_models::KeyValue;
+use aw_models::TryVec;
+use aw_transform::MergePolicy;
+
+use crate::DatastoreError;
+
+/*
+ * ### Database version changelog ###
+ * 1: Initial version
+ * 2: Added 'data' field to buckets
+ * 3: Added 'key_value' table
+ * 4: Added 'deleted' column to buckets (soft-delete support)
+ * 5: Added 'last_updated' column to buckets
+ * 6: Added 'event_provenance' table (ingest provenance per event)
+ * 7: Composite (bucketrow, starttime/endtime) indexes on events
+ * 8: Added 'events_history' table (pre-images of deleted events)
+ */
+static LATEST_DB_VERSION: i32 = 8;
+
+/// Insert batches larger than this (without explicit ids) go through the
+/// multi-row insert path
+const BULK_INSERT_THRESHOLD: usize = 50;
+/// Rows per multi-row INSERT statement, keeping the bind parameter count
+/// well below SQLite's limit
+const BULK_INSERT_CHUNK_ROWS: usize = 100;
+
+fn _get_db_version(conn: &Connection) -> Result<i32, rusqlite::Error> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+pub struct DatastoreInstance {
+    buckets_cache: HashMap<String, Bucket>,
+    pub db_version: i32,
+}
+
+impl DatastoreInstance {
+    pub fn new(
+        conn: &Connection,
+        migrate_enabled: bool,
+    ) -> Result<DatastoreInstance, DatastoreError> {
+        let db_version = match _get_db_version(conn) {
+            Ok(db_version) => db_version,
+            Err(err) => return Err(DatastoreError::from_sqlite(err, "Failed to get db version")),
+        };
+        let first_init = db_version == 0;
+        let mut ds = DatastoreInstance {
+            buckets_cache: HashMap::new(),
+            db_version,
+        };
+        if first_init {
+            ds.create_tables(conn)?;
+        } else if db_version < LATEST_DB_VERSION {
+            if migrate_enabled {
+                ds.migrate(conn)?;
+            } else {
+                return Err(DatastoreError::OldDbVersion(format!(
+                    "DB version is {db_version}, latest version is {LATEST_DB_VERSION} \
+                     and migrations are disabled"
+                )));
+            }
+        }
+        ds.get_stored_buckets(conn)?;
+        Ok(ds)
+    }
+
+    fn create_tables(&mut self, conn: &Connection) -> Result<(), DatastoreError> {
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS buckets (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                type TEXT NOT NULL,
+                client TEXT NOT NULL,
+                hostname TEXT NOT NULL,
+                created TEXT NOT NULL,
+                data TEXT NOT NULL DEFAULT '{}',
+                deleted INTEGER,
+                last_updated TEXT
+            );
+            CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                bucketrow INTEGER NOT NULL,
+                starttime INTEGER NOT NULL,
+                endtime INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                FOREIGN KEY (bucketrow) REFERENCES buckets(id)
+            );
+            CREATE INDEX IF NOT EXISTS events_starttime_index ON events(starttime);
+            CREATE INDEX IF NOT EXISTS events_endtime_index ON events(endtime);
+            CREATE INDEX IF NOT EXISTS events_bucketrow_starttime_index
+                ON events(bucketrow, starttime);
+            CREATE INDEX IF NOT EXISTS events_bucketrow_endtime_index
+                ON events(bucketrow, endtime);
+            CREATE TABLE IF NOT EXISTS key_value (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL,
+                timestamp TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS event_provenance (
+                eventrow INTEGER PRIMARY KEY,
+                keyid TEXT,
+                client TEXT,
+                ingested TEXT NOT NULL,
+                batch TEXT
+            );
+            CREATE INDEX IF NOT EXISTS event_provenance_batch_index
+                ON event_provenance(batch);
+            CREATE TABLE IF NOT EXISTS events_history (
+                eventrow INTEGER NOT NULL,
+                bucketrow INTEGER NOT NULL,
+                starttime INTEGER NOT NULL,
+                endtime INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                removed INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS events_history_bucketrow_starttime_index
+                ON events_history(bucketrow, starttime);
+            ",
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to create tables"))?;
+        self.set_db_version(conn, LATEST_DB_VERSION)?;
+        self.db_version = LATEST_DB_VERSION;
+        Ok(())
+    }
+
+    fn migrate(&mut self, conn: &Connection) -> Result<(), DatastoreError> {
+        if self.db_version < 2 {
+            info!("Migrating database to v2");
+            conn.execute(
+                "ALTER TABLE buckets ADD COLUMN data TEXT NOT NULL DEFAULT '{}'",
+                [],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v2"))?;
+        }
+        if self.db_version < 3 {
+            info!("Migrating database to v3");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS key_value (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL,
+                    timestamp TEXT NOT NULL
+                )",
+                [],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v3"))?;
+        }
+        if self.db_version < 4 {
+            info!("Migrating database to v4");
+            conn.execute("ALTER TABLE buckets ADD COLUMN deleted INTEGER", [])
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v4"))?;
+        }
+        if self.db_version < 5 {
+            info!("Migrating database to v5");
+            conn.execute("ALTER TABLE buckets ADD COLUMN last_updated TEXT", [])
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v5"))?;
+        }
+        if self.db_version < 6 {
+            info!("Migrating database to v6");
+            conn.execute_batch(
+                "
+                CREATE TABLE IF NOT EXISTS event_provenance (
+                    eventrow INTEGER PRIMARY KEY,
+                    keyid TEXT,
+                    client TEXT,
+                    ingested TEXT NOT NULL,
+                    batch TEXT
+                );
+                CREATE INDEX IF NOT EXISTS event_provenance_batch_index
+                    ON event_provenance(batch);
+                ",
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v6"))?;
+        }
+        if self.db_version < 7 {
+            info!("Migrating database to v7");
+            // Time-window queries always scope to one bucket, so composite
+            // indexes let them seek straight to the range instead of
+            // scanning every row of the bucket; the lone bucketrow index
+            // is a prefix of them and becomes redundant
+            conn.execute_batch(
+                "
+                CREATE INDEX IF NOT EXISTS events_bucketrow_starttime_index
+                    ON events(bucketrow, starttime);
+                CREATE INDEX IF NOT EXISTS events_bucketrow_endtime_index
+                    ON events(bucketrow, endtime);
+                DROP INDEX IF EXISTS events_bucketrow_index;
+                ",
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v7"))?;
+        }
+        if self.db_version < 8 {
+            info!("Migrating database to v8");
+            conn.execute_batch(
+                "
+                CREATE TABLE IF NOT EXISTS events_history (
+                    eventrow INTEGER NOT NULL,
+                    bucketrow INTEGER NOT NULL,
+                    starttime INTEGER NOT NULL,
+                    endtime INTEGER NOT NULL,
+                    data TEXT NOT NULL,
+                    removed INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS events_history_bucketrow_starttime_index
+                    ON events_history(bucketrow, starttime);
+                ",
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to migrate db to v8"))?;
+        }
+        self.set_db_version(conn, LATEST_DB_VERSION)?;
+        self.db_version = LATEST_DB_VERSION;
+        Ok(())
+    }
+
+    fn set_db_version(&self, conn: &Connection, version: i32) -> Result<(), DatastoreError> {
+        conn.pragma_update(None, "user_version", version)
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to set db version"))
+    }
+
+    fn get_stored_buckets(&mut self, conn: &Connection) -> Result<(), DatastoreError> {
+        let mut stmt = conn
+            .prepare(
+                "
+                SELECT buckets.id, buckets.name, buckets.type, buckets.client,
+                       buckets.hostname, buckets.created, buckets.data,
+                       min(events.starttime), max(events.endtime),
+                       buckets.last_updated
+                FROM buckets
+                LEFT OUTER JOIN events ON buckets.id = events.bucketrow
+                WHERE buckets.deleted IS NULL
+                GROUP BY buckets.id
+                ",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_stored_buckets query")
+            })?;
+        let buckets = stmt
+            .query_map([], |row| {
+                let opt_start_ns: Option<i64> = row.get(7)?;
+                let opt_start = opt_start_ns.map(|starttime_ns| {
+                    let seconds: i64 = starttime_ns / 1_000_000_000;
+                    let subnanos: u32 = (starttime_ns % 1_000_000_000) as u32;
+                    DateTime::from_timestamp(seconds, subnanos).unwrap()
+                });
+                let opt_end_ns: Option<i64> = row.get(8)?;
+                let opt_end = opt_end_ns.map(|endtime_ns| {
+                    let seconds: i64 = endtime_ns / 1_000_000_000;
+                    let subnanos: u32 = (endtime_ns % 1_000_000_000) as u32;
+                    DateTime::from_timestamp(seconds, subnanos).unwrap()
+                });
+                Ok(Bucket {
+                    bid: row.get(0)?,
+                    id: row.get(1)?,
+                    _type: row.get(2)?,
+                    client: row.get(3)?,
+                    hostname: row.get(4)?,
+                    created: row.get(5)?,
+                    last_updated: row.get(9)?,
+                    event_count: None,
+                    total_duration: None,
+                    data: serde_json::from_str(&row.get::<usize, String>(6)?).unwrap_or_default(),
+                    events: TryVec::new_empty(),
+                    metadata: BucketMetadata {
+                        start: opt_start,
+                        end: opt_end,
+                    },
+                })
+            })
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query stored buckets"))?;
+        for bucket in buckets {
+            match bucket {
+                Ok(bucket) => {
+                    self.buckets_cache.insert(bucket.id.clone(), bucket);
+                }
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse bucket from db",
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_bucket(
+        &mut self,
+        conn: &Connection,
+        mut bucket: Bucket,
+    ) -> Result<(), DatastoreError> {
+        bucket.created = match bucket.created {
+            Some(created) => Some(created),
+            None => Some(Utc::now()),
+        };
+        bucket.last_updated = bucket.created;
+        if self.buckets_cache.contains_key(&bucket.id) {
+            return Err(DatastoreError::BucketAlreadyExists(bucket.id));
+        }
+        // A soft-deleted bucket still occupies the name, restore or purge it first
+        let in_trash: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM buckets WHERE name = ?1 AND deleted IS NOT NULL",
+                params![bucket.id],
+                |row| row.get(0),
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query trash"))?;
+        if in_trash > 0 {
+            return Err(DatastoreError::BucketAlreadyExists(format!(
+                "{} (in trash)",
+                bucket.id
+            )));
+        }
+        let res = conn.execute(
+            "INSERT INTO buckets (name, type, client, hostname, created, data, last_updated)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                bucket.id,
+                bucket._type,
+                bucket.client,
+                bucket.hostname,
+                bucket.created,
+                serde_json::to_string(&bucket.data).unwrap(),
+                bucket.last_updated,
+            ],
+        );
+        match res {
+            Ok(_) => {
+                let rowid = conn.last_insert_rowid();
+                bucket.bid = Some(rowid);
+            }
+            Err(err) => return Err(DatastoreError::from_sqlite(err, "Failed to create bucket")),
+        };
+        let events = bucket.events.take_inner();
+        bucket.events = TryVec::new_empty();
+        self.buckets_cache.insert(bucket.id.clone(), bucket.clone());
+        if !events.is_empty() {
+            self.insert_events(conn, &bucket.id, events)?;
+        }
+        info!("Created bucket {}", bucket.id);
+        Ok(())
+    }
+
+    /// Creates the given buckets and inserts their events inside a
+    /// savepoint, so either the whole import lands or none of it does.
+    /// The worker already batches requests in an outer transaction; the
+    /// savepoint gives the import its own rollback scope within it.
+    pub fn import(
+        &mut self,
+        conn: &Connection,
+        data: HashMap<String, (Bucket, Vec<Event>)>,
+        provenance: Option<&EventProvenance>,
+    ) -> Result<(), DatastoreError> {
+        conn.execute_batch("SAVEPOINT import_data")
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to create import savepoint"))?;
+        let mut created: Vec<String> = Vec::new();
+        let run = || -> Result<(), DatastoreError> {
+            for (_key, (bucket, mut events)) in data {
+                let bucket_id = bucket.id.clone();
+                self.create_bucket(conn, bucket)?;
+                created.push(bucket_id.clone());
+                // Exported ids belong to the source database; keep the
+                // generated ones so they can't clobber existing rows
+                for event in &mut events {
+                    event.id = None;
+                }
+                if !events.is_empty() {
+                    let inserted = self.insert_events(conn, &bucket_id, events)?;
+                    if let Some(provenance) = provenance {
+                        let ids: Vec<i64> = inserted.iter().filter_map(|event| event.id).collect();
+                        self.set_event_provenance(conn, &ids, provenance)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        match run() {
+            Ok(()) => {
+                conn.execute_batch("RELEASE import_data").map_err(|err| {
+                    DatastoreError::from_sqlite(err, "Failed to release import savepoint")
+                })?;
+                Ok(())
+            }
+            Err(err) => {
+                if let Err(rollback_err) =
+                    conn.execute_batch("ROLLBACK TO import_data; RELEASE import_data")
+                {
+                    return Err(DatastoreError::InternalError(format!(
+                        "Failed to roll back import: {rollback_err} (import failed with: {err})"
+                    )));
+                }
+                // The SQL is rolled back, drop the created buckets from
+                // the cache as well
+                for bucket_id in created {
+                    self.buckets_cache.remove(&bucket_id);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn delete_bucket(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+    ) -> Result<(), DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        conn.execute(
+            "DELETE FROM event_provenance WHERE eventrow IN
+                (SELECT id FROM events WHERE bucketrow = ?1)",
+            [bucket.bid],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event provenance"))?;
+        conn.execute("DELETE FROM events WHERE bucketrow = ?1", [bucket.bid])
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete events"))?;
+        conn.execute(
+            "DELETE FROM events_history WHERE bucketrow = ?1",
+            [bucket.bid],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event history"))?;
+        conn.execute("DELETE FROM buckets WHERE id = ?1", [bucket.bid])
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete bucket"))?;
+        self.buckets_cache.remove(bucket_id);
+        info!("Deleted bucket {bucket_id}");
+        Ok(())
+    }
+
+    /// Mark a bucket as deleted, hiding it from listings but keeping its
+    /// events around so it can be restored until it is purged
+    pub fn soft_delete_bucket(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+    ) -> Result<(), DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let deleted_ns = Utc::now().timestamp_nanos_opt().unwrap();
+        conn.execute(
+            "UPDATE buckets SET deleted = ?1 WHERE id = ?2",
+            params![deleted_ns, bucket.bid],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to soft-delete bucket"))?;
+        self.buckets_cache.remove(bucket_id);
+        info!("Moved bucket {bucket_id} to trash");
+        Ok(())
+    }
+
+    /// Undo a soft-delete, making the bucket visible again
+    pub fn restore_bucket(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+    ) -> Result<(), DatastoreError> {
+        let updated = conn
+            .execute(
+                "UPDATE buckets SET deleted = NULL WHERE name = ?1 AND deleted IS NOT NULL",
+                params![bucket_id],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to restore bucket"))?;
+        if updated == 0 {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        self.get_stored_buckets(conn)?;
+        info!("Restored bucket {bucket_id} from trash");
+        Ok(())
+    }
+
+    pub fn get_deleted_buckets(&self, conn: &Connection) -> Result<Vec<String>, DatastoreError> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM buckets WHERE deleted IS NOT NULL")
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_deleted_buckets query")
+            })?;
+        let rows = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query deleted buckets"))?;
+        let mut names = Vec::new();
+        for row in rows {
+            match row {
+                Ok(name) => names.push(name),
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse bucket name from db",
+                    ))
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Permanently remove buckets that were soft-deleted before the cutoff,
+    /// returning the number of buckets purged
+    pub fn purge_deleted_buckets(
+        &self,
+        conn: &Connection,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        let cutoff_ns = before.timestamp_nanos_opt().unwrap();
+        conn.execute(
+            "DELETE FROM events WHERE bucketrow IN
+             (SELECT id FROM buckets WHERE deleted IS NOT NULL AND deleted < ?1)",
+            params![cutoff_ns],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to purge trashed events"))?;
+        let purged = conn
+            .execute(
+                "DELETE FROM buckets WHERE deleted IS NOT NULL AND deleted < ?1",
+                params![cutoff_ns],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to purge trashed buckets"))?;
+        Ok(purged as i64)
+    }
+
+    pub fn get_bucket(&self, bucket_id: &str) -> Result<Bucket, DatastoreError> {
+        match self.buckets_cache.get(bucket_id) {
+            Some(bucket) => Ok(bucket.clone()),
+            None => Err(DatastoreError::NoSuchBucket(bucket_id.to_string())),
+        }
+    }
+
+    pub fn get_buckets(&self) -> HashMap<String, Bucket> {
+        self.buckets_cache.clone()
+    }
+
+    /// Replaces a bucket's metadata `data`, e.g. to set or clear the
+    /// `archived` flag
+    pub fn set_bucket_data(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        data: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), DatastoreError> {
+        let bucket = match self.buckets_cache.get_mut(bucket_id) {
+            Some(bucket) => bucket,
+            None => return Err(DatastoreError::NoSuchBucket(bucket_id.to_string())),
+        };
+        conn.execute(
+            "UPDATE buckets SET data = ?1 WHERE id = ?2",
+            params![serde_json::to_string(&data).unwrap(), bucket.bid],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to update bucket data"))?;
+        bucket.data = data;
+        Ok(())
+    }
+
+    pub fn insert_events(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        mut events: Vec<Event>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        // Large batches without explicit ids (imports, mostly) go through
+        // the multi-row insert path, which is much faster than one
+        // statement per event
+        if events.len() > BULK_INSERT_THRESHOLD && events.iter().all(|event| event.id.is_none()) {
+            return self.insert_events_bulk(conn, bucket_id, events);
+        }
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let mut stmt = conn
+            .prepare(
+                "INSERT OR REPLACE INTO events (id, bucketrow, starttime, endtime, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare insert_events query")
+            })?;
+
+        for event in &mut events {
+            let starttime_nanos = event.timestamp.timestamp_nanos_opt().unwrap();
+            let duration_nanos = match event.duration.num_nanoseconds() {
+                Some(nanos) => nanos,
+                None => {
+                    return Err(DatastoreError::InternalError(
+                        "Failed to convert duration to nanoseconds".to_string(),
+                    ))
+                }
+            };
+            let endtime_nanos = starttime_nanos + duration_nanos;
+            let res = stmt.execute(params![
+                event.id,
+                bucket.bid,
+                starttime_nanos,
+                endtime_nanos,
+                serde_json::to_string(&event.data).unwrap(),
+            ]);
+            match res {
+                Ok(_) => event.id = Some(conn.last_insert_rowid()),
+                Err(err) => return Err(DatastoreError::from_sqlite(err, "Failed to insert event")),
+            }
+            self.update_endtime(bucket_id, event);
+        }
+        self.touch_bucket(conn, bucket_id)?;
+        Ok(events)
+    }
+
+    /// Inserts events in multi-row INSERT chunks, assigning the generated
+    /// ids in order. Only valid for events without explicit ids.
+    fn insert_events_bulk(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        mut events: Vec<Event>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let bid = match bucket.bid {
+            Some(bid) => bid,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "Bucket has no database row id".to_string(),
+                ))
+            }
+        };
+
+        for chunk in events.chunks_mut(BULK_INSERT_CHUNK_ROWS) {
+            let sql = format!(
+                "INSERT INTO events (bucketrow, starttime, endtime, data) VALUES {}",
+                vec!["(?, ?, ?, ?)"; chunk.len()].join(", ")
+            );
+            let mut values: Vec<rusqlite::types::Value> = Vec::with_capacity(chunk.len() * 4);
+            for event in chunk.iter() {
+                let starttime_nanos = event.timestamp.timestamp_nanos_opt().unwrap();
+                let duration_nanos = match event.duration.num_nanoseconds() {
+                    Some(nanos) => nanos,
+                    None => {
+                        return Err(DatastoreError::InternalError(
+                            "Failed to convert duration to nanoseconds".to_string(),
+                        ))
+                    }
+                };
+                values.push(rusqlite::types::Value::Integer(bid));
+                values.push(rusqlite::types::Value::Integer(starttime_nanos));
+                values.push(rusqlite::types::Value::Integer(
+                    starttime_nanos + duration_nanos,
+                ));
+                values.push(rusqlite::types::Value::Text(
+                    serde_json::to_string(&event.data).unwrap(),
+                ));
+            }
+            conn.execute(&sql, rusqlite::params_from_iter(values.iter()))
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to bulk insert events"))?;
+            // The worker thread owns the connection exclusively, so the
+            // rowids of a multi-row insert are consecutive and
+            // last_insert_rowid refers to the last row of this chunk
+            let last_id = conn.last_insert_rowid();
+            let first_id = last_id - chunk.len() as i64 + 1;
+            for (offset, event) in chunk.iter_mut().enumerate() {
+                event.id = Some(first_id + offset as i64);
+            }
+        }
+        for event in &events {
+            self.update_endtime(bucket_id, event);
+        }
+        self.touch_bucket(conn, bucket_id)?;
+        Ok(events)
+    }
+
+    /// Records ingest provenance for the given event rows. The same
+    /// provenance is attached to every id, so callers record one row per
+    /// insert batch.
+    pub fn set_event_provenance(
+        &self,
+        conn: &Connection,
+        event_ids: &[i64],
+        provenance: &EventProvenance,
+    ) -> Result<(), DatastoreError> {
+        let mut stmt = conn
+            .prepare(
+                "INSERT OR REPLACE INTO event_provenance (eventrow, keyid, client, ingested, batch)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare set_event_provenance query")
+            })?;
+        for event_id in event_ids {
+            stmt.execute(params![
+                event_id,
+                provenance.key,
+                provenance.client,
+                &provenance.ingested as &dyn ToSql,
+                provenance.batch,
+            ])
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to insert event provenance"))?;
+        }
+        Ok(())
+    }
+
+    /// Fetches provenance for the given event rows. Events inserted before
+    /// provenance tracking existed simply have no entry in the result.
+    pub fn get_event_provenance(
+        &self,
+        conn: &Connection,
+        event_ids: &[i64],
+    ) -> Result<HashMap<i64, EventProvenance>, DatastoreError> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT keyid, client, ingested, batch FROM event_provenance WHERE eventrow = ?1",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_event_provenance query")
+            })?;
+        let mut provenance = HashMap::new();
+        for event_id in event_ids {
+            let row = stmt
+                .query_row(params![event_id], |row| {
+                    Ok(EventProvenance {
+                        key: row.get(0)?,
+                        client: row.get(1)?,
+                        ingested: row.get(2)?,
+                        batch: row.get(3)?,
+                    })
+                })
+                .map(Some)
+                .or_else(|err| match err {
+                    rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                    _ => Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to get event provenance",
+                    )),
+                })?;
+            if let Some(row) = row {
+                provenance.insert(*event_id, row);
+            }
+        }
+        Ok(provenance)
+    }
+
+    /// Update the start/end metadata of the cached bucket to cover the event
+    fn update_endtime(&mut self, bucket_id: &str, event: &Event) {
+        let bucket = match self.buckets_cache.get_mut(bucket_id) {
+            Some(bucket) => bucket,
+            None => return,
+        };
+        let endtime = event.calculate_endtime();
+        match bucket.metadata.start {
+            None => bucket.metadata.start = Some(event.timestamp),
+            Some(current_start) => {
+                if current_start > event.timestamp {
+                    bucket.metadata.start = Some(event.timestamp);
+                }
+            }
+        }
+        match bucket.metadata.end {
+            None => bucket.metadata.end = Some(endtime),
+            Some(current_end) => {
+                if current_end < endtime {
+                    bucket.metadata.end = Some(endtime);
+                }
+            }
+        }
+    }
+
+    /// Stamps the bucket's last_updated, in the cache and the database,
+    /// after a write. Called once per write operation, not per event.
+    fn touch_bucket(&mut self, conn: &Connection, bucket_id: &str) -> Result<(), DatastoreError> {
+        let now = Utc::now();
+        if let Some(bucket) = self.buckets_cache.get_mut(bucket_id) {
+            bucket.last_updated = Some(now);
+        }
+        conn.execute(
+            "UPDATE buckets SET last_updated = ?2 WHERE name = ?1",
+            params![bucket_id, now],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to update last_updated"))?;
+        Ok(())
+    }
+
+    /// Re-reads a bucket's event extent into the cached metadata, for
+    /// mutations that can shrink it (deletes, in-place updates). Inserts
+    /// and heartbeats only ever grow the extent and go through the cheaper
+    /// [`Self::update_endtime`] instead.
+    fn refresh_metadata(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+    ) -> Result<(), DatastoreError> {
+        let bid = match self.buckets_cache.get(bucket_id).and_then(|b| b.bid) {
+            Some(bid) => bid,
+            None => return Ok(()),
+        };
+        let (start_ns, end_ns) = conn
+            .query_row(
+                "SELECT MIN(starttime), MAX(endtime) FROM events WHERE bucketrow = ?1",
+                params![bid],
+                |row| {
+                    Ok((
+                        row.get::<usize, Option<i64>>(0)?,
+                        row.get::<usize, Option<i64>>(1)?,
+                    ))
+                },
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to read bucket extent"))?;
+        let from_nanos = |ns: i64| {
+            DateTime::from_timestamp(ns / 1_000_000_000, (ns % 1_000_000_000) as u32).unwrap()
+        };
+        if let Some(bucket) = self.buckets_cache.get_mut(bucket_id) {
+            bucket.metadata.start = start_ns.map(from_nanos);
+            bucket.metadata.end = end_ns.map(from_nanos);
+        }
+        Ok(())
+    }
+
+    /// Heartbeat fast path: merges the heartbeat into the bucket's last
+    /// event with a single UPDATE when the data matches and the heartbeat
+    /// falls within the pulsetime window, instead of the old
+    /// read-merge-write sequence. Falls back to a plain insert when no
+    /// merge is possible. Data is compared as serialized JSON, so clients
+    /// must send keys in a stable order for merging to work (they do).
+    ///
+    /// A non-exact merge policy can't be expressed in the SQL comparison,
+    /// so it takes a read-merge-write path instead.
+    pub fn heartbeat(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+        policy: &MergePolicy,
+    ) -> Result<Event, DatastoreError> {
+        if !policy.is_exact() {
+            return self.heartbeat_with_policy(conn, bucket_id, heartbeat, pulsetime, policy);
+        }
+        let bucket = self.get_bucket(bucket_id)?;
+        let starttime_nanos = heartbeat.timestamp.timestamp_nanos_opt().unwrap();
+        let duration_nanos = match heartbeat.duration.num_nanoseconds() {
+            Some(nanos) => nanos,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "Failed to convert duration to nanoseconds".to_string(),
+                ))
+            }
+        };
+        let endtime_nanos = starttime_nanos + duration_nanos;
+        let pulsetime_nanos = (pulsetime * 1_000_000_000.0).round() as i64;
+
+        let mut stmt = conn
+            .prepare(
+                "UPDATE events
+                 SET endtime = MAX(endtime, ?3)
+                 WHERE id = (SELECT id FROM events WHERE bucketrow = ?1
+                             ORDER BY endtime DESC LIMIT 1)
+                   AND data = ?4
+                   AND starttime <= ?2
+                   AND ?2 <= endtime + ?5
+                 RETURNING id, starttime, endtime",
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to prepare heartbeat query"))?;
+        let merged = stmt.query_row(
+            params![
+                bucket.bid,
+                starttime_nanos,
+                endtime_nanos,
+                serde_json::to_string(&heartbeat.data).unwrap(),
+                pulsetime_nanos,
+            ],
+            |row| {
+                let id: i64 = row.get(0)?;
+                let starttime_ns: i64 = row.get(1)?;
+                let endtime_ns: i64 = row.get(2)?;
+                Ok((id, starttime_ns, endtime_ns))
+            },
+        );
+        match merged {
+            Ok((id, starttime_ns, endtime_ns)) => {
+                let time_seconds: i64 = starttime_ns / 1_000_000_000;
+                let time_subnanos: u32 = (starttime_ns % 1_000_000_000) as u32;
+                let event = Event {
+                    id: Some(id),
+                    timestamp: DateTime::from_timestamp(time_seconds, time_subnanos).unwrap(),
+                    duration: Duration::nanoseconds(endtime_ns - starttime_ns),
+                    data: heartbeat.data,
+                };
+                self.update_endtime(bucket_id, &event);
+                self.touch_bucket(conn, bucket_id)?;
+                Ok(event)
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                let mut inserted = self.insert_events(conn, bucket_id, vec![heartbeat])?;
+                Ok(inserted.pop().unwrap())
+            }
+            Err(err) => Err(DatastoreError::from_sqlite(
+                err,
+                "Failed to merge heartbeat",
+            )),
+        }
+    }
+
+    /// Slow heartbeat path for non-exact merge policies: reads the
+    /// bucket's last event, applies the policy-aware merge in Rust, and
+    /// writes the extended extent back. Falls back to a plain insert when
+    /// no merge is possible.
+    fn heartbeat_with_policy(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+        policy: &MergePolicy,
+    ) -> Result<Event, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let last_row = conn.query_row(
+            "SELECT id, starttime, endtime, data FROM events
+             WHERE bucketrow = ?1 ORDER BY endtime DESC LIMIT 1",
+            params![bucket.bid],
+            |row| {
+                Ok((
+                    row.get::<usize, i64>(0)?,
+                    row.get::<usize, i64>(1)?,
+                    row.get::<usize, i64>(2)?,
+                    row.get::<usize, String>(3)?,
+                ))
+            },
+        );
+        let last_event = match last_row {
+            Ok((id, starttime_ns, endtime_ns, data)) => Some(Event {
+                id: Some(id),
+                timestamp: DateTime::from_timestamp(
+                    starttime_ns / 1_000_000_000,
+                    (starttime_ns % 1_000_000_000) as u32,
+                )
+                .unwrap(),
+                duration: Duration::nanoseconds(endtime_ns - starttime_ns),
+                data: serde_json::from_str(&data).map_err(|err| {
+                    DatastoreError::InternalError(format!("Failed to parse event data: {err}"))
+                })?,
+            }),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(err) => {
+                return Err(DatastoreError::from_sqlite(
+                    err,
+                    "Failed to fetch last event for heartbeat",
+                ))
+            }
+        };
+        if let Some(last_event) = last_event {
+            if let Some(mut merged) =
+                aw_transform::heartbeat_with_policy(&last_event, &heartbeat, pulsetime, policy)
+            {
+                merged.id = last_event.id;
+                let starttime_nanos = merged.timestamp.timestamp_nanos_opt().unwrap();
+                let endtime_nanos =
+                    starttime_nanos + merged.duration.num_nanoseconds().unwrap_or(0);
+                conn.execute(
+                    "UPDATE events SET starttime = ?2, endtime = ?3 WHERE id = ?1",
+                    params![merged.id, starttime_nanos, endtime_nanos],
+                )
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to merge heartbeat"))?;
+                self.update_endtime(bucket_id, &merged);
+                self.touch_bucket(conn, bucket_id)?;
+                return Ok(merged);
+            }
+        }
+        let mut inserted = self.insert_events(conn, bucket_id, vec![heartbeat])?;
+        Ok(inserted.pop().unwrap())
+    }
+
+    /// Replays an ordered list of heartbeats through the normal merge
+    /// logic in one call, for watchers flushing a queue built up while
+    /// offline. Runs inside the worker's usual transaction handling, so
+    /// the whole batch commits (or retries) together.
+    pub fn heartbeat_batch(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        heartbeats: Vec<Event>,
+        pulsetime: f64,
+        policy: &MergePolicy,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let mut written = Vec::with_capacity(heartbeats.len());
+        for heartbeat in heartbeats {
+            written.push(self.heartbeat(conn, bucket_id, heartbeat, pulsetime, policy)?);
+        }
+        Ok(written)
+    }
+
+    /// Replaces the bucket's last event. When the caller knows the id of
+    /// the event it intends to replace (`event.id` is set), the update is
+    /// pinned to that primary key so a concurrent insert can't redirect it
+    /// to the wrong row; a stale id (row gone) falls back to the
+    /// last-by-endtime update.
+    pub fn replace_last_event(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        event: &Event,
+    ) -> Result<(), DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let starttime_nanos = event.timestamp.timestamp_nanos_opt().unwrap();
+        let duration_nanos = match event.duration.num_nanoseconds() {
+            Some(nanos) => nanos,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "Failed to convert duration to nanoseconds".to_string(),
+                ))
+            }
+        };
+        let endtime_nanos = starttime_nanos + duration_nanos;
+        let data_str = serde_json::to_string(&event.data).unwrap();
+
+        if let Some(id) = event.id {
+            let mut stmt = conn
+                .prepare(
+                    "UPDATE events
+                     SET starttime = ?3, endtime = ?4, data = ?5
+                     WHERE id = ?2 AND bucketrow = ?1",
+                )
+                .map_err(|err| {
+                    DatastoreError::from_sqlite(err, "Failed to prepare replace_last_event query")
+                })?;
+            let updated = stmt
+                .execute(params![
+                    bucket.bid,
+                    id,
+                    starttime_nanos,
+                    endtime_nanos,
+                    data_str,
+                ])
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to replace last event"))?;
+            if updated > 0 {
+                self.update_endtime(bucket_id, event);
+                self.touch_bucket(conn, bucket_id)?;
+                return Ok(());
+            }
+            warn!("replace_last_event: event {id} no longer exists, replacing last by endtime");
+        }
+
+        let mut stmt = conn
+            .prepare(
+                "UPDATE events
+                 SET starttime = ?2, endtime = ?3, data = ?4
+                 WHERE bucketrow = ?1
+                   AND endtime = (SELECT max(endtime) FROM events WHERE bucketrow = ?1)",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare replace_last_event query")
+            })?;
+        stmt.execute(params![
+            bucket.bid,
+            starttime_nanos,
+            endtime_nanos,
+            data_str
+        ])
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to replace last event"))?;
+        self.update_endtime(bucket_id, event);
+        self.touch_bucket(conn, bucket_id)?;
+        Ok(())
+    }
+
+    pub fn get_events(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let mut list = Vec::new();
+
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns > endtime_filter_ns {
+            warn!("Starttime in event query was lower than endtime!");
+            return Ok(list);
+        }
+        let limit = match limit_opt {
+            Some(limit) => limit as i64,
+            None => -1,
+        };
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, starttime, endtime, data
+                 FROM events
+                 WHERE bucketrow = ?1
+                   AND endtime >= ?2
+                   AND starttime <= ?3
+                 ORDER BY starttime DESC
+                 LIMIT ?4",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_events query")
+            })?;
+
+        let rows = stmt
+            .query_map(
+                params![bucket.bid, starttime_filter_ns, endtime_filter_ns, limit],
+                |row| {
+                    let id = row.get(0)?;
+                    let mut starttime_ns: i64 = row.get(1)?;
+                    let mut endtime_ns: i64 = row.get(2)?;
+                    let data_str: String = row.get(3)?;
+
+                    if starttime_ns < starttime_filter_ns {
+                        starttime_ns = starttime_filter_ns;
+                    }
+                    if endtime_ns > endtime_filter_ns {
+                        endtime_ns = endtime_filter_ns;
+                    }
+                    let duration_ns = endtime_ns - starttime_ns;
+
+                    let time_seconds: i64 = starttime_ns / 1_000_000_000;
+                    let time_subnanos: u32 = (starttime_ns % 1_000_000_000) as u32;
+
+                    Ok(Event {
+                        id,
+                        timestamp: DateTime::from_timestamp(time_seconds, time_subnanos).unwrap(),
+                        duration: Duration::nanoseconds(duration_ns),
+                        data: serde_json::from_str(&data_str).map_err(|err| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                3,
+                                rusqlite::types::Type::Text,
+                                Box::new(err),
+                            )
+                        })?,
+                    })
+                },
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query events"))?;
+        for row in rows {
+            match row {
+                Ok(event) => list.push(event),
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse event from db",
+                    ))
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    /// A single event by id, without loading the rest of the bucket.
+    /// Constraining on bucketrow makes this the ownership check too: an
+    /// id from another bucket matches no row.
+    pub fn get_event_by_id(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        event_id: i64,
+    ) -> Result<Event, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, starttime, endtime, data
+                 FROM events
+                 WHERE bucketrow = ?1 AND id = ?2",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_event_by_id query")
+            })?;
+        let mut rows = stmt
+            .query_map(params![bucket.bid, event_id], |row| {
+                let id = row.get(0)?;
+                let starttime_ns: i64 = row.get(1)?;
+                let endtime_ns: i64 = row.get(2)?;
+                let data_str: String = row.get(3)?;
+                Ok(Event {
+                    id,
+                    timestamp: DateTime::from_timestamp(
+                        starttime_ns / 1_000_000_000,
+                        (starttime_ns % 1_000_000_000) as u32,
+                    )
+                    .unwrap(),
+                    duration: Duration::nanoseconds(endtime_ns - starttime_ns),
+                    data: serde_json::from_str(&data_str).map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            3,
+                            rusqlite::types::Type::Text,
+                            Box::new(err),
+                        )
+                    })?,
+                })
+            })
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query event"))?;
+        match rows.next() {
+            Some(Ok(event)) => Ok(event),
+            Some(Err(err)) => Err(DatastoreError::from_sqlite(
+                err,
+                "Failed to parse event from db",
+            )),
+            None => Err(DatastoreError::NoSuchKey(format!(
+                "No event with id {event_id} in bucket {bucket_id}"
+            ))),
+        }
+    }
+
+    /// Like [`DatastoreInstance::get_events`], but additionally filters on
+    /// exact matches of top-level `data` fields. The conditions are pushed
+    /// into SQL via `json_extract`, so filtering happens before the limit
+    /// is applied and non-matching events never leave the database.
+    /// The most recent `limit` events of every bucket, for bucket
+    /// listings that want a preview without a request per bucket
+    pub fn get_last_events(
+        &self,
+        conn: &Connection,
+        limit: u64,
+    ) -> Result<HashMap<String, Vec<Event>>, DatastoreError> {
+        let bucket_ids: Vec<String> = self.buckets_cache.keys().cloned().collect();
+        let mut last_events = HashMap::new();
+        for bucket_id in bucket_ids {
+            let events = self.get_events(conn, &bucket_id, None, None, Some(limit))?;
+            last_events.insert(bucket_id, events);
+        }
+        Ok(last_events)
+    }
+
+    pub fn get_events_filtered(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        filters: &[(String, serde_json::Value)],
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let bid = match bucket.bid {
+            Some(bid) => bid,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "Bucket has no database row id".to_string(),
+                ))
+            }
+        };
+
+        let mut list = Vec::new();
+
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns > endtime_filter_ns {
+            warn!("Starttime in event query was lower than endtime!");
+            return Ok(list);
+        }
+        let limit = match limit_opt {
+            Some(limit) => limit as i64,
+            None => -1,
+        };
+
+        let mut sql = String::from(
+            "SELECT id, starttime, endtime, data
+             FROM events
+             WHERE bucketrow = ?
+               AND endtime >= ?
+               AND starttime <= ?",
+        );
+        let mut values: Vec<rusqlite::types::Value> = vec![
+            rusqlite::types::Value::Integer(bid),
+            rusqlite::types::Value::Integer(starttime_filter_ns),
+            rusqlite::types::Value::Integer(endtime_filter_ns),
+        ];
+        for (field, value) in filters {
+            sql.push_str(" AND json_extract(data, ?) = ?");
+            values.push(rusqlite::types::Value::Text(format!("$.\"{field}\"")));
+            values.push(match value {
+                serde_json::Value::String(s) => rusqlite::types::Value::Text(s.clone()),
+                // json_extract turns JSON booleans into 0/1 integers
+                serde_json::Value::Bool(b) => rusqlite::types::Value::Integer(*b as i64),
+                serde_json::Value::Number(n) if n.is_i64() => {
+                    rusqlite::types::Value::Integer(n.as_i64().unwrap())
+                }
+                serde_json::Value::Number(n) => {
+                    rusqlite::types::Value::Real(n.as_f64().unwrap_or(f64::NAN))
+                }
+                other => rusqlite::types::Value::Text(other.to_string()),
+            });
+        }
+        sql.push_str(" ORDER BY starttime DESC LIMIT ?");
+        values.push(rusqlite::types::Value::Integer(limit));
+
+        let mut stmt = conn.prepare(&sql).map_err(|err| {
+            DatastoreError::from_sqlite(err, "Failed to prepare get_events_filtered query")
+        })?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(values.iter()), |row| {
+                let id = row.get(0)?;
+                let mut starttime_ns: i64 = row.get(1)?;
+                let mut endtime_ns: i64 = row.get(2)?;
+                let data_str: String = row.get(3)?;
+
+                if starttime_ns < starttime_filter_ns {
+                    starttime_ns = starttime_filter_ns;
+                }
+                if endtime_ns > endtime_filter_ns {
+                    endtime_ns = endtime_filter_ns;
+                }
+                let duration_ns = endtime_ns - starttime_ns;
+
+                let time_seconds: i64 = starttime_ns / 1_000_000_000;
+                let time_subnanos: u32 = (starttime_ns % 1_000_000_000) as u32;
+
+                Ok(Event {
+                    id,
+                    timestamp: DateTime::from_timestamp(time_seconds, time_subnanos).unwrap(),
+                    duration: Duration::nanoseconds(duration_ns),
+                    data: serde_json::from_str(&data_str).map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            3,
+                            rusqlite::types::Type::Text,
+                            Box::new(err),
+                        )
+                    })?,
+                })
+            })
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query events"))?;
+        for row in rows {
+            match row {
+                Ok(event) => list.push(event),
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse event from db",
+                    ))
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    /// Event count and total duration grouped by a top-level `data`
+    /// field, computed in SQL so "time per app" over a year of events
+    /// doesn't require shipping the events anywhere. Durations are
+    /// clipped to the query range like in `get_events`; events without
+    /// the field are excluded. Returns `(count, duration in ns)` keyed by
+    /// the field value's text representation.
+    pub fn get_events_aggregate(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        field: &str,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns > endtime_filter_ns {
+            warn!("Starttime in event query was lower than endtime!");
+            return Ok(HashMap::new());
+        }
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT CAST(json_extract(data, ?1) AS TEXT),
+                        COUNT(*),
+                        SUM(MIN(endtime, ?4) - MAX(starttime, ?3))
+                 FROM events
+                 WHERE bucketrow = ?2
+                   AND endtime >= ?3
+                   AND starttime <= ?4
+                   AND json_extract(data, ?1) IS NOT NULL
+                 GROUP BY 1",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_events_aggregate query")
+            })?;
+        let rows = stmt
+            .query_map(
+                params![
+                    format!("$.\"{field}\""),
+                    bucket.bid,
+                    starttime_filter_ns,
+                    endtime_filter_ns
+                ],
+                |row| {
+                    let key: String = row.get(0)?;
+                    let count: i64 = row.get(1)?;
+                    let duration_ns: i64 = row.get(2)?;
+                    Ok((key, (count, duration_ns)))
+                },
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to aggregate events"))?;
+        let mut aggregate = HashMap::new();
+        for row in rows {
+            match row {
+                Ok((key, value)) => {
+                    aggregate.insert(key, value);
+                }
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse aggregate row from db",
+                    ))
+                }
+            }
+        }
+        Ok(aggregate)
+    }
+
+    /// Event count and total duration per UTC hour, computed in SQL so a
+    /// dashboard timeline doesn't require shipping the events anywhere.
+    /// Each event counts wholly towards the hour its (clipped) start
+    /// falls in; durations are clipped to the query range like in
+    /// `get_events`. Returns `(count, duration in ns)` keyed by the hour
+    /// start as an RFC3339 timestamp.
+    pub fn get_events_hourly_summary(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns > endtime_filter_ns {
+            warn!("Starttime in event query was lower than endtime!");
+            return Ok(HashMap::new());
+        }
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT strftime('%Y-%m-%dT%H:00:00+00:00',
+                                 MAX(starttime, ?2) / 1000000000, 'unixepoch'),
+                        COUNT(*),
+                        SUM(MIN(endtime, ?3) - MAX(starttime, ?2))
+                 FROM events
+                 WHERE bucketrow = ?1
+                   AND endtime >= ?2
+                   AND starttime <= ?3
+                 GROUP BY 1",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_events_hourly_summary query")
+            })?;
+        let rows = stmt
+            .query_map(
+                params![bucket.bid, starttime_filter_ns, endtime_filter_ns],
+                |row| {
+                    let hour: String = row.get(0)?;
+                    let count: i64 = row.get(1)?;
+                    let duration_ns: i64 = row.get(2)?;
+                    Ok((hour, (count, duration_ns)))
+                },
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to summarize events"))?;
+        let mut summary = HashMap::new();
+        for row in rows {
+            match row {
+                Ok((hour, value)) => {
+                    summary.insert(hour, value);
+                }
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse summary row from db",
+                    ))
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Distinct values of a top-level `data` field starting with
+    /// `prefix` (case-insensitively, like SQLite's LIKE), with how often
+    /// each occurs in the range, most frequent first. Backs autocomplete
+    /// in rule editors, so the limit keeps responses suggestion-sized.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_event_suggestions(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        field: &str,
+        prefix: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<Vec<(String, i64)>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns > endtime_filter_ns {
+            warn!("Starttime in event query was lower than endtime!");
+            return Ok(Vec::new());
+        }
+
+        // LIKE treats % and _ as wildcards, escape them so the prefix is
+        // matched literally
+        let escaped = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let mut stmt = conn
+            .prepare(
+                "SELECT CAST(json_extract(data, ?1) AS TEXT) AS value, COUNT(*)
+                 FROM events
+                 WHERE bucketrow = ?2
+                   AND endtime >= ?3
+                   AND starttime <= ?4
+                   AND CAST(json_extract(data, ?1) AS TEXT) LIKE ?5 ESCAPE '\\'
+                 GROUP BY value
+                 ORDER BY COUNT(*) DESC, value ASC
+                 LIMIT ?6",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_event_suggestions query")
+            })?;
+        let rows = stmt
+            .query_map(
+                params![
+                    format!("$.\"{field}\""),
+                    bucket.bid,
+                    starttime_filter_ns,
+                    endtime_filter_ns,
+                    format!("{escaped}%"),
+                    limit as i64,
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query suggestions"))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to parse suggestion row"))
+    }
+
+    /// The bucket's events as they were at `as_of`: live events ingested
+    /// by then (events without recorded provenance are assumed older than
+    /// tracking and included) plus pre-images of events deleted after it.
+    /// Edits are not versioned — heartbeat-merged events appear in their
+    /// current form. Range clipping matches `get_events`.
+    pub fn get_events_as_of(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        as_of: DateTime<Utc>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let mut list = Vec::new();
+
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns > endtime_filter_ns {
+            warn!("Starttime in event query was lower than endtime!");
+            return Ok(list);
+        }
+        let limit = match limit_opt {
+            Some(limit) => limit as i64,
+            None => -1,
+        };
+        let as_of_ns = as_of.timestamp_nanos_opt().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, starttime, endtime, data FROM (
+                     SELECT e.id AS id, e.starttime AS starttime,
+                            e.endtime AS endtime, e.data AS data
+                     FROM events e
+                     LEFT JOIN event_provenance p ON p.eventrow = e.id
+                     WHERE e.bucketrow = ?1
+                       AND e.endtime >= ?2
+                       AND e.starttime <= ?3
+                       AND (p.ingested IS NULL OR p.ingested <= ?4)
+                     UNION ALL
+                     SELECT h.eventrow, h.starttime, h.endtime, h.data
+                     FROM events_history h
+                     WHERE h.bucketrow = ?1
+                       AND h.endtime >= ?2
+                       AND h.starttime <= ?3
+                       AND h.removed > ?5
+                 )
+                 ORDER BY starttime DESC
+                 LIMIT ?6",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_events_as_of query")
+            })?;
+        let rows = stmt
+            .query_map(
+                params![
+                    bucket.bid,
+                    starttime_filter_ns,
+                    endtime_filter_ns,
+                    &as_of as &dyn ToSql,
+                    as_of_ns,
+                    limit
+                ],
+                |row| {
+                    let id = row.get(0)?;
+                    let mut starttime_ns: i64 = row.get(1)?;
+                    let mut endtime_ns: i64 = row.get(2)?;
+                    let data_str: String = row.get(3)?;
+
+                    if starttime_ns < starttime_filter_ns {
+                        starttime_ns = starttime_filter_ns;
+                    }
+                    if endtime_ns > endtime_filter_ns {
+                        endtime_ns = endtime_filter_ns;
+                    }
+                    let duration_ns = endtime_ns - starttime_ns;
+
+                    let time_seconds: i64 = starttime_ns / 1_000_000_000;
+                    let time_subnanos: u32 = (starttime_ns % 1_000_000_000) as u32;
+
+                    Ok(Event {
+                        id,
+                        timestamp: DateTime::from_timestamp(time_seconds, time_subnanos).unwrap(),
+                        duration: Duration::nanoseconds(duration_ns),
+                        data: serde_json::from_str(&data_str).map_err(|err| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                3,
+                                rusqlite::types::Type::Text,
+                                Box::new(err),
+                            )
+                        })?,
+                    })
+                },
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query events"))?;
+        for row in rows {
+            match row {
+                Ok(event) => list.push(event),
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse event from db",
+                    ))
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    /// A page of events for streaming downloads: newest first, keyset
+    /// paginated on `(starttime, id)` so no offset scan is needed, and
+    /// not clipped to the query window like `get_events` — these are
+    /// exports, not aggregations. `before` is the cursor from the last
+    /// event of the previous page; None starts at the newest event.
+    pub fn get_events_page(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        before: Option<(i64, i64)>,
+        limit: u64,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        let (before_ns, before_id) = before.unwrap_or((i64::MAX, i64::MAX));
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, starttime, endtime, data
+                 FROM events
+                 WHERE bucketrow = ?1
+                   AND endtime >= ?2
+                   AND starttime <= ?3
+                   AND (starttime < ?4 OR (starttime = ?4 AND id < ?5))
+                 ORDER BY starttime DESC, id DESC
+                 LIMIT ?6",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_events_page query")
+            })?;
+        let rows = stmt
+            .query_map(
+                params![
+                    bucket.bid,
+                    starttime_filter_ns,
+                    endtime_filter_ns,
+                    before_ns,
+                    before_id,
+                    limit as i64
+                ],
+                |row| {
+                    let id = row.get(0)?;
+                    let starttime_ns: i64 = row.get(1)?;
+                    let endtime_ns: i64 = row.get(2)?;
+                    let data_str: String = row.get(3)?;
+                    let time_seconds: i64 = starttime_ns / 1_000_000_000;
+                    let time_subnanos: u32 = (starttime_ns % 1_000_000_000) as u32;
+                    Ok(Event {
+                        id,
+                        timestamp: DateTime::from_timestamp(time_seconds, time_subnanos).unwrap(),
+                        duration: Duration::nanoseconds(endtime_ns - starttime_ns),
+                        data: serde_json::from_str(&data_str).map_err(|err| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                3,
+                                rusqlite::types::Type::Text,
+                                Box::new(err),
+                            )
+                        })?,
+                    })
+                },
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query events page"))?;
+        rows.collect::<Result<Vec<Event>, _>>()
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to parse event from db"))
+    }
+
+    /// Returns SQLite's query plan (`EXPLAIN QUERY PLAN`) for the exact
+    /// statement `get_events` would run with these parameters, one detail
+    /// line per plan node, for diagnosing slow event queries.
+    pub fn explain_get_events(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<String>, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let starttime_filter_ns: i64 = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns: i64 = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        let limit = match limit_opt {
+            Some(limit) => limit as i64,
+            None => -1,
+        };
+        let mut stmt = conn
+            .prepare(
+                "EXPLAIN QUERY PLAN
+                 SELECT id, starttime, endtime, data
+                 FROM events
+                 WHERE bucketrow = ?1
+                   AND endtime >= ?2
+                   AND starttime <= ?3
+                 ORDER BY starttime DESC
+                 LIMIT ?4",
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to prepare explain query"))?;
+        let rows = stmt
+            .query_map(
+                params![bucket.bid, starttime_filter_ns, endtime_filter_ns, limit],
+                // Columns are (id, parent, notused, detail)
+                |row| row.get::<usize, String>(3),
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to explain query"))?;
+        rows.collect::<Result<Vec<String>, _>>()
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to read query plan"))
+    }
+
+    pub fn get_event_count(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<i64, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+
+        let starttime_filter_ns = match starttime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => 0,
+        };
+        let endtime_filter_ns = match endtime_opt {
+            Some(dt) => dt.timestamp_nanos_opt().unwrap(),
+            None => i64::MAX,
+        };
+        if starttime_filter_ns >= endtime_filter_ns {
+            warn!("Starttime in event count query was lower than endtime!");
+            return Ok(0);
+        }
+
+        let ret: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM events
+                 WHERE bucketrow = ?1 AND endtime >= ?2 AND starttime <= ?3",
+                params![bucket.bid, starttime_filter_ns, endtime_filter_ns],
+                |row| row.get(0),
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query event count"))?;
+        Ok(ret)
+    }
+
+    /// Per-bucket event count and total tracked duration in nanoseconds,
+    /// computed for all buckets in a single aggregate query
+    pub fn get_bucket_rollups(
+        &self,
+        conn: &Connection,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        // Buckets without events never show up in the aggregate, start
+        // them all off at zero
+        let mut rollups: HashMap<String, (i64, i64)> = self
+            .buckets_cache
+            .keys()
+            .map(|bucket_id| (bucket_id.clone(), (0, 0)))
+            .collect();
+        let names: HashMap<i64, &String> = self
+            .buckets_cache
+            .iter()
+            .filter_map(|(bucket_id, bucket)| bucket.bid.map(|bid| (bid, bucket_id)))
+            .collect();
+        let mut stmt = conn
+            .prepare(
+                "SELECT bucketrow, count(*), sum(endtime - starttime)
+                 FROM events GROUP BY bucketrow",
+            )
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_bucket_rollups query")
+            })?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<usize, i64>(0)?,
+                    row.get::<usize, i64>(1)?,
+                    row.get::<usize, i64>(2)?,
+                ))
+            })
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query bucket rollups"))?;
+        for row in rows {
+            let (bid, count, duration_ns) = row
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to parse bucket rollup"))?;
+            if let Some(bucket_id) = names.get(&bid) {
+                rollups.insert((*bucket_id).clone(), (count, duration_ns));
+            }
+        }
+        Ok(rollups)
+    }
+
+    pub fn delete_events_by_id(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        event_ids: Vec<i64>,
+    ) -> Result<(), DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let removed_ns = Utc::now().timestamp_nanos_opt().unwrap();
+        {
+            // Keep a pre-image of each deleted event, so an accidental
+            // delete can be inspected through as_of reads
+            let mut history_stmt = conn
+                .prepare(
+                    "INSERT INTO events_history
+                         (eventrow, bucketrow, starttime, endtime, data, removed)
+                     SELECT id, bucketrow, starttime, endtime, data, ?3
+                     FROM events WHERE bucketrow = ?1 AND id = ?2",
+                )
+                .map_err(|err| {
+                    DatastoreError::from_sqlite(err, "Failed to prepare events_history insert")
+                })?;
+            let mut stmt = conn
+                .prepare("DELETE FROM events WHERE bucketrow = ?1 AND id = ?2")
+                .map_err(|err| {
+                    DatastoreError::from_sqlite(err, "Failed to prepare delete_events_by_id query")
+                })?;
+            for event_id in event_ids {
+                history_stmt
+                    .execute(params![bucket.bid, event_id, removed_ns])
+                    .map_err(|err| {
+                        DatastoreError::from_sqlite(err, "Failed to record event history")
+                    })?;
+                stmt.execute(params![bucket.bid, event_id])
+                    .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event"))?;
+            }
+        }
+        // Deleting the oldest/newest event shrinks the bucket's extent
+        self.refresh_metadata(conn, bucket_id)?;
+        self.touch_bucket(conn, bucket_id)?;
+        Ok(())
+    }
+
+    /// Updates a single event in place (timestamp, duration and data),
+    /// after verifying the bucket owns it. The previous version is kept
+    /// as an `events_history` pre-image, so `as_of` reads still see what
+    /// the event looked like before the correction.
+    pub fn update_event(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        event: Event,
+    ) -> Result<Event, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let event_id = match event.id {
+            Some(id) => id,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "update_event requires an event id".to_string(),
+                ))
+            }
+        };
+        let starttime_nanos = event.timestamp.timestamp_nanos_opt().unwrap();
+        let duration_nanos = match event.duration.num_nanoseconds() {
+            Some(nanos) => nanos,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "Failed to convert duration to nanoseconds".to_string(),
+                ))
+            }
+        };
+        let removed_ns = Utc::now().timestamp_nanos_opt().unwrap();
+        conn.execute(
+            "INSERT INTO events_history
+                 (eventrow, bucketrow, starttime, endtime, data, removed)
+             SELECT id, bucketrow, starttime, endtime, data, ?3
+             FROM events WHERE bucketrow = ?1 AND id = ?2",
+            params![bucket.bid, event_id, removed_ns],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to record event history"))?;
+        // Constraining on bucketrow makes this both the update and the
+        // ownership check: an id from another bucket matches no row
+        let updated = conn
+            .execute(
+                "UPDATE events SET starttime = ?3, endtime = ?4, data = ?5
+                 WHERE bucketrow = ?1 AND id = ?2",
+                params![
+                    bucket.bid,
+                    event_id,
+                    starttime_nanos,
+                    starttime_nanos + duration_nanos,
+                    serde_json::to_string(&event.data).unwrap(),
+                ],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to update event"))?;
+        if updated == 0 {
+            return Err(DatastoreError::NoSuchKey(format!(
+                "No event with id {event_id} in bucket {bucket_id}"
+            )));
+        }
+        // An in-place update can shrink the extent as well as grow it
+        self.refresh_metadata(conn, bucket_id)?;
+        self.touch_bucket(conn, bucket_id)?;
+        Ok(event)
+    }
+
+    /// Deletes every event that arrived in the given import batch, across
+    /// all buckets, and returns how many were removed. This is the rollback
+    /// path for a bad import.
+    pub fn delete_events_by_batch(
+        &mut self,
+        conn: &Connection,
+        batch: &str,
+    ) -> Result<i64, DatastoreError> {
+        let removed_ns = Utc::now().timestamp_nanos_opt().unwrap();
+        conn.execute(
+            "INSERT INTO events_history
+                 (eventrow, bucketrow, starttime, endtime, data, removed)
+             SELECT id, bucketrow, starttime, endtime, data, ?2
+             FROM events WHERE id IN
+                (SELECT eventrow FROM event_provenance WHERE batch = ?1)",
+            params![batch, removed_ns],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to record event history"))?;
+        let deleted = conn
+            .execute(
+                "DELETE FROM events WHERE id IN
+                    (SELECT eventrow FROM event_provenance WHERE batch = ?1)",
+                params![batch],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete events by batch"))?;
+        conn.execute(
+            "DELETE FROM event_provenance WHERE batch = ?1",
+            params![batch],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event provenance"))?;
+        if deleted > 0 {
+            // The batch may have spanned buckets, re-read the cached bucket
+            // metadata rather than guessing which ones were touched
+            self.get_stored_buckets(conn)?;
+            info!("Deleted {deleted} events from import batch {batch}");
+        }
+        Ok(deleted as i64)
+    }
+
+    /// How many of a bucket's events end before `before`; the set a
+    /// retention pass with that cutoff would remove
+    pub fn count_events_before(
+        &self,
+        conn: &Connection,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let before_ns = before.timestamp_nanos_opt().unwrap();
+        let count: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM events WHERE bucketrow = ?1 AND endtime < ?2",
+                params![bucket.bid, before_ns],
+                |row| row.get(0),
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to count events before"))?;
+        Ok(count)
+    }
+
+    /// Deletes every event in the bucket ending before `before` and
+    /// returns how many were removed. This is the retention enforcement
+    /// path: expired events are purged for good, with no `events_history`
+    /// pre-image — keeping one would defeat the point of retention.
+    pub fn delete_events_before(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let before_ns = before.timestamp_nanos_opt().unwrap();
+        conn.execute(
+            "DELETE FROM event_provenance WHERE eventrow IN
+                (SELECT id FROM events WHERE bucketrow = ?1 AND endtime < ?2)",
+            params![bucket.bid, before_ns],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event provenance"))?;
+        let deleted = conn
+            .execute(
+                "DELETE FROM events WHERE bucketrow = ?1 AND endtime < ?2",
+                params![bucket.bid, before_ns],
+            )
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete events before"))?;
+        // Expired pre-images of earlier deletions go too, so as_of reads
+        // can't resurrect data past its retention
+        conn.execute(
+            "DELETE FROM events_history WHERE bucketrow = ?1 AND endtime < ?2",
+            params![bucket.bid, before_ns],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event history"))?;
+        if deleted > 0 {
+            self.refresh_metadata(conn, bucket_id)?;
+            self.touch_bucket(conn, bucket_id)?;
+        }
+        Ok(deleted as i64)
+    }
+
+    /// Merges runs of events with byte-identical data into single rows, a
+    /// retroactive heartbeat pass over events ending before `before`.
+    /// Events whose gap is at most `pulsetime` seconds merge like a
+    /// heartbeat would have: the first event's endtime is extended to the
+    /// run's end and the rest are dropped (along with their provenance),
+    /// so gaps up to the pulsetime are absorbed into the duration.
+    /// Returns how many rows were merged away.
+    pub fn compact_events_before(
+        &mut self,
+        conn: &Connection,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+        pulsetime: f64,
+    ) -> Result<i64, DatastoreError> {
+        let bucket = self.get_bucket(bucket_id)?;
+        let before_ns = before.timestamp_nanos_opt().unwrap();
+        let pulsetime_ns = (pulsetime * 1_000_000_000.0) as i64;
+
+        // (id, starttime, endtime, data) of every compactable event,
+        // oldest first so runs can be found in one sweep
+        let rows: Vec<(i64, i64, i64, String)> = {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT id, starttime, endtime, data
+                     FROM events
+                     WHERE bucketrow = ?1 AND endtime < ?2
+                     ORDER BY starttime ASC",
+                )
+                .map_err(|err| {
+                    DatastoreError::from_sqlite(err, "Failed to prepare compaction query")
+                })?;
+            let rows = stmt
+                .query_map(params![bucket.bid, before_ns], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query events"))?;
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to parse event row"))?
+        };
+
+        let mut merged_away = 0;
+        let mut run_head: Option<(i64, i64, String)> = None; // (id, endtime, data)
+        let mut run_extended = false;
+        let finish_run = |head: &Option<(i64, i64, String)>,
+                              extended: bool|
+         -> Result<(), DatastoreError> {
+            if let (Some((head_id, head_end, _)), true) = (head, extended) {
+                conn.execute(
+                    "UPDATE events SET endtime = ?3 WHERE bucketrow = ?1 AND id = ?2",
+                    params![bucket.bid, head_id, head_end],
+                )
+                .map_err(|err| DatastoreError::from_sqlite(err, "Failed to extend event"))?;
+            }
+            Ok(())
+        };
+        for (id, starttime, endtime, data) in rows {
+            if let Some((head_id, head_end, head_data)) = &run_head {
+                if *head_data == data && starttime - head_end <= pulsetime_ns {
+                    let new_end = endtime.max(*head_end);
+                    conn.execute(
+                        "DELETE FROM event_provenance WHERE eventrow = ?1",
+                        params![id],
+                    )
+                    .map_err(|err| {
+                        DatastoreError::from_sqlite(err, "Failed to delete event provenance")
+                    })?;
+                    conn.execute(
+                        "DELETE FROM events WHERE bucketrow = ?1 AND id = ?2",
+                        params![bucket.bid, id],
+                    )
+                    .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete event"))?;
+                    merged_away += 1;
+                    run_head = Some((*head_id, new_end, data));
+                    run_extended = true;
+                    continue;
+                }
+                finish_run(&run_head, run_extended)?;
+            }
+            run_head = Some((id, endtime, data));
+            run_extended = false;
+        }
+        finish_run(&run_head, run_extended)?;
+
+        if merged_away > 0 {
+            self.touch_bucket(conn, bucket_id)?;
+            info!("Compacted {merged_away} events away in bucket {bucket_id}");
+        }
+        Ok(merged_away)
+    }
+
+    pub fn insert_key_value(
+        &self,
+        conn: &Connection,
+        key: &str,
+        data: &str,
+    ) -> Result<(), DatastoreError> {
+        conn.execute(
+            "INSERT OR REPLACE INTO key_value (key, value, timestamp) VALUES (?1, ?2, ?3)",
+            params![key, data, &Utc::now() as &dyn ToSql],
+        )
+        .map_err(|err| DatastoreError::from_sqlite(err, "Failed to insert key_value"))?;
+        Ok(())
+    }
+
+    pub fn delete_key_value(&self, conn: &Connection, key: &str) -> Result<(), DatastoreError> {
+        conn.execute("DELETE FROM key_value WHERE key = ?1", params![key])
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to delete key_value"))?;
+        Ok(())
+    }
+
+    pub fn get_key_value(&self, conn: &Connection, key: &str) -> Result<KeyValue, DatastoreError> {
+        conn.query_row(
+            "SELECT key, value, timestamp FROM key_value WHERE key = ?1",
+            params![key],
+            |row| {
+                Ok(KeyValue {
+                    key: row.get(0)?,
+                    value: row.get(1)?,
+                    timestamp: Some(row.get(2)?),
+                })
+            },
+        )
+        .map_err(|err| match err {
+            rusqlite::Error::QueryReturnedNoRows => DatastoreError::NoSuchKey(key.to_string()),
+            _ => DatastoreError::from_sqlite(err, "Failed to get key_value"),
+        })
+    }
+
+    pub fn get_keys_starting(
+        &self,
+        conn: &Connection,
+        pattern: &str,
+    ) -> Result<Vec<String>, DatastoreError> {
+        let mut stmt = conn
+            .prepare("SELECT key FROM key_value WHERE key LIKE ?1")
+            .map_err(|err| {
+                DatastoreError::from_sqlite(err, "Failed to prepare get_keys_starting query")
+            })?;
+        let rows = stmt
+            .query_map(params![pattern], |row| row.get(0))
+            .map_err(|err| DatastoreError::from_sqlite(err, "Failed to query keys"))?;
+        let mut keys = Vec::new();
+        for row in rows {
+            match row {
+                Ok(key) => keys.push(key),
+                Err(err) => {
+                    return Err(DatastoreError::from_sqlite(
+                        err,
+                        "Failed to parse key from db",
+                    ))
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+
+use rusqlite::Connection;
+use rusqlite::OpenFlags;
+
+use aw_models::Bucket;
+use aw_models::BucketMetadata;
+use aw_models::Event;
+use aw_models::TryVec;
+
+use crate::DatastoreError;
+
+/// Reads buckets and events from a legacy aw-server-python SQLite database
+/// (the peewee schema: `bucketmodel` and `eventmodel` tables) and converts
+/// them to the current models, ready to be loaded through the
+/// transactional import path.
+pub fn read_legacy_db(path: &str) -> Result<HashMap<String, (Bucket, Vec<Event>)>, DatastoreError> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|err| {
+            DatastoreError::InternalError(format!("Failed to open legacy database: {err}"))
+        })?;
+
+    let mut buckets_stmt = conn
+        .prepare("SELECT key, id, type, client, hostname, created FROM bucketmodel")
+        .map_err(|err| {
+            DatastoreError::InternalError(format!(
+                "Failed to read legacy buckets (is this an aw-server-python database?): {err}"
+            ))
+        })?;
+    let bucket_rows: Vec<(i64, String, String, String, String, String)> = buckets_stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })
+        .and_then(|rows| rows.collect())
+        .map_err(|err| {
+            DatastoreError::InternalError(format!("Failed to read legacy buckets: {err}"))
+        })?;
+
+    let mut data = HashMap::new();
+    for (key, id, _type, client, hostname, created) in bucket_rows {
+        let bucket = Bucket {
+            bid: None,
+            id: id.clone(),
+            _type,
+            client,
+            hostname,
+            created: parse_timestamp(&created).ok(),
+            last_updated: None,
+            event_count: None,
+            total_duration: None,
+            data: serde_json::Map::new(),
+            events: TryVec::new_empty(),
+            metadata: BucketMetadata::default(),
+        };
+        let events = read_legacy_events(&conn, key)?;
+        info!("Read legacy bucket '{id}' with {} events", events.len());
+        data.insert(id, (bucket, events));
+    }
+    Ok(data)
+}
+
+fn read_legacy_events(conn: &Connection, bucket_key: i64) -> Result<Vec<Event>, DatastoreError> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT timestamp, CAST(duration AS REAL), datastr
+             FROM eventmodel WHERE bucket_id = ?1 ORDER BY timestamp",
+        )
+        .map_err(|err| {
+            DatastoreError::InternalError(format!("Failed to read legacy events: {err}"))
+        })?;
+    let rows: Vec<(String, f64, String)> = stmt
+        .query_map([bucket_key], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .and_then(|rows| rows.collect())
+        .map_err(|err| {
+            DatastoreError::InternalError(format!("Failed to read legacy events: {err}"))
+        })?;
+
+    let mut events = Vec::with_capacity(rows.len());
+    for (timestamp, duration, datastr) in rows {
+        let timestamp = parse_timestamp(&timestamp)?;
+        let data = serde_json::from_str(&datastr).map_err(|err| {
+            DatastoreError::InternalError(format!("Failed to parse legacy event data: {err}"))
+        })?;
+        events.push(Event {
+            id: None,
+            timestamp,
+            duration: Duration::milliseconds((duration * 1000.0).round() as i64),
+            data,
+        });
+    }
+    Ok(events)
+}
+
+/// Peewee stores datetimes as strings, with a space separator and with or
+/// without a UTC offset depending on the version that wrote them
+fn parse_timestamp(string: &str) -> Result<DateTime<Utc>, DatastoreError> {
+    let string = string.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(&string.replacen(' ', "T", 1)) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(string, format) {
+            // Naive timestamps from old databases are in UTC
+            return Ok(DateTime::from_naive_utc_and_offset(naive, Utc));
+        }
+    }
+    Err(DatastoreError::InternalError(format!(
+        "Failed to parse legacy timestamp '{string}'"
+    )))
+}
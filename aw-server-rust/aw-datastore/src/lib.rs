@@ -0,0 +1,75 @@
+#[macro_use]
+extern crate log;
+
+use std::fmt;
+
+pub mod backend;
+mod datastore;
+pub mod legacy_import;
+pub mod memory;
+mod worker;
+
+pub use crate::backend::StorageBackend;
+pub use crate::memory::MemoryBackend;
+pub use crate::worker::db_metrics;
+pub use crate::worker::Datastore;
+pub use crate::worker::EventNotification;
+pub use crate::worker::InsertHook;
+pub use crate::worker::Transform;
+pub use crate::worker::QUERY_CACHE_PREFIX;
+
+#[derive(Debug, Clone)]
+pub enum DatastoreError {
+    NoSuchBucket(String),
+    BucketAlreadyExists(String),
+    NoSuchKey(String),
+    MpscError,
+    /// The database is busy or locked by another connection; the
+    /// operation is safe to retry
+    DatabaseBusy(String),
+    InternalError(String),
+    // Errors specific to when the database is of an older version
+    Uninitialized(String),
+    OldDbVersion(String),
+}
+
+impl DatastoreError {
+    /// Wraps a SQLite error with context while preserving its kind: a
+    /// busy or locked database becomes `DatabaseBusy`, which the server
+    /// maps to 503 so clients retry instead of treating it as a bug.
+    /// Everything else stays an `InternalError`.
+    pub fn from_sqlite(err: rusqlite::Error, context: &str) -> DatastoreError {
+        match &err {
+            rusqlite::Error::SqliteFailure(e, _)
+                if matches!(
+                    e.code,
+                    rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+                ) =>
+            {
+                DatastoreError::DatabaseBusy(format!("{context}: {err}"))
+            }
+            _ => DatastoreError::InternalError(format!("{context}: {err}")),
+        }
+    }
+}
+
+impl fmt::Display for DatastoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatastoreError::NoSuchBucket(bucket_id) => {
+                write!(f, "No such bucket '{bucket_id}'")
+            }
+            DatastoreError::BucketAlreadyExists(bucket_id) => {
+                write!(f, "Bucket '{bucket_id}' already exists")
+            }
+            DatastoreError::NoSuchKey(key) => write!(f, "No such key '{key}'"),
+            DatastoreError::MpscError => write!(f, "Unexpected Mpsc error!"),
+            DatastoreError::DatabaseBusy(msg) => write!(f, "Database busy: {msg}"),
+            DatastoreError::InternalError(msg) => write!(f, "Internal error: {msg}"),
+            DatastoreError::Uninitialized(msg) => write!(f, "Database is uninitialized: {msg}"),
+            DatastoreError::OldDbVersion(msg) => {
+                write!(f, "Database is of an older version: {msg}")
+            }
+        }
+    }
+}
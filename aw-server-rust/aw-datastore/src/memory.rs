@@ -0,0 +1,892 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Utc;
+
+use aw_models::Bucket;
+use aw_models::Event;
+use aw_models::EventProvenance;
+use aw_models::KeyValue;
+use aw_models::TryVec;
+use aw_transform::MergePolicy;
+
+use crate::backend::StorageBackend;
+use crate::DatastoreError;
+
+/// A storage backend holding everything in HashMaps, for tests and
+/// ephemeral runs where nothing should touch disk. Implements the same
+/// semantics as the SQLite backend (range clamping in get_events,
+/// newest-first ordering, trash retention), just without a database.
+pub struct MemoryBackend {
+    buckets: HashMap<String, Bucket>,
+    /// Soft-deleted buckets with their events, keyed by bucket id
+    trash: HashMap<String, (Bucket, Vec<Event>, DateTime<Utc>)>,
+    events: HashMap<String, Vec<Event>>,
+    /// Ingest provenance keyed by event id
+    provenance: HashMap<i64, EventProvenance>,
+    /// Pre-images of deleted events with their removal time, for as_of
+    /// reads
+    history: Vec<(String, Event, DateTime<Utc>)>,
+    key_value: HashMap<String, KeyValue>,
+    next_event_id: i64,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend {
+            buckets: HashMap::new(),
+            trash: HashMap::new(),
+            events: HashMap::new(),
+            provenance: HashMap::new(),
+            history: Vec::new(),
+            key_value: HashMap::new(),
+            next_event_id: 1,
+        }
+    }
+
+    fn update_endtime(&mut self, bucket_id: &str, event: &Event) {
+        let bucket = match self.buckets.get_mut(bucket_id) {
+            Some(bucket) => bucket,
+            None => return,
+        };
+        bucket.last_updated = Some(Utc::now());
+        let endtime = event.calculate_endtime();
+        match bucket.metadata.start {
+            None => bucket.metadata.start = Some(event.timestamp),
+            Some(current_start) => {
+                if current_start > event.timestamp {
+                    bucket.metadata.start = Some(event.timestamp);
+                }
+            }
+        }
+        match bucket.metadata.end {
+            None => bucket.metadata.end = Some(endtime),
+            Some(current_end) => {
+                if current_end < endtime {
+                    bucket.metadata.end = Some(endtime);
+                }
+            }
+        }
+    }
+
+    /// Recomputes the bucket's extent metadata from scratch, for
+    /// mutations that can shrink it (deletes, in-place updates)
+    fn refresh_metadata(&mut self, bucket_id: &str) {
+        let (start, end) = match self.events.get(bucket_id) {
+            Some(events) => (
+                events.iter().map(|event| event.timestamp).min(),
+                events.iter().map(|event| event.calculate_endtime()).max(),
+            ),
+            None => (None, None),
+        };
+        if let Some(bucket) = self.buckets.get_mut(bucket_id) {
+            bucket.metadata.start = start;
+            bucket.metadata.end = end;
+        }
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    // No transactions to batch
+    fn begin(&mut self) {}
+    fn commit(&mut self) {}
+
+    fn create_bucket(&mut self, mut bucket: Bucket) -> Result<(), DatastoreError> {
+        if self.buckets.contains_key(&bucket.id) {
+            return Err(DatastoreError::BucketAlreadyExists(bucket.id));
+        }
+        if self.trash.contains_key(&bucket.id) {
+            return Err(DatastoreError::BucketAlreadyExists(format!(
+                "{} (in trash)",
+                bucket.id
+            )));
+        }
+        bucket.created = match bucket.created {
+            Some(created) => Some(created),
+            None => Some(Utc::now()),
+        };
+        bucket.last_updated = bucket.created;
+        let events = bucket.events.take_inner();
+        bucket.events = TryVec::new_empty();
+        let bucket_id = bucket.id.clone();
+        self.buckets.insert(bucket_id.clone(), bucket);
+        self.events.insert(bucket_id.clone(), Vec::new());
+        if !events.is_empty() {
+            self.insert_events(&bucket_id, events)?;
+        }
+        info!("Created bucket {bucket_id}");
+        Ok(())
+    }
+
+    fn delete_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError> {
+        if self.buckets.remove(bucket_id).is_none() && self.trash.remove(bucket_id).is_none() {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        if let Some(events) = self.events.remove(bucket_id) {
+            for event in events {
+                if let Some(event_id) = event.id {
+                    self.provenance.remove(&event_id);
+                }
+            }
+        }
+        self.history.retain(|(id, _, _)| id != bucket_id);
+        Ok(())
+    }
+
+    fn soft_delete_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError> {
+        let bucket = self
+            .buckets
+            .remove(bucket_id)
+            .ok_or_else(|| DatastoreError::NoSuchBucket(bucket_id.to_string()))?;
+        let events = self.events.remove(bucket_id).unwrap_or_default();
+        self.trash
+            .insert(bucket_id.to_string(), (bucket, events, Utc::now()));
+        Ok(())
+    }
+
+    fn restore_bucket(&mut self, bucket_id: &str) -> Result<(), DatastoreError> {
+        let (bucket, events, _deleted) = self
+            .trash
+            .remove(bucket_id)
+            .ok_or_else(|| DatastoreError::NoSuchBucket(bucket_id.to_string()))?;
+        self.buckets.insert(bucket_id.to_string(), bucket);
+        self.events.insert(bucket_id.to_string(), events);
+        Ok(())
+    }
+
+    fn get_deleted_buckets(&mut self) -> Result<Vec<String>, DatastoreError> {
+        Ok(self.trash.keys().cloned().collect())
+    }
+
+    fn purge_deleted_buckets(&mut self, before: DateTime<Utc>) -> Result<i64, DatastoreError> {
+        let expired: Vec<String> = self
+            .trash
+            .iter()
+            .filter(|(_, (_, _, deleted))| *deleted < before)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let purged = expired.len() as i64;
+        for id in expired {
+            self.trash.remove(&id);
+        }
+        Ok(purged)
+    }
+
+    fn get_bucket(&mut self, bucket_id: &str) -> Result<Bucket, DatastoreError> {
+        match self.buckets.get(bucket_id) {
+            Some(bucket) => Ok(bucket.clone()),
+            None => Err(DatastoreError::NoSuchBucket(bucket_id.to_string())),
+        }
+    }
+
+    fn get_buckets(&mut self) -> HashMap<String, Bucket> {
+        self.buckets.clone()
+    }
+
+    fn get_bucket_rollups(&mut self) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        Ok(self
+            .buckets
+            .keys()
+            .map(|bucket_id| {
+                let events = &self.events[bucket_id];
+                let duration_ns: i64 = events
+                    .iter()
+                    .map(|event| event.duration.num_nanoseconds().unwrap_or(0))
+                    .sum();
+                (bucket_id.clone(), (events.len() as i64, duration_ns))
+            })
+            .collect())
+    }
+
+    fn set_bucket_data(
+        &mut self,
+        bucket_id: &str,
+        data: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), DatastoreError> {
+        match self.buckets.get_mut(bucket_id) {
+            Some(bucket) => {
+                bucket.data = data;
+                Ok(())
+            }
+            None => Err(DatastoreError::NoSuchBucket(bucket_id.to_string())),
+        }
+    }
+
+    fn import(
+        &mut self,
+        data: HashMap<String, (Bucket, Vec<Event>)>,
+        provenance: Option<&EventProvenance>,
+    ) -> Result<(), DatastoreError> {
+        // Validate up front so a failed import changes nothing
+        for (_, (bucket, _)) in data.iter() {
+            if self.buckets.contains_key(&bucket.id) || self.trash.contains_key(&bucket.id) {
+                return Err(DatastoreError::BucketAlreadyExists(bucket.id.clone()));
+            }
+        }
+        for (_, (bucket, mut events)) in data {
+            let bucket_id = bucket.id.clone();
+            self.create_bucket(bucket)?;
+            for event in &mut events {
+                event.id = None;
+            }
+            let inserted = self.insert_events(&bucket_id, events)?;
+            if let Some(provenance) = provenance {
+                let ids: Vec<i64> = inserted.iter().filter_map(|event| event.id).collect();
+                self.set_event_provenance(&ids, provenance)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_events(
+        &mut self,
+        bucket_id: &str,
+        mut events: Vec<Event>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        for event in &mut events {
+            match event.id {
+                // Replace in place, like INSERT OR REPLACE does
+                Some(id) => {
+                    let stored = self.events.get_mut(bucket_id).unwrap();
+                    stored.retain(|stored_event| stored_event.id != Some(id));
+                    stored.push(event.clone());
+                }
+                None => {
+                    event.id = Some(self.next_event_id);
+                    self.next_event_id += 1;
+                    self.events.get_mut(bucket_id).unwrap().push(event.clone());
+                }
+            }
+            self.update_endtime(bucket_id, event);
+        }
+        Ok(events)
+    }
+
+    fn set_event_provenance(
+        &mut self,
+        event_ids: &[i64],
+        provenance: &EventProvenance,
+    ) -> Result<(), DatastoreError> {
+        for event_id in event_ids {
+            self.provenance.insert(*event_id, provenance.clone());
+        }
+        Ok(())
+    }
+
+    fn get_event_provenance(
+        &mut self,
+        event_ids: &[i64],
+    ) -> Result<HashMap<i64, EventProvenance>, DatastoreError> {
+        Ok(event_ids
+            .iter()
+            .filter_map(|event_id| {
+                self.provenance
+                    .get(event_id)
+                    .map(|provenance| (*event_id, provenance.clone()))
+            })
+            .collect())
+    }
+
+    fn delete_events_by_batch(&mut self, batch: &str) -> Result<i64, DatastoreError> {
+        let batch_ids: Vec<i64> = self
+            .provenance
+            .iter()
+            .filter(|(_, provenance)| provenance.batch.as_deref() == Some(batch))
+            .map(|(event_id, _)| *event_id)
+            .collect();
+        let removed = Utc::now();
+        let mut deleted = 0;
+        for (bucket_id, events) in self.events.iter_mut() {
+            let before = events.len();
+            for event in events.iter() {
+                if event.id.is_some_and(|event_id| batch_ids.contains(&event_id)) {
+                    self.history
+                        .push((bucket_id.clone(), event.clone(), removed));
+                }
+            }
+            events.retain(|event| {
+                event
+                    .id
+                    .is_none_or(|event_id| !batch_ids.contains(&event_id))
+            });
+            deleted += (before - events.len()) as i64;
+        }
+        for event_id in batch_ids {
+            self.provenance.remove(&event_id);
+        }
+        Ok(deleted)
+    }
+
+    fn count_events_before(
+        &self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let count = self.events[bucket_id]
+            .iter()
+            .filter(|event| event.timestamp + event.duration < before)
+            .count();
+        Ok(count as i64)
+    }
+
+    fn delete_events_before(
+        &mut self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let events = self.events.get_mut(bucket_id).unwrap();
+        let count_before = events.len();
+        let mut expired_ids = Vec::new();
+        events.retain(|event| {
+            if event.timestamp + event.duration < before {
+                if let Some(event_id) = event.id {
+                    expired_ids.push(event_id);
+                }
+                false
+            } else {
+                true
+            }
+        });
+        let deleted = (count_before - events.len()) as i64;
+        for event_id in expired_ids {
+            self.provenance.remove(&event_id);
+        }
+        // Expired pre-images go too, matching the sqlite backend
+        self.history.retain(|(history_bucket, event, _)| {
+            history_bucket != bucket_id || event.timestamp + event.duration >= before
+        });
+        if deleted > 0 {
+            self.refresh_metadata(bucket_id);
+        }
+        Ok(deleted)
+    }
+
+    fn get_event_suggestions(
+        &mut self,
+        bucket_id: &str,
+        field: &str,
+        prefix: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<Vec<(String, i64)>, DatastoreError> {
+        let events = self.get_events(bucket_id, starttime_opt, endtime_opt, None)?;
+        let prefix_lower = prefix.to_lowercase();
+        let mut counts: HashMap<String, i64> = HashMap::new();
+        for event in events {
+            let value = match event.data.get(field) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(serde_json::Value::Bool(b)) => (*b as i64).to_string(),
+                Some(serde_json::Value::Null) | None => continue,
+                Some(other) => other.to_string(),
+            };
+            // Case-insensitive prefix match, like SQLite's LIKE
+            if value.to_lowercase().starts_with(&prefix_lower) {
+                *counts.entry(value).or_insert(0) += 1;
+            }
+        }
+        let mut suggestions: Vec<(String, i64)> = counts.into_iter().collect();
+        suggestions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        suggestions.truncate(limit as usize);
+        Ok(suggestions)
+    }
+
+    fn compact_events_before(
+        &mut self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+        pulsetime: f64,
+    ) -> Result<i64, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let events = self.events.get_mut(bucket_id).unwrap();
+        events.sort_by_key(|event| event.timestamp);
+        let pulsetime = chrono::Duration::nanoseconds((pulsetime * 1_000_000_000.0) as i64);
+        let mut compacted: Vec<Event> = Vec::new();
+        let mut merged_ids = Vec::new();
+        for event in events.drain(..) {
+            if event.timestamp + event.duration < before {
+                if let Some(head) = compacted.last_mut() {
+                    let head_end = head.timestamp + head.duration;
+                    if head.data == event.data && event.timestamp - head_end <= pulsetime {
+                        let new_end = (event.timestamp + event.duration).max(head_end);
+                        head.duration = new_end - head.timestamp;
+                        if let Some(event_id) = event.id {
+                            merged_ids.push(event_id);
+                        }
+                        continue;
+                    }
+                }
+            }
+            compacted.push(event);
+        }
+        *events = compacted;
+        for event_id in &merged_ids {
+            self.provenance.remove(event_id);
+        }
+        Ok(merged_ids.len() as i64)
+    }
+
+    fn replace_last_event(&mut self, bucket_id: &str, event: &Event) -> Result<(), DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let stored = self.events.get_mut(bucket_id).unwrap();
+        // Pin the replacement to the caller's known id when set, like the
+        // SQLite backend; otherwise replace the last event by endtime
+        let index = event
+            .id
+            .and_then(|id| {
+                stored
+                    .iter()
+                    .position(|stored_event| stored_event.id == Some(id))
+            })
+            .or_else(|| {
+                (0..stored.len())
+                    .max_by_key(|&i| stored[i].calculate_endtime())
+            });
+        if let Some(index) = index {
+            let id = stored[index].id;
+            stored[index] = event.clone();
+            stored[index].id = id;
+        }
+        self.update_endtime(bucket_id, event);
+        Ok(())
+    }
+
+    fn heartbeat(
+        &mut self,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Event, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let last_event = self.events[bucket_id]
+            .iter()
+            .max_by_key(|event| event.calculate_endtime())
+            .cloned();
+        if let Some(last_event) = last_event {
+            if let Some(mut merged) =
+                aw_transform::heartbeat_with_policy(&last_event, &heartbeat, pulsetime, &policy)
+            {
+                merged.id = last_event.id;
+                self.replace_last_event(bucket_id, &merged)?;
+                return Ok(merged);
+            }
+        }
+        let mut inserted = self.insert_events(bucket_id, vec![heartbeat])?;
+        Ok(inserted.pop().unwrap())
+    }
+
+    fn heartbeat_batch(
+        &mut self,
+        bucket_id: &str,
+        heartbeats: Vec<Event>,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let mut written = Vec::with_capacity(heartbeats.len());
+        for heartbeat in heartbeats {
+            written.push(self.heartbeat(bucket_id, heartbeat, pulsetime, policy.clone())?);
+        }
+        Ok(written)
+    }
+
+    fn get_events(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        if let (Some(start), Some(end)) = (starttime_opt, endtime_opt) {
+            if start > end {
+                warn!("Starttime in event query was lower than endtime!");
+                return Ok(Vec::new());
+            }
+        }
+        let mut list: Vec<Event> = self.events[bucket_id]
+            .iter()
+            .filter(|event| {
+                starttime_opt.is_none_or(|start| event.calculate_endtime() >= start)
+                    && endtime_opt.is_none_or(|end| event.timestamp <= end)
+            })
+            .map(|event| {
+                // Clamp events to the query range, like the SQL query does
+                let mut event = event.clone();
+                let mut endtime = event.calculate_endtime();
+                if let Some(start) = starttime_opt {
+                    if event.timestamp < start {
+                        event.timestamp = start;
+                    }
+                }
+                if let Some(end) = endtime_opt {
+                    if endtime > end {
+                        endtime = end;
+                    }
+                }
+                event.duration = endtime - event.timestamp;
+                event
+            })
+            .collect();
+        list.sort_by_key(|event| std::cmp::Reverse(event.timestamp));
+        if let Some(limit) = limit_opt {
+            list.truncate(limit as usize);
+        }
+        Ok(list)
+    }
+
+    fn get_event_by_id(
+        &mut self,
+        bucket_id: &str,
+        event_id: i64,
+    ) -> Result<Event, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        match self.events[bucket_id]
+            .iter()
+            .find(|event| event.id == Some(event_id))
+        {
+            Some(event) => Ok(event.clone()),
+            None => Err(DatastoreError::NoSuchKey(format!(
+                "No event with id {event_id} in bucket {bucket_id}"
+            ))),
+        }
+    }
+
+    fn get_events_filtered(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        filters: &[(String, serde_json::Value)],
+    ) -> Result<Vec<Event>, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        if let (Some(start), Some(end)) = (starttime_opt, endtime_opt) {
+            if start > end {
+                warn!("Starttime in event query was lower than endtime!");
+                return Ok(Vec::new());
+            }
+        }
+        let mut list: Vec<Event> = self.events[bucket_id]
+            .iter()
+            .filter(|event| {
+                starttime_opt.is_none_or(|start| event.calculate_endtime() >= start)
+                    && endtime_opt.is_none_or(|end| event.timestamp <= end)
+                    && filters
+                        .iter()
+                        .all(|(field, value)| event.data.get(field) == Some(value))
+            })
+            .map(|event| {
+                // Clamp events to the query range, like the SQL query does
+                let mut event = event.clone();
+                let mut endtime = event.calculate_endtime();
+                if let Some(start) = starttime_opt {
+                    if event.timestamp < start {
+                        event.timestamp = start;
+                    }
+                }
+                if let Some(end) = endtime_opt {
+                    if endtime > end {
+                        endtime = end;
+                    }
+                }
+                event.duration = endtime - event.timestamp;
+                event
+            })
+            .collect();
+        list.sort_by_key(|event| std::cmp::Reverse(event.timestamp));
+        if let Some(limit) = limit_opt {
+            list.truncate(limit as usize);
+        }
+        Ok(list)
+    }
+
+    fn get_events_as_of(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        as_of: DateTime<Utc>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        if let (Some(start), Some(end)) = (starttime_opt, endtime_opt) {
+            if start > end {
+                warn!("Starttime in event query was lower than endtime!");
+                return Ok(Vec::new());
+            }
+        }
+        // Live events ingested by as_of (no recorded provenance means
+        // older than tracking, so included), plus events deleted after it
+        let live = self.events[bucket_id].iter().filter(|event| {
+            event.id.is_none_or(|id| {
+                self.provenance
+                    .get(&id)
+                    .is_none_or(|provenance| provenance.ingested <= as_of)
+            })
+        });
+        let deleted = self
+            .history
+            .iter()
+            .filter(|(id, _, removed)| id == bucket_id && *removed > as_of)
+            .map(|(_, event, _)| event);
+        let mut list: Vec<Event> = live
+            .chain(deleted)
+            .filter(|event| {
+                starttime_opt.is_none_or(|start| event.calculate_endtime() >= start)
+                    && endtime_opt.is_none_or(|end| event.timestamp <= end)
+            })
+            .map(|event| {
+                // Clamp events to the query range, like the SQL query does
+                let mut event = event.clone();
+                let mut endtime = event.calculate_endtime();
+                if let Some(start) = starttime_opt {
+                    if event.timestamp < start {
+                        event.timestamp = start;
+                    }
+                }
+                if let Some(end) = endtime_opt {
+                    if endtime > end {
+                        endtime = end;
+                    }
+                }
+                event.duration = endtime - event.timestamp;
+                event
+            })
+            .collect();
+        list.sort_by_key(|event| std::cmp::Reverse(event.timestamp));
+        if let Some(limit) = limit_opt {
+            list.truncate(limit as usize);
+        }
+        Ok(list)
+    }
+
+    fn get_events_aggregate(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        field: &str,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        // get_events already clamps events to the query range
+        let events = self.get_events(bucket_id, starttime_opt, endtime_opt, None)?;
+        let mut aggregate: HashMap<String, (i64, i64)> = HashMap::new();
+        for event in events {
+            let key = match event.data.get(field) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                // SQLite stores JSON booleans as 0/1, match its text form
+                Some(serde_json::Value::Bool(b)) => (*b as i64).to_string(),
+                Some(serde_json::Value::Null) | None => continue,
+                Some(other) => other.to_string(),
+            };
+            let entry = aggregate.entry(key).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += event.duration.num_nanoseconds().unwrap_or(0);
+        }
+        Ok(aggregate)
+    }
+
+    fn get_events_hourly_summary(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        // get_events already clamps events to the query range
+        let events = self.get_events(bucket_id, starttime_opt, endtime_opt, None)?;
+        let mut summary: HashMap<String, (i64, i64)> = HashMap::new();
+        for event in events {
+            let hour = event.timestamp.format("%Y-%m-%dT%H:00:00+00:00").to_string();
+            let entry = summary.entry(hour).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += event.duration.num_nanoseconds().unwrap_or(0);
+        }
+        Ok(summary)
+    }
+
+    fn get_events_page(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        before: Option<(i64, i64)>,
+        limit: u64,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let cursor_key = |event: &Event| {
+            (
+                event.timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX),
+                event.id.unwrap_or(i64::MAX),
+            )
+        };
+        let mut list: Vec<Event> = self.events[bucket_id]
+            .iter()
+            .filter(|event| {
+                starttime_opt.is_none_or(|start| event.calculate_endtime() >= start)
+                    && endtime_opt.is_none_or(|end| event.timestamp <= end)
+                    && before.is_none_or(|before| cursor_key(event) < before)
+            })
+            .cloned()
+            .collect();
+        list.sort_by_key(|event| std::cmp::Reverse(cursor_key(event)));
+        list.truncate(limit as usize);
+        Ok(list)
+    }
+
+    fn get_event_count(
+        &mut self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<i64, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let count = self.events[bucket_id]
+            .iter()
+            .filter(|event| {
+                starttime_opt.is_none_or(|start| event.calculate_endtime() >= start)
+                    && endtime_opt.is_none_or(|end| event.timestamp <= end)
+            })
+            .count();
+        Ok(count as i64)
+    }
+
+    fn explain_get_events(
+        &mut self,
+        bucket_id: &str,
+        _starttime_opt: Option<DateTime<Utc>>,
+        _endtime_opt: Option<DateTime<Utc>>,
+        _limit_opt: Option<u64>,
+    ) -> Result<Vec<String>, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        Ok(vec![format!(
+            "LINEAR SCAN OF {} IN-MEMORY EVENTS",
+            self.events[bucket_id].len()
+        )])
+    }
+
+    fn get_last_events(
+        &mut self,
+        limit: u64,
+    ) -> Result<HashMap<String, Vec<Event>>, DatastoreError> {
+        let bucket_ids: Vec<String> = self.buckets.keys().cloned().collect();
+        let mut last_events = HashMap::new();
+        for bucket_id in bucket_ids {
+            let events = self.get_events(&bucket_id, None, None, Some(limit))?;
+            last_events.insert(bucket_id, events);
+        }
+        Ok(last_events)
+    }
+
+    fn delete_events_by_id(
+        &mut self,
+        bucket_id: &str,
+        event_ids: Vec<i64>,
+    ) -> Result<(), DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let removed = Utc::now();
+        let events = self.events.get_mut(bucket_id).unwrap();
+        for event in events.iter() {
+            if event.id.is_some_and(|id| event_ids.contains(&id)) {
+                self.history
+                    .push((bucket_id.to_string(), event.clone(), removed));
+            }
+        }
+        events.retain(|event| !event.id.is_some_and(|id| event_ids.contains(&id)));
+        self.refresh_metadata(bucket_id);
+        self.buckets.get_mut(bucket_id).unwrap().last_updated = Some(Utc::now());
+        Ok(())
+    }
+
+    fn update_event(&mut self, bucket_id: &str, event: Event) -> Result<Event, DatastoreError> {
+        if !self.buckets.contains_key(bucket_id) {
+            return Err(DatastoreError::NoSuchBucket(bucket_id.to_string()));
+        }
+        let event_id = match event.id {
+            Some(id) => id,
+            None => {
+                return Err(DatastoreError::InternalError(
+                    "update_event requires an event id".to_string(),
+                ))
+            }
+        };
+        let events = self.events.get_mut(bucket_id).unwrap();
+        let Some(index) = events.iter().position(|stored| stored.id == Some(event_id)) else {
+            return Err(DatastoreError::NoSuchKey(format!(
+                "No event with id {event_id} in bucket {bucket_id}"
+            )));
+        };
+        // Keep the previous version, matching the sqlite pre-image
+        let previous = std::mem::replace(&mut events[index], event.clone());
+        self.history
+            .push((bucket_id.to_string(), previous, Utc::now()));
+        self.refresh_metadata(bucket_id);
+        self.buckets.get_mut(bucket_id).unwrap().last_updated = Some(Utc::now());
+        Ok(event)
+    }
+
+    fn insert_key_value(&mut self, key: &str, data: &str) -> Result<(), DatastoreError> {
+        self.key_value
+            .insert(key.to_string(), KeyValue::new(key, data, Utc::now()));
+        Ok(())
+    }
+
+    fn get_key_value(&mut self, key: &str) -> Result<KeyValue, DatastoreError> {
+        match self.key_value.get(key) {
+            Some(kv) => Ok(kv.clone()),
+            None => Err(DatastoreError::NoSuchKey(key.to_string())),
+        }
+    }
+
+    fn get_keys_starting(&mut self, pattern: &str) -> Result<Vec<String>, DatastoreError> {
+        // Callers pass SQL LIKE patterns of the form "prefix%"
+        let prefix = pattern.strip_suffix('%').unwrap_or(pattern);
+        Ok(self
+            .key_value
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn delete_key_value(&mut self, key: &str) -> Result<(), DatastoreError> {
+        self.key_value.remove(key);
+        Ok(())
+    }
+}
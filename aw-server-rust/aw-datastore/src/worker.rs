@@ -0,0 +1,1553 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use aw_models::Bucket;
+use aw_models::Event;
+use aw_models::EventProvenance;
+use aw_models::KeyValue;
+use aw_transform::MergePolicy;
+
+use tokio::sync::broadcast;
+
+use crate::backend::{SqliteBackend, StorageBackend};
+use crate::memory::MemoryBackend;
+use crate::DatastoreError;
+
+type Requester =
+    mpsc_requests::RequestSender<Command, Result<Response, DatastoreError>>;
+type Responder =
+    mpsc_requests::RequestReceiver<Command, Result<Response, DatastoreError>>;
+
+/// Capacity of the live notification channel; subscribers that fall more
+/// than this many events behind see a lag error instead of stale data
+const NOTIFY_CHANNEL_CAPACITY: usize = 256;
+
+/// A newly inserted event or merged heartbeat, published to subscribers
+/// so they can update without polling.
+///
+/// Fan-out is in-process only: the datastore assumes exclusive ownership
+/// of its SQLite database, so there are no other instances to notify.
+/// A multi-instance deployment on a shared database (e.g. Postgres with
+/// LISTEN/NOTIFY) would need a backend-provided notification source
+/// feeding this channel instead of the worker publishing directly.
+#[derive(Clone, Debug)]
+pub struct EventNotification {
+    pub bucket_id: String,
+    pub event: Event,
+}
+
+#[derive(Clone)]
+pub struct Datastore {
+    requester: Requester,
+    notify: broadcast::Sender<EventNotification>,
+}
+
+impl std::fmt::Debug for Datastore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Datastore()")
+    }
+}
+
+/*
+ * TODO:
+ * - Allow read requests to go straight through a read-only connection instead
+ *   of having to pass the worker thread
+ */
+
+/// How long soft-deleted buckets are kept in the trash before the background
+/// job purges them permanently
+const TRASH_RETENTION_DAYS: i64 = 30;
+
+/// How often the background purge job runs
+const TRASH_PURGE_INTERVAL_HOURS: i64 = 1;
+
+/// Keys under this prefix in the key_value table are treated as cached
+/// query results. Entries are JSON objects with `start` and `end` rfc3339
+/// timestamps; the worker deletes entries whose timeperiod overlaps
+/// modified events, so stale results are never served.
+pub static QUERY_CACHE_PREFIX: &str = "querycache.";
+
+/// Total number of commands handled and total time spent handling them,
+/// across all datastore instances, for debug instrumentation
+static COMMANDS_HANDLED: AtomicU64 = AtomicU64::new(0);
+static COMMAND_TIME_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// Snapshot of the worker's command counters: (commands handled, time
+/// spent handling them in microseconds). Counters are global and only ever
+/// increase, so callers can diff two snapshots to measure a section.
+pub fn db_metrics() -> (u64, u64) {
+    (
+        COMMANDS_HANDLED.load(Ordering::Relaxed),
+        COMMAND_TIME_MICROS.load(Ordering::Relaxed),
+    )
+}
+
+/// A rule the worker runs after events land in `source_bucket`: each new
+/// event is passed to `transform`, and whatever it returns is written to
+/// `target_bucket`. Events arriving as heartbeats propagate as heartbeats
+/// with the same pulsetime, so derived buckets merge the same way their
+/// source does. Derived inserts trigger hooks in turn, so chains work;
+/// each cascade visits a bucket at most once, so rule cycles cannot loop.
+#[derive(Clone)]
+pub struct InsertHook {
+    pub source_bucket: String,
+    pub target_bucket: String,
+    pub transform: Transform,
+}
+
+/// Maps a newly stored event to a derived one, or None to emit nothing
+pub type Transform = Arc<dyn Fn(&Event) -> Option<Event> + Send + Sync>;
+
+impl std::fmt::Debug for InsertHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "InsertHook({} -> {})",
+            self.source_bucket, self.target_bucket
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    CreateBucket(Bucket),
+    DeleteBucket(String),
+    SoftDeleteBucket(String),
+    RestoreBucket(String),
+    GetDeletedBuckets(),
+    PurgeDeletedBuckets(DateTime<Utc>),
+    GetBucket(String),
+    GetBuckets(),
+    GetBucketRollups(),
+    SetBucketData(String, serde_json::Map<String, serde_json::Value>),
+    Import(HashMap<String, (Bucket, Vec<Event>)>, Option<EventProvenance>),
+    InsertEvents(String, Vec<Event>, Option<EventProvenance>),
+    Heartbeat(String, Event, f64, MergePolicy),
+    HeartbeatBatch(String, Vec<Event>, f64, MergePolicy),
+    GetEvents(
+        String,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        Option<u64>,
+    ),
+    GetEventById(String, i64),
+    GetEventsFiltered(
+        String,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        Option<u64>,
+        Vec<(String, serde_json::Value)>,
+    ),
+    GetEventsAsOf(
+        String,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        Option<u64>,
+        DateTime<Utc>,
+    ),
+    GetEventsAggregate(String, Option<DateTime<Utc>>, Option<DateTime<Utc>>, String),
+    GetEventsHourlySummary(String, Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    GetEventSuggestions(
+        String,
+        String,
+        String,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        u64,
+    ),
+    GetEventsPage(
+        String,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        Option<(i64, i64)>,
+        u64,
+    ),
+    GetEventCount(String, Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    GetLastEvents(u64),
+    ExplainGetEvents(
+        String,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        Option<u64>,
+    ),
+    DeleteEventsById(String, Vec<i64>),
+    UpdateEvent(String, Event),
+    GetEventProvenance(Vec<i64>),
+    DeleteEventsByBatch(String),
+    CountEventsBefore(String, DateTime<Utc>),
+    DeleteEventsBefore(String, DateTime<Utc>),
+    CompactEventsBefore(String, DateTime<Utc>, f64),
+    ForceCommit(),
+    InsertKeyValue(String, String),
+    GetKeyValue(String),
+    GetKeysStarting(String),
+    DeleteKeyValue(String),
+    AddInsertHook(InsertHook),
+    Close(),
+}
+
+#[derive(Debug)]
+pub enum Response {
+    Empty(),
+    Bucket(Bucket),
+    BucketMap(HashMap<String, Bucket>),
+    Event(Event),
+    EventList(Vec<Event>),
+    Count(i64),
+    KeyValue(KeyValue),
+    StringVec(Vec<String>),
+    Rollups(HashMap<String, (i64, i64)>),
+    Provenance(HashMap<i64, EventProvenance>),
+    Suggestions(Vec<(String, i64)>),
+    EventMap(HashMap<String, Vec<Event>>),
+}
+
+fn _unwrap_response(receiver: mpsc_requests::ResponseReceiver<Result<Response, DatastoreError>>)
+    -> Result<(), DatastoreError>
+{
+    match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+        Ok(r) => match r {
+            Response::Empty() => Ok(()),
+            _ => panic!("Invalid response"),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Time range spanned by a set of events, None if there are none
+fn events_range(events: &[Event]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = events.iter().map(|event| event.timestamp).min()?;
+    let end = events.iter().map(|event| event.calculate_endtime()).max()?;
+    Some((start, end))
+}
+
+/// Parses an rfc3339 timestamp field of a cache entry
+fn entry_time(entry: &serde_json::Value, field: &str) -> Option<DateTime<Utc>> {
+    entry
+        .get(field)?
+        .as_str()
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Drops cached query results whose timeperiod overlaps the given range,
+/// or all of them when the affected range is unknown. Called after event
+/// mutations so stale cached results are never served.
+fn invalidate_query_cache(
+    backend: &mut dyn StorageBackend,
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+) {
+    let keys = match backend.get_keys_starting(&format!("{QUERY_CACHE_PREFIX}%")) {
+        Ok(keys) => keys,
+        Err(_) => return,
+    };
+    for key in keys {
+        let stale = match range {
+            None => true,
+            Some((start, end)) => match backend.get_key_value(&key) {
+                Ok(kv) => match serde_json::from_str::<serde_json::Value>(&kv.value) {
+                    Ok(entry) => {
+                        match (entry_time(&entry, "start"), entry_time(&entry, "end")) {
+                            (Some(entry_start), Some(entry_end)) => {
+                                entry_end >= start && entry_start <= end
+                            }
+                            // Entries without a timeperiod cannot be
+                            // checked, drop them to be safe
+                            _ => true,
+                        }
+                    }
+                    Err(_) => true,
+                },
+                Err(_) => false,
+            },
+        };
+        if stale {
+            let _ = backend.delete_key_value(&key);
+        }
+    }
+}
+
+struct DatastoreWorker {
+    responder: Responder,
+    notify: broadcast::Sender<EventNotification>,
+    legacy_import: bool,
+    quit: bool,
+    uncommitted_events: usize,
+    commit: bool,
+    last_purge: DateTime<Utc>,
+    hooks: Vec<InsertHook>,
+}
+
+impl DatastoreWorker {
+    pub fn new(
+        responder: Responder,
+        notify: broadcast::Sender<EventNotification>,
+        legacy_import: bool,
+    ) -> Self {
+        DatastoreWorker {
+            responder,
+            notify,
+            legacy_import,
+            quit: false,
+            uncommitted_events: 0,
+            commit: false,
+            // Epoch, so the first loop iteration purges expired trash on startup
+            last_purge: DateTime::from_timestamp(0, 0).unwrap(),
+            hooks: Vec::new(),
+        }
+    }
+
+    fn work_loop(&mut self, backend: &mut dyn StorageBackend) {
+        let _ = self.legacy_import;
+
+        loop {
+            // Background job: purge buckets whose trash retention has expired
+            let now = Utc::now();
+            if now - self.last_purge > Duration::hours(TRASH_PURGE_INTERVAL_HOURS) {
+                let cutoff = now - Duration::days(TRASH_RETENTION_DAYS);
+                match backend.purge_deleted_buckets(cutoff) {
+                    Ok(purged) if purged > 0 => info!("Purged {purged} buckets from trash"),
+                    Ok(_) => (),
+                    Err(err) => warn!("Failed to purge trash: {err}"),
+                }
+                self.last_purge = now;
+            }
+
+            backend.begin();
+
+            self.uncommitted_events = 0;
+            self.commit = false;
+            let last_commit_time: DateTime<Utc> = Utc::now();
+            loop {
+                let (request, response_sender) = match self.responder.poll() {
+                    Ok((req, res_sender)) => (req, res_sender),
+                    Err(_) => {
+                        // All references to the requester is gone, quit
+                        info!("DB worker quitting");
+                        self.quit = true;
+                        break;
+                    }
+                };
+                let started = std::time::Instant::now();
+                let response = self.handle_request(request, backend);
+                COMMANDS_HANDLED.fetch_add(1, Ordering::Relaxed);
+                COMMAND_TIME_MICROS
+                    .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+                response_sender.respond(response);
+                let now: DateTime<Utc> = Utc::now();
+                let commit_interval_passed: bool = (now - last_commit_time) > Duration::seconds(15);
+                if self.commit
+                    || commit_interval_passed
+                    || self.uncommitted_events > 100
+                    || self.quit
+                {
+                    break;
+                };
+            }
+            debug!(
+                "Committing DB! Force commit {}, {} uncommitted events",
+                self.commit, self.uncommitted_events
+            );
+            backend.commit();
+            if self.quit {
+                break;
+            };
+        }
+        info!("DB Worker thread finished");
+    }
+
+    /// Cascades insert hooks from an insert or heartbeat that just landed
+    /// in `bucket_id`. Derived writes count towards the commit threshold,
+    /// invalidate the query cache and feed the notification stream like
+    /// direct inserts. `pulsetime` is set when the trigger was a
+    /// heartbeat, in which case derived events merge in their target
+    /// bucket the same way. Each bucket is written at most once per
+    /// cascade, so a cycle in the rules stops instead of looping.
+    fn run_insert_hooks(
+        &mut self,
+        backend: &mut dyn StorageBackend,
+        bucket_id: &str,
+        events: &[Event],
+        pulsetime: Option<f64>,
+    ) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let hooks = self.hooks.clone();
+        let mut visited: HashSet<String> = HashSet::new();
+        visited.insert(bucket_id.to_string());
+        let mut queue: Vec<(String, Vec<Event>)> = vec![(bucket_id.to_string(), events.to_vec())];
+        while let Some((source, events)) = queue.pop() {
+            for hook in &hooks {
+                if hook.source_bucket != source {
+                    continue;
+                }
+                if !visited.insert(hook.target_bucket.clone()) {
+                    warn!("Insert hook cycle at {hook:?}, stopping cascade");
+                    continue;
+                }
+                let derived: Vec<Event> = events
+                    .iter()
+                    .filter_map(|event| {
+                        let mut derived = (hook.transform)(event)?;
+                        derived.id = None;
+                        Some(derived)
+                    })
+                    .collect();
+                if derived.is_empty() {
+                    continue;
+                }
+                let written = match pulsetime {
+                    Some(pulsetime) => {
+                        let mut written = Vec::new();
+                        for derived in derived {
+                            match backend.heartbeat(
+                                &hook.target_bucket,
+                                derived,
+                                pulsetime,
+                                MergePolicy::default(),
+                            ) {
+                                Ok(event) => written.push(event),
+                                Err(err) => warn!("{hook:?} failed: {err}"),
+                            }
+                        }
+                        written
+                    }
+                    None => match backend.insert_events(&hook.target_bucket, derived) {
+                        Ok(written) => written,
+                        Err(err) => {
+                            warn!("{hook:?} failed: {err}");
+                            Vec::new()
+                        }
+                    },
+                };
+                if written.is_empty() {
+                    continue;
+                }
+                self.uncommitted_events += written.len();
+                invalidate_query_cache(backend, events_range(&written));
+                for event in &written {
+                    let _ = self.notify.send(EventNotification {
+                        bucket_id: hook.target_bucket.clone(),
+                        event: event.clone(),
+                    });
+                }
+                queue.push((hook.target_bucket.clone(), written));
+            }
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: Command,
+        backend: &mut dyn StorageBackend,
+    ) -> Result<Response, DatastoreError> {
+        match request {
+            Command::CreateBucket(bucket) => match backend.create_bucket(bucket) {
+                Ok(_) => {
+                    self.commit = true;
+                    Ok(Response::Empty())
+                }
+                Err(e) => Err(e),
+            },
+            Command::DeleteBucket(bucket_id) => match backend.delete_bucket(&bucket_id) {
+                Ok(_) => {
+                    self.commit = true;
+                    invalidate_query_cache(backend, None);
+                    Ok(Response::Empty())
+                }
+                Err(e) => Err(e),
+            },
+            Command::SoftDeleteBucket(bucket_id) => {
+                match backend.soft_delete_bucket(&bucket_id) {
+                    Ok(_) => {
+                        self.commit = true;
+                        invalidate_query_cache(backend, None);
+                        Ok(Response::Empty())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::RestoreBucket(bucket_id) => match backend.restore_bucket(&bucket_id) {
+                Ok(_) => {
+                    self.commit = true;
+                    invalidate_query_cache(backend, None);
+                    Ok(Response::Empty())
+                }
+                Err(e) => Err(e),
+            },
+            Command::GetDeletedBuckets() => match backend.get_deleted_buckets() {
+                Ok(names) => Ok(Response::StringVec(names)),
+                Err(e) => Err(e),
+            },
+            Command::PurgeDeletedBuckets(before) => {
+                match backend.purge_deleted_buckets(before) {
+                    Ok(purged) => {
+                        self.commit = true;
+                        Ok(Response::Count(purged))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetBucket(bucket_id) => match backend.get_bucket(&bucket_id) {
+                Ok(bucket) => Ok(Response::Bucket(bucket)),
+                Err(e) => Err(e),
+            },
+            Command::GetBuckets() => Ok(Response::BucketMap(backend.get_buckets())),
+            Command::GetBucketRollups() => match backend.get_bucket_rollups() {
+                Ok(rollups) => Ok(Response::Rollups(rollups)),
+                Err(e) => Err(e),
+            },
+            Command::SetBucketData(bucket_id, data) => {
+                match backend.set_bucket_data(&bucket_id, data) {
+                    Ok(()) => {
+                        self.commit = true;
+                        // Canonical queries pick buckets by their metadata,
+                        // so cached results may be stale now
+                        invalidate_query_cache(backend, None);
+                        Ok(Response::Empty())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::Import(data, provenance) => match backend.import(data, provenance.as_ref()) {
+                Ok(()) => {
+                    self.commit = true;
+                    invalidate_query_cache(backend, None);
+                    Ok(Response::Empty())
+                }
+                Err(e) => Err(e),
+            },
+            Command::InsertEvents(bucket_id, events, provenance) => {
+                match backend.insert_events(&bucket_id, events) {
+                    Ok(events) => {
+                        if let Some(provenance) = &provenance {
+                            let ids: Vec<i64> =
+                                events.iter().filter_map(|event| event.id).collect();
+                            backend.set_event_provenance(&ids, provenance)?;
+                        }
+                        self.uncommitted_events += events.len();
+                        if !events.is_empty() {
+                            invalidate_query_cache(backend, events_range(&events));
+                        }
+                        for event in &events {
+                            // Fails only when there are no subscribers
+                            let _ = self.notify.send(EventNotification {
+                                bucket_id: bucket_id.clone(),
+                                event: event.clone(),
+                            });
+                        }
+                        self.run_insert_hooks(backend, &bucket_id, &events, None);
+                        Ok(Response::EventList(events))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::Heartbeat(bucket_id, event, pulsetime, policy) => {
+                match backend.heartbeat(&bucket_id, event, pulsetime, policy) {
+                    Ok(event) => {
+                        self.uncommitted_events += 1;
+                        invalidate_query_cache(
+                            backend,
+                            Some((event.timestamp, event.calculate_endtime())),
+                        );
+                        let _ = self.notify.send(EventNotification {
+                            bucket_id: bucket_id.clone(),
+                            event: event.clone(),
+                        });
+                        self.run_insert_hooks(
+                            backend,
+                            &bucket_id,
+                            std::slice::from_ref(&event),
+                            Some(pulsetime),
+                        );
+                        Ok(Response::Event(event))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::HeartbeatBatch(bucket_id, heartbeats, pulsetime, policy) => {
+                match backend.heartbeat_batch(&bucket_id, heartbeats, pulsetime, policy) {
+                    Ok(written) => {
+                        self.uncommitted_events += written.len();
+                        let start = written.iter().map(|event| event.timestamp).min();
+                        let end = written.iter().map(|event| event.calculate_endtime()).max();
+                        if let (Some(start), Some(end)) = (start, end) {
+                            invalidate_query_cache(backend, Some((start, end)));
+                        }
+                        // One notification with the final state, not one
+                        // per replayed heartbeat
+                        if let Some(event) = written.last() {
+                            let _ = self.notify.send(EventNotification {
+                                bucket_id: bucket_id.clone(),
+                                event: event.clone(),
+                            });
+                        }
+                        self.run_insert_hooks(backend, &bucket_id, &written, Some(pulsetime));
+                        Ok(Response::EventList(written))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEvents(bucket_id, starttime_opt, endtime_opt, limit_opt) => {
+                match backend.get_events(&bucket_id, starttime_opt, endtime_opt, limit_opt) {
+                    Ok(events) => Ok(Response::EventList(events)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventById(bucket_id, event_id) => {
+                match backend.get_event_by_id(&bucket_id, event_id) {
+                    Ok(event) => Ok(Response::Event(event)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventsFiltered(bucket_id, starttime_opt, endtime_opt, limit_opt, filters) => {
+                match backend.get_events_filtered(
+                    &bucket_id,
+                    starttime_opt,
+                    endtime_opt,
+                    limit_opt,
+                    &filters,
+                ) {
+                    Ok(events) => Ok(Response::EventList(events)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventsAsOf(bucket_id, starttime_opt, endtime_opt, limit_opt, as_of) => {
+                match backend.get_events_as_of(&bucket_id, starttime_opt, endtime_opt, limit_opt, as_of)
+                {
+                    Ok(events) => Ok(Response::EventList(events)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventsAggregate(bucket_id, starttime_opt, endtime_opt, field) => {
+                match backend.get_events_aggregate(&bucket_id, starttime_opt, endtime_opt, &field) {
+                    Ok(aggregate) => Ok(Response::Rollups(aggregate)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventsHourlySummary(bucket_id, starttime_opt, endtime_opt) => {
+                match backend.get_events_hourly_summary(&bucket_id, starttime_opt, endtime_opt) {
+                    Ok(summary) => Ok(Response::Rollups(summary)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventSuggestions(bucket_id, field, prefix, starttime_opt, endtime_opt, limit) => {
+                match backend
+                    .get_event_suggestions(&bucket_id, &field, &prefix, starttime_opt, endtime_opt, limit)
+                {
+                    Ok(suggestions) => Ok(Response::Suggestions(suggestions)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventsPage(bucket_id, starttime_opt, endtime_opt, before, limit) => {
+                match backend.get_events_page(&bucket_id, starttime_opt, endtime_opt, before, limit)
+                {
+                    Ok(events) => Ok(Response::EventList(events)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventCount(bucket_id, starttime_opt, endtime_opt) => {
+                match backend.get_event_count(&bucket_id, starttime_opt, endtime_opt) {
+                    Ok(count) => Ok(Response::Count(count)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetLastEvents(limit) => match backend.get_last_events(limit) {
+                Ok(last_events) => Ok(Response::EventMap(last_events)),
+                Err(e) => Err(e),
+            },
+            Command::ExplainGetEvents(bucket_id, starttime_opt, endtime_opt, limit_opt) => {
+                match backend.explain_get_events(&bucket_id, starttime_opt, endtime_opt, limit_opt)
+                {
+                    Ok(plan) => Ok(Response::StringVec(plan)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetEventProvenance(event_ids) => {
+                match backend.get_event_provenance(&event_ids) {
+                    Ok(provenance) => Ok(Response::Provenance(provenance)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::DeleteEventsByBatch(batch) => match backend.delete_events_by_batch(&batch) {
+                Ok(deleted) => {
+                    self.commit = true;
+                    // The batch may have spanned buckets and time ranges
+                    invalidate_query_cache(backend, None);
+                    Ok(Response::Count(deleted))
+                }
+                Err(e) => Err(e),
+            },
+            Command::CountEventsBefore(bucket_id, before) => {
+                match backend.count_events_before(&bucket_id, before) {
+                    Ok(count) => Ok(Response::Count(count)),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::DeleteEventsBefore(bucket_id, before) => {
+                match backend.delete_events_before(&bucket_id, before) {
+                    Ok(deleted) => {
+                        self.commit = true;
+                        // Everything up to the cutoff is gone, drop any
+                        // cached query results overlapping it
+                        invalidate_query_cache(backend, None);
+                        Ok(Response::Count(deleted))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::CompactEventsBefore(bucket_id, before, pulsetime) => {
+                match backend.compact_events_before(&bucket_id, before, pulsetime) {
+                    Ok(merged) => {
+                        if merged > 0 {
+                            self.commit = true;
+                            invalidate_query_cache(backend, None);
+                        }
+                        Ok(Response::Count(merged))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::DeleteEventsById(bucket_id, event_ids) => {
+                match backend.delete_events_by_id(&bucket_id, event_ids) {
+                    Ok(()) => {
+                        // The range of the deleted events is not known here
+                        invalidate_query_cache(backend, None);
+                        Ok(Response::Empty())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::UpdateEvent(bucket_id, event) => {
+                match backend.update_event(&bucket_id, event) {
+                    Ok(event) => {
+                        self.commit = true;
+                        // The old extent of the event is not known here
+                        invalidate_query_cache(backend, None);
+                        Ok(Response::Event(event))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::ForceCommit() => {
+                self.commit = true;
+                Ok(Response::Empty())
+            }
+            Command::InsertKeyValue(key, data) => {
+                match backend.insert_key_value(&key, &data) {
+                    Ok(()) => Ok(Response::Empty()),
+                    Err(e) => Err(e),
+                }
+            }
+            Command::GetKeyValue(key) => match backend.get_key_value(&key) {
+                Ok(kv) => Ok(Response::KeyValue(kv)),
+                Err(e) => Err(e),
+            },
+            Command::GetKeysStarting(pattern) => match backend.get_keys_starting(&pattern) {
+                Ok(keys) => Ok(Response::StringVec(keys)),
+                Err(e) => Err(e),
+            },
+            Command::DeleteKeyValue(key) => match backend.delete_key_value(&key) {
+                Ok(()) => Ok(Response::Empty()),
+                Err(e) => Err(e),
+            },
+            Command::AddInsertHook(hook) => {
+                self.hooks.push(hook);
+                Ok(Response::Empty())
+            }
+            Command::Close() => {
+                self.quit = true;
+                self.commit = true;
+                Ok(Response::Empty())
+            }
+        }
+    }
+
+}
+
+impl Datastore {
+    pub fn new(dbpath: String, legacy_import: bool) -> Self {
+        Datastore::_new_internal(dbpath, false, legacy_import)
+    }
+
+    pub fn new_in_memory(legacy_import: bool) -> Self {
+        Datastore::_new_internal("in-memory".to_string(), true, legacy_import)
+    }
+
+    /// An ephemeral datastore backed by plain HashMaps instead of SQLite,
+    /// for tests and quick demos where nothing should touch disk
+    pub fn new_ephemeral(legacy_import: bool) -> Self {
+        let (requester, responder) =
+            mpsc_requests::channel::<Command, Result<Response, DatastoreError>>();
+        let (notify, _) = broadcast::channel(NOTIFY_CHANNEL_CAPACITY);
+        let worker_notify = notify.clone();
+        let _thread = thread::spawn(move || {
+            let mut backend = MemoryBackend::new();
+            let mut di = DatastoreWorker::new(responder, worker_notify, legacy_import);
+            di.work_loop(&mut backend);
+        });
+        Datastore { requester, notify }
+    }
+
+    fn _new_internal(dbpath: String, in_memory: bool, legacy_import: bool) -> Self {
+        let (requester, responder) =
+            mpsc_requests::channel::<Command, Result<Response, DatastoreError>>();
+        let (notify, _) = broadcast::channel(NOTIFY_CHANNEL_CAPACITY);
+        let worker_notify = notify.clone();
+        let _thread = thread::spawn(move || {
+            let mut backend = SqliteBackend::new(&dbpath, in_memory);
+            let mut di = DatastoreWorker::new(responder, worker_notify, legacy_import);
+            di.work_loop(&mut backend);
+        });
+        Datastore { requester, notify }
+    }
+
+    /// Subscribes to notifications for newly inserted events and merged
+    /// heartbeats. The notification is sent before the insert call
+    /// returns, so subscribers never miss events inserted after they
+    /// subscribed.
+    pub fn subscribe(&self) -> broadcast::Receiver<EventNotification> {
+        self.notify.subscribe()
+    }
+
+    /// Registers a hook deriving events into another bucket on every
+    /// insert or heartbeat, see [`InsertHook`]. The target bucket must
+    /// exist by the time the first matching event arrives.
+    pub fn add_insert_hook(&self, hook: InsertHook) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::AddInsertHook(hook))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn create_bucket(&self, bucket: &Bucket) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::CreateBucket(bucket.clone()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn delete_bucket(&self, bucket_id: &str) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::DeleteBucket(bucket_id.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn soft_delete_bucket(&self, bucket_id: &str) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::SoftDeleteBucket(bucket_id.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    /// Replaces a bucket's metadata `data`, e.g. to set or clear the
+    /// `archived` flag
+    pub fn set_bucket_data(
+        &self,
+        bucket_id: &str,
+        data: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::SetBucketData(bucket_id.to_string(), data))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn restore_bucket(&self, bucket_id: &str) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::RestoreBucket(bucket_id.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn get_deleted_buckets(&self) -> Result<Vec<String>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetDeletedBuckets())
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::StringVec(names) => Ok(names),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn purge_deleted_buckets(&self, before: DateTime<Utc>) -> Result<i64, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::PurgeDeletedBuckets(before))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Count(count) => Ok(count),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_bucket(&self, bucket_id: &str) -> Result<Bucket, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetBucket(bucket_id.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Bucket(b) => Ok(b),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_buckets(&self) -> Result<HashMap<String, Bucket>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetBuckets())
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::BucketMap(map) => Ok(map),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Per-bucket `(event count, total duration in nanoseconds)` for all
+    /// buckets, from a single aggregate query
+    pub fn get_bucket_rollups(&self) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetBucketRollups())
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Rollups(rollups) => Ok(rollups),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Imports buckets and their events atomically: on any error the
+    /// whole import is rolled back, so partial imports never happen.
+    /// Returns the generated batch id, recorded as provenance on every
+    /// imported event so a bad import can be rolled back afterwards.
+    pub fn import(
+        &self,
+        data: HashMap<String, (Bucket, Vec<Event>)>,
+    ) -> Result<String, DatastoreError> {
+        let now = Utc::now();
+        let batch = format!("import-{}", now.format("%Y%m%dT%H%M%S%.3fZ"));
+        let provenance = EventProvenance {
+            key: None,
+            client: None,
+            ingested: now,
+            batch: Some(batch.clone()),
+        };
+        let receiver = self
+            .requester
+            .request(Command::Import(data, Some(provenance)))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)?;
+        Ok(batch)
+    }
+
+    pub fn insert_events(
+        &self,
+        bucket_id: &str,
+        events: &[Event],
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::InsertEvents(
+                bucket_id.to_string(),
+                events.to_vec(),
+                None,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(events) => Ok(events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like [`Datastore::insert_events`], but also records the given
+    /// ingest provenance on every inserted event
+    pub fn insert_events_with_provenance(
+        &self,
+        bucket_id: &str,
+        events: &[Event],
+        provenance: EventProvenance,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::InsertEvents(
+                bucket_id.to_string(),
+                events.to_vec(),
+                Some(provenance),
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(events) => Ok(events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Provenance for the given event ids; events without recorded
+    /// provenance (inserted before tracking existed, or by the server
+    /// itself) are absent from the result
+    pub fn get_event_provenance(
+        &self,
+        event_ids: Vec<i64>,
+    ) -> Result<HashMap<i64, EventProvenance>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventProvenance(event_ids))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Provenance(provenance) => Ok(provenance),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deletes every event from the given import batch, across all
+    /// buckets, returning how many events were removed
+    pub fn delete_events_by_batch(&self, batch: &str) -> Result<i64, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::DeleteEventsByBatch(batch.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Count(deleted) => Ok(deleted),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// How many of the bucket's events end before `before`; the dry-run
+    /// counterpart of [`Datastore::delete_events_before`]
+    pub fn count_events_before(
+        &self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::CountEventsBefore(bucket_id.to_string(), before))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Count(count) => Ok(count),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Permanently deletes the bucket's events ending before `before`
+    /// (no history pre-image), returning how many were removed; the
+    /// retention enforcement path
+    pub fn delete_events_before(
+        &self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+    ) -> Result<i64, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::DeleteEventsBefore(bucket_id.to_string(), before))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Count(deleted) => Ok(deleted),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Merges runs of identical-data events ending before `before` into
+    /// single rows, a retroactive heartbeat pass with the given
+    /// pulsetime; returns how many rows were merged away
+    pub fn compact_events_before(
+        &self,
+        bucket_id: &str,
+        before: DateTime<Utc>,
+        pulsetime: f64,
+    ) -> Result<i64, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::CompactEventsBefore(
+                bucket_id.to_string(),
+                before,
+                pulsetime,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Count(merged) => Ok(merged),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn heartbeat(
+        &self,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+    ) -> Result<Event, DatastoreError> {
+        self.heartbeat_with_policy(bucket_id, heartbeat, pulsetime, MergePolicy::default())
+    }
+
+    pub fn heartbeat_with_policy(
+        &self,
+        bucket_id: &str,
+        heartbeat: Event,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Event, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::Heartbeat(
+                bucket_id.to_string(),
+                heartbeat,
+                pulsetime,
+                policy,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Event(e) => Ok(e),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn heartbeat_batch(
+        &self,
+        bucket_id: &str,
+        heartbeats: Vec<Event>,
+        pulsetime: f64,
+        policy: MergePolicy,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::HeartbeatBatch(
+                bucket_id.to_string(),
+                heartbeats,
+                pulsetime,
+                policy,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(written) => Ok(written),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_events(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEvents(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+                limit_opt,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(events) => Ok(events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A single event by id, fetched with a single-row query instead of
+    /// loading the whole bucket
+    pub fn get_event_by_id(&self, bucket_id: &str, event_id: i64) -> Result<Event, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventById(bucket_id.to_string(), event_id))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Event(event) => Ok(event),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like [`Datastore::get_events`], but additionally filters on exact
+    /// matches of top-level `data` fields, applied before the limit
+    pub fn get_events_filtered(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        filters: Vec<(String, serde_json::Value)>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventsFiltered(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+                limit_opt,
+                filters,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(events) => Ok(events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The bucket's events as they were at `as_of`: live events ingested
+    /// by then plus pre-images of events deleted after it; see
+    /// [`crate::datastore::DatastoreInstance::get_events_as_of`]
+    pub fn get_events_as_of(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+        as_of: DateTime<Utc>,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventsAsOf(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+                limit_opt,
+                as_of,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(events) => Ok(events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `(event count, total duration in ns)` grouped by a top-level
+    /// `data` field, computed in the datastore; see
+    /// [`crate::datastore::DatastoreInstance::get_events_aggregate`]
+    pub fn get_events_aggregate(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        field: &str,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventsAggregate(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+                field.to_string(),
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Rollups(aggregate) => Ok(aggregate),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `(event count, total duration in ns)` per UTC hour, keyed by the
+    /// hour start as an RFC3339 timestamp; see
+    /// [`crate::datastore::DatastoreInstance::get_events_hourly_summary`]
+    pub fn get_events_hourly_summary(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<HashMap<String, (i64, i64)>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventsHourlySummary(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Rollups(summary) => Ok(summary),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Distinct values of a top-level `data` field starting with
+    /// `prefix`, with occurrence counts, most frequent first; see
+    /// [`crate::datastore::DatastoreInstance::get_event_suggestions`]
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_event_suggestions(
+        &self,
+        bucket_id: &str,
+        field: &str,
+        prefix: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit: u64,
+    ) -> Result<Vec<(String, i64)>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventSuggestions(
+                bucket_id.to_string(),
+                field.to_string(),
+                prefix.to_string(),
+                starttime_opt,
+                endtime_opt,
+                limit,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Suggestions(suggestions) => Ok(suggestions),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A page of events for streaming downloads: newest first, keyset
+    /// paginated on `(starttime ns, id)` via `before`, and not clipped
+    /// to the query window like `get_events`
+    pub fn get_events_page(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        before: Option<(i64, i64)>,
+        limit: u64,
+    ) -> Result<Vec<Event>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventsPage(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+                before,
+                limit,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventList(events) => Ok(events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_event_count(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+    ) -> Result<i64, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetEventCount(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Count(count) => Ok(count),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the backend's plan for the corresponding `get_events`
+    /// call, one line per plan step (SQLite's EXPLAIN QUERY PLAN output)
+    pub fn explain_get_events(
+        &self,
+        bucket_id: &str,
+        starttime_opt: Option<DateTime<Utc>>,
+        endtime_opt: Option<DateTime<Utc>>,
+        limit_opt: Option<u64>,
+    ) -> Result<Vec<String>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::ExplainGetEvents(
+                bucket_id.to_string(),
+                starttime_opt,
+                endtime_opt,
+                limit_opt,
+            ))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::StringVec(plan) => Ok(plan),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_last_events(
+        &self,
+        limit: u64,
+    ) -> Result<HashMap<String, Vec<Event>>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetLastEvents(limit))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::EventMap(last_events) => Ok(last_events),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn delete_events_by_id(
+        &self,
+        bucket_id: &str,
+        event_ids: Vec<i64>,
+    ) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::DeleteEventsById(bucket_id.to_string(), event_ids))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn update_event(&self, bucket_id: &str, event: Event) -> Result<Event, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::UpdateEvent(bucket_id.to_string(), event))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::Event(event) => Ok(event),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn force_commit(&self) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::ForceCommit())
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn insert_key_value(&self, key: &str, data: &str) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::InsertKeyValue(key.to_string(), data.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn get_key_value(&self, key: &str) -> Result<KeyValue, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetKeyValue(key.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::KeyValue(kv) => Ok(kv),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_keys_starting(&self, pattern: &str) -> Result<Vec<String>, DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::GetKeysStarting(pattern.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        match receiver.collect().map_err(|_| DatastoreError::MpscError)? {
+            Ok(r) => match r {
+                Response::StringVec(keys) => Ok(keys),
+                _ => panic!("Invalid response"),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn delete_key_value(&self, key: &str) -> Result<(), DatastoreError> {
+        let receiver = self
+            .requester
+            .request(Command::DeleteKeyValue(key.to_string()))
+            .map_err(|_| DatastoreError::MpscError)?;
+        _unwrap_response(receiver)
+    }
+
+    pub fn close(&self) {
+        if self
+            .requester
+            .request(Command::Close()).map(|r| r.collect())
+            .is_err()
+        {
+            warn!("Failed to send close request to datastore");
+        }
+    }
+}
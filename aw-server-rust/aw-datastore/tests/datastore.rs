@@ -0,0 +1,543 @@
+extern crate aw_datastore;
+extern crate aw_models;
+extern crate chrono;
+extern crate serde_json;
+
+#[cfg(test)]
+mod datastore_tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_datastore::Datastore;
+    use aw_datastore::DatastoreError;
+    use aw_models::Bucket;
+    use aw_models::BucketMetadata;
+    use aw_models::Event;
+    use aw_models::TryVec;
+
+    fn test_bucket() -> Bucket {
+        Bucket {
+            bid: None,
+            id: "testid".to_string(),
+            _type: "testtype".to_string(),
+            client: "testclient".to_string(),
+            hostname: "testhost".to_string(),
+            created: None,
+            last_updated: None,
+            event_count: None,
+            total_duration: None,
+            data: Map::new(),
+            events: TryVec::new_empty(),
+            metadata: BucketMetadata::default(),
+        }
+    }
+
+    fn test_event(data_value: i64) -> Event {
+        let mut data = Map::new();
+        data.insert("test".to_string(), Value::from(data_value));
+        Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_bucket_create_delete() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        match ds.create_bucket(&bucket) {
+            Err(DatastoreError::BucketAlreadyExists(_)) => (),
+            r => panic!("Expected BucketAlreadyExists, got {r:?}"),
+        }
+
+        let fetched = ds.get_bucket(&bucket.id).unwrap();
+        assert_eq!(fetched.id, bucket.id);
+        assert_eq!(fetched._type, bucket._type);
+
+        ds.delete_bucket(&bucket.id).unwrap();
+        match ds.get_bucket(&bucket.id) {
+            Err(DatastoreError::NoSuchBucket(_)) => (),
+            r => panic!("Expected NoSuchBucket, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_events_insert_get() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        let event = test_event(1);
+        ds.insert_events(&bucket.id, std::slice::from_ref(&event))
+            .unwrap();
+
+        let fetched_events = ds.get_events(&bucket.id, None, None, None).unwrap();
+        assert_eq!(fetched_events.len(), 1);
+        assert_eq!(fetched_events[0].data, event.data);
+
+        let count = ds.get_event_count(&bucket.id, None, None).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_events_delete_by_id() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        ds.insert_events(&bucket.id, &[test_event(1)]).unwrap();
+        let events = ds.get_events(&bucket.id, None, None, None).unwrap();
+        ds.delete_events_by_id(&bucket.id, vec![events[0].id.unwrap()])
+            .unwrap();
+        let count = ds.get_event_count(&bucket.id, None, None).unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_soft_delete_restore() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+        ds.insert_events(&bucket.id, &[test_event(1)]).unwrap();
+
+        // Soft-deleted buckets are hidden but show up in the trash
+        ds.soft_delete_bucket(&bucket.id).unwrap();
+        match ds.get_bucket(&bucket.id) {
+            Err(DatastoreError::NoSuchBucket(_)) => (),
+            r => panic!("Expected NoSuchBucket, got {r:?}"),
+        }
+        assert_eq!(ds.get_deleted_buckets().unwrap(), vec![bucket.id.clone()]);
+
+        // The name is still taken while the bucket is in the trash
+        match ds.create_bucket(&bucket) {
+            Err(DatastoreError::BucketAlreadyExists(_)) => (),
+            r => panic!("Expected BucketAlreadyExists, got {r:?}"),
+        }
+
+        // Restoring brings back the bucket with its events
+        ds.restore_bucket(&bucket.id).unwrap();
+        assert_eq!(ds.get_event_count(&bucket.id, None, None).unwrap(), 1);
+        match ds.restore_bucket(&bucket.id) {
+            Err(DatastoreError::NoSuchBucket(_)) => (),
+            r => panic!("Expected NoSuchBucket, got {r:?}"),
+        }
+
+        // Purging the trash permanently removes the bucket
+        ds.soft_delete_bucket(&bucket.id).unwrap();
+        assert_eq!(ds.purge_deleted_buckets(Utc::now()).unwrap(), 1);
+        assert!(ds.get_deleted_buckets().unwrap().is_empty());
+        match ds.restore_bucket(&bucket.id) {
+            Err(DatastoreError::NoSuchBucket(_)) => (),
+            r => panic!("Expected NoSuchBucket, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_merges() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        let now = Utc::now();
+        let mut heartbeat = test_event(1);
+        heartbeat.timestamp = now;
+        ds.heartbeat(&bucket.id, heartbeat.clone(), 10.0).unwrap();
+
+        let mut heartbeat2 = heartbeat.clone();
+        heartbeat2.timestamp = now + Duration::seconds(1);
+        ds.heartbeat(&bucket.id, heartbeat2, 10.0).unwrap();
+
+        let events = ds.get_events(&bucket.id, None, None, None).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].duration, Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_key_value() {
+        let ds = Datastore::new_in_memory(false);
+        ds.insert_key_value("test_key", "test_value").unwrap();
+        let kv = ds.get_key_value("test_key").unwrap();
+        assert_eq!(kv.value, "test_value");
+
+        ds.delete_key_value("test_key").unwrap();
+        match ds.get_key_value("test_key") {
+            Err(DatastoreError::NoSuchKey(_)) => (),
+            r => panic!("Expected NoSuchKey, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_bulk_insert() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        // Mark where generated ids start
+        let marker = ds.insert_events(&bucket.id, &[test_event(0)]).unwrap();
+        let marker_id = marker[0].id.unwrap();
+
+        // Large batches without explicit ids take the multi-row insert path
+        let start = Utc::now();
+        let events: Vec<Event> = (1..=150)
+            .map(|i| {
+                let mut event = test_event(i);
+                event.timestamp = start + Duration::seconds(i);
+                event
+            })
+            .collect();
+        let inserted = ds.insert_events(&bucket.id, &events).unwrap();
+        assert_eq!(inserted.len(), 150);
+        for (i, event) in inserted.iter().enumerate() {
+            // Generated ids come back in order
+            assert_eq!(event.id.unwrap(), marker_id + 1 + i as i64);
+        }
+
+        // Events round-trip with the right data and count
+        let fetched = ds.get_events(&bucket.id, None, None, None).unwrap();
+        assert_eq!(fetched.len(), 151);
+        assert_eq!(fetched[0].data["test"], 150);
+        assert_eq!(
+            ds.get_event_count(&bucket.id, None, None).unwrap(),
+            151
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        let ds = Datastore::new_in_memory(false);
+
+        let mut bucket1 = test_bucket();
+        bucket1.id = "import1".to_string();
+        let mut bucket2 = test_bucket();
+        bucket2.id = "import2".to_string();
+
+        let mut data = std::collections::HashMap::new();
+        data.insert(
+            bucket1.id.clone(),
+            (bucket1.clone(), vec![test_event(1), test_event(2)]),
+        );
+        data.insert(bucket2.id.clone(), (bucket2.clone(), vec![test_event(3)]));
+        ds.import(data).unwrap();
+
+        assert_eq!(ds.get_events(&bucket1.id, None, None, None).unwrap().len(), 2);
+        assert_eq!(ds.get_events(&bucket2.id, None, None, None).unwrap().len(), 1);
+
+        // An import containing an existing bucket fails and rolls back
+        // entirely, so the new bucket must not be created either
+        let mut bucket3 = test_bucket();
+        bucket3.id = "import3".to_string();
+        let mut data = std::collections::HashMap::new();
+        data.insert(bucket1.id.clone(), (bucket1.clone(), vec![test_event(4)]));
+        data.insert(bucket3.id.clone(), (bucket3.clone(), vec![test_event(5)]));
+        match ds.import(data) {
+            Err(DatastoreError::BucketAlreadyExists(_)) => (),
+            r => panic!("Expected BucketAlreadyExists, got {r:?}"),
+        }
+        match ds.get_bucket(&bucket3.id) {
+            Err(DatastoreError::NoSuchBucket(_)) => (),
+            r => panic!("Expected NoSuchBucket, got {r:?}"),
+        }
+        // The existing bucket is untouched
+        assert_eq!(ds.get_events(&bucket1.id, None, None, None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_event_provenance() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        // Events inserted with provenance report it back
+        let provenance = aw_models::EventProvenance {
+            key: Some("testkey".to_string()),
+            client: Some("testclient/1.0".to_string()),
+            ingested: Utc::now(),
+            batch: None,
+        };
+        let inserted = ds
+            .insert_events_with_provenance(
+                &bucket.id,
+                &[test_event(1), test_event(2)],
+                provenance.clone(),
+            )
+            .unwrap();
+        let ids: Vec<i64> = inserted.iter().filter_map(|event| event.id).collect();
+        let stored = ds.get_event_provenance(ids.clone()).unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[&ids[0]], provenance);
+
+        // Events inserted the plain way have none
+        let plain = ds.insert_events(&bucket.id, &[test_event(3)]).unwrap();
+        let stored = ds.get_event_provenance(vec![plain[0].id.unwrap()]).unwrap();
+        assert!(stored.is_empty());
+
+        // Imported events carry the returned batch id, and deleting the
+        // batch removes exactly them
+        let mut import_bucket = test_bucket();
+        import_bucket.id = "import-prov".to_string();
+        let mut data = std::collections::HashMap::new();
+        data.insert(
+            import_bucket.id.clone(),
+            (import_bucket.clone(), vec![test_event(4), test_event(5)]),
+        );
+        let batch = ds.import(data).unwrap();
+        let imported = ds.get_events(&import_bucket.id, None, None, None).unwrap();
+        let imported_ids: Vec<i64> = imported.iter().filter_map(|event| event.id).collect();
+        let stored = ds.get_event_provenance(imported_ids.clone()).unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[&imported_ids[0]].batch, Some(batch.clone()));
+
+        assert_eq!(ds.delete_events_by_batch(&batch).unwrap(), 2);
+        assert_eq!(
+            ds.get_events(&import_bucket.id, None, None, None)
+                .unwrap()
+                .len(),
+            0
+        );
+        assert!(ds.get_event_provenance(imported_ids).unwrap().is_empty());
+        // Events outside the batch are untouched
+        assert_eq!(ds.get_events(&bucket.id, None, None, None).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_legacy_import() {
+        // Write a minimal legacy aw-server-python (peewee) database
+        let path = std::env::temp_dir().join(format!("aw-legacy-test-{}.db", std::process::id()));
+        let path_str = path.to_str().unwrap().to_string();
+        {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE bucketmodel (
+                     key INTEGER PRIMARY KEY, id TEXT, created TEXT,
+                     name TEXT, type TEXT, client TEXT, hostname TEXT);
+                 CREATE TABLE eventmodel (
+                     id INTEGER PRIMARY KEY, bucket_id INTEGER,
+                     timestamp TEXT, duration REAL, datastr TEXT);
+                 INSERT INTO bucketmodel (key, id, created, type, client, hostname)
+                     VALUES (1, 'legacy-bucket', '2018-01-01 00:00:00.000000+00:00',
+                             'testtype', 'testclient', 'testhost');
+                 INSERT INTO eventmodel (bucket_id, timestamp, duration, datastr)
+                     VALUES (1, '2018-01-01 12:00:00.000000+00:00', 1.5, '{\"test\": 1}'),
+                            (1, '2018-01-01 12:00:02', 2.0, '{\"test\": 2}');",
+            )
+            .unwrap();
+        }
+
+        let data = aw_datastore::legacy_import::read_legacy_db(&path_str).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), 1);
+
+        let ds = Datastore::new_in_memory(false);
+        ds.import(data).unwrap();
+        let bucket = ds.get_bucket("legacy-bucket").unwrap();
+        assert_eq!(bucket._type, "testtype");
+        let events = ds.get_events("legacy-bucket", None, None, None).unwrap();
+        assert_eq!(events.len(), 2);
+        // Newest first; the naive timestamp is taken as UTC
+        assert_eq!(events[0].data["test"], 2);
+        assert_eq!(events[0].duration, Duration::seconds(2));
+        assert_eq!(events[1].duration, Duration::milliseconds(1500));
+    }
+
+    #[test]
+    fn test_event_notifications() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        // The notification is published before the insert call returns
+        let mut subscriber = ds.subscribe();
+        let event = test_event(1);
+        ds.insert_events(&bucket.id, std::slice::from_ref(&event))
+            .unwrap();
+        let notification = subscriber.try_recv().unwrap();
+        assert_eq!(notification.bucket_id, bucket.id);
+        assert_eq!(notification.event.data, event.data);
+
+        // Heartbeats publish the resulting event as well
+        ds.heartbeat(&bucket.id, test_event(2), 1.0).unwrap();
+        let notification = subscriber.try_recv().unwrap();
+        assert_eq!(notification.event.data["test"], 2);
+    }
+
+    #[test]
+    fn test_insert_hooks() {
+        use std::sync::Arc;
+
+        use aw_datastore::InsertHook;
+
+        let ds = Datastore::new_in_memory(false);
+        let source = test_bucket();
+        let mut derived = test_bucket();
+        derived.id = "derived".to_string();
+        ds.create_bucket(&source).unwrap();
+        ds.create_bucket(&derived).unwrap();
+
+        // Doubles the "test" value into the derived bucket
+        ds.add_insert_hook(InsertHook {
+            source_bucket: source.id.clone(),
+            target_bucket: derived.id.clone(),
+            transform: Arc::new(|event| {
+                let mut derived = event.clone();
+                let value = event.data["test"].as_i64().unwrap();
+                derived
+                    .data
+                    .insert("test".to_string(), Value::from(value * 2));
+                Some(derived)
+            }),
+        })
+        .unwrap();
+        // A cycle back into the source bucket must not loop
+        ds.add_insert_hook(InsertHook {
+            source_bucket: derived.id.clone(),
+            target_bucket: source.id.clone(),
+            transform: Arc::new(|event| Some(event.clone())),
+        })
+        .unwrap();
+
+        ds.insert_events(&source.id, &[test_event(1)]).unwrap();
+        let events = ds.get_events(&derived.id, None, None, None).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data["test"], 2);
+        // The cycle was cut, so the source still has only the original event
+        assert_eq!(ds.get_event_count(&source.id, None, None).unwrap(), 1);
+
+        // Heartbeats propagate as heartbeats: the derived event merges
+        let mut heartbeat = test_event(1);
+        heartbeat.timestamp = events[0].timestamp + Duration::seconds(1);
+        ds.heartbeat(&source.id, heartbeat, 2.0).unwrap();
+        let events = ds.get_events(&derived.id, None, None, None).unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn test_ephemeral() {
+        // The HashMap-backed datastore should behave like the SQLite one
+        let ds = Datastore::new_ephemeral(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+        match ds.create_bucket(&bucket) {
+            Err(DatastoreError::BucketAlreadyExists(_)) => (),
+            r => panic!("Expected BucketAlreadyExists, got {r:?}"),
+        }
+
+        let now = Utc::now();
+        let mut event = test_event(1);
+        event.timestamp = now;
+        event.duration = Duration::seconds(10);
+
+        // Heartbeats merge through the same worker codepath
+        ds.heartbeat(&bucket.id, event.clone(), 1.0).unwrap();
+        let mut heartbeat = event.clone();
+        heartbeat.timestamp = now + Duration::seconds(10);
+        heartbeat.duration = Duration::seconds(5);
+        ds.heartbeat(&bucket.id, heartbeat, 1.0).unwrap();
+        let events = ds.get_events(&bucket.id, None, None, None).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].duration, Duration::seconds(15));
+
+        // Events are clamped to the query range, newest first
+        let clamped = ds
+            .get_events(
+                &bucket.id,
+                Some(now + Duration::seconds(5)),
+                Some(now + Duration::seconds(12)),
+                None,
+            )
+            .unwrap();
+        assert_eq!(clamped.len(), 1);
+        assert_eq!(clamped[0].timestamp, now + Duration::seconds(5));
+        assert_eq!(clamped[0].duration, Duration::seconds(7));
+
+        // Key-value store
+        ds.insert_key_value("settings.test", "1").unwrap();
+        assert_eq!(ds.get_key_value("settings.test").unwrap().value, "1");
+        assert_eq!(
+            ds.get_keys_starting("settings.%").unwrap(),
+            vec!["settings.test".to_string()]
+        );
+        ds.delete_key_value("settings.test").unwrap();
+        match ds.get_key_value("settings.test") {
+            Err(DatastoreError::NoSuchKey(_)) => (),
+            r => panic!("Expected NoSuchKey, got {r:?}"),
+        }
+
+        // Trash keeps the name reserved until the bucket is restored
+        ds.soft_delete_bucket(&bucket.id).unwrap();
+        match ds.create_bucket(&bucket) {
+            Err(DatastoreError::BucketAlreadyExists(_)) => (),
+            r => panic!("Expected BucketAlreadyExists, got {r:?}"),
+        }
+        ds.restore_bucket(&bucket.id).unwrap();
+        let count = ds.get_event_count(&bucket.id, None, None).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_last_updated() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        // Set to the creation time on creation
+        let fetched = ds.get_bucket(&bucket.id).unwrap();
+        assert_eq!(fetched.last_updated, fetched.created);
+
+        // Advanced by writes
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        ds.insert_events(&bucket.id, &[test_event(1)]).unwrap();
+        let updated = ds.get_bucket(&bucket.id).unwrap().last_updated.unwrap();
+        assert!(updated > fetched.created.unwrap());
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        ds.heartbeat(&bucket.id, test_event(1), 10.0).unwrap();
+        let after_heartbeat = ds.get_bucket(&bucket.id).unwrap().last_updated.unwrap();
+        assert!(after_heartbeat > updated);
+    }
+
+    #[test]
+    fn test_metadata_shrinks_on_delete() {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = test_bucket();
+        ds.create_bucket(&bucket).unwrap();
+
+        let mut early = test_event(1);
+        early.timestamp = Utc::now() - Duration::hours(2);
+        let mut late = test_event(2);
+        late.timestamp = Utc::now();
+        ds.insert_events(&bucket.id, &[early.clone(), late.clone()])
+            .unwrap();
+
+        let metadata = ds.get_bucket(&bucket.id).unwrap().metadata;
+        assert_eq!(metadata.start, Some(early.timestamp));
+        assert_eq!(metadata.end, Some(late.calculate_endtime()));
+
+        // Deleting the newest event pulls the end back to the remaining one
+        let events = ds.get_events(&bucket.id, None, None, None).unwrap();
+        let late_id = events
+            .iter()
+            .find(|event| event.data["test"] == serde_json::json!(2))
+            .unwrap()
+            .id
+            .unwrap();
+        ds.delete_events_by_id(&bucket.id, vec![late_id]).unwrap();
+        let metadata = ds.get_bucket(&bucket.id).unwrap().metadata;
+        assert_eq!(metadata.start, Some(early.timestamp));
+        assert_eq!(metadata.end, Some(early.calculate_endtime()));
+
+        // Deleting the last event clears the extent
+        let events = ds.get_events(&bucket.id, None, None, None).unwrap();
+        ds.delete_events_by_id(&bucket.id, vec![events[0].id.unwrap()])
+            .unwrap();
+        let metadata = ds.get_bucket(&bucket.id).unwrap().metadata;
+        assert_eq!(metadata.start, None);
+        assert_eq!(metadata.end, None);
+    }
+}
@@ -0,0 +1,99 @@
+use chrono::DateTime;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::map::Map;
+use serde_json::value::Value;
+use std::collections::HashMap;
+
+use crate::Event;
+use crate::TryVec;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Bucket {
+    #[serde(skip)]
+    pub bid: Option<i64>,
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(rename = "type")]
+    pub _type: String,
+    pub client: String,
+    pub hostname: String,
+    /// Timestamp of when the bucket was created, will be set by the server
+    /// if not provided by the client.
+    pub created: Option<DateTime<Utc>>,
+    /// Timestamp of the last write to the bucket (insert, heartbeat,
+    /// replace or delete), maintained by the server for cheap change
+    /// detection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<DateTime<Utc>>,
+    /// Arbitrary JSON metadata attached to the bucket.
+    #[serde(default)]
+    pub data: Map<String, Value>,
+    /// Only used during import/export, contains all events in the bucket.
+    #[serde(skip_serializing_if = "TryVec::is_empty", default)]
+    pub events: TryVec<Event>,
+    /// Number of events in the bucket; only populated when the bucket
+    /// listing is requested with metrics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_count: Option<i64>,
+    /// Total tracked duration in seconds; only populated when the bucket
+    /// listing is requested with metrics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_duration: Option<f64>,
+    #[serde(default, skip_serializing)]
+    pub metadata: BucketMetadata,
+}
+
+impl Bucket {
+    /// Whether the bucket is archived (retired machine, old watcher
+    /// version). The flag lives in `data`, so it needs no schema change
+    /// and survives export/import. Archived buckets are hidden from
+    /// default listings and not picked by canonical queries, but their
+    /// data stays queryable on request.
+    pub fn is_archived(&self) -> bool {
+        self.data
+            .get("archived")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct BucketMetadata {
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct BucketsExport {
+    pub buckets: HashMap<String, Bucket>,
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Map;
+
+    use super::{Bucket, BucketMetadata, TryVec};
+
+    #[test]
+    fn test_bucket() {
+        let b = Bucket {
+            bid: None,
+            id: "id".to_string(),
+            _type: "type".to_string(),
+            client: "client".to_string(),
+            hostname: "hostname".to_string(),
+            created: None,
+            last_updated: None,
+            event_count: None,
+            total_duration: None,
+            data: Map::new(),
+            events: TryVec::new(Vec::new()),
+            metadata: BucketMetadata::default(),
+        };
+        debug!("bucket: {:?}", b);
+    }
+}
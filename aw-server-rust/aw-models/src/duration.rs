@@ -0,0 +1,27 @@
+use chrono::Duration;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Serializes a `chrono::Duration` as a number of seconds (float), which is
+/// the representation used throughout the ActivityWatch REST API.
+pub struct DurationSerialization;
+
+impl DurationSerialization {
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_f64(duration.num_milliseconds() as f64 / 1000.0)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let seconds = f64::deserialize(deserializer)?;
+        if !seconds.is_finite() {
+            return Err(D::Error::custom("duration is not a finite number"));
+        }
+        Ok(Duration::milliseconds((seconds * 1000.0).round() as i64))
+    }
+}
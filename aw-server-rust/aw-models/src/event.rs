@@ -0,0 +1,183 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
+use serde_json::Map;
+
+use crate::duration::DurationSerialization;
+use crate::TimeInterval;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Event {
+    /// An unique id for this event.
+    /// Will be assigned once the event has reached the servers datastore.
+    ///
+    /// **WARNING:** If you set the ID and insert the event to the server it will
+    /// replace the previous event with that ID. Only do this if you are completely
+    /// sure what you are doing.
+    ///
+    /// Accepted as either a JSON number or a decimal string: ids above
+    /// 2^53 lose precision as numbers in JavaScript, so clients on
+    /// long-lived servers may send them stringified. Output stays
+    /// numeric for compatibility with existing v0 API clients.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_id"
+    )]
+    #[schemars(with = "Option<i64>")]
+    pub id: Option<i64>,
+    /// An rfc3339 timestamp which represents the start of the event
+    pub timestamp: DateTime<Utc>,
+    /// Duration of the event as a floating point number in seconds.
+    /// Appended to the timestamp it can represent the end of the event.
+    /// Maximum precision is milliseconds.
+    #[serde(with = "DurationSerialization", default = "default_duration")]
+    #[schemars(with = "f64")]
+    pub duration: Duration,
+    /// Can contain any arbitrary JSON data that represents the value of the event.
+    /// All events in a bucket should follow the format of it's respective bucket-type.
+    pub data: Map<String, Value>,
+}
+
+impl Event {
+    pub fn new(timestamp: DateTime<Utc>, duration: Duration, data: Map<String, Value>) -> Event {
+        Event {
+            id: None,
+            timestamp,
+            duration,
+            data,
+        }
+    }
+
+    pub fn calculate_endtime(&self) -> DateTime<Utc> {
+        self.timestamp + self.duration
+    }
+
+    pub fn interval(&self) -> TimeInterval {
+        TimeInterval::new(self.timestamp, self.calculate_endtime())
+    }
+}
+
+/// Ingest provenance for an event, stored alongside the event itself so
+/// imported data can be told apart from live data and bad import batches
+/// can be rolled back.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct EventProvenance {
+    /// Id of the API key the event was ingested with, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The client that submitted the event, where known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// When the event was written to the datastore (not the event timestamp)
+    pub ingested: DateTime<Utc>,
+    /// Import batch the event arrived in, set only for imported events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Event) -> bool {
+        self.timestamp == other.timestamp
+            && self.duration == other.duration
+            && self.data == other.data
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::zero(),
+            data: serde_json::Map::new(),
+        }
+    }
+}
+
+fn default_duration() -> Duration {
+    Duration::zero()
+}
+
+/// Deserializes an event id from either a JSON number or a decimal
+/// string, see the field documentation on [`Event::id`]
+fn deserialize_id<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IdRepr {
+        Number(i64),
+        String(String),
+    }
+
+    match Option::<IdRepr>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(IdRepr::Number(id)) => Ok(Some(id)),
+        Some(IdRepr::String(s)) => s
+            .parse::<i64>()
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("invalid event id: {s:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use super::Event;
+
+    fn json_map(key: &str, value: Value) -> Map<String, Value> {
+        let mut map = Map::new();
+        map.insert(key.to_string(), value);
+        map
+    }
+
+    #[test]
+    fn test_event() {
+        let e = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data: json_map("test", json!(1)),
+        };
+        debug!("event: {:?}", e);
+    }
+
+    #[test]
+    fn test_event_serialize() {
+        let e = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data: json_map("test", json!(1)),
+        };
+        let serialized = serde_json::to_string(&e).unwrap();
+        let deserialized: Event = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(e, deserialized);
+    }
+
+    #[test]
+    fn test_event_id_from_string() {
+        // Ids round-trip as strings without precision loss, even above
+        // JavaScript's 2^53 safe-integer range
+        let json = r#"{"id": "9007199254740993", "timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}"#;
+        let e: Event = serde_json::from_str(json).unwrap();
+        assert_eq!(e.id, Some(9007199254740993));
+
+        let json = r#"{"id": 1, "timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}"#;
+        let e: Event = serde_json::from_str(json).unwrap();
+        assert_eq!(e.id, Some(1));
+
+        let json = r#"{"id": "not-a-number", "timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}"#;
+        assert!(serde_json::from_str::<Event>(json).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use chrono::DateTime;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl KeyValue {
+    pub fn new<T: Into<String>>(key: T, value: T, timestamp: DateTime<Utc>) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: value.into(),
+            timestamp: Some(timestamp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyValue;
+    use chrono::Utc;
+
+    #[test]
+    fn test_keyvalue_serialization_roundtrip() {
+        let kv = KeyValue::new("key", "value", Utc::now());
+        let serialized = serde_json::to_string(&kv).unwrap();
+        let deserialized: KeyValue = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(kv, deserialized);
+    }
+}
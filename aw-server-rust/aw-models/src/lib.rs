@@ -0,0 +1,22 @@
+#[macro_use]
+extern crate log;
+
+mod bucket;
+mod duration;
+mod event;
+mod info;
+mod key_value;
+mod query;
+mod timeinterval;
+mod tryvec;
+
+pub use self::bucket::Bucket;
+pub use self::bucket::BucketMetadata;
+pub use self::bucket::BucketsExport;
+pub use self::event::Event;
+pub use self::event::EventProvenance;
+pub use self::info::Info;
+pub use self::key_value::KeyValue;
+pub use self::query::Query;
+pub use self::timeinterval::TimeInterval;
+pub use self::tryvec::TryVec;
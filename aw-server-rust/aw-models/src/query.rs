@@ -0,0 +1,12 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::TimeInterval;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Query {
+    #[serde(rename = "timeperiods")]
+    pub timeperiods: Vec<TimeInterval>,
+    /// The query statements, one statement per line.
+    pub query: Vec<String>,
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A time interval, encoded as two rfc3339 timestamps separated by a slash
+/// (as in ISO 8601), e.g. `2000-01-01T00:00:00Z/2000-01-02T00:00:00Z`.
+#[derive(Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct TimeInterval {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum TimeIntervalError {
+    ParseError(),
+}
+
+impl TimeInterval {
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> TimeInterval {
+        TimeInterval { start, end }
+    }
+
+    pub fn new_from_string(period: &str) -> Result<TimeInterval, TimeIntervalError> {
+        let splits: Vec<&str> = period.split('/').collect();
+        if splits.len() != 2 {
+            return Err(TimeIntervalError::ParseError());
+        }
+        let start = DateTime::parse_from_rfc3339(splits[0])
+            .map_err(|_| TimeIntervalError::ParseError())?
+            .with_timezone(&Utc);
+        let end = DateTime::parse_from_rfc3339(splits[1])
+            .map_err(|_| TimeIntervalError::ParseError())?
+            .with_timezone(&Utc);
+        Ok(TimeInterval { start, end })
+    }
+
+    pub fn start(&self) -> &DateTime<Utc> {
+        &self.start
+    }
+
+    pub fn end(&self) -> &DateTime<Utc> {
+        &self.end
+    }
+
+    /// Duration of the interval
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+impl fmt::Display for TimeInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.start.to_rfc3339(),
+            self.end.to_rfc3339()
+        )
+    }
+}
+
+impl Serialize for TimeInterval {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeInterval {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        TimeInterval::new_from_string(&s)
+            .map_err(|_| serde::de::Error::custom("Unable to parse TimeInterval"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TimeInterval;
+
+    #[test]
+    fn test_timeinterval_roundtrip() {
+        let s = "2000-01-01T00:00:00+00:00/2000-01-02T00:00:00+00:00";
+        let ti = TimeInterval::new_from_string(s).unwrap();
+        assert_eq!(ti.to_string(), s);
+        assert_eq!(ti.duration(), chrono::Duration::days(1));
+    }
+
+    #[test]
+    fn test_timeinterval_invalid() {
+        assert!(TimeInterval::new_from_string("2000-01-01T00:00:00+00:00").is_err());
+        assert!(TimeInterval::new_from_string("a/b").is_err());
+    }
+}
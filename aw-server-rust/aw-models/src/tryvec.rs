@@ -0,0 +1,148 @@
+use core::marker::PhantomData;
+use schemars::JsonSchema;
+use serde::de::{SeqAccess, Visitor};
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::Value;
+use std::fmt;
+
+/// A Vec wrapper which, when deserializing, skips (and logs) elements that
+/// fail to deserialize instead of failing the whole collection. Used for
+/// imports, where a single malformed event shouldn't discard the bucket.
+#[derive(Clone, Debug)]
+pub struct TryVec<T> {
+    inner: Vec<TryParse<T>>,
+}
+
+#[derive(Clone, Debug)]
+enum TryParse<T> {
+    Parsed(T),
+    Unparsed,
+}
+
+impl<T> TryVec<T> {
+    pub fn new(vec: Vec<T>) -> Self {
+        TryVec {
+            inner: vec.into_iter().map(TryParse::Parsed).collect(),
+        }
+    }
+
+    pub fn new_empty() -> Self {
+        TryVec { inner: Vec::new() }
+    }
+
+    pub fn take_inner(self) -> Vec<T> {
+        self.inner
+            .into_iter()
+            .filter_map(|item| match item {
+                TryParse::Parsed(value) => Some(value),
+                TryParse::Unparsed => None,
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl<T> Default for TryVec<T> {
+    fn default() -> Self {
+        TryVec::new_empty()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for TryParse<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer) {
+            Ok(value) => match T::deserialize(value) {
+                Ok(parsed) => Ok(TryParse::Parsed(parsed)),
+                Err(e) => {
+                    warn!("Failed to deserialize element, skipping: {e}");
+                    Ok(TryParse::Unparsed)
+                }
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for TryVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TryVecVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for TryVecVisitor<T> {
+            type Value = TryVec<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a sequence")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut inner = Vec::new();
+                while let Some(item) = seq.next_element::<TryParse<T>>()? {
+                    inner.push(item);
+                }
+                Ok(TryVec { inner })
+            }
+        }
+
+        deserializer.deserialize_seq(TryVecVisitor(PhantomData))
+    }
+}
+
+impl<T: Serialize> Serialize for TryVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for item in &self.inner {
+            if let TryParse::Parsed(value) = item {
+                seq.serialize_element(value)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for TryVec<T> {
+    fn schema_name() -> String {
+        format!("TryVec_{}", T::schema_name())
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        Vec::<T>::json_schema(gen)
+    }
+}
+
+impl<T: PartialEq> PartialEq for TryVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let a: Vec<&T> = self
+            .inner
+            .iter()
+            .filter_map(|i| match i {
+                TryParse::Parsed(v) => Some(v),
+                TryParse::Unparsed => None,
+            })
+            .collect();
+        let b: Vec<&T> = other
+            .inner
+            .iter()
+            .filter_map(|i| match i {
+                TryParse::Parsed(v) => Some(v),
+                TryParse::Unparsed => None,
+            })
+            .collect();
+        a == b
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TryVec;
+
+    #[test]
+    fn test_tryvec_skips_unparsable() {
+        let json = r#"[1, "not a number", 3]"#;
+        let vec: TryVec<i64> = serde_json::from_str(json).unwrap();
+        assert_eq!(vec.take_inner(), vec![1, 3]);
+    }
+}
@@ -0,0 +1,29 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    List(Vec<Expr>),
+    Dict(Vec<(String, Expr)>),
+    Var(String),
+    Function(String, Vec<Expr>),
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Assign(String, Expr),
+    Return(Expr),
+    Expr(Expr),
+}
+
+pub type Program = Vec<Statement>;
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+use crate::functions::QueryFn;
+use crate::QueryError;
+
+#[derive(Clone)]
+pub enum DataType {
+    None(),
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Event(Event),
+    List(Vec<DataType>),
+    Dict(HashMap<String, DataType>),
+    Function(String, QueryFn),
+}
+
+impl fmt::Debug for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataType::None() => write!(f, "None"),
+            DataType::Bool(b) => write!(f, "Bool({b})"),
+            DataType::Number(n) => write!(f, "Number({n})"),
+            DataType::String(s) => write!(f, "String({s})"),
+            DataType::Event(e) => write!(f, "Event({e:?})"),
+            DataType::List(l) => write!(f, "List({l:?})"),
+            DataType::Dict(d) => write!(f, "Dict({d:?})"),
+            DataType::Function(name, _) => write!(f, "Function({name})"),
+        }
+    }
+}
+
+impl Serialize for DataType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DataType::None() => serializer.serialize_none(),
+            DataType::Bool(b) => serializer.serialize_bool(*b),
+            DataType::Number(n) => serializer.serialize_f64(*n),
+            DataType::String(s) => serializer.serialize_str(s),
+            DataType::Event(e) => e.serialize(serializer),
+            DataType::List(l) => {
+                let mut seq = serializer.serialize_seq(Some(l.len()))?;
+                for item in l {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            DataType::Dict(d) => {
+                let mut map = serializer.serialize_map(Some(d.len()))?;
+                for (k, v) in d {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+            // Functions are not serializable, but this should never be
+            // triggered as queries cannot return functions
+            DataType::Function(name, _) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl DataType {
+    pub fn query_fn(&self) -> Result<QueryFn, QueryError> {
+        match self {
+            DataType::Function(_, f) => Ok(*f),
+            _ => Err(QueryError::InvalidType(format!(
+                "Expected function, got {self:?}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&DataType> for Vec<Event> {
+    type Error = QueryError;
+    fn try_from(value: &DataType) -> Result<Self, Self::Error> {
+        let list = match value {
+            DataType::List(list) => list,
+            _ => {
+                return Err(QueryError::InvalidType(format!(
+                    "Expected list of events, got {value:?}"
+                )))
+            }
+        };
+        let mut events = Vec::new();
+        for item in list {
+            match item {
+                DataType::Event(event) => events.push(event.clone()),
+                _ => {
+                    return Err(QueryError::InvalidType(format!(
+                        "Expected event in list, got {item:?}"
+                    )))
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl From<Vec<Event>> for DataType {
+    fn from(events: Vec<Event>) -> DataType {
+        DataType::List(events.into_iter().map(DataType::Event).collect())
+    }
+}
+
+impl TryFrom<&DataType> for Vec<String> {
+    type Error = QueryError;
+    fn try_from(value: &DataType) -> Result<Self, Self::Error> {
+        let list = match value {
+            DataType::List(list) => list,
+            _ => {
+                return Err(QueryError::InvalidType(format!(
+                    "Expected list of strings, got {value:?}"
+                )))
+            }
+        };
+        let mut strings = Vec::new();
+        for item in list {
+            match item {
+                DataType::String(s) => strings.push(s.clone()),
+                _ => {
+                    return Err(QueryError::InvalidType(format!(
+                        "Expected string in list, got {item:?}"
+                    )))
+                }
+            }
+        }
+        Ok(strings)
+    }
+}
+
+impl TryFrom<&DataType> for String {
+    type Error = QueryError;
+    fn try_from(value: &DataType) -> Result<Self, Self::Error> {
+        match value {
+            DataType::String(s) => Ok(s.clone()),
+            _ => Err(QueryError::InvalidType(format!(
+                "Expected string, got {value:?}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&DataType> for f64 {
+    type Error = QueryError;
+    fn try_from(value: &DataType) -> Result<Self, Self::Error> {
+        match value {
+            DataType::Number(n) => Ok(*n),
+            _ => Err(QueryError::InvalidType(format!(
+                "Expected number, got {value:?}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&DataType> for Value {
+    type Error = QueryError;
+    fn try_from(value: &DataType) -> Result<Self, Self::Error> {
+        serde_json::to_value(value).map_err(|err| {
+            QueryError::InvalidType(format!("Failed to convert to JSON value: {err}"))
+        })
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+
+use aw_datastore::Datastore;
+use aw_models::TimeInterval;
+
+use crate::DataType;
+use crate::QueryError;
+
+pub type QueryFn =
+    fn(args: Vec<DataType>, ti: &TimeInterval, ds: &Datastore) -> Result<DataType, QueryError>;
+
+macro_rules! fill_env_fn {
+    ($env:expr, $name:literal, $fun:expr) => {
+        $env.insert(
+            $name.to_string(),
+            DataType::Function($name.to_string(), $fun),
+        );
+    };
+}
+
+pub fn fill_env() -> HashMap<String, DataType> {
+    let mut env = HashMap::new();
+    fill_env_fn!(env, "query_bucket", qfunctions::query_bucket);
+    fill_env_fn!(env, "query_bucket_names", qfunctions::query_bucket_names);
+    fill_env_fn!(env, "find_bucket", qfunctions::find_bucket);
+    fill_env_fn!(env, "flood", qfunctions::flood);
+    fill_env_fn!(env, "infer_afk", qfunctions::infer_afk);
+    fill_env_fn!(env, "sort_by_timestamp", qfunctions::sort_by_timestamp);
+    fill_env_fn!(env, "sort_by_duration", qfunctions::sort_by_duration);
+    fill_env_fn!(env, "limit_events", qfunctions::limit_events);
+    fill_env_fn!(env, "sum_durations", qfunctions::sum_durations);
+    fill_env_fn!(env, "concat", qfunctions::concat);
+    fill_env_fn!(env, "merge_events_by_keys", qfunctions::merge_events_by_keys);
+    fill_env_fn!(env, "chunk_events_by_key", qfunctions::chunk_events_by_key);
+    fill_env_fn!(env, "filter_keyvals", qfunctions::filter_keyvals);
+    fill_env_fn!(env, "filter_keyvals_regex", qfunctions::filter_keyvals_regex);
+    fill_env_fn!(env, "exclude_keyvals", qfunctions::exclude_keyvals);
+    fill_env_fn!(
+        env,
+        "filter_period_intersect",
+        qfunctions::filter_period_intersect
+    );
+    fill_env_fn!(env, "period_union", qfunctions::period_union);
+    fill_env_fn!(env, "union_no_overlap", qfunctions::union_no_overlap);
+    fill_env_fn!(env, "split_url_events", qfunctions::split_url_events);
+    fill_env_fn!(env, "categorize", qfunctions::categorize);
+    fill_env_fn!(env, "tag", qfunctions::tag);
+    env
+}
+
+mod qfunctions {
+    use aw_datastore::Datastore;
+    use aw_models::Event;
+    use aw_models::TimeInterval;
+    use serde_json::value::Value;
+
+    use super::validate;
+    use crate::DataType;
+    use crate::QueryError;
+
+    pub fn query_bucket(
+        args: Vec<DataType>,
+        ti: &TimeInterval,
+        ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let bucket_id: String = (&args[0]).try_into()?;
+        let events = ds.get_events(&bucket_id, Some(*ti.start()), Some(*ti.end()), None)?;
+        Ok(events.into())
+    }
+
+    pub fn query_bucket_names(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 0)?;
+        let buckets = ds.get_buckets()?;
+        Ok(DataType::List(
+            buckets.keys().map(|id| DataType::String(id.clone())).collect(),
+        ))
+    }
+
+    pub fn find_bucket(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 1, 2)?;
+        let bucket_filter: String = (&args[0]).try_into()?;
+        let hostname_filter: Option<String> = match args.get(1) {
+            Some(arg) => Some(arg.try_into()?),
+            None => None,
+        };
+        let buckets = ds.get_buckets()?;
+        match aw_transform::find_bucket(&bucket_filter, &hostname_filter, buckets.values()) {
+            Some(bucket_id) => Ok(DataType::String(bucket_id)),
+            None => Err(QueryError::BucketQueryError(
+                aw_datastore::DatastoreError::NoSuchBucket(bucket_filter),
+            )),
+        }
+    }
+
+    pub fn flood(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        Ok(aw_transform::flood(events, chrono::Duration::seconds(5)).into())
+    }
+
+    /// infer_afk(window_events) or infer_afk(window_events, max_gap_seconds);
+    /// the gap threshold defaults to 180 seconds
+    pub fn infer_afk(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length_range(&args, 1, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let max_gap: f64 = match args.get(1) {
+            Some(arg) => arg.try_into()?,
+            None => 180.0,
+        };
+        Ok(aw_transform::infer_afk(events, chrono::Duration::seconds(max_gap as i64)).into())
+    }
+
+    pub fn sort_by_timestamp(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        Ok(aw_transform::sort_by_timestamp(events).into())
+    }
+
+    pub fn sort_by_duration(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        Ok(aw_transform::sort_by_duration(events).into())
+    }
+
+    pub fn limit_events(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let mut events: Vec<Event> = (&args[0]).try_into()?;
+        let limit: f64 = (&args[1]).try_into()?;
+        events.truncate(limit as usize);
+        Ok(events.into())
+    }
+
+    pub fn sum_durations(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let mut duration = chrono::Duration::zero();
+        for event in &events {
+            duration += event.duration;
+        }
+        Ok(DataType::Number(
+            duration.num_milliseconds() as f64 / 1000.0,
+        ))
+    }
+
+    pub fn concat(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        let mut events: Vec<Event> = Vec::new();
+        for arg in &args {
+            let mut arg_events: Vec<Event> = arg.try_into()?;
+            events.append(&mut arg_events);
+        }
+        Ok(events.into())
+    }
+
+    pub fn merge_events_by_keys(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let keys: Vec<String> = (&args[1]).try_into()?;
+        Ok(aw_transform::merge_events_by_keys(events, keys).into())
+    }
+
+    pub fn chunk_events_by_key(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let key: String = (&args[1]).try_into()?;
+        Ok(aw_transform::chunk_events_by_key(events, &key).into())
+    }
+
+    pub fn filter_keyvals(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let key: String = (&args[1]).try_into()?;
+        let vals: Vec<Value> = validate::arg_value_list(&args[2])?;
+        Ok(aw_transform::filter_keyvals(events, &key, &vals).into())
+    }
+
+    pub fn filter_keyvals_regex(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let key: String = (&args[1]).try_into()?;
+        let regex_str: String = (&args[2]).try_into()?;
+        let regex = fancy_regex::Regex::new(&regex_str)
+            .map_err(|err| QueryError::RegexCompileError(format!("{err}")))?;
+        Ok(aw_transform::filter_keyvals_regex(events, &key, &regex).into())
+    }
+
+    pub fn exclude_keyvals(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 3)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let key: String = (&args[1]).try_into()?;
+        let vals: Vec<Value> = validate::arg_value_list(&args[2])?;
+        Ok(aw_transform::exclude_keyvals(events, &key, &vals).into())
+    }
+
+    pub fn filter_period_intersect(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let filter_events: Vec<Event> = (&args[1]).try_into()?;
+        Ok(aw_transform::filter_period_intersect(&events, &filter_events).into())
+    }
+
+    pub fn period_union(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events1: Vec<Event> = (&args[0]).try_into()?;
+        let events2: Vec<Event> = (&args[1]).try_into()?;
+        Ok(aw_transform::period_union(&events1, &events2).into())
+    }
+
+    pub fn union_no_overlap(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events1: Vec<Event> = (&args[0]).try_into()?;
+        let events2: Vec<Event> = (&args[1]).try_into()?;
+        Ok(aw_transform::union_no_overlap(events1, events2).into())
+    }
+
+    pub fn split_url_events(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 1)?;
+        let mut events: Vec<Event> = (&args[0]).try_into()?;
+        for event in &mut events {
+            aw_transform::split_url_event(event);
+        }
+        Ok(events.into())
+    }
+
+    pub fn categorize(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let rules = validate::arg_category_rules(&args[1])?;
+        Ok(aw_transform::categorize(events, &rules).into())
+    }
+
+    pub fn tag(
+        args: Vec<DataType>,
+        _ti: &TimeInterval,
+        _ds: &Datastore,
+    ) -> Result<DataType, QueryError> {
+        validate::args_length(&args, 2)?;
+        let events: Vec<Event> = (&args[0]).try_into()?;
+        let rules = validate::arg_tag_rules(&args[1])?;
+        Ok(aw_transform::tag(events, &rules).into())
+    }
+}
+
+mod validate {
+    use serde_json::value::Value;
+
+    use aw_transform::Rule;
+
+    use crate::DataType;
+    use crate::QueryError;
+
+    pub fn args_length(args: &[DataType], len: usize) -> Result<(), QueryError> {
+        if args.len() != len {
+            return Err(QueryError::InvalidFunctionParameters(format!(
+                "Expected {} parameters, got {}",
+                len,
+                args.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn args_length_range(args: &[DataType], min: usize, max: usize) -> Result<(), QueryError> {
+        if args.len() < min || args.len() > max {
+            return Err(QueryError::InvalidFunctionParameters(format!(
+                "Expected between {} and {} parameters, got {}",
+                min,
+                max,
+                args.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn arg_value_list(arg: &DataType) -> Result<Vec<Value>, QueryError> {
+        let list = match arg {
+            DataType::List(list) => list,
+            _ => {
+                return Err(QueryError::InvalidFunctionParameters(format!(
+                    "Expected list of values, got {arg:?}"
+                )))
+            }
+        };
+        let mut values = Vec::new();
+        for item in list {
+            values.push(item.try_into()?);
+        }
+        Ok(values)
+    }
+
+    fn parse_rule(value: &DataType) -> Result<Rule, QueryError> {
+        let dict = match value {
+            DataType::Dict(dict) => dict,
+            _ => {
+                return Err(QueryError::InvalidFunctionParameters(format!(
+                    "Expected rule dict, got {value:?}"
+                )))
+            }
+        };
+        let rule_type: String = match dict.get("type") {
+            Some(value) => value.try_into()?,
+            None => {
+                return Err(QueryError::InvalidFunctionParameters(
+                    "Rule is missing 'type'".to_string(),
+                ))
+            }
+        };
+        match rule_type.as_str() {
+            "none" => Ok(Rule::None),
+            "regex" => {
+                let regex_str: String = match dict.get("regex") {
+                    Some(value) => value.try_into()?,
+                    None => {
+                        return Err(QueryError::InvalidFunctionParameters(
+                            "Regex rule is missing 'regex'".to_string(),
+                        ))
+                    }
+                };
+                let ignore_case = matches!(dict.get("ignore_case"), Some(DataType::Bool(true)));
+                let rule = aw_transform::Rule::Regex(
+                    aw_transform::RegexRule::new(&regex_str, ignore_case)
+                        .map_err(|err| QueryError::RegexCompileError(format!("{err}")))?,
+                );
+                Ok(rule)
+            }
+            _ => Err(QueryError::InvalidFunctionParameters(format!(
+                "Unknown rule type '{rule_type}'"
+            ))),
+        }
+    }
+
+    pub fn arg_category_rules(
+        arg: &DataType,
+    ) -> Result<Vec<(Vec<String>, Rule)>, QueryError> {
+        let list = match arg {
+            DataType::List(list) => list,
+            _ => {
+                return Err(QueryError::InvalidFunctionParameters(format!(
+                    "Expected list of rules, got {arg:?}"
+                )))
+            }
+        };
+        let mut rules = Vec::new();
+        for item in list {
+            let pair = match item {
+                DataType::List(pair) if pair.len() == 2 => pair,
+                _ => {
+                    return Err(QueryError::InvalidFunctionParameters(format!(
+                        "Expected [category, rule] pair, got {item:?}"
+                    )))
+                }
+            };
+            let category: Vec<String> = (&pair[0]).try_into()?;
+            let rule = parse_rule(&pair[1])?;
+            rules.push((category, rule));
+        }
+        Ok(rules)
+    }
+
+    pub fn arg_tag_rules(arg: &DataType) -> Result<Vec<(String, Rule)>, QueryError> {
+        let list = match arg {
+            DataType::List(list) => list,
+            _ => {
+                return Err(QueryError::InvalidFunctionParameters(format!(
+                    "Expected list of rules, got {arg:?}"
+                )))
+            }
+        };
+        let mut rules = Vec::new();
+        for item in list {
+            let pair = match item {
+                DataType::List(pair) if pair.len() == 2 => pair,
+                _ => {
+                    return Err(QueryError::InvalidFunctionParameters(format!(
+                        "Expected [tag, rule] pair, got {item:?}"
+                    )))
+                }
+            };
+            let tag: String = (&pair[0]).try_into()?;
+            let rule = parse_rule(&pair[1])?;
+            rules.push((tag, rule));
+        }
+        Ok(rules)
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use aw_datastore::Datastore;
+use aw_models::TimeInterval;
+
+use crate::ast::{BinOp, Expr, Program, Statement};
+use crate::functions;
+use crate::DataType;
+use crate::QueryError;
+use crate::QueryLimits;
+
+pub struct QueryEnv<'a> {
+    pub vars: HashMap<String, DataType>,
+    pub ti: &'a TimeInterval,
+    pub ds: &'a Datastore,
+    limits: &'a QueryLimits,
+    started: std::time::Instant,
+    events_processed: u64,
+}
+
+impl QueryEnv<'_> {
+    /// Account for a query function result and abort if any limit has been
+    /// exceeded. Checked after every function call, so even a single
+    /// expensive statement cannot run away.
+    fn check_limits(&mut self, result: &DataType) -> Result<(), QueryError> {
+        self.events_processed += count_events(result);
+        if let Some(max_events) = self.limits.max_events {
+            if self.events_processed > max_events {
+                return Err(QueryError::ResourceLimitExceeded(format!(
+                    "query processed more than {max_events} events"
+                )));
+            }
+        }
+        if let Some(max_memory_kib) = self.limits.max_memory_kib {
+            let bytes: u64 = self.vars.values().map(estimate_size).sum::<u64>()
+                + estimate_size(result);
+            if bytes > max_memory_kib * 1024 {
+                return Err(QueryError::ResourceLimitExceeded(format!(
+                    "query holds more than {max_memory_kib} KiB of data"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Abort if the caller has requested cancellation or the wall-clock
+    /// limit has been exceeded. Checked before every statement, so a
+    /// multi-statement query cannot outlive its caller by much.
+    fn check_deadline(&self) -> Result<(), QueryError> {
+        if let Some(cancelled) = &self.limits.cancelled {
+            if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err(QueryError::Cancelled());
+            }
+        }
+        if let Some(max_duration) = self.limits.max_duration {
+            if self.started.elapsed() > max_duration {
+                return Err(QueryError::ResourceLimitExceeded(format!(
+                    "query ran for longer than {max_duration:?}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of events contained in a query value
+fn count_events(value: &DataType) -> u64 {
+    match value {
+        DataType::Event(_) => 1,
+        DataType::List(list) => list.iter().map(count_events).sum(),
+        DataType::Dict(dict) => dict.values().map(count_events).sum(),
+        _ => 0,
+    }
+}
+
+/// Rough estimate of the memory held by a query value, in bytes
+fn estimate_size(value: &DataType) -> u64 {
+    match value {
+        DataType::None() => 8,
+        DataType::Bool(_) | DataType::Number(_) => 8,
+        DataType::String(s) => 24 + s.len() as u64,
+        DataType::Event(e) => {
+            64 + e.data.iter().map(|(k, v)| k.len() as u64 + estimate_value_size(v)).sum::<u64>()
+        }
+        DataType::List(list) => 24 + list.iter().map(estimate_size).sum::<u64>(),
+        DataType::Dict(dict) => {
+            24 + dict.iter().map(|(k, v)| k.len() as u64 + estimate_size(v)).sum::<u64>()
+        }
+        DataType::Function(name, _) => 32 + name.len() as u64,
+    }
+}
+
+fn estimate_value_size(value: &serde_json::Value) -> u64 {
+    match value {
+        serde_json::Value::Null | serde_json::Value::Bool(_) | serde_json::Value::Number(_) => 8,
+        serde_json::Value::String(s) => 24 + s.len() as u64,
+        serde_json::Value::Array(arr) => {
+            24 + arr.iter().map(estimate_value_size).sum::<u64>()
+        }
+        serde_json::Value::Object(obj) => {
+            24 + obj
+                .iter()
+                .map(|(k, v)| k.len() as u64 + estimate_value_size(v))
+                .sum::<u64>()
+        }
+    }
+}
+
+pub fn interpret_program(
+    program: &Program,
+    ti: &TimeInterval,
+    ds: &Datastore,
+    limits: &QueryLimits,
+) -> Result<DataType, QueryError> {
+    let mut env = QueryEnv {
+        vars: functions::fill_env(),
+        ti,
+        ds,
+        limits,
+        started: std::time::Instant::now(),
+        events_processed: 0,
+    };
+    let mut ret = None;
+    for statement in program {
+        env.check_deadline()?;
+        ret = interpret_statement(statement, &mut env)?;
+    }
+    match ret {
+        Some(ret) => Ok(ret),
+        None => Err(QueryError::EmptyQuery()),
+    }
+}
+
+fn interpret_statement(
+    statement: &Statement,
+    env: &mut QueryEnv,
+) -> Result<Option<DataType>, QueryError> {
+    match statement {
+        Statement::Assign(var, expr) => {
+            let value = interpret_expr(expr, env)?;
+            env.vars.insert(var.clone(), value);
+            Ok(None)
+        }
+        Statement::Return(expr) => Ok(Some(interpret_expr(expr, env)?)),
+        Statement::Expr(expr) => {
+            interpret_expr(expr, env)?;
+            Ok(None)
+        }
+    }
+}
+
+fn interpret_expr(expr: &Expr, env: &mut QueryEnv) -> Result<DataType, QueryError> {
+    match expr {
+        Expr::Number(n) => Ok(DataType::Number(*n)),
+        Expr::String(s) => Ok(DataType::String(s.clone())),
+        Expr::Bool(b) => Ok(DataType::Bool(*b)),
+        Expr::List(exprs) => {
+            let mut list = Vec::new();
+            for expr in exprs {
+                list.push(interpret_expr(expr, env)?);
+            }
+            Ok(DataType::List(list))
+        }
+        Expr::Dict(entries) => {
+            let mut dict = HashMap::new();
+            for (key, expr) in entries {
+                dict.insert(key.clone(), interpret_expr(expr, env)?);
+            }
+            Ok(DataType::Dict(dict))
+        }
+        Expr::Var(var) => match env.vars.get(var) {
+            Some(value) => Ok(value.clone()),
+            None => Err(QueryError::VariableNotDefined(var.clone())),
+        },
+        Expr::Function(name, arg_exprs) => {
+            let fun = match env.vars.get(name) {
+                Some(value) => value.query_fn()?,
+                None => return Err(QueryError::VariableNotDefined(name.clone())),
+            };
+            let mut args = Vec::new();
+            for expr in arg_exprs {
+                args.push(interpret_expr(expr, env)?);
+            }
+            env.check_deadline()?;
+            let result = fun(args, env.ti, env.ds)?;
+            env.check_limits(&result)?;
+            Ok(result)
+        }
+        Expr::BinOp(op, lhs, rhs) => {
+            let lhs = interpret_expr(lhs, env)?;
+            let rhs = interpret_expr(rhs, env)?;
+            interpret_binop(op, lhs, rhs)
+        }
+    }
+}
+
+fn interpret_binop(op: &BinOp, lhs: DataType, rhs: DataType) -> Result<DataType, QueryError> {
+    match (op, lhs, rhs) {
+        (BinOp::Add, DataType::Number(a), DataType::Number(b)) => Ok(DataType::Number(a + b)),
+        (BinOp::Add, DataType::String(a), DataType::String(b)) => {
+            Ok(DataType::String(a + &b))
+        }
+        (BinOp::Add, DataType::List(mut a), DataType::List(b)) => {
+            a.extend(b);
+            Ok(DataType::List(a))
+        }
+        (BinOp::Sub, DataType::Number(a), DataType::Number(b)) => Ok(DataType::Number(a - b)),
+        (BinOp::Mul, DataType::Number(a), DataType::Number(b)) => Ok(DataType::Number(a * b)),
+        (BinOp::Div, DataType::Number(a), DataType::Number(b)) => {
+            if b == 0.0 {
+                return Err(QueryError::MathError("Tried to divide by zero".to_string()));
+            }
+            Ok(DataType::Number(a / b))
+        }
+        (BinOp::Mod, DataType::Number(a), DataType::Number(b)) => {
+            if b == 0.0 {
+                return Err(QueryError::MathError(
+                    "Tried to modulo by zero".to_string(),
+                ));
+            }
+            Ok(DataType::Number(a % b))
+        }
+        (op, lhs, rhs) => Err(QueryError::InvalidType(format!(
+            "Cannot apply {op:?} to {lhs:?} and {rhs:?}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::Map;
+
+    use aw_datastore::Datastore;
+    use aw_models::{Bucket, BucketMetadata, Event, TimeInterval, TryVec};
+
+    use crate::{query_with_limits, QueryError, QueryLimits};
+
+    fn setup_datastore() -> (Datastore, TimeInterval) {
+        let ds = Datastore::new_in_memory(false);
+        let bucket = Bucket {
+            bid: None,
+            id: "testid".to_string(),
+            _type: "testtype".to_string(),
+            client: "testclient".to_string(),
+            hostname: "testhost".to_string(),
+            created: None,
+            last_updated: None,
+            event_count: None,
+            total_duration: None,
+            data: Map::new(),
+            events: TryVec::new_empty(),
+            metadata: BucketMetadata::default(),
+        };
+        ds.create_bucket(&bucket).unwrap();
+        let start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let events: Vec<Event> = (0..3)
+            .map(|i| Event {
+                id: None,
+                timestamp: start + Duration::seconds(i),
+                duration: Duration::seconds(1),
+                data: Map::new(),
+            })
+            .collect();
+        ds.insert_events("testid", &events).unwrap();
+        (ds, TimeInterval::new(start, start + Duration::hours(1)))
+    }
+
+    fn assert_limit_exceeded(result: Result<crate::DataType, QueryError>) {
+        match result {
+            Err(QueryError::ResourceLimitExceeded(_)) => (),
+            r => panic!("Expected ResourceLimitExceeded, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_max_events() {
+        let (ds, ti) = setup_datastore();
+        let code = "RETURN query_bucket(\"testid\");";
+
+        let limits = QueryLimits {
+            max_events: Some(3),
+            ..Default::default()
+        };
+        query_with_limits(code, &ti, &ds, &limits).unwrap();
+
+        let limits = QueryLimits {
+            max_events: Some(2),
+            ..Default::default()
+        };
+        assert_limit_exceeded(query_with_limits(code, &ti, &ds, &limits));
+    }
+
+    #[test]
+    fn test_max_duration() {
+        let (ds, ti) = setup_datastore();
+        let limits = QueryLimits {
+            max_duration: Some(std::time::Duration::ZERO),
+            ..Default::default()
+        };
+        assert_limit_exceeded(query_with_limits(
+            "RETURN query_bucket(\"testid\");",
+            &ti,
+            &ds,
+            &limits,
+        ));
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let (ds, ti) = setup_datastore();
+        let cancelled = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let limits = QueryLimits {
+            cancelled: Some(cancelled.clone()),
+            ..Default::default()
+        };
+        let code = "RETURN query_bucket(\"testid\");";
+        query_with_limits(code, &ti, &ds, &limits).unwrap();
+
+        cancelled.store(true, std::sync::atomic::Ordering::Relaxed);
+        match query_with_limits(code, &ti, &ds, &limits) {
+            Err(QueryError::Cancelled()) => (),
+            r => panic!("Expected Cancelled, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let (ds, ti) = setup_datastore();
+        let limits = QueryLimits {
+            max_memory_kib: Some(0),
+            ..Default::default()
+        };
+        assert_limit_exceeded(query_with_limits(
+            "RETURN query_bucket(\"testid\");",
+            &ti,
+            &ds,
+            &limits,
+        ));
+    }
+}
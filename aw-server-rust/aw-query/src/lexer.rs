@@ -0,0 +1,170 @@
+use crate::QueryError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    Return,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Colon,
+    Semi,
+    Assign,
+}
+
+pub fn tokenize(code: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = code.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\r' => {
+                chars.next();
+            }
+            '\n' | ';' => {
+                chars.next();
+                tokens.push(Token::Semi);
+            }
+            '#' => {
+                // Comment, skip until end of line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        tokens.push(Token::Semi);
+                        break;
+                    }
+                }
+            }
+            '+' => {
+                chars.next();
+                tokens.push(Token::Plus);
+            }
+            '-' => {
+                chars.next();
+                tokens.push(Token::Minus);
+            }
+            '*' => {
+                chars.next();
+                tokens.push(Token::Star);
+            }
+            '/' => {
+                chars.next();
+                tokens.push(Token::Slash);
+            }
+            '%' => {
+                chars.next();
+                tokens.push(Token::Percent);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '[' => {
+                chars.next();
+                tokens.push(Token::LBracket);
+            }
+            ']' => {
+                chars.next();
+                tokens.push(Token::RBracket);
+            }
+            '{' => {
+                chars.next();
+                tokens.push(Token::LBrace);
+            }
+            '}' => {
+                chars.next();
+                tokens.push(Token::RBrace);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            ':' => {
+                chars.next();
+                tokens.push(Token::Colon);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Assign);
+            }
+            '"' | '\'' => {
+                let quote = c;
+                chars.next();
+                let mut s = String::new();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    if c == quote {
+                        closed = true;
+                        break;
+                    }
+                    if c == '\\' {
+                        match chars.next() {
+                            Some(escaped) => s.push(escaped),
+                            None => break,
+                        }
+                    } else {
+                        s.push(c);
+                    }
+                }
+                if !closed {
+                    return Err(QueryError::ParsingError(
+                        "Unterminated string literal".to_string(),
+                    ));
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_ascii_digit() => {
+                let mut num = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || c == '.' {
+                        num.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let parsed = num.parse::<f64>().map_err(|_| {
+                    QueryError::ParsingError(format!("Invalid number literal '{num}'"))
+                })?;
+                tokens.push(Token::Number(parsed));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match ident.as_str() {
+                    "RETURN" => tokens.push(Token::Return),
+                    "True" | "true" => tokens.push(Token::Bool(true)),
+                    "False" | "false" => tokens.push(Token::Bool(false)),
+                    _ => tokens.push(Token::Ident(ident)),
+                }
+            }
+            c => {
+                return Err(QueryError::ParsingError(format!(
+                    "Unexpected character '{c}'"
+                )))
+            }
+        }
+    }
+    Ok(tokens)
+}
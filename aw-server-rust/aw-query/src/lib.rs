@@ -0,0 +1,197 @@
+use std::fmt;
+
+use aw_datastore::Datastore;
+use aw_datastore::DatastoreError;
+use aw_models::TimeInterval;
+
+mod ast;
+mod datatype;
+mod functions;
+mod interpret;
+mod lexer;
+mod parser;
+
+pub use crate::datatype::DataType;
+
+#[derive(Debug)]
+pub enum QueryError {
+    // Parser
+    ParsingError(String),
+    EmptyQuery(),
+
+    // Interpreter
+    VariableNotDefined(String),
+    MathError(String),
+    InvalidType(String),
+    InvalidFunctionParameters(String),
+    TimeIntervalError(String),
+    BucketQueryError(DatastoreError),
+    RegexCompileError(String),
+    ResourceLimitExceeded(String),
+    Cancelled(),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::ParsingError(msg) => write!(f, "Parsing failed: {msg}"),
+            QueryError::EmptyQuery() => write!(f, "Tried to run an empty query"),
+            QueryError::VariableNotDefined(var) => {
+                write!(f, "Variable '{var}' is not defined")
+            }
+            QueryError::MathError(msg) => write!(f, "Math error: {msg}"),
+            QueryError::InvalidType(msg) => write!(f, "Invalid type: {msg}"),
+            QueryError::InvalidFunctionParameters(msg) => {
+                write!(f, "Invalid function parameters: {msg}")
+            }
+            QueryError::TimeIntervalError(msg) => {
+                write!(f, "Invalid time interval: {msg}")
+            }
+            QueryError::BucketQueryError(err) => write!(f, "Bucket query error: {err}"),
+            QueryError::RegexCompileError(msg) => {
+                write!(f, "Failed to compile regex: {msg}")
+            }
+            QueryError::ResourceLimitExceeded(msg) => {
+                write!(f, "Resource limit exceeded: {msg}")
+            }
+            QueryError::Cancelled() => write!(f, "Query was cancelled"),
+        }
+    }
+}
+
+/// Resource limits guarding the server against runaway queries. The default
+/// is unlimited.
+#[derive(Clone, Default)]
+pub struct QueryLimits {
+    /// Max number of events that may pass through query functions
+    pub max_events: Option<u64>,
+    /// Max wall-clock execution time
+    pub max_duration: Option<std::time::Duration>,
+    /// Max estimated memory held by query variables, in KiB
+    pub max_memory_kib: Option<u64>,
+    /// Cooperative cancellation: when set to true by the caller, the query
+    /// aborts with `QueryError::Cancelled` at the next statement or
+    /// function call
+    pub cancelled: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
+}
+
+impl From<DatastoreError> for QueryError {
+    fn from(err: DatastoreError) -> QueryError {
+        QueryError::BucketQueryError(err)
+    }
+}
+
+/// Parses and executes a query within the specified time interval against the
+/// specified datastore.
+pub fn query(code: &str, ti: &TimeInterval, ds: &Datastore) -> Result<DataType, QueryError> {
+    query_with_limits(code, ti, ds, &QueryLimits::default())
+}
+
+/// Like [`query`], but aborts with `QueryError::ResourceLimitExceeded` when
+/// the query exceeds any of the given limits.
+pub fn query_with_limits(
+    code: &str,
+    ti: &TimeInterval,
+    ds: &Datastore,
+    limits: &QueryLimits,
+) -> Result<DataType, QueryError> {
+    let program = parser::parse(code)?;
+    interpret::interpret_program(&program, ti, ds, limits)
+}
+
+/// Result of statically checking a query without executing it, see
+/// [`validate`]
+pub struct QueryAnalysis {
+    /// Bucket ids referenced by string literals in `query_bucket` calls
+    pub bucket_ids: Vec<String>,
+    /// Problems that would make execution fail
+    pub errors: Vec<String>,
+    /// Suspicious constructs that would still execute
+    pub warnings: Vec<String>,
+}
+
+/// Parses and statically checks a query without executing it: syntax
+/// errors, unknown functions and variables used before assignment are
+/// reported as errors, and referenced bucket ids are collected so
+/// callers can check that they exist. Parse failures are reported
+/// through `errors` too, so callers get a uniform shape.
+pub fn validate(code: &str) -> QueryAnalysis {
+    let mut analysis = QueryAnalysis {
+        bucket_ids: Vec::new(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let program = match parser::parse(code) {
+        Ok(program) => program,
+        Err(err) => {
+            analysis.errors.push(err.to_string());
+            return analysis;
+        }
+    };
+    let mut defined: std::collections::HashSet<String> =
+        functions::fill_env().keys().cloned().collect();
+    let mut has_return = false;
+    for statement in &program {
+        match statement {
+            ast::Statement::Assign(var, expr) => {
+                check_expr(expr, &defined, &mut analysis);
+                defined.insert(var.clone());
+            }
+            ast::Statement::Return(expr) => {
+                has_return = true;
+                check_expr(expr, &defined, &mut analysis);
+            }
+            ast::Statement::Expr(expr) => check_expr(expr, &defined, &mut analysis),
+        }
+    }
+    if !has_return {
+        analysis
+            .warnings
+            .push("Query has no RETURN statement".to_string());
+    }
+    analysis
+}
+
+fn check_expr(
+    expr: &ast::Expr,
+    defined: &std::collections::HashSet<String>,
+    analysis: &mut QueryAnalysis,
+) {
+    match expr {
+        ast::Expr::Function(name, args) => {
+            if !defined.contains(name) {
+                analysis.errors.push(format!("Unknown function '{name}'"));
+            }
+            if name == "query_bucket" {
+                if let Some(ast::Expr::String(bucket_id)) = args.first() {
+                    if !analysis.bucket_ids.contains(bucket_id) {
+                        analysis.bucket_ids.push(bucket_id.clone());
+                    }
+                }
+            }
+            for arg in args {
+                check_expr(arg, defined, analysis);
+            }
+        }
+        ast::Expr::Var(name) if !defined.contains(name) => {
+            analysis
+                .errors
+                .push(format!("Variable '{name}' is not defined"));
+        }
+        ast::Expr::List(items) => {
+            for item in items {
+                check_expr(item, defined, analysis);
+            }
+        }
+        ast::Expr::Dict(entries) => {
+            for (_, entry) in entries {
+                check_expr(entry, defined, analysis);
+            }
+        }
+        ast::Expr::BinOp(_, lhs, rhs) => {
+            check_expr(lhs, defined, analysis);
+            check_expr(rhs, defined, analysis);
+        }
+        _ => (),
+    }
+}
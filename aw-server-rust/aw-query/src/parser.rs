@@ -0,0 +1,245 @@
+use crate::ast::{BinOp, Expr, Program, Statement};
+use crate::lexer::{tokenize, Token};
+use crate::QueryError;
+
+pub fn parse(code: &str) -> Result<Program, QueryError> {
+    let tokens = tokenize(code)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    parser.parse_program()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), QueryError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(QueryError::ParsingError(format!(
+                "Expected {expected:?}, got {token:?}"
+            ))),
+            None => Err(QueryError::ParsingError(format!(
+                "Expected {expected:?}, got end of query"
+            ))),
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<Program, QueryError> {
+        let mut statements = Vec::new();
+        while self.peek().is_some() {
+            // Skip empty statements
+            if self.peek() == Some(&Token::Semi) {
+                self.next();
+                continue;
+            }
+            statements.push(self.parse_statement()?);
+            match self.next() {
+                Some(Token::Semi) | None => (),
+                Some(token) => {
+                    return Err(QueryError::ParsingError(format!(
+                        "Expected end of statement, got {token:?}"
+                    )))
+                }
+            }
+        }
+        if statements.is_empty() {
+            return Err(QueryError::EmptyQuery());
+        }
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, QueryError> {
+        match self.peek() {
+            Some(Token::Return) => {
+                self.next();
+                Ok(Statement::Return(self.parse_expr()?))
+            }
+            Some(Token::Ident(_)) if self.tokens.get(self.pos + 1) == Some(&Token::Assign) => {
+                let name = match self.next() {
+                    Some(Token::Ident(name)) => name,
+                    _ => unreachable!(),
+                };
+                self.next(); // consume '='
+                Ok(Statement::Assign(name, self.parse_expr()?))
+            }
+            _ => Ok(Statement::Expr(self.parse_expr()?)),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, QueryError> {
+        let mut lhs = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinOp::Add,
+                Some(Token::Minus) => BinOp::Sub,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.parse_term()?;
+            lhs = Expr::BinOp(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, QueryError> {
+        let mut lhs = self.parse_primary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinOp::Mul,
+                Some(Token::Slash) => BinOp::Div,
+                Some(Token::Percent) => BinOp::Mod,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.parse_primary()?;
+            lhs = Expr::BinOp(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Str(s)) => Ok(Expr::String(s)),
+            Some(Token::Bool(b)) => Ok(Expr::Bool(b)),
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.next();
+                    let args = self.parse_args()?;
+                    Ok(Expr::Function(name, args))
+                } else {
+                    Ok(Expr::Var(name))
+                }
+            }
+            Some(Token::LParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::LBracket) => {
+                let mut items = Vec::new();
+                if self.peek() == Some(&Token::RBracket) {
+                    self.next();
+                    return Ok(Expr::List(items));
+                }
+                loop {
+                    items.push(self.parse_expr()?);
+                    match self.next() {
+                        Some(Token::Comma) => continue,
+                        Some(Token::RBracket) => break,
+                        token => {
+                            return Err(QueryError::ParsingError(format!(
+                                "Expected ',' or ']' in list, got {token:?}"
+                            )))
+                        }
+                    }
+                }
+                Ok(Expr::List(items))
+            }
+            Some(Token::LBrace) => {
+                let mut entries = Vec::new();
+                if self.peek() == Some(&Token::RBrace) {
+                    self.next();
+                    return Ok(Expr::Dict(entries));
+                }
+                loop {
+                    let key = match self.next() {
+                        Some(Token::Str(key)) => key,
+                        token => {
+                            return Err(QueryError::ParsingError(format!(
+                                "Expected string key in dict, got {token:?}"
+                            )))
+                        }
+                    };
+                    self.expect(Token::Colon)?;
+                    entries.push((key, self.parse_expr()?));
+                    match self.next() {
+                        Some(Token::Comma) => continue,
+                        Some(Token::RBrace) => break,
+                        token => {
+                            return Err(QueryError::ParsingError(format!(
+                                "Expected ',' or '}}' in dict, got {token:?}"
+                            )))
+                        }
+                    }
+                }
+                Ok(Expr::Dict(entries))
+            }
+            token => Err(QueryError::ParsingError(format!(
+                "Unexpected token {token:?}"
+            ))),
+        }
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Expr>, QueryError> {
+        let mut args = Vec::new();
+        if self.peek() == Some(&Token::RParen) {
+            self.next();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_expr()?);
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::RParen) => break,
+                token => {
+                    return Err(QueryError::ParsingError(format!(
+                        "Expected ',' or ')' in function call, got {token:?}"
+                    )))
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::ast::{Expr, Statement};
+    use crate::QueryError;
+
+    #[test]
+    fn test_parse_return() {
+        let program = parse("RETURN 1;").unwrap();
+        assert_eq!(program, vec![Statement::Return(Expr::Number(1.0))]);
+    }
+
+    #[test]
+    fn test_parse_assign() {
+        let program = parse("a = \"str\";\nRETURN a;").unwrap();
+        assert_eq!(program.len(), 2);
+        assert_eq!(
+            program[0],
+            Statement::Assign("a".to_string(), Expr::String("str".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_function_call() {
+        let program = parse("RETURN f(1, [2], {\"k\": 3});").unwrap();
+        assert_eq!(program.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        match parse("") {
+            Err(QueryError::EmptyQuery()) => (),
+            r => panic!("Expected EmptyQuery, got {r:?}"),
+        }
+    }
+}
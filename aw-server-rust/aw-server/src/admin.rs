@@ -0,0 +1,168 @@
+//! One-off admin tasks run as CLI subcommands against the database
+//! directly, so headless deployments and container entrypoints don't
+//! need to curl a live server: full export/import, API key management,
+//! and running schema migrations (which happen on open) explicitly.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde_json::json;
+use uuid::Uuid;
+
+use aw_datastore::Datastore;
+use aw_models::BucketsExport;
+use aw_models::TryVec;
+
+use crate::endpoints::apikey::{key_hash, ApiKeyInfo, Scope, APIKEY_PREFIX};
+
+/// Opening the database already ran any pending schema migrations; this
+/// just verifies it answers, so orchestrators can run migrations as a
+/// separate step before starting the server proper
+pub fn migrate(datastore: &Datastore) -> Result<(), String> {
+    datastore
+        .get_buckets()
+        .map_err(|err| format!("Database did not answer after migration: {err}"))?;
+    println!("Database schema is up to date");
+    Ok(())
+}
+
+/// Writes all buckets with their events to a JSON file, in the same
+/// format as the export endpoint
+pub fn export(datastore: &Datastore, path: &Path) -> Result<(), String> {
+    let mut buckets = datastore
+        .get_buckets()
+        .map_err(|err| format!("Failed to list buckets: {err}"))?;
+    for (id, bucket) in buckets.iter_mut() {
+        let events = datastore
+            .get_events(id, None, None, None)
+            .map_err(|err| format!("Failed to fetch events for bucket '{id}': {err}"))?;
+        bucket.events = TryVec::new(events);
+    }
+    let export = BucketsExport { buckets };
+    let data = serde_json::to_string(&export).unwrap();
+    std::fs::write(path, data).map_err(|err| format!("Failed to write {path:?}: {err}"))?;
+    println!("Exported {} buckets to {path:?}", export.buckets.len());
+    Ok(())
+}
+
+/// Imports buckets and events from a JSON export file. The import is
+/// transactional: on any error the database is left untouched.
+pub fn import(datastore: &Datastore, path: &Path) -> Result<(), String> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {path:?}: {err}"))?;
+    let export: BucketsExport = serde_json::from_str(&data)
+        .map_err(|err| format!("Failed to parse {path:?} as an export: {err}"))?;
+    let mut import_data = HashMap::new();
+    for (id, mut bucket) in export.buckets {
+        let events = bucket.events.take_inner();
+        bucket.events = TryVec::new_empty();
+        import_data.insert(id, (bucket, events));
+    }
+    let count = import_data.len();
+    let batch = datastore
+        .import(import_data)
+        .map_err(|err| format!("Import failed: {err}"))?;
+    println!("Imported {count} buckets from {path:?} as batch {batch}");
+    Ok(())
+}
+
+fn parse_scope(scope: &str) -> Result<Scope, String> {
+    match scope {
+        "read" => Ok(Scope::Read),
+        "write" => Ok(Scope::Write),
+        "admin" => Ok(Scope::Admin),
+        other => Err(format!(
+            "Unknown scope '{other}' (expected read, write or admin)"
+        )),
+    }
+}
+
+/// Creates an API key, printing the same JSON as the create endpoint.
+/// The plaintext key appears only in this output; only its hash is
+/// stored.
+pub fn apikey_create(
+    datastore: &Datastore,
+    name: &str,
+    scopes: &[String],
+    buckets: Vec<String>,
+    max_events_per_day: Option<u64>,
+    max_total_events: Option<u64>,
+    hostname: Option<String>,
+) -> Result<(), String> {
+    let scopes = scopes
+        .iter()
+        .map(|scope| parse_scope(scope))
+        .collect::<Result<Vec<Scope>, String>>()?;
+    if scopes.is_empty() {
+        return Err("An API key needs at least one scope".to_string());
+    }
+    let key = format!("aw_{}", Uuid::new_v4().simple());
+    let hash = key_hash(&key);
+    let info = ApiKeyInfo {
+        name: name.to_string(),
+        scopes,
+        buckets,
+        max_events_per_day,
+        max_total_events,
+        hostname,
+    };
+    datastore
+        .insert_key_value(
+            &format!("{APIKEY_PREFIX}{hash}"),
+            &serde_json::to_string(&info).unwrap(),
+        )
+        .map_err(|err| format!("Failed to store key: {err}"))?;
+    datastore
+        .force_commit()
+        .map_err(|err| format!("Failed to commit: {err}"))?;
+    let out = json!({
+        "key": key,
+        "id": hash,
+        "name": info.name,
+        "scopes": info.scopes,
+        "buckets": info.buckets,
+        "max_events_per_day": info.max_events_per_day,
+        "max_total_events": info.max_total_events,
+        "hostname": info.hostname,
+    });
+    println!("{}", serde_json::to_string_pretty(&out).unwrap());
+    Ok(())
+}
+
+/// Lists the configured keys by id (hash) with their permissions
+pub fn apikey_list(datastore: &Datastore) -> Result<(), String> {
+    let keys = datastore
+        .get_keys_starting(&format!("{APIKEY_PREFIX}%"))
+        .map_err(|err| format!("Failed to list keys: {err}"))?;
+    let mut list = Vec::new();
+    for key in keys {
+        if let Ok(kv) = datastore.get_key_value(&key) {
+            if let Ok(info) = serde_json::from_str::<ApiKeyInfo>(&kv.value) {
+                list.push(json!({
+                    "id": key.strip_prefix(APIKEY_PREFIX).unwrap_or(&key),
+                    "name": info.name,
+                    "scopes": info.scopes,
+                    "buckets": info.buckets,
+                }));
+            }
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&list).unwrap());
+    Ok(())
+}
+
+/// Revokes a key by id (the hash printed at creation and in the list)
+pub fn apikey_revoke(datastore: &Datastore, id: &str) -> Result<(), String> {
+    let key = format!("{APIKEY_PREFIX}{id}");
+    datastore
+        .get_key_value(&key)
+        .map_err(|err| format!("No such key: {err}"))?;
+    datastore
+        .delete_key_value(&key)
+        .map_err(|err| format!("Failed to revoke key: {err}"))?;
+    datastore
+        .force_commit()
+        .map_err(|err| format!("Failed to commit: {err}"))?;
+    println!("Revoked key {id}");
+    Ok(())
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::thread;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use aw_datastore::Datastore;
+use aw_models::TimeInterval;
+
+use crate::endpoints::query::SavedQuery;
+
+/// Alert rules are stored in the key_value table, prefixed with `alert.`;
+/// evaluation state uses its own prefix so it doesn't show up as a rule.
+pub static ALERT_PREFIX: &str = "alert.";
+pub static STATE_PREFIX: &str = "alert_state.";
+
+/// How often the alerting thread evaluates the rules
+const CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// The condition an alert rule fires on
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// Fires when a saved query, run over the trailing `period_hours` of
+    /// data, returns a number above the threshold (or below it, when
+    /// `below` is set). Useful for rules like "more than 3h in category
+    /// Social today".
+    QueryThreshold {
+        query: String,
+        threshold: f64,
+        #[serde(default = "default_period_hours")]
+        period_hours: f64,
+        #[serde(default)]
+        below: bool,
+    },
+    /// Fires when a bucket has received no events for the given number of
+    /// hours, e.g. to detect a watcher or host that stopped reporting
+    BucketInactive {
+        bucket_id: String,
+        inactive_hours: f64,
+    },
+}
+
+fn default_period_hours() -> f64 {
+    24.0
+}
+
+/// An alert rule. When the rule starts or stops firing, a JSON payload is
+/// POSTed to each webhook URL, and a human-readable message is sent through
+/// each named notification channel from the config file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AlertRule {
+    #[serde(flatten)]
+    pub condition: AlertCondition,
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+/// A push notification channel, defined under `[notification_channels.<name>]`
+/// in the config file and selected per-rule by name
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotificationChannel {
+    /// An ntfy server (<https://ntfy.sh> or self-hosted): messages are
+    /// POSTed to `<url>/<topic>`
+    Ntfy { url: String, topic: String },
+    /// A Gotify server: messages are POSTed to `<url>/message` with the
+    /// application token
+    Gotify { url: String, token: String },
+}
+
+/// Evaluation state of an alert rule, kept across checks so notifications
+/// are only sent on transitions
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AlertState {
+    pub firing: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_value: Option<f64>,
+}
+
+/// Spawns the alerting thread, which periodically evaluates all alert rules
+pub fn start(datastore: Datastore, channels: HashMap<String, NotificationChannel>) {
+    thread::spawn(move || loop {
+        check_alerts(&datastore, &channels, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+/// Evaluates every alert rule and sends notifications for rules that
+/// started or stopped firing. Public (with an injectable clock) so it can
+/// be tested and force-triggered over the API.
+pub fn check_alerts(
+    datastore: &Datastore,
+    channels: &HashMap<String, NotificationChannel>,
+    now: DateTime<Utc>,
+) {
+    let keys = match datastore.get_keys_starting(&format!("{ALERT_PREFIX}%")) {
+        Ok(keys) => keys,
+        Err(err) => {
+            warn!("Alerting failed to list rules: {err}");
+            return;
+        }
+    };
+    for key in keys {
+        let name = &key[ALERT_PREFIX.len()..];
+        let rule = match datastore
+            .get_key_value(&key)
+            .map_err(|err| err.to_string())
+            .and_then(|kv| {
+                serde_json::from_str::<AlertRule>(&kv.value).map_err(|err| err.to_string())
+            }) {
+            Ok(rule) => rule,
+            Err(err) => {
+                warn!("Alerting failed to parse rule '{name}': {err}");
+                continue;
+            }
+        };
+
+        let (firing, value) = match evaluate(datastore, &rule.condition, now) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("Alerting failed to evaluate rule '{name}': {err}");
+                continue;
+            }
+        };
+
+        let state_key = format!("{STATE_PREFIX}{name}");
+        let mut state: AlertState = datastore
+            .get_key_value(&state_key)
+            .ok()
+            .and_then(|kv| serde_json::from_str(&kv.value).ok())
+            .unwrap_or_default();
+
+        if firing != state.firing {
+            info!(
+                "Alert '{name}' {}",
+                if firing { "started firing" } else { "recovered" }
+            );
+            notify(&rule, channels, name, firing, value, now);
+            state.since = if firing { Some(now) } else { None };
+        }
+        state.firing = firing;
+        state.last_checked = Some(now);
+        state.last_value = value;
+
+        if let Err(err) =
+            datastore.insert_key_value(&state_key, &serde_json::to_string(&state).unwrap())
+        {
+            warn!("Alerting failed to save state for '{name}': {err}");
+        }
+    }
+}
+
+/// Evaluates an alert condition, returning whether it fires and the observed
+/// value (for threshold rules)
+fn evaluate(
+    datastore: &Datastore,
+    condition: &AlertCondition,
+    now: DateTime<Utc>,
+) -> Result<(bool, Option<f64>), String> {
+    match condition {
+        AlertCondition::QueryThreshold {
+            query,
+            threshold,
+            period_hours,
+            below,
+        } => {
+            let kv = datastore
+                .get_key_value(&format!("query.{query}"))
+                .map_err(|err| format!("failed to load saved query '{query}': {err}"))?;
+            let saved: SavedQuery = serde_json::from_str(&kv.value)
+                .map_err(|err| format!("failed to parse saved query '{query}': {err}"))?;
+            let code = saved.query.join("\n");
+            let period = Duration::milliseconds((period_hours * 3_600_000.0) as i64);
+            let interval = TimeInterval::new(now - period, now);
+            let result = aw_query::query(&code, &interval, datastore)
+                .map_err(|err| format!("query error: {err}"))?;
+            let value = match result {
+                aw_query::DataType::Number(n) => n,
+                other => {
+                    return Err(format!(
+                        "threshold rules need a number result, got {other:?}"
+                    ))
+                }
+            };
+            let firing = if *below {
+                value < *threshold
+            } else {
+                value > *threshold
+            };
+            Ok((firing, Some(value)))
+        }
+        AlertCondition::BucketInactive {
+            bucket_id,
+            inactive_hours,
+        } => {
+            let events = datastore
+                .get_events(bucket_id, None, None, Some(1))
+                .map_err(|err| format!("failed to get events for '{bucket_id}': {err}"))?;
+            let max_inactive = Duration::milliseconds((inactive_hours * 3_600_000.0) as i64);
+            let firing = match events.first() {
+                Some(event) => now - (event.timestamp + event.duration) > max_inactive,
+                // A bucket without any events counts as inactive
+                None => true,
+            };
+            Ok((firing, None))
+        }
+    }
+}
+
+/// Sends a notification to each webhook and channel of the rule. Delivery
+/// failures are logged but don't fail the check; the alert state is kept
+/// regardless.
+fn notify(
+    rule: &AlertRule,
+    channels: &HashMap<String, NotificationChannel>,
+    name: &str,
+    firing: bool,
+    value: Option<f64>,
+    now: DateTime<Utc>,
+) {
+    let payload = json!({
+        "alert": name,
+        "firing": firing,
+        "value": value,
+        "timestamp": now.to_rfc3339(),
+    });
+    for url in &rule.webhooks {
+        if let Err(err) = ureq::post(url)
+            .timeout(std::time::Duration::from_secs(10))
+            .send_json(&payload)
+        {
+            warn!("Failed to deliver alert '{name}' to {url}: {err}");
+        }
+    }
+
+    let message = match (firing, value) {
+        (true, Some(value)) => format!("Alert '{name}' started firing (value {value})"),
+        (true, None) => format!("Alert '{name}' started firing"),
+        (false, _) => format!("Alert '{name}' recovered"),
+    };
+    for channel_name in &rule.channels {
+        let Some(channel) = channels.get(channel_name) else {
+            warn!("Alert '{name}' references unknown channel '{channel_name}'");
+            continue;
+        };
+        if let Err(err) = send_to_channel(channel, &message) {
+            warn!("Failed to deliver alert '{name}' to channel '{channel_name}': {err}");
+        }
+    }
+}
+
+fn send_to_channel(channel: &NotificationChannel, message: &str) -> Result<(), Box<ureq::Error>> {
+    match channel {
+        NotificationChannel::Ntfy { url, topic } => {
+            ureq::post(&format!("{}/{topic}", url.trim_end_matches('/')))
+                .timeout(std::time::Duration::from_secs(10))
+                .set("Title", "ActivityWatch")
+                .send_string(message)?;
+        }
+        NotificationChannel::Gotify { url, token } => {
+            ureq::post(&format!("{}/message", url.trim_end_matches('/')))
+                .timeout(std::time::Duration::from_secs(10))
+                .set("X-Gotify-Key", token)
+                .send_json(json!({
+                    "title": "ActivityWatch",
+                    "message": message,
+                }))?;
+        }
+    }
+    Ok(())
+}
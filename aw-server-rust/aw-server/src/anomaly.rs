@@ -0,0 +1,210 @@
+//! Anomaly detection over daily activity: flags days that look unlike
+//! the rest of the trailing window, giving users a "something changed"
+//! feed without manual dashboard archaeology.
+//!
+//! Three signals are checked per full day, all computed from the active
+//! (non-AFK window) events the stats endpoints use:
+//!
+//! - total active time with a z-score far from the window's mean,
+//! - a dominant app that was never dominant on an earlier day,
+//! - substantial activity at odd hours (00:00-05:00 local) on a day when
+//!   the window otherwise has close to none.
+//!
+//! A background thread recomputes the feed periodically and stores it in
+//! the key_value table, so listing anomalies is a cheap read; the check
+//! endpoint forces a recompute.
+
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+
+use chrono::{DateTime, Duration, DurationRound, Timelike, Utc};
+use serde_json::{json, Value};
+
+use aw_datastore::Datastore;
+
+use crate::endpoints::stats::{active_events, get_timezone};
+
+/// Key-value store key the computed feed is cached under
+pub static STATE_KEY: &str = "anomaly_state.feed";
+
+/// How often the detection thread recomputes the feed
+const CHECK_INTERVAL_SECONDS: u64 = 3600;
+
+/// How many trailing days form the baseline
+const WINDOW_DAYS: i64 = 30;
+
+/// How far from the mean (in standard deviations) a day's total has to
+/// be before it is flagged
+const ZSCORE_THRESHOLD: f64 = 2.0;
+
+/// Local hours considered "odd"; activity here is flagged when the rest
+/// of the window has close to none
+const ODD_HOURS: (u32, u32) = (0, 5);
+
+/// Minimum odd-hours activity (and minimum dominant-app time) worth
+/// flagging, so a stray minute doesn't make the feed cry wolf
+const MIN_FLAGGED_SECONDS: f64 = 1800.0;
+
+/// Spawns the detection thread, which periodically recomputes the feed
+pub fn start(datastore: Datastore) {
+    thread::spawn(move || loop {
+        check(&datastore, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+/// Recomputes the feed and stores it. Public (with an injectable clock)
+/// so it can be tested and force-triggered over the API.
+pub fn check(datastore: &Datastore, now: DateTime<Utc>) {
+    let anomalies = detect(datastore, now);
+    let feed = json!({
+        "checked": now.to_rfc3339(),
+        "anomalies": anomalies,
+    });
+    if let Err(err) = datastore.insert_key_value(STATE_KEY, &feed.to_string()) {
+        warn!("Anomaly detection failed to store its feed: {err}");
+    }
+}
+
+/// Per-day activity stats the detectors work from
+#[derive(Default)]
+struct DayStats {
+    total_seconds: f64,
+    odd_seconds: f64,
+    app_seconds: HashMap<String, f64>,
+}
+
+/// Detects anomalies over the trailing window ending at `now`. The
+/// current (incomplete) day is excluded — half a day always looks
+/// anomalous. Missing window or AFK buckets mean no data to judge, so
+/// the feed is empty rather than an error.
+pub fn detect(datastore: &Datastore, now: DateTime<Utc>) -> Vec<Value> {
+    let tz = get_timezone(datastore);
+    let start = now - Duration::days(WINDOW_DAYS);
+    let active = match active_events(datastore, Some(start), Some(now)) {
+        Ok(events) => events,
+        Err(_) => return Vec::new(),
+    };
+    let today = now.with_timezone(&tz).format("%Y-%m-%d").to_string();
+
+    let mut days: BTreeMap<String, DayStats> = BTreeMap::new();
+    for event in &active {
+        let day = event.timestamp.with_timezone(&tz).format("%Y-%m-%d").to_string();
+        if day == today {
+            continue;
+        }
+        let seconds = event.duration.num_milliseconds() as f64 / 1000.0;
+        let stats = days.entry(day).or_default();
+        stats.total_seconds += seconds;
+        if let Some(Value::String(app)) = event.data.get("app") {
+            *stats.app_seconds.entry(app.clone()).or_insert(0.0) += seconds;
+        }
+        // Walk hour by hour like the heatmap, so events spanning the
+        // odd-hours boundary split correctly
+        let mut t = event.timestamp;
+        let event_end = event.calculate_endtime();
+        while t < event_end {
+            let next_hour = t.duration_trunc(Duration::hours(1)).unwrap() + Duration::hours(1);
+            let segment_end = std::cmp::min(event_end, next_hour);
+            let hour = t.with_timezone(&tz).hour();
+            if ODD_HOURS.0 <= hour && hour < ODD_HOURS.1 {
+                stats.odd_seconds += (segment_end - t).num_milliseconds() as f64 / 1000.0;
+            }
+            t = segment_end;
+        }
+    }
+
+    let dominant = |stats: &DayStats| -> Option<(String, f64)> {
+        stats
+            .app_seconds
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(app, seconds)| (app.clone(), *seconds))
+    };
+
+    let mut anomalies = Vec::new();
+    let day_keys: Vec<&String> = days.keys().collect();
+    for (index, day) in day_keys.iter().enumerate() {
+        let stats = &days[*day];
+        let others: Vec<f64> = day_keys
+            .iter()
+            .filter(|other| **other != *day)
+            .map(|other| days[*other].total_seconds)
+            .collect();
+
+        // Total time z-score needs a baseline to deviate from
+        if others.len() >= 3 {
+            let mean = others.iter().sum::<f64>() / others.len() as f64;
+            let variance =
+                others.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / others.len() as f64;
+            let std = variance.sqrt();
+            if std > 0.0 {
+                let zscore = (stats.total_seconds - mean) / std;
+                if zscore.abs() >= ZSCORE_THRESHOLD {
+                    anomalies.push(json!({
+                        "date": day,
+                        "kind": "total_time",
+                        "message": format!(
+                            "{:.1}h active, {:.1} standard deviations from the {:.1}h average",
+                            stats.total_seconds / 3600.0,
+                            zscore,
+                            mean / 3600.0,
+                        ),
+                        "value": zscore,
+                    }));
+                }
+            }
+        }
+
+        // A dominant app no earlier day in the window was dominated by
+        if index >= 3 {
+            if let Some((app, seconds)) = dominant(stats) {
+                let seen_before = day_keys[..index]
+                    .iter()
+                    .any(|earlier| match dominant(&days[*earlier]) {
+                        Some((earlier_app, _)) => earlier_app == app,
+                        None => false,
+                    });
+                if seconds >= MIN_FLAGGED_SECONDS && !seen_before {
+                    anomalies.push(json!({
+                        "date": day,
+                        "kind": "new_dominant_app",
+                        "message": format!(
+                            "'{}' became the most used app ({:.1}h), which it never was before",
+                            app,
+                            seconds / 3600.0,
+                        ),
+                        "value": seconds,
+                    }));
+                }
+            }
+        }
+
+        // Odd-hours activity on a day when the window has close to none
+        if others.len() >= 3 && stats.odd_seconds >= MIN_FLAGGED_SECONDS {
+            let others_odd: f64 = day_keys
+                .iter()
+                .filter(|other| **other != *day)
+                .map(|other| days[*other].odd_seconds)
+                .sum::<f64>()
+                / others.len() as f64;
+            if stats.odd_seconds > 4.0 * others_odd {
+                anomalies.push(json!({
+                    "date": day,
+                    "kind": "odd_hours",
+                    "message": format!(
+                        "{:.1}h of activity between {:02}:00 and {:02}:00, far above the usual",
+                        stats.odd_seconds / 3600.0,
+                        ODD_HOURS.0,
+                        ODD_HOURS.1,
+                    ),
+                    "value": stats.odd_seconds,
+                }));
+            }
+        }
+    }
+
+    // Newest first, like a feed should read
+    anomalies.reverse();
+    anomalies
+}
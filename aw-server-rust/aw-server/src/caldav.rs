@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::thread;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use aw_datastore::Datastore;
+use aw_models::Event;
+
+use crate::endpoints::stats::active_events;
+
+/// How often the trailing day is synced to the calendar
+const SYNC_INTERVAL_SECONDS: u64 = 900;
+
+/// Gaps shorter than this are bridged when merging events into blocks, so
+/// a quick glance at another window doesn't split a session in two
+const MERGE_GAP_SECONDS: i64 = 300;
+
+/// A CalDAV collection to write aggregated work blocks to, e.g.
+/// `[caldav] url = "https://dav.example.com/calendars/me/work/"
+/// username = "me" password = "..."`. Only blocks longer than
+/// `min_block_minutes` are written.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CalDavConfig {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default = "default_min_block_minutes")]
+    pub min_block_minutes: f64,
+}
+
+fn default_min_block_minutes() -> f64 {
+    30.0
+}
+
+/// A contiguous stretch of activity, labelled with its dominant app
+pub struct WorkBlock {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub app: String,
+}
+
+/// Spawns the sync thread, which writes work blocks from the trailing day
+/// to the CalDAV collection. Started only when `caldav` is configured.
+pub fn start(datastore: Datastore, config: CalDavConfig) {
+    thread::spawn(move || loop {
+        sync_calendar(&datastore, &config, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(SYNC_INTERVAL_SECONDS));
+    });
+}
+
+fn sync_calendar(datastore: &Datastore, config: &CalDavConfig, now: DateTime<Utc>) {
+    let start = now - Duration::hours(24);
+    let active = match active_events(datastore, Some(start), Some(now)) {
+        Ok(active) => active,
+        Err(err) => {
+            warn!("CalDAV sync failed to collect events: {err:?}");
+            return;
+        }
+    };
+    let blocks = merge_blocks(&active, config.min_block_minutes);
+    for block in blocks {
+        if let Err(err) = put_block(config, &block) {
+            warn!("CalDAV sync failed to write block at {}: {err}", block.start);
+        }
+    }
+}
+
+/// A block under construction: start, end and per-app milliseconds
+type PartialBlock = (DateTime<Utc>, DateTime<Utc>, HashMap<String, i64>);
+
+/// Merges active events into work blocks, bridging short gaps, and keeps
+/// blocks of at least `min_minutes`. Events are expected newest-first, as
+/// the datastore returns them.
+pub fn merge_blocks(events: &[Event], min_minutes: f64) -> Vec<WorkBlock> {
+    let mut blocks: Vec<PartialBlock> = Vec::new();
+    for event in events.iter().rev() {
+        let end = event.timestamp + event.duration;
+        let app = event
+            .data
+            .get("app")
+            .and_then(|value| value.as_str())
+            .unwrap_or("unknown")
+            .to_string();
+        match blocks.last_mut() {
+            Some(block)
+                if (event.timestamp - block.1).num_seconds() <= MERGE_GAP_SECONDS =>
+            {
+                if end > block.1 {
+                    block.1 = end;
+                }
+                *block.2.entry(app).or_insert(0) += event.duration.num_milliseconds();
+            }
+            _ => {
+                let mut per_app = HashMap::new();
+                per_app.insert(app, event.duration.num_milliseconds());
+                blocks.push((event.timestamp, end, per_app));
+            }
+        }
+    }
+    blocks
+        .into_iter()
+        .filter(|(start, end, _)| {
+            (*end - *start).num_milliseconds() as f64 >= min_minutes * 60.0 * 1000.0
+        })
+        .map(|(start, end, per_app)| {
+            let app = per_app
+                .into_iter()
+                .max_by_key(|(_, millis)| *millis)
+                .map(|(app, _)| app)
+                .unwrap_or_else(|| "unknown".to_string());
+            WorkBlock { start, end, app }
+        })
+        .collect()
+}
+
+/// PUTs the block as a single-event iCalendar resource. The UID is derived
+/// from the block start, so re-syncing an ongoing block updates the same
+/// calendar event instead of duplicating it.
+fn put_block(config: &CalDavConfig, block: &WorkBlock) -> Result<(), Box<ureq::Error>> {
+    let uid = format!("aw-{}@activitywatch", block.start.timestamp());
+    let url = format!("{}/{uid}.ics", config.url.trim_end_matches('/'));
+    let auth = format!(
+        "Basic {}",
+        base64_encode(format!("{}:{}", config.username, config.password).as_bytes())
+    );
+    let ics = format!(
+        "BEGIN:VCALENDAR\r\n\
+         VERSION:2.0\r\n\
+         PRODID:-//ActivityWatch//aw-server-rust//EN\r\n\
+         BEGIN:VEVENT\r\n\
+         UID:{uid}\r\n\
+         DTSTAMP:{}\r\n\
+         DTSTART:{}\r\n\
+         DTEND:{}\r\n\
+         SUMMARY:Work: {}\r\n\
+         END:VEVENT\r\n\
+         END:VCALENDAR\r\n",
+        format_utc(Utc::now()),
+        format_utc(block.start),
+        format_utc(block.end),
+        block.app,
+    );
+    ureq::put(&url)
+        .timeout(std::time::Duration::from_secs(10))
+        .set("Authorization", &auth)
+        .set("Content-Type", "text/calendar; charset=utf-8")
+        .send_string(&ics)?;
+    Ok(())
+}
+
+fn format_utc(datetime: DateTime<Utc>) -> String {
+    datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Standard base64, enough for the Authorization header
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        out.push(ALPHABET[(n >> 18 & 0x3f) as usize] as char);
+        out.push(ALPHABET[(n >> 12 & 0x3f) as usize] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(n >> 6 & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[(n & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn event(offset_minutes: i64, duration_minutes: i64, app: &str) -> Event {
+        Event {
+            id: None,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()
+                + Duration::minutes(offset_minutes),
+            duration: Duration::minutes(duration_minutes),
+            data: json!({ "app": app }).as_object().unwrap().clone(),
+        }
+    }
+
+    #[test]
+    fn test_merge_blocks() {
+        // Newest-first, as the datastore returns events
+        let events = vec![
+            event(120, 20, "editor"), // separate block, below the minimum
+            event(45, 10, "editor"),  // 4 min gap, bridged
+            event(21, 20, "browser"),
+            event(0, 20, "editor"),
+        ];
+        let blocks = merge_blocks(&events, 30.0);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].start, event(0, 0, "").timestamp);
+        assert_eq!(blocks[0].end, event(55, 0, "").timestamp);
+        assert_eq!(blocks[0].app, "editor");
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::alerts::NotificationChannel;
+use crate::caldav::CalDavConfig;
+use crate::dirs;
+
+/// Current config schema version, written to new config files. Files
+/// declaring a newer version are rejected instead of silently misread,
+/// so a config written by a newer server fails loudly on downgrade.
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct AWConfig {
+    /// Schema version of this file, see [`CONFIG_VERSION`]
+    pub config_version: u32,
+
+    pub address: String,
+    pub port: u16,
+
+    #[serde(skip, default = "default_testing")]
+    pub testing: bool, // This is not written to the config file (serde(skip))
+
+    #[serde(skip, default = "default_verbose")]
+    pub verbose: bool, // This is not written to the config file (serde(skip))
+
+    /// Extra allowed CORS origins besides the localhost webui ports,
+    /// for webuis served from another origin; `*` allows any origin
+    pub cors: Vec<String>,
+
+    /// Methods and headers advertised in CORS responses, and whether
+    /// credentialed requests are allowed. The defaults cover everything
+    /// the bundled webui and clients use (including API keys via
+    /// X-API-Key), so these only need changing for custom frontends.
+    pub cors_allow_methods: Vec<String>,
+    pub cors_allow_headers: Vec<String>,
+    pub cors_allow_credentials: bool,
+
+    /// Paths to a PEM certificate chain and its private key; when both
+    /// are set the server listens over HTTPS directly, so API keys are
+    /// never sent in the clear even without a reverse proxy in front.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+
+    /// An age recipient (e.g. "age1...") used to encrypt exports requested
+    /// via the encrypted export endpoint, so backups can be stored on
+    /// untrusted storage. Can be overridden per-request.
+    pub export_encryption_recipient: Option<String>,
+
+    /// Push notification channels for the alerting subsystem, e.g.
+    /// `[notification_channels.phone] type = "ntfy" url = "https://ntfy.sh"
+    /// topic = "mytopic"`. Alert rules select channels by name.
+    pub notification_channels: HashMap<String, NotificationChannel>,
+
+    /// A Prometheus remote-write endpoint (e.g.
+    /// "http://localhost:9090/api/v1/write") to push active-seconds gauges
+    /// to every minute. Unset disables the exporter.
+    pub prometheus_remote_write_url: Option<String>,
+
+    /// A CalDAV collection to write significant work blocks to, so time
+    /// spent shows up in the user's normal calendar. Unset disables the
+    /// sync.
+    pub caldav: Option<CalDavConfig>,
+
+    /// Resource limits for query execution, so one wild query can't take
+    /// down the server. Unset means unlimited.
+    pub query_max_events: Option<u64>,
+    pub query_max_duration_seconds: Option<f64>,
+    pub query_max_memory_kib: Option<u64>,
+
+    /// Size quota for the database file in MiB. When exceeded, the server
+    /// switches to a degraded read-only mode rejecting API writes with
+    /// 507 until space is freed, instead of letting SQLite hit a full
+    /// disk mid-transaction. Unset means no quota.
+    pub db_quota_mb: Option<u64>,
+
+    /// Maximum lengths (in characters) for string fields in event data,
+    /// per bucket type, e.g. `[field_limits."web.tab.current"] url = 2048`.
+    /// Longer values are trimmed at ingest with a `…` marker, bounding row
+    /// size when watchers send multi-kilobyte titles or URLs. Empty means
+    /// no trimming.
+    pub field_limits: HashMap<String, HashMap<String, usize>>,
+
+    /// Max events per minute accepted per bucket, keyed by bucket type
+    /// like `field_limits`, e.g. `[rate_limits] "web.tab.current" = 120`.
+    /// Events beyond the rate are dropped and counted in metrics, so a
+    /// buggy watcher emitting 50 events/sec degrades into sampling
+    /// instead of melting the database. Empty means no limits.
+    pub rate_limits: HashMap<String, u64>,
+
+    /// Heartbeat merge policies per bucket type, keyed like
+    /// `field_limits`, e.g. `[merge_policies."currentwindow"]
+    /// fuzzy_keys = ["title"]`. `ignore_keys` are left out of the data
+    /// comparison entirely; `fuzzy_keys` compare case-insensitively with
+    /// whitespace collapsed. Useful when trivially-changing fields (a
+    /// clock in a window title) would otherwise fragment events. Empty
+    /// means exact comparison everywhere.
+    pub merge_policies: HashMap<String, MergePolicyConfig>,
+}
+
+/// Config-file form of [`aw_transform::MergePolicy`]
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct MergePolicyConfig {
+    pub ignore_keys: Vec<String>,
+    pub fuzzy_keys: Vec<String>,
+}
+
+impl MergePolicyConfig {
+    pub fn to_policy(&self) -> aw_transform::MergePolicy {
+        aw_transform::MergePolicy {
+            ignore_keys: self.ignore_keys.clone(),
+            fuzzy_keys: self.fuzzy_keys.clone(),
+        }
+    }
+}
+
+impl Default for AWConfig {
+    fn default() -> AWConfig {
+        AWConfig {
+            config_version: CONFIG_VERSION,
+            address: Ipv4Addr::LOCALHOST.to_string(),
+            port: 5600,
+            testing: default_testing(),
+            verbose: default_verbose(),
+            cors: Vec::new(),
+            cors_allow_methods: ["POST", "GET", "PUT", "DELETE", "OPTIONS"]
+                .map(String::from)
+                .to_vec(),
+            cors_allow_headers: ["Content-Type", "X-API-Key", "Authorization", "X-Request-Id"]
+                .map(String::from)
+                .to_vec(),
+            cors_allow_credentials: true,
+            tls_cert: None,
+            tls_key: None,
+            export_encryption_recipient: None,
+            notification_channels: HashMap::new(),
+            prometheus_remote_write_url: None,
+            caldav: None,
+            query_max_events: None,
+            query_max_duration_seconds: None,
+            query_max_memory_kib: None,
+            db_quota_mb: None,
+            field_limits: HashMap::new(),
+            rate_limits: HashMap::new(),
+            merge_policies: HashMap::new(),
+        }
+    }
+}
+
+impl AWConfig {
+    pub fn to_rocket_config(&self) -> rocket::Config {
+        let mut config = rocket::Config::release_default();
+        if self.testing {
+            config.log_level = rocket::config::LogLevel::Debug;
+        }
+        config.address = self.address.parse().unwrap();
+        config.port = self.port;
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config.tls = Some(rocket::config::TlsConfig::from_paths(cert, key));
+        }
+        config
+    }
+
+    pub fn query_limits(&self) -> aw_query::QueryLimits {
+        aw_query::QueryLimits {
+            max_events: self.query_max_events,
+            max_duration: self
+                .query_max_duration_seconds
+                .map(std::time::Duration::from_secs_f64),
+            max_memory_kib: self.query_max_memory_kib,
+            cancelled: None,
+        }
+    }
+}
+
+fn default_testing() -> bool {
+    false
+}
+
+/// Applies environment overrides to the parsed config file: an
+/// `AW_SERVER_<KEY>` variable (e.g. `AW_SERVER_PORT=5601`) replaces the
+/// top-level config key of the same lowercased name. Values are parsed
+/// as TOML so numbers, booleans and arrays work; anything unparseable
+/// is taken as a plain string.
+fn apply_env_overrides(table: &mut toml::Table) {
+    for (name, value) in std::env::vars() {
+        let Some(key) = name.strip_prefix("AW_SERVER_") else {
+            continue;
+        };
+        let parsed = format!("v = {value}")
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut parsed| parsed.remove("v"))
+            .unwrap_or(toml::Value::String(value));
+        table.insert(key.to_lowercase(), parsed);
+    }
+}
+
+fn default_verbose() -> bool {
+    false
+}
+
+/// Loads the config file, creating a commented default on first run.
+/// Returns an error (instead of panicking) on an unparseable file, so
+/// the caller can exit with a config-error code that restart policies
+/// can tell apart from transient failures.
+pub fn create_config(testing: bool) -> Result<AWConfig, String> {
+    let mut config_path = dirs::get_config_dir().unwrap();
+    if !testing {
+        config_path.push("config.toml")
+    } else {
+        config_path.push("config-testing.toml")
+    }
+
+    /* If there is no config file, create a new config file with default values
+     * but every value commented out, this way we can easily add new config options
+     * without invalidating old config files */
+    if !config_path.exists() {
+        debug!("Writing default commented config at {config_path:?}");
+        let default_config = AWConfig::default();
+        let default_config_str =
+            toml::to_string(&default_config).expect("Failed to convert default config to string");
+        let mut default_config_str_commented = String::new();
+        default_config_str_commented.push_str("### DEFAULT SETTINGS ###\n");
+        for line in default_config_str.lines() {
+            default_config_str_commented.push_str(&format!("#{line}\n"));
+        }
+        File::create(&config_path)
+            .expect("Failed to create config file")
+            .write_all(default_config_str_commented.as_bytes())
+            .expect("Failed to write config to file");
+    }
+
+    debug!("Reading config at {config_path:?}");
+    let mut config_str = String::new();
+    File::open(&config_path)
+        .expect("Failed to open config file for reading")
+        .read_to_string(&mut config_str)
+        .expect("Failed to read config file");
+
+    let mut table: toml::Table = toml::from_str(&config_str)
+        .map_err(|err| format!("Failed to parse config file at {config_path:?}: {err}"))?;
+    apply_env_overrides(&mut table);
+    let mut config: AWConfig = table
+        .try_into()
+        .map_err(|err| format!("Failed to parse config file at {config_path:?}: {err}"))?;
+    if config.config_version > CONFIG_VERSION {
+        return Err(format!(
+            "Config file at {config_path:?} declares schema version {}, \
+             but this server only understands up to {CONFIG_VERSION}",
+            config.config_version
+        ));
+    }
+    config.testing = testing;
+    if testing {
+        config.port = 5666;
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,45 @@
+#![allow(clippy::result_unit_err)]
+
+use std::path::PathBuf;
+
+pub fn get_config_dir() -> Result<PathBuf, ()> {
+    let mut dir = dirs::config_dir().ok_or(())?;
+    dir.push("activitywatch");
+    dir.push("aw-server-rust");
+    std::fs::create_dir_all(dir.clone()).map_err(|_| ())?;
+    Ok(dir)
+}
+
+pub fn get_data_dir() -> Result<PathBuf, ()> {
+    let mut dir = dirs::data_local_dir().ok_or(())?;
+    dir.push("activitywatch");
+    dir.push("aw-server-rust");
+    std::fs::create_dir_all(dir.clone()).map_err(|_| ())?;
+    Ok(dir)
+}
+
+pub fn get_cache_dir() -> Result<PathBuf, ()> {
+    let mut dir = dirs::cache_dir().ok_or(())?;
+    dir.push("activitywatch");
+    dir.push("aw-server-rust");
+    std::fs::create_dir_all(dir.clone()).map_err(|_| ())?;
+    Ok(dir)
+}
+
+pub fn get_log_dir(module: &str) -> Result<PathBuf, ()> {
+    let mut dir = get_cache_dir()?;
+    dir.push("log");
+    dir.push(module);
+    std::fs::create_dir_all(dir.clone()).map_err(|_| ())?;
+    Ok(dir)
+}
+
+pub fn db_path(testing: bool) -> Result<PathBuf, ()> {
+    let mut db_path = get_data_dir()?;
+    if testing {
+        db_path.push("sqlite-testing.db");
+    } else {
+        db_path.push("sqlite.db");
+    }
+    Ok(db_path)
+}
@@ -0,0 +1,112 @@
+//! Degraded read-only mode when the database approaches a configured
+//! size quota. A checker thread measures the database file (including
+//! the WAL and shm sidecars) on an interval; once the quota is
+//! exceeded, a fairing rejects API writes with 507 Insufficient
+//! Storage instead of letting SQLite fail mid-transaction on a full
+//! disk. DELETE is still allowed so users can free space, and the mode
+//! clears on its own once the database shrinks below the quota again.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::{Data, Request};
+
+use crate::endpoints::util::HttpErrorJson;
+
+/// How often the database size is re-measured
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static DEGRADED: AtomicBool = AtomicBool::new(false);
+
+/// Last measured database size in bytes; 0 until the first check ran
+static DB_SIZE: AtomicU64 = AtomicU64::new(0);
+
+/// Whether writes are currently being rejected because the database
+/// exceeds its quota
+pub fn is_degraded() -> bool {
+    DEGRADED.load(Ordering::Relaxed)
+}
+
+/// The database size as of the last check, if one has run
+pub fn db_size_bytes() -> Option<u64> {
+    match DB_SIZE.load(Ordering::Relaxed) {
+        0 => None,
+        size => Some(size),
+    }
+}
+
+/// The database file plus the `-wal` and `-shm` sidecars SQLite keeps
+/// next to it; missing files count as zero
+fn measure(db_path: &str) -> u64 {
+    [String::new(), "-wal".to_string(), "-shm".to_string()]
+        .iter()
+        .filter_map(|suffix| std::fs::metadata(format!("{db_path}{suffix}")).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Spawns the checker thread. Only useful for file-backed datastores;
+/// callers skip this for ephemeral ones.
+pub fn start(db_path: String, quota_mb: u64) {
+    let quota_bytes = quota_mb * 1024 * 1024;
+    thread::spawn(move || loop {
+        let size = measure(&db_path);
+        DB_SIZE.store(size, Ordering::Relaxed);
+        let over = size > quota_bytes;
+        let was_over = DEGRADED.swap(over, Ordering::Relaxed);
+        if over && !was_over {
+            warn!(
+                "Database is {size} bytes, over the {quota_mb} MiB quota; \
+                 rejecting writes until space is freed"
+            );
+        } else if !over && was_over {
+            info!("Database is back under the {quota_mb} MiB quota, accepting writes again");
+        }
+        thread::sleep(CHECK_INTERVAL);
+    });
+}
+
+/// Mounted at `/` to have somewhere to reroute rejected writes to,
+/// like `hostcheck::badhost`
+#[get("/dbfull")]
+pub fn dbfull() -> HttpErrorJson {
+    HttpErrorJson::new(
+        Status::InsufficientStorage,
+        "Database size quota exceeded, free up space or raise db_quota_mb".to_string(),
+    )
+}
+
+/// Reroutes API writes to a 507 response while the database is over
+/// quota
+pub struct DiskGuard;
+
+#[rocket::async_trait]
+impl Fairing for DiskGuard {
+    fn info(&self) -> Info {
+        Info {
+            name: "DiskGuard",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if !is_degraded() {
+            return;
+        }
+        // DELETE is how users free space, so it stays allowed
+        if !matches!(
+            request.method(),
+            Method::Post | Method::Put | Method::Patch
+        ) {
+            return;
+        }
+        if !request.uri().path().starts_with("/api/") {
+            return;
+        }
+        request.set_uri(Origin::parse("/dbfull").unwrap());
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::alerts;
+use crate::alerts::{AlertCondition, AlertRule, AlertState};
+use crate::config::AWConfig;
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+fn parse_name(name: &str) -> Result<String, HttpErrorJson> {
+    let namespace = alerts::ALERT_PREFIX.to_string();
+    if name.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long alert name".to_string(),
+        ));
+    }
+    Ok(namespace + name)
+}
+
+#[get("/")]
+pub fn alerts_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{}%", alerts::ALERT_PREFIX))?;
+    let names = keys
+        .into_iter()
+        .map(|key| key[alerts::ALERT_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(names))
+}
+
+#[get("/<name>")]
+pub fn alert_get(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<AlertRule>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let rule: AlertRule = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse alert rule: {err}"),
+        )
+    })?;
+    Ok(Json(rule))
+}
+
+#[post("/<name>", data = "<message>", format = "application/json")]
+pub fn alert_set(
+    name: &str,
+    message: Json<AlertRule>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Status, HttpErrorJson> {
+    // Alert rules run queries against all buckets in the background, so
+    // defining them requires an unrestricted Write key
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let rule = message.into_inner();
+    let valid = match &rule.condition {
+        AlertCondition::QueryThreshold { period_hours, .. } => *period_hours > 0.0,
+        AlertCondition::BucketInactive { inactive_hours, .. } => *inactive_hours > 0.0,
+    };
+    if !valid {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Alert rule period must be positive".to_string(),
+        ));
+    }
+    for channel in &rule.channels {
+        if !config.notification_channels.contains_key(channel) {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Unknown notification channel '{channel}'"),
+            ));
+        }
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.insert_key_value(&key, &serde_json::to_string(&rule).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<name>")]
+pub fn alert_delete(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    datastore.delete_key_value(&key)?;
+    datastore.delete_key_value(&format!("{}{name}", alerts::STATE_PREFIX))?;
+    Ok(())
+}
+
+/// Returns the evaluation state of an alert rule. A rule that has never
+/// been evaluated yet reports the default (not firing) state.
+#[get("/<name>/state")]
+pub fn alert_state(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<AlertState>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    // 404 for rules that don't exist, default state for unevaluated ones
+    datastore.get_key_value(&format!("{}{name}", alerts::ALERT_PREFIX))?;
+    let alert_state: AlertState = datastore
+        .get_key_value(&format!("{}{name}", alerts::STATE_PREFIX))
+        .ok()
+        .and_then(|kv| serde_json::from_str(&kv.value).ok())
+        .unwrap_or_default();
+    Ok(Json(alert_state))
+}
+
+/// Evaluates all alert rules immediately, regardless of the check interval
+#[post("/check")]
+pub fn alerts_check(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    alerts::check_alerts(&datastore, &config.notification_channels, Utc::now());
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Endpoints for the anomaly feed: the cached result of the detection
+//! thread, plus a way to force a recompute without waiting for the
+//! interval.
+
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::{json, Value};
+
+use crate::anomaly;
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Returns the anomaly feed. Before the detection thread has run (or
+/// when it has nothing stored yet) the feed is computed on the fly.
+#[get("/")]
+pub fn anomalies_get(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    // The feed is derived from every window/AFK bucket (including
+    // per-day dominant app names), so bucket-restricted keys may not
+    // read it
+    auth.require_all_buckets(Scope::Read)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    if let Ok(kv) = datastore.get_key_value(anomaly::STATE_KEY) {
+        if let Ok(feed) = serde_json::from_str(&kv.value) {
+            return Ok(Json(feed));
+        }
+    }
+    let now = Utc::now();
+    Ok(Json(json!({
+        "checked": now.to_rfc3339(),
+        "anomalies": anomaly::detect(&datastore, now),
+    })))
+}
+
+/// Recomputes and stores the feed immediately, regardless of the
+/// detection interval
+#[post("/check")]
+pub fn anomalies_check(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    anomaly::check(&datastore, Utc::now());
+    Ok(())
+}
@@ -0,0 +1,461 @@
+//! API keys with scopes and per-bucket permissions.
+//!
+//! Keys are opt-in: as long as no key has been created the server behaves
+//! as before and every request is allowed, so existing local deployments
+//! are unaffected. Once at least one key exists, requests to protected
+//! endpoints must present a valid key via `X-API-Key` or
+//! `Authorization: Bearer`.
+//!
+//! Only the SHA-256 hash of a key is stored (under `apikey.<hash>` in the
+//! key-value store, like other `settings.`-style prefixes), together with
+//! its scopes and optional bucket-id patterns. The plaintext key is shown
+//! exactly once, in the create response.
+//!
+//! Key management itself is open only until the first admin-scoped key
+//! exists; after that, creating, listing and revoking keys requires
+//! admin. The `AW_ADMIN_APIKEY` environment variable names a bootstrap
+//! key that is always accepted with admin rights, for provisioning the
+//! first real key or recovering from a lockout.
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::Json;
+use rocket::{Request, State};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Key-value store prefix for API key entries, keyed by the hex SHA-256
+/// hash of the key
+pub const APIKEY_PREFIX: &str = "apikey.";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+/// What a stored API key is allowed to do. `buckets` holds bucket-id
+/// patterns (exact ids or a `*` suffix for prefix matches); empty means
+/// all buckets.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    /// Max events this key may ingest per (UTC) day; None is unlimited.
+    /// Useful on shared servers so one runaway watcher can't consume
+    /// all storage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_events_per_day: Option<u64>,
+    /// Max events stored across the buckets this key can write to;
+    /// None is unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_events: Option<u64>,
+    /// Canonical hostname stamped onto buckets created with this key,
+    /// overriding whatever the client sends. Keeps bucket identities
+    /// stable for watchers behind a gateway, where the client-side
+    /// hostname changes across reinstalls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+impl ApiKeyInfo {
+    fn has_scope(&self, scope: Scope) -> bool {
+        // Admin implies read and write
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    fn bucket_allowed(&self, bucket_id: &str) -> bool {
+        self.buckets.is_empty()
+            || self.buckets.iter().any(|pattern| {
+                match pattern.strip_suffix('*') {
+                    Some(prefix) => bucket_id.starts_with(prefix),
+                    None => pattern == bucket_id,
+                }
+            })
+    }
+}
+
+enum AuthState {
+    /// No API keys configured, everything is allowed
+    Disabled,
+    /// A valid key was presented; `id` is its stored hash, used to track
+    /// quota usage
+    Authorized { id: String, info: ApiKeyInfo },
+    /// Keys are configured but the request carried none, or an unknown one
+    Invalid,
+}
+
+/// Request guard resolving the presented API key. It always succeeds;
+/// handlers enforce permissions by calling [`ApiKeyAuth::require`], so
+/// rejections go through the usual JSON error responses.
+pub struct ApiKeyAuth(AuthState);
+
+impl ApiKeyAuth {
+    /// Checks that the request may perform `scope` on `bucket_id` (None
+    /// for endpoints not tied to one bucket). Returns 401 for missing or
+    /// unknown keys and 403 for valid keys lacking the scope or bucket.
+    pub fn require(&self, scope: Scope, bucket_id: Option<&str>) -> Result<(), HttpErrorJson> {
+        match &self.0 {
+            AuthState::Disabled => Ok(()),
+            AuthState::Invalid => Err(HttpErrorJson::new(
+                Status::Unauthorized,
+                "Missing or invalid API key".to_string(),
+            )),
+            AuthState::Authorized { info, .. } => {
+                if !info.has_scope(scope) {
+                    return Err(HttpErrorJson::new(
+                        Status::Forbidden,
+                        format!("API key '{}' lacks the {scope:?} scope", info.name),
+                    ));
+                }
+                if let Some(bucket_id) = bucket_id {
+                    if !info.bucket_allowed(bucket_id) {
+                        return Err(HttpErrorJson::new(
+                            Status::Forbidden,
+                            format!(
+                                "API key '{}' may not access bucket '{bucket_id}'",
+                                info.name
+                            ),
+                        ));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the caller may see `bucket_id` at all; used to filter
+    /// bucket listings and full exports down to the caller's buckets,
+    /// so a restricted key doesn't learn about data it can't read
+    pub fn bucket_visible(&self, bucket_id: &str) -> bool {
+        match &self.0 {
+            AuthState::Disabled => true,
+            AuthState::Invalid => false,
+            AuthState::Authorized { info, .. } => {
+                info.has_scope(Scope::Read) && info.bucket_allowed(bucket_id)
+            }
+        }
+    }
+
+    /// Id of the presented API key, None when authentication is disabled
+    /// or the request carried no valid key. Used to record ingest
+    /// provenance on inserted events.
+    pub fn key_id(&self) -> Option<&str> {
+        match &self.0 {
+            AuthState::Authorized { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Canonical hostname configured for the presented key, if any;
+    /// applied to buckets at creation
+    pub fn canonical_hostname(&self) -> Option<&str> {
+        match &self.0 {
+            AuthState::Authorized { info, .. } => info.hostname.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Like [`ApiKeyAuth::require`], but for endpoints that can touch
+    /// arbitrary buckets (query execution). The query engine reads
+    /// whatever buckets the query names, so per-bucket checks can't be
+    /// applied inside it; keys restricted to a subset of buckets are
+    /// rejected instead.
+    pub fn require_all_buckets(&self, scope: Scope) -> Result<(), HttpErrorJson> {
+        self.require(scope, None)?;
+        if let AuthState::Authorized { info, .. } = &self.0 {
+            if !info.buckets.is_empty() {
+                return Err(HttpErrorJson::new(
+                    Status::Forbidden,
+                    format!(
+                        "API key '{}' is restricted to specific buckets and may not use endpoints spanning all buckets",
+                        info.name
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key-value store prefix for per-key quota usage counters, keyed by
+/// the same id (hash) as the key itself
+pub const APIKEY_USAGE_PREFIX: &str = "apikeyusage.";
+
+/// Daily ingest counter for a key; the counter resets when `date`
+/// (UTC) rolls over
+#[derive(Serialize, Deserialize, Default)]
+struct KeyUsage {
+    date: String,
+    today: u64,
+}
+
+impl ApiKeyAuth {
+    /// Charges `count` ingested events against the presented key's
+    /// quotas, rejecting with 429 when one would be exceeded. The daily
+    /// quota is a counter per UTC day; the total quota counts events
+    /// actually stored in the buckets the key can write to, so merged
+    /// heartbeats don't eat into it. Keys without quotas (and requests
+    /// without keys) pass through untouched.
+    pub fn charge_quota(
+        &self,
+        datastore: &aw_datastore::Datastore,
+        count: u64,
+    ) -> Result<(), HttpErrorJson> {
+        let AuthState::Authorized { id, info } = &self.0 else {
+            return Ok(());
+        };
+        if let Some(max_total) = info.max_total_events {
+            let buckets = datastore.get_buckets().unwrap_or_default();
+            let stored: i64 = buckets
+                .keys()
+                .filter(|bucket_id| info.bucket_allowed(bucket_id))
+                .filter_map(|bucket_id| datastore.get_event_count(bucket_id, None, None).ok())
+                .sum();
+            if stored.max(0) as u64 + count > max_total {
+                return Err(HttpErrorJson::new(
+                    Status::TooManyRequests,
+                    format!(
+                        "API key '{}' exceeded its stored event quota ({max_total}); \
+                         delete old events or raise the quota",
+                        info.name
+                    ),
+                ));
+            }
+        }
+        if let Some(max_daily) = info.max_events_per_day {
+            let usage_key = format!("{APIKEY_USAGE_PREFIX}{id}");
+            let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+            let mut usage = datastore
+                .get_key_value(&usage_key)
+                .ok()
+                .and_then(|kv| serde_json::from_str::<KeyUsage>(&kv.value).ok())
+                .unwrap_or_default();
+            if usage.date != today {
+                usage = KeyUsage { date: today, today: 0 };
+            }
+            if usage.today + count > max_daily {
+                return Err(HttpErrorJson::new(
+                    Status::TooManyRequests,
+                    format!(
+                        "API key '{}' exceeded its daily event quota ({max_daily})",
+                        info.name
+                    ),
+                ));
+            }
+            usage.today += count;
+            let data = serde_json::to_string(&usage).unwrap();
+            datastore.insert_key_value(&usage_key, &data)?;
+        }
+        Ok(())
+    }
+}
+
+/// The hex SHA-256 hash under which a key is stored, shared with the
+/// CLI key management commands
+pub fn key_hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Whether any stored key carries the admin scope. Until one does, key
+/// management stays open so the system can be bootstrapped.
+fn admin_key_exists(datastore: &aw_datastore::Datastore) -> bool {
+    let keys = datastore
+        .get_keys_starting(&format!("{APIKEY_PREFIX}%"))
+        .unwrap_or_default();
+    keys.iter().any(|key| {
+        datastore
+            .get_key_value(key)
+            .ok()
+            .and_then(|kv| serde_json::from_str::<ApiKeyInfo>(&kv.value).ok())
+            .is_some_and(|info| info.scopes.contains(&Scope::Admin))
+    })
+}
+
+/// Admin check for the management endpoints: open while no admin key
+/// exists, admin-only afterwards
+fn require_management(
+    auth: &ApiKeyAuth,
+    datastore: &aw_datastore::Datastore,
+) -> Result<(), HttpErrorJson> {
+    if !admin_key_exists(datastore) {
+        return Ok(());
+    }
+    auth.require(Scope::Admin, None)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKeyAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let state = request
+            .rocket()
+            .state::<ServerState>()
+            .expect("ServerState not managed");
+        let datastore = match state.datastore.lock() {
+            Ok(datastore) => datastore,
+            Err(_) => return Outcome::Success(ApiKeyAuth(AuthState::Invalid)),
+        };
+        let keys = datastore
+            .get_keys_starting(&format!("{APIKEY_PREFIX}%"))
+            .unwrap_or_default();
+        if keys.is_empty() {
+            return Outcome::Success(ApiKeyAuth(AuthState::Disabled));
+        }
+        let presented = request.headers().get_one("X-API-Key").or_else(|| {
+            request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|auth| auth.strip_prefix("Bearer "))
+        });
+        // Bootstrap key from the environment, always admin
+        if let (Some(presented), Ok(bootstrap)) = (presented, std::env::var("AW_ADMIN_APIKEY")) {
+            if !bootstrap.is_empty() && presented == bootstrap {
+                return Outcome::Success(ApiKeyAuth(AuthState::Authorized {
+                    id: "bootstrap".to_string(),
+                    info: ApiKeyInfo {
+                        name: "bootstrap".to_string(),
+                        scopes: vec![Scope::Admin],
+                        buckets: Vec::new(),
+                        max_events_per_day: None,
+                        max_total_events: None,
+                        hostname: None,
+                    },
+                }));
+            }
+        }
+        let auth = match presented {
+            None => AuthState::Invalid,
+            Some(key) => {
+                let id = key_hash(key);
+                match datastore.get_key_value(&format!("{APIKEY_PREFIX}{id}")) {
+                    Ok(kv) => match serde_json::from_str::<ApiKeyInfo>(&kv.value) {
+                        Ok(info) => AuthState::Authorized { id, info },
+                        Err(_) => AuthState::Invalid,
+                    },
+                    Err(_) => AuthState::Invalid,
+                }
+            }
+        };
+        Outcome::Success(ApiKeyAuth(auth))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyNew {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    #[serde(default)]
+    pub max_events_per_day: Option<u64>,
+    #[serde(default)]
+    pub max_total_events: Option<u64>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+/// Creates a new API key. The response is the only place the plaintext
+/// key ever appears; only its hash is stored.
+#[post("/", data = "<message>", format = "application/json")]
+pub fn apikey_create(
+    message: Json<ApiKeyNew>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    let new = message.into_inner();
+    if new.scopes.is_empty() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "An API key needs at least one scope".to_string(),
+        ));
+    }
+    let key = format!("aw_{}", Uuid::new_v4().simple());
+    let hash = key_hash(&key);
+    let info = ApiKeyInfo {
+        name: new.name,
+        scopes: new.scopes,
+        buckets: new.buckets,
+        max_events_per_day: new.max_events_per_day,
+        max_total_events: new.max_total_events,
+        hostname: new.hostname,
+    };
+    let datastore = endpoints_get_lock!(state.datastore);
+    require_management(&auth, &datastore)?;
+    let data = serde_json::to_string(&info).unwrap();
+    match datastore.insert_key_value(&format!("{APIKEY_PREFIX}{hash}"), &data) {
+        Ok(()) => Ok(Json(json!({
+            "key": key,
+            "id": hash,
+            "name": info.name,
+            "scopes": info.scopes,
+            "buckets": info.buckets,
+            "max_events_per_day": info.max_events_per_day,
+            "max_total_events": info.max_total_events,
+            "hostname": info.hostname,
+        }))),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Lists the configured keys by id (hash) with their permissions; the
+/// keys themselves are not recoverable.
+#[get("/")]
+pub fn apikey_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    let datastore = endpoints_get_lock!(state.datastore);
+    require_management(&auth, &datastore)?;
+    let keys = datastore.get_keys_starting(&format!("{APIKEY_PREFIX}%"))?;
+    let mut list = Vec::new();
+    for key in keys {
+        if let Ok(kv) = datastore.get_key_value(&key) {
+            if let Ok(info) = serde_json::from_str::<ApiKeyInfo>(&kv.value) {
+                list.push(json!({
+                    "id": key.strip_prefix(APIKEY_PREFIX).unwrap_or(&key),
+                    "name": info.name,
+                    "scopes": info.scopes,
+                    "buckets": info.buckets,
+                    "max_events_per_day": info.max_events_per_day,
+                    "max_total_events": info.max_total_events,
+                    "hostname": info.hostname,
+                }));
+            }
+        }
+    }
+    Ok(Json(list))
+}
+
+/// Revokes a key by id (the hash returned at creation and in the list)
+#[delete("/<id>")]
+pub fn apikey_delete(
+    id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    let datastore = endpoints_get_lock!(state.datastore);
+    require_management(&auth, &datastore)?;
+    match datastore.get_key_value(&format!("{APIKEY_PREFIX}{id}")) {
+        Ok(_) => (),
+        Err(err) => return Err(err.into()),
+    }
+    match datastore.delete_key_value(&format!("{APIKEY_PREFIX}{id}")) {
+        Ok(()) => {
+            // Any quota counter for the key is stale once it's gone
+            let _ = datastore.delete_key_value(&format!("{APIKEY_USAGE_PREFIX}{id}"));
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rocket::http::{ContentType, Status};
+use rocket::response::stream::TextStream;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use aw_datastore::DatastoreError;
+use aw_models::Bucket;
+use aw_models::BucketsExport;
+use aw_models::Event;
+use aw_models::EventProvenance;
+use aw_models::TryVec;
+
+use crate::config::AWConfig;
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::{ClientInfo, HttpErrorJson};
+use crate::endpoints::ServerState;
+
+use crate::endpoints::util::parse_rfc3339_param;
+
+/// Trims string fields in event data to the per-bucket-type limits from
+/// the config, appending a `…` marker so trimmed values are recognizable.
+/// Limits count characters, so multi-byte text is never cut mid-codepoint.
+fn apply_field_limits(config: &AWConfig, bucket_type: &str, events: &mut [Event]) {
+    let limits = match config.field_limits.get(bucket_type) {
+        Some(limits) => limits,
+        None => return,
+    };
+    for event in events.iter_mut() {
+        for (field, max_len) in limits {
+            if let Some(serde_json::Value::String(value)) = event.data.get_mut(field) {
+                if value.chars().count() > *max_len {
+                    let mut trimmed: String = value.chars().take(*max_len).collect();
+                    trimmed.push('…');
+                    *value = trimmed;
+                }
+            }
+        }
+    }
+}
+
+/// Lists the buckets. `updated_since` (RFC 3339) filters to buckets
+/// written to since that time, for cheap change detection by sync and
+/// dashboard pollers; buckets without a last_updated stamp (created
+/// before it existed and not written to since) are always included,
+/// since they can't be proven unchanged. `metrics=true` fills in each
+/// bucket's event_count and total_duration from a single aggregate
+/// query, so the UI can show bucket sizes without a request per bucket.
+/// `include_last_events=N` fills in each bucket's `events` field with its
+/// N most recent events (capped at 100), saving previews a request per
+/// bucket. Archived buckets are hidden unless `include_archived=true`.
+#[get("/?<updated_since>&<metrics>&<include_archived>&<include_last_events>")]
+pub fn buckets_get(
+    updated_since: Option<&str>,
+    metrics: Option<bool>,
+    include_archived: Option<bool>,
+    include_last_events: Option<u64>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<HashMap<String, Bucket>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let updated_since = parse_rfc3339_param(updated_since, "updated_since")?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.get_buckets() {
+        Ok(mut bucketlist) => {
+            // A key restricted to certain buckets only sees those
+            bucketlist.retain(|id, _| auth.bucket_visible(id));
+            if include_archived != Some(true) {
+                bucketlist.retain(|_, bucket| !bucket.is_archived());
+            }
+            if let Some(since) = updated_since {
+                bucketlist
+                    .retain(|_, bucket| bucket.last_updated.is_none_or(|updated| updated >= since));
+            }
+            if metrics == Some(true) {
+                let rollups = datastore.get_bucket_rollups().map_err(HttpErrorJson::from)?;
+                for (id, bucket) in bucketlist.iter_mut() {
+                    if let Some((count, duration_ns)) = rollups.get(id) {
+                        bucket.event_count = Some(*count);
+                        bucket.total_duration = Some(*duration_ns as f64 / 1_000_000_000.0);
+                    }
+                }
+            }
+            if let Some(limit) = include_last_events {
+                let mut last_events = datastore
+                    .get_last_events(limit.min(100))
+                    .map_err(HttpErrorJson::from)?;
+                for (id, bucket) in bucketlist.iter_mut() {
+                    if let Some(events) = last_events.remove(id) {
+                        bucket.events = TryVec::new(events);
+                    }
+                }
+            }
+            Ok(Json(bucketlist))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Archives or unarchives a bucket. Archived buckets keep their data
+/// and stay queryable by id, but are hidden from default listings and
+/// skipped by canonical queries — for retired machines and old watcher
+/// versions that shouldn't clutter the UI.
+#[post("/<bucket_id>/archive?<archived>")]
+pub fn bucket_archive(
+    bucket_id: &str,
+    archived: bool,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let bucket = datastore.get_bucket(bucket_id).map_err(HttpErrorJson::from)?;
+    let mut data = bucket.data;
+    if archived {
+        data.insert("archived".to_string(), serde_json::Value::Bool(true));
+    } else {
+        data.remove("archived");
+    }
+    match datastore.set_bucket_data(bucket_id, data) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[get("/<bucket_id>")]
+pub fn bucket_get(
+    bucket_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Bucket>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.get_bucket(bucket_id) {
+        Ok(bucket) => Ok(Json(bucket)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[post("/<bucket_id>", data = "<message>", format = "application/json")]
+pub fn bucket_new(
+    bucket_id: &str,
+    message: Json<Bucket>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let mut bucket = message.into_inner();
+    if bucket.id != bucket_id {
+        bucket.id = bucket_id.to_string();
+    }
+    // Keys can carry a canonical hostname; it wins over the one the
+    // client sent, so bucket identities stay stable across reinstalls
+    // when watchers run behind a gateway
+    if let Some(hostname) = auth.canonical_hostname() {
+        bucket.hostname = hostname.to_string();
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    let ret = datastore.create_bucket(&bucket);
+    match ret {
+        Ok(_) => Ok(()),
+        // Watchers unconditionally POST their bucket at startup; the v0
+        // protocol answers an existing bucket with 304, which aw-client's
+        // raise_for_status tolerates (a 4xx would abort every stock
+        // watcher)
+        Err(DatastoreError::BucketAlreadyExists(_)) => Err(HttpErrorJson::new(
+            Status::NotModified,
+            format!("Bucket '{}' already exists", bucket.id),
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns a bucket's events. With `include_provenance=true` each event
+/// additionally carries a `provenance` key describing how it was
+/// ingested (API key, client, ingest time and import batch), so imported
+/// data can be told apart from live data. Events predating provenance
+/// tracking have no such key.
+///
+/// Remaining query parameters of the form `data.<field>=<value>` filter
+/// on exact matches of top-level data fields, applied server-side before
+/// the limit (e.g. `?data.app=Firefox&data.status=not-afk`). Values
+/// parsing as JSON numbers or booleans are matched as such, anything
+/// else as a string.
+///
+/// `as_of=<rfc3339>` returns the events as they were at that point:
+/// events deleted since then reappear and events ingested since then are
+/// hidden, so an accidental delete can be inspected and its events
+/// re-inserted without restoring a backup. Edits are not versioned.
+#[get("/<bucket_id>/events?<start>&<end>&<limit>&<include_provenance>&<as_of>&<filters..>")]
+#[allow(clippy::too_many_arguments)]
+pub fn bucket_events_get(
+    bucket_id: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    limit: Option<u64>,
+    include_provenance: Option<bool>,
+    as_of: Option<&str>,
+    filters: HashMap<String, HashMap<String, String>>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<serde_json::Value>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let starttime = parse_rfc3339_param(start, "starttime")?;
+    let endtime = parse_rfc3339_param(end, "endtime")?;
+    let as_of = parse_rfc3339_param(as_of, "as_of")?;
+    let mut data_filters: Vec<(String, serde_json::Value)> = Vec::new();
+    // Rocket parses `data.app=Firefox` as a nested form field, so the
+    // trailing parameters arrive as a map of maps keyed by prefix
+    for (group, fields) in filters {
+        if group != "data" {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Unknown query parameter '{group}' (expected data.<field>)"),
+            ));
+        }
+        for (field, value) in fields {
+            if field.is_empty() || field.contains('"') {
+                return Err(HttpErrorJson::new(
+                    Status::BadRequest,
+                    format!("Invalid data field name '{field}'"),
+                ));
+            }
+            let value = serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value));
+            data_filters.push((field, value));
+        }
+    }
+    if as_of.is_some() && !data_filters.is_empty() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "as_of cannot be combined with data field filters".to_string(),
+        ));
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    let events = if let Some(as_of) = as_of {
+        datastore.get_events_as_of(bucket_id, starttime, endtime, limit, as_of)
+    } else if data_filters.is_empty() {
+        datastore.get_events(bucket_id, starttime, endtime, limit)
+    } else {
+        datastore.get_events_filtered(bucket_id, starttime, endtime, limit, data_filters)
+    }
+    .map_err(HttpErrorJson::from)?;
+    if include_provenance != Some(true) {
+        return Ok(Json(serde_json::to_value(events).unwrap()));
+    }
+    let ids: Vec<i64> = events.iter().filter_map(|event| event.id).collect();
+    let provenance = datastore
+        .get_event_provenance(ids)
+        .map_err(HttpErrorJson::from)?;
+    let events: Vec<serde_json::Value> = events
+        .into_iter()
+        .map(|event| {
+            let entry = event.id.and_then(|id| provenance.get(&id));
+            let mut value = serde_json::to_value(&event).unwrap();
+            if let Some(entry) = entry {
+                value
+                    .as_object_mut()
+                    .unwrap()
+                    .insert("provenance".to_string(), serde_json::to_value(entry).unwrap());
+            }
+            value
+        })
+        .collect();
+    Ok(Json(serde_json::Value::Array(events)))
+}
+
+/// Streams events as newline-delimited JSON, newest first, fetched from
+/// the datastore page by page so downloading a year of window events
+/// doesn't buffer everything in memory. Unlike `/events`, events are not
+/// clipped to the query range — this is an export, not an aggregation.
+/// The datastore handle is cloned so the endpoint lock isn't held for
+/// the duration of the download.
+#[get("/<bucket_id>/events/stream?<start>&<end>")]
+pub fn bucket_events_stream(
+    bucket_id: String,
+    start: Option<&str>,
+    end: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(ContentType, TextStream![String]), HttpErrorJson> {
+    auth.require(Scope::Read, Some(&bucket_id))?;
+    let starttime = parse_rfc3339_param(start, "starttime")?;
+    let endtime = parse_rfc3339_param(end, "endtime")?;
+    let datastore = {
+        let datastore = endpoints_get_lock!(state.datastore);
+        // Fail before the stream starts, so a missing bucket gets a
+        // clean 404 instead of an aborted response body
+        datastore.get_bucket(&bucket_id).map_err(HttpErrorJson::from)?;
+        datastore.clone()
+    };
+    const PAGE_SIZE: u64 = 1000;
+    Ok((
+        ContentType::new("application", "x-ndjson"),
+        TextStream! {
+            let mut before: Option<(i64, i64)> = None;
+            loop {
+                let page = match datastore.get_events_page(
+                    &bucket_id, starttime, endtime, before, PAGE_SIZE,
+                ) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        // Mid-stream there is no way to change the
+                        // status anymore; truncating is all we can do
+                        warn!("Event stream for {bucket_id} aborted: {err}");
+                        break;
+                    }
+                };
+                let done = (page.len() as u64) < PAGE_SIZE;
+                before = page.last().map(|event| {
+                    (
+                        event.timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX),
+                        event.id.unwrap_or(i64::MAX),
+                    )
+                });
+                let mut chunk = String::new();
+                for event in &page {
+                    chunk.push_str(&serde_json::to_string(event).unwrap());
+                    chunk.push('\n');
+                }
+                yield chunk;
+                if done {
+                    break;
+                }
+            }
+        },
+    ))
+}
+
+/// Event count and total duration (in seconds) grouped by a top-level
+/// data field, computed in the datastore, so "time per app" over a long
+/// range doesn't require downloading the raw events. `group_by` must be
+/// `data.<field>`; durations are clipped to the query range and events
+/// without the field are excluded.
+#[get("/<bucket_id>/events/aggregate?<group_by>&<start>&<end>")]
+pub fn bucket_events_aggregate(
+    bucket_id: &str,
+    group_by: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<serde_json::Value>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let starttime = parse_rfc3339_param(start, "starttime")?;
+    let endtime = parse_rfc3339_param(end, "endtime")?;
+    let field = match group_by.strip_prefix("data.") {
+        Some(field) if !field.is_empty() && !field.contains('"') => field,
+        _ => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Invalid group_by '{group_by}' (expected data.<field>)"),
+            ))
+        }
+    };
+    let datastore = endpoints_get_lock!(state.datastore);
+    let aggregate = datastore
+        .get_events_aggregate(bucket_id, starttime, endtime, field)
+        .map_err(HttpErrorJson::from)?;
+    let mut result = serde_json::Map::new();
+    for (key, (count, duration_ns)) in aggregate {
+        result.insert(
+            key,
+            serde_json::json!({
+                "count": count,
+                "duration": duration_ns as f64 / 1_000_000_000.0,
+            }),
+        );
+    }
+    Ok(Json(serde_json::Value::Object(result)))
+}
+
+/// Distinct values of a top-level data field starting with `prefix`
+/// (case-insensitive), with how often each occurs in the range, most
+/// frequent first — autocomplete for rule editors and filters. `key`
+/// must be `data.<field>`; `limit` defaults to 10 and is capped at 100.
+#[get("/<bucket_id>/events/suggest?<key>&<prefix>&<start>&<end>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+pub fn bucket_events_suggest(
+    bucket_id: &str,
+    key: &str,
+    prefix: Option<&str>,
+    start: Option<&str>,
+    end: Option<&str>,
+    limit: Option<u64>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<serde_json::Value>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let starttime = parse_rfc3339_param(start, "starttime")?;
+    let endtime = parse_rfc3339_param(end, "endtime")?;
+    let field = match key.strip_prefix("data.") {
+        Some(field) if !field.is_empty() && !field.contains('"') => field,
+        _ => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Invalid key '{key}' (expected data.<field>)"),
+            ))
+        }
+    };
+    let limit = limit.unwrap_or(10).min(100);
+    let datastore = endpoints_get_lock!(state.datastore);
+    let suggestions = datastore
+        .get_event_suggestions(
+            bucket_id,
+            field,
+            prefix.unwrap_or(""),
+            starttime,
+            endtime,
+            limit,
+        )
+        .map_err(HttpErrorJson::from)?;
+    let list: Vec<serde_json::Value> = suggestions
+        .into_iter()
+        .map(|(value, count)| serde_json::json!({ "value": value, "count": count }))
+        .collect();
+    Ok(Json(serde_json::Value::Array(list)))
+}
+
+#[post("/<bucket_id>/events", data = "<events>", format = "application/json")]
+pub fn bucket_events_create(
+    bucket_id: &str,
+    events: Json<Vec<Event>>,
+    auth: ApiKeyAuth,
+    client: ClientInfo,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Vec<Event>>, HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let mut events = events.into_inner();
+    let datastore = endpoints_get_lock!(state.datastore);
+    auth.charge_quota(&datastore, events.len() as u64)?;
+    if !config.field_limits.is_empty() || !config.rate_limits.is_empty() {
+        let bucket = datastore.get_bucket(bucket_id).map_err(HttpErrorJson::from)?;
+        apply_field_limits(config, &bucket._type, &mut events);
+        if let Some(&limit) = config.rate_limits.get(&bucket._type) {
+            let allowed = crate::ratelimit::check(bucket_id, limit, events.len() as u64);
+            events.truncate(allowed as usize);
+        }
+    }
+    let provenance = EventProvenance {
+        key: auth.key_id().map(|id| id.to_string()),
+        client: client.0,
+        ingested: Utc::now(),
+        batch: None,
+    };
+    let res = datastore.insert_events_with_provenance(bucket_id, &events, provenance);
+    match res {
+        Ok(events) => Ok(Json(events)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[post(
+    "/<bucket_id>/heartbeat?<pulsetime>",
+    data = "<heartbeat_json>",
+    format = "application/json"
+)]
+pub fn bucket_events_heartbeat(
+    bucket_id: &str,
+    heartbeat_json: Json<Event>,
+    pulsetime: f64,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Event>, HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let mut heartbeat = heartbeat_json.into_inner();
+    let datastore = endpoints_get_lock!(state.datastore);
+    auth.charge_quota(&datastore, 1)?;
+    let mut policy = aw_transform::MergePolicy::default();
+    if !config.field_limits.is_empty()
+        || !config.rate_limits.is_empty()
+        || !config.merge_policies.is_empty()
+    {
+        let bucket = datastore.get_bucket(bucket_id).map_err(HttpErrorJson::from)?;
+        apply_field_limits(config, &bucket._type, std::slice::from_mut(&mut heartbeat));
+        if let Some(&limit) = config.rate_limits.get(&bucket._type) {
+            // A sampled-out heartbeat is acknowledged but not stored, so
+            // the watcher doesn't retry it
+            if crate::ratelimit::check(bucket_id, limit, 1) == 0 {
+                return Ok(Json(heartbeat));
+            }
+        }
+        if let Some(policy_config) = config.merge_policies.get(&bucket._type) {
+            policy = policy_config.to_policy();
+        }
+    }
+    match datastore.heartbeat_with_policy(bucket_id, heartbeat, pulsetime, policy) {
+        Ok(e) => Ok(Json(e)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replays an ordered list of queued heartbeats in one call, for watchers
+/// flushing a backlog built up while offline. Each entry goes through the
+/// same merge logic as the single heartbeat endpoint, but the whole batch
+/// is one datastore command and commits together.
+#[post(
+    "/<bucket_id>/heartbeat/batch?<pulsetime>",
+    data = "<heartbeats_json>",
+    format = "application/json"
+)]
+pub fn bucket_events_heartbeat_batch(
+    bucket_id: &str,
+    heartbeats_json: Json<Vec<Event>>,
+    pulsetime: f64,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Vec<Event>>, HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let mut heartbeats = heartbeats_json.into_inner();
+    let datastore = endpoints_get_lock!(state.datastore);
+    auth.charge_quota(&datastore, heartbeats.len() as u64)?;
+    let mut policy = aw_transform::MergePolicy::default();
+    if !config.field_limits.is_empty()
+        || !config.rate_limits.is_empty()
+        || !config.merge_policies.is_empty()
+    {
+        let bucket = datastore.get_bucket(bucket_id).map_err(HttpErrorJson::from)?;
+        apply_field_limits(config, &bucket._type, &mut heartbeats);
+        if let Some(&limit) = config.rate_limits.get(&bucket._type) {
+            let allowed = crate::ratelimit::check(bucket_id, limit, heartbeats.len() as u64);
+            heartbeats.truncate(allowed as usize);
+        }
+        if let Some(policy_config) = config.merge_policies.get(&bucket._type) {
+            policy = policy_config.to_policy();
+        }
+    }
+    match datastore.heartbeat_batch(bucket_id, heartbeats, pulsetime, policy) {
+        Ok(written) => Ok(Json(written)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns the database's plan for the corresponding events query, for
+/// diagnosing "the server is slow" reports. Only available in testing
+/// mode, since the plan leaks schema internals.
+#[get("/<bucket_id>/events/explain?<start>&<end>&<limit>")]
+pub fn bucket_events_explain(
+    bucket_id: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    limit: Option<u64>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    if !config.testing {
+        return Err(HttpErrorJson::new(
+            Status::Forbidden,
+            "Query plans are only available in testing mode".to_string(),
+        ));
+    }
+    let starttime = parse_rfc3339_param(start, "starttime")?;
+    let endtime = parse_rfc3339_param(end, "endtime")?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.explain_get_events(bucket_id, starttime, endtime, limit) {
+        Ok(plan) => Ok(Json(plan)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns a downsampled view of a bucket's events for zoomed-out
+/// timeline rendering: the range is split into `points` equal slots
+/// (default 1000, think one per pixel) and each non-empty slot reports
+/// the event data that dominates it (most overlap time) plus how much of
+/// the slot is covered at all. Month-scale views this way fetch a bounded
+/// number of rows instead of every raw event.
+#[get("/<bucket_id>/events/downsampled?<start>&<end>&<points>")]
+pub fn bucket_events_downsampled(
+    bucket_id: &str,
+    start: &str,
+    end: &str,
+    points: Option<u64>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<serde_json::Value>>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let starttime = parse_rfc3339_param(Some(start), "starttime")?.unwrap();
+    let endtime = parse_rfc3339_param(Some(end), "endtime")?.unwrap();
+    if endtime <= starttime {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "end must be after start".to_string(),
+        ));
+    }
+    let points = points.unwrap_or(1000);
+    if points == 0 || points > 100_000 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "points must be between 1 and 100000".to_string(),
+        ));
+    }
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    // get_events clamps events to the query range, so overlaps only need
+    // to be distributed over slots
+    let events = datastore.get_events(bucket_id, Some(starttime), Some(endtime), None)?;
+
+    let slot_millis = (endtime - starttime).num_milliseconds() as f64 / points as f64;
+    // Per slot: total covered milliseconds, and overlap per distinct data
+    // payload (keyed by its serialization)
+    let mut covered = vec![0.0f64; points as usize];
+    let mut dominant: Vec<HashMap<String, f64>> = vec![HashMap::new(); points as usize];
+    let mut payloads: HashMap<String, serde_json::Value> = HashMap::new();
+    for event in &events {
+        let event_start = (event.timestamp - starttime).num_milliseconds() as f64;
+        let event_end = event_start + event.duration.num_milliseconds() as f64;
+        if event.duration.num_milliseconds() <= 0 {
+            continue;
+        }
+        let key = serde_json::to_string(&event.data).unwrap();
+        payloads
+            .entry(key.clone())
+            .or_insert_with(|| serde_json::Value::Object(event.data.clone()));
+        let first_slot = (event_start / slot_millis).floor() as usize;
+        let last_slot = (((event_end / slot_millis).ceil() as usize).max(1) - 1)
+            .min(points as usize - 1);
+        for slot in first_slot..=last_slot {
+            let slot_start = slot as f64 * slot_millis;
+            let slot_end = slot_start + slot_millis;
+            let overlap = event_end.min(slot_end) - event_start.max(slot_start);
+            if overlap <= 0.0 {
+                continue;
+            }
+            covered[slot] += overlap;
+            *dominant[slot].entry(key.clone()).or_insert(0.0) += overlap;
+        }
+    }
+
+    let mut result = Vec::new();
+    for (slot, per_payload) in dominant.iter().enumerate() {
+        let Some((key, _)) = per_payload
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+        else {
+            continue;
+        };
+        let slot_start =
+            starttime + chrono::Duration::milliseconds((slot as f64 * slot_millis) as i64);
+        result.push(serde_json::json!({
+            "timestamp": slot_start.to_rfc3339(),
+            "duration": slot_millis / 1000.0,
+            "data": payloads[key],
+            "coverage": (covered[slot] / slot_millis).min(1.0),
+        }));
+    }
+    Ok(Json(result))
+}
+
+#[get("/<bucket_id>/events/count")]
+pub fn bucket_event_count(
+    bucket_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<u64>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let res = datastore.get_event_count(bucket_id, None, None);
+    match res {
+        Ok(eventcount) => Ok(Json(eventcount as u64)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<bucket_id>/events/<event_id>")]
+pub fn bucket_events_delete_by_id(
+    bucket_id: &str,
+    event_id: i64,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.delete_events_by_id(bucket_id, vec![event_id]) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replaces an event's timestamp, duration and data in place, e.g. to
+/// re-label a mis-categorized block after the fact. The id in the path is
+/// authoritative; an id in the body is ignored. 404 if the bucket does not
+/// own an event with that id.
+#[put("/<bucket_id>/events/<event_id>", data = "<event>", format = "application/json")]
+pub fn bucket_events_update(
+    bucket_id: &str,
+    event_id: i64,
+    event: Json<Event>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Event>, HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let mut event = event.into_inner();
+    event.id = Some(event_id);
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.update_event(bucket_id, event) {
+        Ok(event) => Ok(Json(event)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Splits an event into two at the given timestamp, so users can correct a
+/// long merged event that spans a context switch. Both halves keep the
+/// original data; the first half keeps the event id, the second gets a new
+/// one. Both are written in a single datastore call, so the split is atomic.
+#[post("/<bucket_id>/events/<event_id>/split?<at>")]
+pub fn bucket_event_split(
+    bucket_id: &str,
+    event_id: i64,
+    at: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Event>>, HttpErrorJson> {
+    auth.require(Scope::Write, Some(bucket_id))?;
+    let at = parse_rfc3339_param(Some(at), "at")?.unwrap();
+    let datastore = endpoints_get_lock!(state.datastore);
+    let event = match datastore.get_event_by_id(bucket_id, event_id) {
+        Ok(event) => event,
+        Err(DatastoreError::NoSuchKey(_)) => {
+            return Err(HttpErrorJson::new(
+                Status::NotFound,
+                format!("No event with id {event_id} in bucket {bucket_id}"),
+            ))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let endtime = event.calculate_endtime();
+    if at <= event.timestamp || at >= endtime {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Split timestamp must be strictly inside the event".to_string(),
+        ));
+    }
+    let first = Event {
+        id: event.id,
+        timestamp: event.timestamp,
+        duration: at - event.timestamp,
+        data: event.data.clone(),
+    };
+    let second = Event {
+        id: None,
+        timestamp: at,
+        duration: endtime - at,
+        data: event.data,
+    };
+    // Inserting an event with an id replaces it, so this one call shrinks
+    // the original and adds the second half
+    let inserted = datastore.insert_events(bucket_id, &[first, second])?;
+    Ok(Json(inserted))
+}
+
+#[get("/<bucket_id>/export")]
+pub fn bucket_export(
+    bucket_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<BucketsExport>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let mut bucket = datastore.get_bucket(bucket_id)?;
+    bucket.events = TryVec::new(datastore.get_events(bucket_id, None, None, None)?);
+    let mut buckets = HashMap::new();
+    buckets.insert(bucket_id.to_string(), bucket);
+    Ok(Json(BucketsExport { buckets }))
+}
+
+/// Duplicates a bucket (metadata + events) under a new id, optionally
+/// restricted to a time range. Useful for snapshotting data before running
+/// destructive cleanups or experiments.
+#[post("/<bucket_id>/copy?<new_id>&<start>&<end>")]
+pub fn bucket_copy(
+    bucket_id: &str,
+    new_id: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Read, Some(bucket_id))?;
+    auth.require(Scope::Write, Some(new_id))?;
+    let starttime = parse_rfc3339_param(start, "start")?;
+    let endtime = parse_rfc3339_param(end, "end")?;
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let mut bucket = datastore.get_bucket(bucket_id)?;
+    let mut events = datastore.get_events(bucket_id, starttime, endtime, None)?;
+    // Strip ids so the copies get fresh ids in the new bucket
+    for event in &mut events {
+        event.id = None;
+    }
+
+    bucket.bid = None;
+    bucket.id = new_id.to_string();
+    bucket.created = None;
+    match datastore.create_bucket(&bucket) {
+        Ok(_) => (),
+        Err(err) => return Err(err.into()),
+    }
+    match datastore.insert_events(new_id, &events) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Moves the bucket to the trash, from where it can be restored until the
+/// retention period expires. Pass `force=true` to delete it permanently.
+#[delete("/<bucket_id>?<force>")]
+pub fn bucket_delete(
+    bucket_id: &str,
+    force: Option<bool>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Admin, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let res = if force.unwrap_or(false) {
+        datastore.delete_bucket(bucket_id)
+    } else {
+        datastore.soft_delete_bucket(bucket_id)
+    };
+    match res {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[get("/")]
+pub fn trash_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Admin, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.get_deleted_buckets() {
+        Ok(names) => Ok(Json(names)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[post("/<bucket_id>/restore")]
+pub fn trash_restore(
+    bucket_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Admin, Some(bucket_id))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.restore_bucket(bucket_id) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Permanently deletes everything in the trash, without waiting for the
+/// retention period to expire
+#[delete("/")]
+pub fn trash_empty(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<i64>, HttpErrorJson> {
+    auth.require(Scope::Admin, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.purge_deleted_buckets(Utc::now()) {
+        Ok(purged) => Ok(Json(purged)),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,243 @@
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value;
+
+use aw_models::TimeInterval;
+use aw_transform::filter_keyvals;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::query::SavedQuery;
+use crate::endpoints::stats::{active_events, days_into_week, get_timezone, get_week_start};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Budgets are stored in the key_value table, prefixed with `budget.`
+pub static BUDGET_PREFIX: &str = "budget.";
+
+fn parse_name(name: &str) -> Result<String, HttpErrorJson> {
+    let namespace = BUDGET_PREFIX.to_string();
+    if name.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long budget name".to_string(),
+        ));
+    }
+    Ok(namespace + name)
+}
+
+/// What a budget measures
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BudgetTarget {
+    /// Active (non-AFK) time in a given application, taken from the window
+    /// watcher bucket
+    App { app: String },
+    /// Seconds returned by a saved query over the budget period, which
+    /// covers categories and other custom groupings
+    Query { query: String },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPeriod {
+    Day,
+    Week,
+}
+
+/// A time goal: at most (or at least, the interpretation is up to the
+/// client) `hours` on the target per day or week. Progress is computed
+/// server-side so every client shows the same numbers.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Budget {
+    #[serde(flatten)]
+    pub target: BudgetTarget,
+    pub period: BudgetPeriod,
+    pub hours: f64,
+}
+
+/// Start of the current budget period in the configured timezone: local
+/// midnight today for daily budgets, local midnight on the configured
+/// week start day for weekly ones
+fn period_start(
+    period: &BudgetPeriod,
+    tz: &Tz,
+    week_start: chrono::Weekday,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let local = now.with_timezone(tz);
+    let mut date = local.date_naive();
+    if let BudgetPeriod::Week = period {
+        date -= Duration::days(days_into_week(local.weekday(), week_start) as i64);
+    }
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    // On DST transitions where local midnight doesn't exist, take the
+    // earliest valid instant after it
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        None => DateTime::from_naive_utc_and_offset(midnight, Utc),
+    }
+}
+
+#[get("/")]
+pub fn budgets_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{BUDGET_PREFIX}%"))?;
+    let names = keys
+        .into_iter()
+        .map(|key| key[BUDGET_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(names))
+}
+
+#[get("/<name>")]
+pub fn budget_get(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Budget>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let budget: Budget = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse budget: {err}"),
+        )
+    })?;
+    Ok(Json(budget))
+}
+
+#[post("/<name>", data = "<message>", format = "application/json")]
+pub fn budget_set(
+    name: &str,
+    message: Json<Budget>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Status, HttpErrorJson> {
+    // Budget progress may execute saved queries over all buckets, so
+    // defining budgets requires an unrestricted Write key
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let budget = message.into_inner();
+    if budget.hours <= 0.0 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Budget hours must be positive".to_string(),
+        ));
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.insert_key_value(&key, &serde_json::to_string(&budget).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<name>")]
+pub fn budget_delete(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.delete_key_value(&key) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Computes how much of the budget has been spent in the current period,
+/// returning the period start together with spent and budgeted seconds.
+/// Shared with the summary reporter.
+pub fn compute_progress(
+    datastore: &aw_datastore::Datastore,
+    budget: &Budget,
+    now: chrono::DateTime<Utc>,
+) -> Result<(chrono::DateTime<Utc>, f64, f64), HttpErrorJson> {
+    let tz = get_timezone(datastore);
+    let week_start = get_week_start(datastore);
+    let start = period_start(&budget.period, &tz, week_start, now);
+
+    let spent_seconds = match &budget.target {
+        BudgetTarget::App { app } => {
+            let active = active_events(datastore, Some(start), Some(now))?;
+            let app_events = filter_keyvals(active, "app", &[Value::String(app.clone())]);
+            app_events
+                .iter()
+                .map(|event| event.duration.num_milliseconds() as f64 / 1000.0)
+                .sum()
+        }
+        BudgetTarget::Query { query } => {
+            let kv = datastore.get_key_value(&format!("query.{query}"))?;
+            let saved: SavedQuery = serde_json::from_str(&kv.value).map_err(|err| {
+                HttpErrorJson::new(
+                    Status::InternalServerError,
+                    format!("Failed to parse saved query '{query}': {err}"),
+                )
+            })?;
+            let code = saved.query.join("\n");
+            let interval = TimeInterval::new(start, now);
+            match aw_query::query(&code, &interval, datastore) {
+                Ok(aw_query::DataType::Number(seconds)) => seconds,
+                Ok(other) => {
+                    return Err(HttpErrorJson::new(
+                        Status::BadRequest,
+                        format!("Budget queries must return a number, got {other:?}"),
+                    ))
+                }
+                Err(err) => {
+                    return Err(HttpErrorJson::new(
+                        Status::BadRequest,
+                        format!("Query error: {err}"),
+                    ))
+                }
+            }
+        }
+    };
+
+    Ok((start, spent_seconds, budget.hours * 3600.0))
+}
+
+/// Returns how much of the budget has been spent in the current period
+#[get("/<name>/progress")]
+pub fn budget_progress(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    // Progress runs active-event intersection or a saved query over all
+    // buckets, like direct query execution
+    auth.require_all_buckets(Scope::Read)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let budget: Budget = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse budget: {err}"),
+        )
+    })?;
+    let (start, spent_seconds, budget_seconds) =
+        compute_progress(&datastore, &budget, Utc::now())?;
+    Ok(Json(json!({
+        "period_start": start.to_rfc3339(),
+        "spent_seconds": spent_seconds,
+        "budget_seconds": budget_seconds,
+        "fraction": spent_seconds / budget_seconds,
+    })))
+}
@@ -0,0 +1,128 @@
+//! Chaos injection for validating client resilience: in testing mode,
+//! rules configured via `/debug/chaos` add latency or force 5xx
+//! responses on matching routes, so aw-client and the watchers can have
+//! their retry and offline-queue behavior exercised against a real
+//! server. A forced error replaces the response with an empty-bodied
+//! 5xx, which is also the closest a fairing can get to a dropped
+//! connection — Rocket offers no way to sever the TCP stream itself.
+
+use std::sync::Mutex;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{Data, Request, Response, State};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AWConfig;
+use crate::endpoints::util::HttpErrorJson;
+
+/// One injection rule. Requests whose path starts with `path_prefix`
+/// are delayed by `latency_ms` and, if `status` is set, answered with
+/// that status and an empty body instead of being handled. `every`
+/// triggers the rule on every Nth matching request (1 = always), so
+/// intermittent failures can be simulated deterministically.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChaosRule {
+    pub path_prefix: String,
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default = "default_every")]
+    pub every: u64,
+    #[serde(skip)]
+    hits: u64,
+}
+
+fn default_every() -> u64 {
+    1
+}
+
+static RULES: Mutex<Vec<ChaosRule>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Default)]
+struct ForcedStatus(Option<u16>);
+
+pub struct Chaos;
+
+#[rocket::async_trait]
+impl Fairing for Chaos {
+    fn info(&self) -> Info {
+        Info {
+            name: "Chaos",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let path = request.uri().path().to_string();
+        if path.starts_with("/debug") {
+            return;
+        }
+        // Decide under the lock, sleep outside it
+        let (latency_ms, status) = {
+            let mut rules = RULES.lock().expect("Chaos rules poisoned");
+            let Some(rule) = rules
+                .iter_mut()
+                .find(|rule| path.starts_with(&rule.path_prefix))
+            else {
+                return;
+            };
+            rule.hits += 1;
+            if rule.hits % rule.every.max(1) != 0 {
+                return;
+            }
+            (rule.latency_ms, rule.status)
+        };
+        if latency_ms > 0 {
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(latency_ms)).await;
+        }
+        if status.is_some() {
+            warn!("Chaos: forcing {status:?} for {path}");
+            request.local_cache(|| ForcedStatus(status));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let ForcedStatus(forced) = request.local_cache(ForcedStatus::default);
+        if let Some(code) = forced {
+            response.set_status(Status::new(*code));
+            response.set_sized_body(0, std::io::Cursor::new(""));
+        }
+    }
+}
+
+fn require_testing(config: &AWConfig) -> Result<(), HttpErrorJson> {
+    if !config.testing {
+        return Err(HttpErrorJson::new(
+            Status::Forbidden,
+            "Chaos injection is only available in testing mode".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[get("/chaos")]
+pub fn chaos_get(config: &State<AWConfig>) -> Result<Json<Vec<ChaosRule>>, HttpErrorJson> {
+    require_testing(config)?;
+    Ok(Json(RULES.lock().expect("Chaos rules poisoned").clone()))
+}
+
+/// Replaces the active rule set
+#[put("/chaos", data = "<message>", format = "application/json")]
+pub fn chaos_set(
+    message: Json<Vec<ChaosRule>>,
+    config: &State<AWConfig>,
+) -> Result<(), HttpErrorJson> {
+    require_testing(config)?;
+    *RULES.lock().expect("Chaos rules poisoned") = message.into_inner();
+    Ok(())
+}
+
+#[delete("/chaos")]
+pub fn chaos_clear(config: &State<AWConfig>) -> Result<(), HttpErrorJson> {
+    require_testing(config)?;
+    RULES.lock().expect("Chaos rules poisoned").clear();
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+use crate::config::AWConfig;
+
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allow_methods: String,
+    allow_headers: String,
+    allow_credentials: bool,
+}
+
+pub fn cors(config: &AWConfig) -> Cors {
+    let mut allowed_origins: Vec<String> = config.cors.clone();
+    allowed_origins.push("http://localhost:27180".to_string());
+    if config.testing {
+        allowed_origins.push("http://127.0.0.1:27180".to_string());
+        allowed_origins.push("http://localhost:5173".to_string());
+    }
+    Cors {
+        allowed_origins,
+        allow_methods: config.cors_allow_methods.join(", "),
+        allow_headers: config.cors_allow_headers.join(", "),
+        allow_credentials: config.cors_allow_credentials,
+    }
+}
+
+impl Cors {
+    /// `*` in the configured origins allows any origin; the actual
+    /// origin is still echoed back rather than a literal `*`, which
+    /// wouldn't be valid together with credentials
+    fn origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) => origin.to_string(),
+            None => return,
+        };
+        if self.origin_allowed(&origin) {
+            response.set_header(Header::new("Access-Control-Allow-Origin", origin));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Methods",
+                self.allow_methods.clone(),
+            ));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                self.allow_headers.clone(),
+            ));
+            if self.allow_credentials {
+                response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+            }
+        }
+    }
+}
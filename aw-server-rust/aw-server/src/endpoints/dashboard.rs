@@ -0,0 +1,246 @@
+//! Server-side storage for the web UI's dashboard configuration
+//! (category trees and pinned views), so configs roam across browsers
+//! instead of living in localStorage. Unlike the generic settings
+//! endpoints these are structured and validated, so a buggy client
+//! can't store a blob the UI later chokes on.
+//!
+//! Dashboards are scoped per server; when multi-user support lands the
+//! key prefix gains a user component.
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Key-value store prefix for dashboard entries, keyed by dashboard id
+pub const DASHBOARD_PREFIX: &str = "dashboard.";
+
+/// How a category matches events: `none` for pure grouping nodes,
+/// `regex` with a pattern matched against event data by the UI
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CategoryRule {
+    #[serde(rename = "type")]
+    pub rule_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore_case: Option<bool>,
+}
+
+/// A node in the category tree; `name` is the path from the root, e.g.
+/// `["Work", "Programming"]`. `data` carries display metadata like the
+/// category color, passed through untouched for the UI.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Category {
+    pub name: Vec<String>,
+    pub rule: CategoryRule,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Dashboard {
+    pub name: String,
+    /// Category tree used to classify events
+    #[serde(default)]
+    pub categories: Vec<Category>,
+    /// Ids of views pinned to this dashboard, in display order
+    #[serde(default)]
+    pub pinned_views: Vec<String>,
+}
+
+fn parse_id(id: &str) -> Result<String, HttpErrorJson> {
+    if id.is_empty() || id.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Dashboard id must be 1-127 characters".to_string(),
+        ));
+    }
+    Ok(format!("{DASHBOARD_PREFIX}{id}"))
+}
+
+fn validate(dashboard: &Dashboard) -> Result<(), HttpErrorJson> {
+    for category in &dashboard.categories {
+        if category.name.is_empty() {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                "Category path must not be empty".to_string(),
+            ));
+        }
+        match category.rule.rule_type.as_str() {
+            "none" => (),
+            "regex" => {
+                let pattern = category.rule.regex.as_deref().unwrap_or("");
+                if pattern.is_empty() {
+                    return Err(HttpErrorJson::new(
+                        Status::BadRequest,
+                        format!(
+                            "Category '{}' has a regex rule without a pattern",
+                            category.name.join(" > ")
+                        ),
+                    ));
+                }
+                let ignore_case = category.rule.ignore_case.unwrap_or(false);
+                if let Err(err) = aw_transform::RegexRule::new(pattern, ignore_case) {
+                    return Err(HttpErrorJson::new(
+                        Status::BadRequest,
+                        format!(
+                            "Category '{}' has an invalid regex: {err}",
+                            category.name.join(" > ")
+                        ),
+                    ));
+                }
+            }
+            other => {
+                return Err(HttpErrorJson::new(
+                    Status::BadRequest,
+                    format!("Unknown category rule type '{other}' (expected none or regex)"),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[get("/")]
+pub fn dashboards_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{DASHBOARD_PREFIX}%"))?;
+    let ids = keys
+        .into_iter()
+        .map(|key| key[DASHBOARD_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(ids))
+}
+
+#[get("/<id>")]
+pub fn dashboard_get(
+    id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Dashboard>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_id(id)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let dashboard: Dashboard = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse dashboard: {err}"),
+        )
+    })?;
+    Ok(Json(dashboard))
+}
+
+#[post("/<id>", data = "<message>", format = "application/json")]
+pub fn dashboard_set(
+    id: &str,
+    message: Json<Dashboard>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Status, HttpErrorJson> {
+    auth.require(Scope::Write, None)?;
+    let key = parse_id(id)?;
+    let dashboard = message.into_inner();
+    validate(&dashboard)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.insert_key_value(&key, &serde_json::to_string(&dashboard).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The category export document aw-webui reads and writes
+/// (`aw-category-export.json`); `id` fields are local bookkeeping the
+/// webui regenerates, so import drops them
+#[derive(Deserialize)]
+pub struct CategoryExport {
+    pub categories: Vec<Category>,
+}
+
+/// Exports the dashboard's category tree in the aw-webui format, for
+/// loading into a browser-local webui or another server
+#[get("/<id>/categories/export")]
+pub fn dashboard_categories_export(
+    id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<serde_json::Value>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_id(id)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let dashboard: Dashboard = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse dashboard: {err}"),
+        )
+    })?;
+    let categories: Vec<serde_json::Value> = dashboard
+        .categories
+        .iter()
+        .enumerate()
+        .map(|(index, category)| {
+            let mut value = serde_json::to_value(category).unwrap();
+            value["id"] = serde_json::json!(index);
+            value
+        })
+        .collect();
+    Ok(Json(serde_json::json!({ "categories": categories })))
+}
+
+/// Imports a category tree in the aw-webui export format, replacing the
+/// dashboard's current one. A dashboard that doesn't exist yet is
+/// created, so carrying over a browser-local tree is a single request.
+#[post("/<id>/categories/import", data = "<message>", format = "application/json")]
+pub fn dashboard_categories_import(
+    id: &str,
+    message: Json<CategoryExport>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Status, HttpErrorJson> {
+    auth.require(Scope::Write, None)?;
+    let key = parse_id(id)?;
+    let categories = message.into_inner().categories;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let mut dashboard = match datastore.get_key_value(&key) {
+        Ok(kv) => serde_json::from_str(&kv.value).map_err(|err| {
+            HttpErrorJson::new(
+                Status::InternalServerError,
+                format!("Failed to parse dashboard: {err}"),
+            )
+        })?,
+        Err(_) => Dashboard {
+            name: id.to_string(),
+            categories: Vec::new(),
+            pinned_views: Vec::new(),
+        },
+    };
+    dashboard.categories = categories;
+    validate(&dashboard)?;
+    match datastore.insert_key_value(&key, &serde_json::to_string(&dashboard).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<id>")]
+pub fn dashboard_delete(
+    id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Write, None)?;
+    let key = parse_id(id)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    datastore.delete_key_value(&key)?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! Per-request datastore metrics, attached in testing mode only.
+//!
+//! Adds `X-DB-Queries` (datastore commands issued) and `X-DB-Time-Ms`
+//! (time the worker spent on them) headers to every response, making
+//! endpoint N+1 problems (e.g. per-event inserts) visible during
+//! development. The counters are global, so work done by concurrent
+//! requests may be attributed to each other — good enough for debugging,
+//! not for accounting.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+
+pub struct DbMetrics;
+
+#[derive(Copy, Clone, Default)]
+struct Snapshot {
+    queries: u64,
+    time_micros: u64,
+}
+
+#[rocket::async_trait]
+impl Fairing for DbMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "DbMetrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let (queries, time_micros) = aw_datastore::db_metrics();
+        request.local_cache(|| Snapshot {
+            queries,
+            time_micros,
+        });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let before: &Snapshot = request.local_cache(Snapshot::default);
+        let (queries, time_micros) = aw_datastore::db_metrics();
+        response.set_header(Header::new(
+            "X-DB-Queries",
+            queries.saturating_sub(before.queries).to_string(),
+        ));
+        response.set_header(Header::new(
+            "X-DB-Time-Ms",
+            format!(
+                "{:.1}",
+                time_micros.saturating_sub(before.time_micros) as f64 / 1000.0
+            ),
+        ));
+    }
+}
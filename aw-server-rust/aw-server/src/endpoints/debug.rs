@@ -0,0 +1,87 @@
+//! Runtime statistics for diagnosing performance issues on a running
+//! server: process uptime, memory usage, the datastore worker's command
+//! counters and bucket counts. Gated to testing mode, like the
+//! per-request DB metrics headers.
+
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::{json, Value};
+
+use crate::config::AWConfig;
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Resident and peak memory of the process as the OS reports it. There
+/// is no custom global allocator, so allocator-internal statistics are
+/// not available; on non-Linux targets this returns null.
+#[cfg(target_os = "linux")]
+fn memory_stats() -> Value {
+    let status = match std::fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return Value::Null,
+    };
+    let field_kib = |name: &str| -> Value {
+        status
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kb| kb.parse::<u64>().ok())
+            .map(Value::from)
+            .unwrap_or(Value::Null)
+    };
+    json!({
+        "resident_kib": field_kib("VmRSS:"),
+        "peak_resident_kib": field_kib("VmHWM:"),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn memory_stats() -> Value {
+    Value::Null
+}
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Called at server startup so uptime counts from launch, not from the
+/// first request to this endpoint.
+pub fn init() {
+    LazyLock::force(&STARTED);
+}
+
+#[get("/stats")]
+pub fn debug_stats(
+    config: &State<AWConfig>,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    if !config.testing {
+        return Err(HttpErrorJson::new(
+            Status::Forbidden,
+            "Debug stats are only available in testing mode".to_string(),
+        ));
+    }
+    let (db_commands, db_time_micros) = aw_datastore::db_metrics();
+    let (buckets, events) = {
+        let datastore = endpoints_get_lock!(state.datastore);
+        let buckets = datastore.get_buckets().unwrap_or_default();
+        let events: i64 = buckets
+            .keys()
+            .filter_map(|id| datastore.get_event_count(id, None, None).ok())
+            .sum();
+        (buckets.len(), events)
+    };
+    Ok(Json(json!({
+        "uptime_seconds": STARTED.elapsed().as_secs(),
+        "memory": memory_stats(),
+        "db_commands_handled": db_commands,
+        "db_time_micros": db_time_micros,
+        "buckets": buckets,
+        "events": events,
+        "db_size_bytes": crate::diskguard::db_size_bytes(),
+        "degraded": crate::diskguard::is_degraded(),
+        "events_dropped": crate::ratelimit::dropped_total(),
+    })))
+}
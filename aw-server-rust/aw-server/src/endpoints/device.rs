@@ -0,0 +1,73 @@
+//! Device metadata: the device id with platform and server flavor, plus
+//! a user-set display name stored in the datastore. Sync tooling and
+//! the UI use this for labeling devices with something friendlier than
+//! a hostname or UUID.
+
+use gethostname::gethostname;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Key-value store key for the user-set display name
+const DISPLAY_NAME_KEY: &str = "device.display_name";
+
+#[get("/")]
+pub fn device_get(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let hostname = gethostname()
+        .into_string()
+        .unwrap_or_else(|_| "unknown".to_string());
+    let display_name = {
+        let datastore = endpoints_get_lock!(state.datastore);
+        datastore
+            .get_key_value(DISPLAY_NAME_KEY)
+            .ok()
+            .map(|kv| kv.value)
+    };
+    Ok(Json(json!({
+        "device_id": state.device_id,
+        "hostname": hostname,
+        "platform": std::env::consts::OS,
+        "flavor": format!("aw-server-rust v{}", env!("CARGO_PKG_VERSION")),
+        "display_name": display_name,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct DeviceUpdate {
+    pub display_name: String,
+}
+
+/// Sets the display name; an empty name clears it back to unset
+#[put("/", data = "<message>", format = "application/json")]
+pub fn device_set(
+    message: Json<DeviceUpdate>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Write, None)?;
+    let update = message.into_inner();
+    if update.display_name.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long display name".to_string(),
+        ));
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    if update.display_name.is_empty() {
+        // Deleting a key that was never set is fine
+        datastore.delete_key_value(DISPLAY_NAME_KEY)?;
+    } else {
+        datastore.insert_key_value(DISPLAY_NAME_KEY, &update.display_name)?;
+    }
+    Ok(())
+}
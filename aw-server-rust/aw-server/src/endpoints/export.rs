@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use rocket::http::ContentType;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use aw_models::BucketsExport;
+use aw_models::TimeInterval;
+use aw_models::TryVec;
+
+use rocket::http::Status;
+
+use crate::config::AWConfig;
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// How many buckets are fetched concurrently during a full export
+const EXPORT_CONCURRENCY: usize = 4;
+
+fn export_all(
+    auth: &ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<BucketsExport, HttpErrorJson> {
+    // Clone the handle so the endpoint lock isn't held for the whole
+    // export; other requests keep being served while we fetch
+    let datastore = {
+        let datastore = endpoints_get_lock!(state.datastore);
+        datastore.clone()
+    };
+    let mut buckets = datastore.get_buckets()?;
+    // A key restricted to certain buckets only exports those
+    buckets.retain(|id, _| auth.bucket_visible(id));
+    let ids: Vec<String> = buckets.keys().cloned().collect();
+
+    // Fetch buckets with bounded concurrency. Storage access serializes
+    // at the worker thread, but deserialization of the event rows (the
+    // bulk of the work for large buckets) overlaps between threads.
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..EXPORT_CONCURRENCY.min(ids.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(id) = ids.get(index) else { break };
+                let events = datastore.get_events(id, None, None, None);
+                results.lock().unwrap().push((id.clone(), events));
+            });
+        }
+    });
+
+    let mut export = BucketsExport {
+        buckets: HashMap::new(),
+    };
+    for (bid, events) in results.into_inner().unwrap() {
+        let mut bucket = buckets.remove(&bid).unwrap();
+        bucket.events = TryVec::new(events?);
+        export.buckets.insert(bid, bucket);
+    }
+    Ok(export)
+}
+
+#[get("/")]
+pub fn buckets_export(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<BucketsExport>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    Ok(Json(export_all(&auth, state)?))
+}
+
+/// Exports all buckets as an age-encrypted JSON export, so backups
+/// containing sensitive data (e.g. browsing history) can be stored on
+/// untrusted storage.
+///
+/// The recipient can either be passed as a query parameter or configured
+/// with `export_encryption_recipient` in the config file.
+#[get("/encrypted?<recipient>")]
+pub fn buckets_export_encrypted(
+    recipient: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<(ContentType, Vec<u8>), HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let recipient_str = match recipient {
+        Some(recipient) => recipient.to_string(),
+        None => match &config.export_encryption_recipient {
+            Some(recipient) => recipient.clone(),
+            None => {
+                return Err(HttpErrorJson::new(
+                    Status::BadRequest,
+                    "No recipient given and no export_encryption_recipient configured"
+                        .to_string(),
+                ))
+            }
+        },
+    };
+    let recipient = age::x25519::Recipient::from_str(&recipient_str).map_err(|err| {
+        HttpErrorJson::new(
+            Status::BadRequest,
+            format!("Failed to parse age recipient: {err}"),
+        )
+    })?;
+
+    let export = export_all(&auth, state)?;
+    let export_json = serde_json::to_vec(&export).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to serialize export: {err}"),
+        )
+    })?;
+
+    let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+        .expect("we always pass a recipient");
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut encrypted).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to encrypt export: {err}"),
+        )
+    })?;
+    writer
+        .write_all(&export_json)
+        .and_then(|_| writer.finish().map(|_| ()))
+        .map_err(|err| {
+            HttpErrorJson::new(
+                Status::InternalServerError,
+                format!("Failed to encrypt export: {err}"),
+            )
+        })?;
+
+    Ok((ContentType::Binary, encrypted))
+}
+
+/// Exports the events returned by a saved query as timewarrior intervals
+/// (`inc <start> - <end> # tag...`), so time can be reported with the
+/// timewarrior toolchain. Tags are taken from the events' `$category`
+/// (falling back to `app`); defaults to the trailing day.
+#[get("/timewarrior?<query>&<start>&<end>")]
+pub fn export_timewarrior(
+    query: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<(ContentType, String), HttpErrorJson> {
+    // The saved query may read any bucket
+    auth.require_all_buckets(Scope::Read)?;
+    let end = match end {
+        Some(end) => DateTime::parse_from_rfc3339(end)
+            .map_err(|err| {
+                HttpErrorJson::new(Status::BadRequest, format!("Invalid end: {err}"))
+            })?
+            .with_timezone(&Utc),
+        None => Utc::now(),
+    };
+    let start = match start {
+        Some(start) => DateTime::parse_from_rfc3339(start)
+            .map_err(|err| {
+                HttpErrorJson::new(Status::BadRequest, format!("Invalid start: {err}"))
+            })?
+            .with_timezone(&Utc),
+        None => end - chrono::Duration::hours(24),
+    };
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&format!("query.{query}"))?;
+    let saved: crate::endpoints::query::SavedQuery =
+        serde_json::from_str(&kv.value).map_err(|err| {
+            HttpErrorJson::new(
+                Status::InternalServerError,
+                format!("Failed to parse saved query: {err}"),
+            )
+        })?;
+
+    let code = saved.query.join("\n");
+    let interval = TimeInterval::new(start, end);
+    let limits = config.query_limits();
+    let result = aw_query::query_with_limits(&code, &interval, &datastore, &limits)
+        .map_err(|err| {
+            HttpErrorJson::new(Status::BadRequest, format!("Query failed: {err:?}"))
+        })?;
+    let events = match result {
+        aw_query::DataType::List(items) => items
+            .into_iter()
+            .map(|item| match item {
+                aw_query::DataType::Event(event) => Ok(event),
+                other => Err(HttpErrorJson::new(
+                    Status::BadRequest,
+                    format!("Query returned a non-event item: {other:?}"),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        other => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Query must return a list of events, got {other:?}"),
+            ))
+        }
+    };
+
+    // Timewarrior expects intervals oldest-first
+    let mut events = events;
+    events.sort_by_key(|event| event.timestamp);
+    let lines: Vec<String> = events
+        .iter()
+        .map(|event| {
+            (
+                event.timestamp,
+                event.timestamp + event.duration,
+                event_tags(event),
+            )
+        })
+        .map(|(start, end, tags)| {
+            let mut line = format!(
+                "inc {} - {}",
+                start.format("%Y%m%dT%H%M%SZ"),
+                end.format("%Y%m%dT%H%M%SZ")
+            );
+            if !tags.is_empty() {
+                line.push_str(" # ");
+                line.push_str(&tags.join(" "));
+            }
+            line
+        })
+        .collect();
+    let mut body = lines.join("\n");
+    if !body.is_empty() {
+        body.push('\n');
+    }
+    Ok((ContentType::Plain, body))
+}
+
+/// Tags for an event: the `$category` hierarchy if the query categorized
+/// events, otherwise the app name. Tags with spaces are quoted, as
+/// timewarrior expects.
+fn event_tags(event: &aw_models::Event) -> Vec<String> {
+    let mut tags = Vec::new();
+    if let Some(categories) = event.data.get("$category").and_then(|value| value.as_array()) {
+        for category in categories {
+            if let Some(category) = category.as_str() {
+                tags.push(quote_tag(category));
+            }
+        }
+    }
+    if tags.is_empty() {
+        if let Some(app) = event.data.get("app").and_then(|value| value.as_str()) {
+            tags.push(quote_tag(app));
+        }
+    }
+    tags
+}
+
+fn quote_tag(tag: &str) -> String {
+    if tag.contains(' ') {
+        format!("\"{tag}\"")
+    } else {
+        tag.to_string()
+    }
+}
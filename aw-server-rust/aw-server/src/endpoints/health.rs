@@ -0,0 +1,36 @@
+//! Liveness and readiness probes for container orchestration (Kubernetes
+//! probes, Docker HEALTHCHECK). Both are exempt from the Host header
+//! check since they expose no data and probes send arbitrary Host values.
+
+use rocket::http::Status;
+use rocket::State;
+
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Liveness: the process is up and Rocket is serving requests. Still
+/// 200 while over the database quota — the process is healthy, it just
+/// refuses writes — but the body says so, for humans and dashboards.
+#[get("/healthz")]
+pub fn healthz() -> &'static str {
+    if crate::diskguard::is_degraded() {
+        "degraded: database quota exceeded"
+    } else {
+        "ok"
+    }
+}
+
+/// Readiness: a round-trip through the datastore worker succeeds, which
+/// proves the worker thread is alive and the database opened and migrated
+/// at startup is still answering.
+#[get("/readyz")]
+pub fn readyz(state: &State<ServerState>) -> Result<&'static str, HttpErrorJson> {
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.get_buckets() {
+        Ok(_) => Ok("ok"),
+        Err(err) => Err(HttpErrorJson::new(
+            Status::ServiceUnavailable,
+            format!("Datastore is not responding: {err}"),
+        )),
+    }
+}
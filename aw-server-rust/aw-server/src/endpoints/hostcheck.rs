@@ -0,0 +1,74 @@
+//! Host header check to prevent DNS rebinding attacks.
+//!
+//! A malicious website can use JavaScript to make requests to
+//! `localhost:5600`, and since the server runs without authentication this
+//! would expose all data to the website. Checking that the Host header is a
+//! localhost address prevents this for modern browsers.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::Status;
+use rocket::{Data, Request};
+
+use crate::config::AWConfig;
+use crate::endpoints::util::HttpErrorJson;
+
+/// Checks the Host header, if it isn't a localhost address the request gets
+/// rerouted to a 400 response.
+pub struct HostCheck {
+    validate: bool,
+}
+
+impl HostCheck {
+    pub fn new(config: &AWConfig) -> HostCheck {
+        // We only validate the host if the server binds a loopback address
+        let validate = matches!(config.address.as_str(), "127.0.0.1" | "localhost");
+        HostCheck { validate }
+    }
+}
+
+/// Mounted at `/` to have somewhere to reroute blocked requests to.
+#[get("/badhost")]
+pub fn badhost() -> HttpErrorJson {
+    HttpErrorJson::new(
+        Status::BadRequest,
+        "Host header is not a localhost address".to_string(),
+    )
+}
+
+#[rocket::async_trait]
+impl Fairing for HostCheck {
+    fn info(&self) -> Info {
+        Info {
+            name: "HostCheck",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if !self.validate {
+            return;
+        }
+
+        // Health probes expose no data and orchestrators send arbitrary
+        // Host values, so they bypass the check.
+        if matches!(request.uri().path().as_str(), "/healthz" | "/readyz") {
+            return;
+        }
+
+        // Requests without a Host header can't be made by a browser, so they
+        // can't be used for DNS rebinding and are safe to let through.
+        let valid_host = match request.host() {
+            Some(host) => matches!(host.domain().as_str(), "127.0.0.1" | "localhost"),
+            None => true,
+        };
+
+        if !valid_host {
+            warn!(
+                "Blocked request with invalid host header: {:?}",
+                request.host()
+            );
+            request.set_uri(Origin::parse("/badhost").unwrap());
+        }
+    }
+}
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use gethostname::gethostname;
+use rocket::data::ToByteUnit;
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::Data;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use aw_datastore::{Datastore, DatastoreError};
+use aw_models::{Bucket, BucketMetadata, BucketsExport, Event, TryVec};
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::stats::get_timezone;
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+fn import(
+    state: &State<ServerState>,
+    auth: &ApiKeyAuth,
+    import: BucketsExport,
+) -> Result<Json<Value>, HttpErrorJson> {
+    let datastore = endpoints_get_lock!(state.datastore);
+    // The transactional import command rolls everything back on error, so
+    // a half-failed import never leaves partial buckets behind
+    let mut data = HashMap::new();
+    for (bucketname, mut bucket) in import.buckets {
+        let events = bucket.events.take_inner();
+        bucket.events = TryVec::new_empty();
+        data.insert(bucketname, (bucket, events));
+    }
+    let event_count: u64 = data.values().map(|(_, events)| events.len() as u64).sum();
+    auth.charge_quota(&datastore, event_count)?;
+    let batch = datastore.import(data)?;
+    Ok(Json(json!({ "batch": batch })))
+}
+
+#[post("/", data = "<json_data>", format = "application/json")]
+pub async fn bucket_import_json(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    json_data: Data<'_>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    // Imports write arbitrary buckets, so bucket-restricted keys may not
+    // use them
+    auth.require_all_buckets(Scope::Write)?;
+    let data_str = match json_data.open(1_i32.gibibytes()).into_string().await {
+        Ok(data_str) => data_str.into_inner(),
+        Err(err) => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to read import data: {err}"),
+            ))
+        }
+    };
+    let import_data: BucketsExport = match serde_json::from_str(&data_str) {
+        Ok(import_data) => import_data,
+        Err(err) => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to parse import data as JSON: {err}"),
+            ))
+        }
+    };
+    import(state, &auth, import_data)
+}
+
+/// Clients upload the file under the field name `buckets.json`, which
+/// Rocket's form parser reads as a nested field
+#[derive(FromForm)]
+pub struct ImportBuckets<'f> {
+    json: TempFile<'f>,
+}
+
+#[derive(FromForm)]
+pub struct ImportForm<'f> {
+    buckets: ImportBuckets<'f>,
+}
+
+/// Imports an aw-buckets-export.json file uploaded as multipart form
+/// data, for browsers posting the file from a form
+#[post("/", data = "<form_data>", format = "multipart/form-data")]
+pub async fn bucket_import_form(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    form_data: Form<ImportForm<'_>>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let tempfile = form_data.into_inner().buckets.json;
+    let mut data_str = String::new();
+    tempfile
+        .open()
+        .await
+        .map_err(|err| {
+            HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to open uploaded file: {err}"),
+            )
+        })?
+        .read_to_string(&mut data_str)
+        .await
+        .map_err(|err| {
+            HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to read uploaded file: {err}"),
+            )
+        })?;
+    let import_data: BucketsExport = match serde_json::from_str(&data_str) {
+        Ok(import_data) => import_data,
+        Err(err) => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to parse import data as JSON: {err}"),
+            ))
+        }
+    };
+    import(state, &auth, import_data)
+}
+
+/// State for a resumable chunked import, stored in the key_value table so
+/// interrupted imports can continue where they left off.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportSessionState {
+    /// The index of the next chunk the server expects
+    pub next_chunk: u64,
+    /// Checksum of the last successfully imported chunk
+    pub last_checksum: Option<String>,
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("import.session.{session_id}")
+}
+
+/// Returns the resume state of a chunked import session, so a client can
+/// continue an interrupted import without re-sending imported chunks.
+#[get("/chunked/<session_id>")]
+pub fn import_chunked_status(
+    session_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<ImportSessionState>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let session_state = match datastore.get_key_value(&session_key(session_id)) {
+        Ok(kv) => serde_json::from_str(&kv.value).map_err(|err| {
+            HttpErrorJson::new(
+                Status::InternalServerError,
+                format!("Failed to parse import session state: {err}"),
+            )
+        })?,
+        Err(DatastoreError::NoSuchKey(_)) => ImportSessionState {
+            next_chunk: 0,
+            last_checksum: None,
+        },
+        Err(err) => return Err(err.into()),
+    };
+    Ok(Json(session_state))
+}
+
+/// Imports a single chunk of a chunked import.
+///
+/// The chunk body is a regular buckets export containing a subset of the
+/// events, and is verified against the provided sha256 checksum before
+/// anything is written. Chunks must arrive in order, but re-sending an
+/// already imported chunk is accepted and ignored, so clients can safely
+/// retry after a connection failure. Events in chunks should carry explicit
+/// ids to make retried inserts idempotent.
+#[post(
+    "/chunked/<session_id>?<chunk>&<checksum>",
+    data = "<json_data>",
+    format = "application/json"
+)]
+pub async fn import_chunked(
+    session_id: &str,
+    chunk: u64,
+    checksum: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    json_data: Data<'_>,
+) -> Result<Json<ImportSessionState>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let data_str = match json_data.open(1_i32.gibibytes()).into_string().await {
+        Ok(data_str) => data_str.into_inner(),
+        Err(err) => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to read import data: {err}"),
+            ))
+        }
+    };
+
+    let actual_checksum = hex::encode(Sha256::digest(data_str.as_bytes()));
+    if actual_checksum != checksum.to_lowercase() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            format!("Chunk checksum mismatch, expected {checksum}, got {actual_checksum}"),
+        ));
+    }
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let key = session_key(session_id);
+    let mut session_state: ImportSessionState = match datastore.get_key_value(&key) {
+        Ok(kv) => serde_json::from_str(&kv.value).map_err(|err| {
+            HttpErrorJson::new(
+                Status::InternalServerError,
+                format!("Failed to parse import session state: {err}"),
+            )
+        })?,
+        Err(DatastoreError::NoSuchKey(_)) => ImportSessionState {
+            next_chunk: 0,
+            last_checksum: None,
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    // Already imported chunks are acknowledged without re-importing, so
+    // clients which never received our response can safely retry.
+    if chunk < session_state.next_chunk {
+        return Ok(Json(session_state));
+    }
+    if chunk > session_state.next_chunk {
+        return Err(HttpErrorJson::new(
+            Status::Conflict,
+            format!(
+                "Unexpected chunk {chunk}, expected chunk {}",
+                session_state.next_chunk
+            ),
+        ));
+    }
+
+    let import_data: BucketsExport = match serde_json::from_str(&data_str) {
+        Ok(import_data) => import_data,
+        Err(err) => {
+            return Err(HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to parse import data as JSON: {err}"),
+            ))
+        }
+    };
+
+    let mut chunks: Vec<(Bucket, Vec<Event>)> = Vec::new();
+    for (_bucketname, mut bucket) in import_data.buckets {
+        let events = bucket.events.take_inner();
+        bucket.events = aw_models::TryVec::new_empty();
+        chunks.push((bucket, events));
+    }
+    let event_count: u64 = chunks.iter().map(|(_, events)| events.len() as u64).sum();
+    auth.charge_quota(&datastore, event_count)?;
+    for (bucket, events) in chunks {
+        match datastore.create_bucket(&bucket) {
+            Ok(_) | Err(DatastoreError::BucketAlreadyExists(_)) => (),
+            Err(err) => return Err(err.into()),
+        }
+        if let Err(err) = datastore.insert_events(&bucket.id, &events) {
+            return Err(err.into());
+        }
+    }
+
+    session_state.next_chunk = chunk + 1;
+    session_state.last_checksum = Some(actual_checksum);
+    let session_state_str = serde_json::to_string(&session_state).unwrap();
+    match datastore.insert_key_value(&key, &session_state_str) {
+        Ok(_) => (),
+        Err(err) => return Err(err.into()),
+    }
+    // Make sure the imported events hit the disk together with the resume
+    // token, otherwise a crash could roll back behind what we acknowledged.
+    match datastore.force_commit() {
+        Ok(_) => Ok(Json(session_state)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Creates an import bucket if it doesn't exist yet
+fn ensure_import_bucket(
+    datastore: &Datastore,
+    bucket_id: &str,
+    _type: &str,
+) -> Result<(), HttpErrorJson> {
+    let bucket = Bucket {
+        bid: None,
+        id: bucket_id.to_string(),
+        _type: _type.to_string(),
+        client: "aw-server".to_string(),
+        hostname: gethostname()
+            .into_string()
+            .unwrap_or_else(|_| "unknown".to_string()),
+        created: None,
+        last_updated: None,
+        event_count: None,
+        total_duration: None,
+        data: Map::new(),
+        events: TryVec::new_empty(),
+        metadata: BucketMetadata::default(),
+    };
+    match datastore.create_bucket(&bucket) {
+        Ok(_) | Err(DatastoreError::BucketAlreadyExists(_)) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Looks up a CSV column by header name, case-insensitively
+fn csv_column(headers: &csv::StringRecord, name: &str) -> Result<usize, HttpErrorJson> {
+    headers
+        .iter()
+        .position(|header| header.trim().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Missing CSV column '{name}'"),
+            )
+        })
+}
+
+/// Interprets a naive timestamp from an export in the configured timezone
+/// (see the `timezone` setting), falling back to UTC around DST gaps
+fn localize(naive: NaiveDateTime, tz: &Tz) -> DateTime<Utc> {
+    match tz.from_local_datetime(&naive).earliest() {
+        Some(dt) => dt.with_timezone(&Utc),
+        None => DateTime::from_naive_utc_and_offset(naive, Utc),
+    }
+}
+
+async fn read_csv_body(csv_data: Data<'_>) -> Result<String, HttpErrorJson> {
+    match csv_data.open(1_i32.gibibytes()).into_string().await {
+        Ok(data_str) => Ok(data_str.into_inner()),
+        Err(err) => Err(HttpErrorJson::new(
+            Status::BadRequest,
+            format!("Failed to read import data: {err}"),
+        )),
+    }
+}
+
+/// Imports a Toggl detailed CSV export into the `toggl-import` bucket, so
+/// users migrating from Toggl keep their history. Project, client,
+/// description and tags are mapped to data keys; timestamps are interpreted
+/// in the configured timezone.
+#[post("/toggl", data = "<csv_data>")]
+pub async fn import_toggl(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    csv_data: Data<'_>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require(Scope::Write, Some("toggl-import"))?;
+    let data_str = read_csv_body(csv_data).await?;
+    let mut reader = csv::Reader::from_reader(data_str.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|err| {
+            HttpErrorJson::new(Status::BadRequest, format!("Failed to parse CSV: {err}"))
+        })?
+        .clone();
+    let start_date_col = csv_column(&headers, "Start date")?;
+    let start_time_col = csv_column(&headers, "Start time")?;
+    let duration_col = csv_column(&headers, "Duration")?;
+    let project_col = csv_column(&headers, "Project").ok();
+    let client_col = csv_column(&headers, "Client").ok();
+    let description_col = csv_column(&headers, "Description").ok();
+    let tags_col = csv_column(&headers, "Tags").ok();
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let tz = get_timezone(&datastore);
+
+    let mut events = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(|err| {
+            HttpErrorJson::new(Status::BadRequest, format!("Failed to parse CSV: {err}"))
+        })?;
+        let parse_err = |what: &str| {
+            HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to parse {what} on row {}", line + 1),
+            )
+        };
+        let naive = NaiveDateTime::parse_from_str(
+            &format!("{} {}", &record[start_date_col], &record[start_time_col]),
+            "%Y-%m-%d %H:%M:%S",
+        )
+        .map_err(|_| parse_err("start timestamp"))?;
+        // The duration column is formatted as HH:MM:SS
+        let parts: Vec<i64> = record[duration_col]
+            .split(':')
+            .map(|part| part.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| parse_err("duration"))?;
+        if parts.len() != 3 {
+            return Err(parse_err("duration"));
+        }
+        let duration = Duration::seconds(parts[0] * 3600 + parts[1] * 60 + parts[2]);
+
+        let mut data = Map::new();
+        for (col, key) in [
+            (project_col, "project"),
+            (client_col, "client"),
+            (description_col, "description"),
+            (tags_col, "tags"),
+        ] {
+            if let Some(col) = col {
+                if !record[col].is_empty() {
+                    data.insert(key.to_string(), Value::String(record[col].to_string()));
+                }
+            }
+        }
+        events.push(Event {
+            id: None,
+            timestamp: localize(naive, &tz),
+            duration,
+            data,
+        });
+    }
+
+    auth.charge_quota(&datastore, events.len() as u64)?;
+    ensure_import_bucket(&datastore, "toggl-import", "manualentry")?;
+    datastore.insert_events("toggl-import", &events)?;
+    Ok(Json(json!({
+        "bucket": "toggl-import",
+        "events_imported": events.len(),
+    })))
+}
+
+/// Imports a RescueTime activity log CSV export into the
+/// `rescuetime-import` bucket. Each row is one activity within a reporting
+/// slot; activity, category and productivity score are mapped to data keys.
+#[post("/rescuetime", data = "<csv_data>")]
+pub async fn import_rescuetime(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    csv_data: Data<'_>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require(Scope::Write, Some("rescuetime-import"))?;
+    let data_str = read_csv_body(csv_data).await?;
+    let mut reader = csv::Reader::from_reader(data_str.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|err| {
+            HttpErrorJson::new(Status::BadRequest, format!("Failed to parse CSV: {err}"))
+        })?
+        .clone();
+    let date_col = csv_column(&headers, "Date")?;
+    let seconds_col = csv_column(&headers, "Time Spent (seconds)")?;
+    let activity_col = csv_column(&headers, "Activity")?;
+    let category_col = csv_column(&headers, "Category").ok();
+    let productivity_col = csv_column(&headers, "Productivity").ok();
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let tz = get_timezone(&datastore);
+
+    let mut events = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.map_err(|err| {
+            HttpErrorJson::new(Status::BadRequest, format!("Failed to parse CSV: {err}"))
+        })?;
+        let parse_err = |what: &str| {
+            HttpErrorJson::new(
+                Status::BadRequest,
+                format!("Failed to parse {what} on row {}", line + 1),
+            )
+        };
+        let date_str = record[date_col].trim().to_string();
+        let naive = NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%dT%H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%d %H:%M:%S"))
+            .map_err(|_| parse_err("date"))?;
+        let seconds: i64 = record[seconds_col]
+            .trim()
+            .parse()
+            .map_err(|_| parse_err("time spent"))?;
+
+        let mut data = Map::new();
+        data.insert(
+            "activity".to_string(),
+            Value::String(record[activity_col].to_string()),
+        );
+        if let Some(col) = category_col {
+            data.insert("category".to_string(), Value::String(record[col].to_string()));
+        }
+        if let Some(col) = productivity_col {
+            if let Ok(productivity) = record[col].trim().parse::<i64>() {
+                data.insert("productivity".to_string(), Value::from(productivity));
+            }
+        }
+        events.push(Event {
+            id: None,
+            timestamp: localize(naive, &tz),
+            duration: Duration::seconds(seconds),
+            data,
+        });
+    }
+
+    auth.charge_quota(&datastore, events.len() as u64)?;
+    ensure_import_bucket(&datastore, "rescuetime-import", "activity")?;
+    datastore.insert_events("rescuetime-import", &events)?;
+    Ok(Json(json!({
+        "bucket": "rescuetime-import",
+        "events_imported": events.len(),
+    })))
+}
+
+/// Rolls back an import batch, deleting every event that arrived in it
+/// (across all buckets) in one datastore operation. Batch ids are
+/// returned by the import endpoints and recorded as provenance on the
+/// imported events. Unknown (or already rolled back) batch ids get a
+/// 404, so a mistyped id is distinguishable from a successful rollback.
+#[delete("/<batch_id>", rank = 2)]
+pub fn import_rollback(
+    batch_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Admin)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let deleted = datastore.delete_events_by_batch(batch_id)?;
+    if deleted == 0 {
+        return Err(HttpErrorJson::new(
+            Status::NotFound,
+            format!("No events belong to import batch '{batch_id}'"),
+        ));
+    }
+    Ok(Json(json!({ "events_deleted": deleted })))
+}
+
+/// Ends a chunked import session and removes its resume state.
+#[delete("/chunked/<session_id>")]
+pub fn import_chunked_finish(
+    session_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.delete_key_value(&session_key(session_id)) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::json;
+use serde_json::Value;
+
+use aw_transform::find_bucket;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::stats::{active_events, get_timezone};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+fn local_midnight(tz: &Tz, now: DateTime<Utc>) -> DateTime<Utc> {
+    let local = now.with_timezone(tz);
+    let midnight = local.date_naive().and_hms_opt(0, 0, 0).unwrap();
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        None => DateTime::from_naive_utc_and_offset(midnight, Utc),
+    }
+}
+
+/// Compact state payload for Home Assistant: the current app and title, AFK
+/// state and today's active time in one call, so HA sensors can poll it
+/// without custom templates. Fields are null when the matching watcher
+/// bucket is missing or empty.
+#[get("/homeassistant")]
+pub fn homeassistant_state(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    // Same derived reads of the window and AFK buckets as the stats
+    // endpoints, so the same unrestricted Read key is required
+    auth.require_all_buckets(Scope::Read)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let buckets = datastore.get_buckets()?;
+
+    let mut current_app = Value::Null;
+    let mut current_title = Value::Null;
+    if let Some(window_bucket) = find_bucket("aw-watcher-window", &None, buckets.values()) {
+        if let Some(event) = datastore.get_events(&window_bucket, None, None, Some(1))?.pop() {
+            current_app = event.data.get("app").cloned().unwrap_or(Value::Null);
+            current_title = event.data.get("title").cloned().unwrap_or(Value::Null);
+        }
+    }
+
+    let mut afk = Value::Null;
+    if let Some(afk_bucket) = find_bucket("aw-watcher-afk", &None, buckets.values()) {
+        if let Some(event) = datastore.get_events(&afk_bucket, None, None, Some(1))?.pop() {
+            if let Some(status) = event.data.get("status").and_then(|value| value.as_str()) {
+                afk = Value::Bool(status != "not-afk");
+            }
+        }
+    }
+
+    let now = Utc::now();
+    let tz = get_timezone(&datastore);
+    let active_seconds_today = match active_events(&datastore, Some(local_midnight(&tz, now)), Some(now)) {
+        Ok(events) => Value::from(
+            events
+                .iter()
+                .map(|event| event.duration.num_milliseconds() as f64 / 1000.0)
+                .sum::<f64>(),
+        ),
+        // Without both watcher buckets today's total can't be computed
+        Err(_) => Value::Null,
+    };
+
+    Ok(Json(json!({
+        "current_app": current_app,
+        "current_title": current_title,
+        "afk": afk,
+        "active_seconds_today": active_seconds_today,
+    })))
+}
+
+/// MQTT discovery messages for the sensors backing the state payload.
+/// Returns topic/payload pairs following the Home Assistant discovery
+/// convention, ready to be published (retained) to a broker by any MQTT
+/// client, so the sensors show up without manual configuration.
+#[get("/homeassistant/discovery?<state_topic>")]
+pub fn homeassistant_discovery(
+    state_topic: Option<&str>,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    let state_topic = state_topic.unwrap_or("activitywatch/state");
+    let device_id = state.device_id.clone();
+    let device = json!({
+        "identifiers": [format!("activitywatch_{device_id}")],
+        "name": "ActivityWatch",
+    });
+    let sensors = [
+        ("current_app", "Current app", None),
+        ("current_title", "Current title", None),
+        ("afk", "AFK", None),
+        ("active_seconds_today", "Active time today", Some("s")),
+    ];
+    let messages = sensors
+        .iter()
+        .map(|(key, name, unit)| {
+            let mut payload = json!({
+                "name": name,
+                "unique_id": format!("activitywatch_{device_id}_{key}"),
+                "state_topic": state_topic,
+                "value_template": format!("{{{{ value_json.{key} }}}}"),
+                "device": device,
+            });
+            if let Some(unit) = unit {
+                payload["unit_of_measurement"] = json!(unit);
+            }
+            json!({
+                "topic": format!("homeassistant/sensor/activitywatch_{device_id}/{key}/config"),
+                "payload": payload,
+            })
+        })
+        .collect();
+    Ok(Json(messages))
+}
@@ -0,0 +1,72 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::json;
+use serde_json::Value;
+
+use aw_models::Query;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+use crate::jobs;
+use crate::jobs::QueryJob;
+
+/// Submits a query for asynchronous execution, returning the job id to poll
+#[post("/", data = "<message>", format = "application/json")]
+pub fn job_submit(
+    message: Json<Query>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    // Jobs execute the same query code as /api/0/query, so they need the
+    // same unrestricted Read key
+    auth.require_all_buckets(Scope::Read)?;
+    let query = message.into_inner();
+    if query.query.is_empty() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Empty query".to_string(),
+        ));
+    }
+    let job = QueryJob::new(query);
+    let datastore = endpoints_get_lock!(state.datastore);
+    jobs::save_job(&datastore, &job);
+    Ok(Json(json!({ "id": job.id })))
+}
+
+#[get("/<job_id>")]
+pub fn job_get(
+    job_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<QueryJob>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&format!("{}{job_id}", jobs::JOB_PREFIX))?;
+    let job: QueryJob = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse job: {err}"),
+        )
+    })?;
+    Ok(Json(job))
+}
+
+#[delete("/<job_id>")]
+pub fn job_delete(
+    job_id: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.delete_key_value(&format!("{}{job_id}", jobs::JOB_PREFIX)) {
+        Ok(_) => {
+            // If the job is mid-execution, abort the query too
+            jobs::cancel(job_id);
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,296 @@
+use std::sync::Mutex;
+
+use gethostname::gethostname;
+use rocket::serde::json::Json;
+use rocket::{Build, Rocket, State};
+
+use crate::config::AWConfig;
+
+use aw_datastore::Datastore;
+use aw_models::Info;
+
+pub mod alert;
+pub mod anomaly;
+pub mod apikey;
+pub mod bucket;
+pub mod budget;
+pub mod chaos;
+pub mod cors;
+pub mod dashboard;
+pub mod dbmetrics;
+pub mod debug;
+pub mod device;
+pub mod export;
+pub mod health;
+pub mod hostcheck;
+pub mod import;
+pub mod integration;
+pub mod job;
+pub mod query;
+pub mod recorder;
+pub mod report;
+pub mod requestid;
+pub mod retention;
+pub mod schedule;
+pub mod settings;
+pub mod stats;
+pub mod stream;
+pub mod summary;
+pub mod timeentry;
+pub mod util;
+
+pub struct ServerState {
+    pub datastore: Mutex<Datastore>,
+    pub device_id: String,
+}
+
+#[get("/")]
+fn server_info(config: &State<AWConfig>, state: &State<ServerState>) -> Json<Info> {
+    let hostname = gethostname().into_string().unwrap_or_else(|_| "unknown".to_string());
+    const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+    Json(Info {
+        hostname,
+        version: format!("v{VERSION} (rust)"),
+        testing: config.testing,
+        device_id: state.device_id.clone(),
+    })
+}
+
+pub fn build_rocket(server_state: ServerState, config: AWConfig) -> Rocket<Build> {
+    info!(
+        "Starting aw-server-rust at {}:{}",
+        config.address, config.port
+    );
+    let cors = cors::cors(&config);
+    let hostcheck = hostcheck::HostCheck::new(&config);
+
+    // The job dispatcher gets its own handle to the datastore, so it can
+    // execute jobs without holding the endpoint lock
+    {
+        let datastore = server_state
+            .datastore
+            .lock()
+            .expect("Failed to lock datastore")
+            .clone();
+        crate::jobs::start(datastore, config.query_limits());
+    }
+
+    debug::init();
+
+    let mut rocket = rocket::custom(config.to_rocket_config())
+        .attach(cors)
+        .attach(hostcheck)
+        .attach(crate::diskguard::DiskGuard)
+        .attach(requestid::RequestId);
+    if config.testing {
+        rocket = rocket
+            .attach(dbmetrics::DbMetrics)
+            .attach(recorder::Recorder)
+            .attach(chaos::Chaos);
+    }
+    rocket
+        .mount(
+            "/",
+            routes![
+                hostcheck::badhost,
+                crate::diskguard::dbfull,
+                health::healthz,
+                health::readyz,
+            ],
+        )
+        .mount("/api/0/info", routes![server_info])
+        .mount("/api/0/summary", routes![summary::summary_get])
+        .mount(
+            "/api/0/anomalies",
+            routes![anomaly::anomalies_get, anomaly::anomalies_check],
+        )
+        .mount(
+            "/api/0/retention",
+            routes![retention::retention_report, retention::retention_run],
+        )
+        .mount(
+            "/api/0/dashboards",
+            routes![
+                dashboard::dashboards_list,
+                dashboard::dashboard_get,
+                dashboard::dashboard_set,
+                dashboard::dashboard_delete,
+                dashboard::dashboard_categories_export,
+                dashboard::dashboard_categories_import,
+            ],
+        )
+        .mount(
+            "/api/0/device",
+            routes![device::device_get, device::device_set],
+        )
+        .mount(
+            "/api/0/buckets",
+            routes![
+                bucket::bucket_new,
+                bucket::bucket_delete,
+                bucket::buckets_get,
+                bucket::bucket_get,
+                bucket::bucket_events_get,
+                bucket::bucket_events_stream,
+                bucket::bucket_events_aggregate,
+                bucket::bucket_events_suggest,
+                bucket::bucket_events_explain,
+                bucket::bucket_events_downsampled,
+                bucket::bucket_events_create,
+                bucket::bucket_events_heartbeat,
+                bucket::bucket_events_heartbeat_batch,
+                bucket::bucket_event_count,
+                bucket::bucket_events_delete_by_id,
+                bucket::bucket_events_update,
+                bucket::bucket_event_split,
+                bucket::bucket_export,
+                bucket::bucket_copy,
+                bucket::bucket_archive,
+            ],
+        )
+        .mount(
+            "/api/0/trash",
+            routes![
+                bucket::trash_list,
+                bucket::trash_restore,
+                bucket::trash_empty,
+            ],
+        )
+        .mount(
+            "/api/0/export",
+            routes![
+                export::buckets_export,
+                export::buckets_export_encrypted,
+                export::export_timewarrior,
+            ],
+        )
+        .mount(
+            "/api/0/import",
+            routes![
+                import::bucket_import_json,
+                import::bucket_import_form,
+                import::import_chunked_status,
+                import::import_chunked,
+                import::import_chunked_finish,
+                import::import_rollback,
+                import::import_toggl,
+                import::import_rescuetime,
+            ],
+        )
+        .mount(
+            "/api/0/query",
+            routes![query::query, query::query_validate],
+        )
+        .mount(
+            "/api/0/queries",
+            routes![
+                query::queries_list,
+                query::query_get,
+                query::query_set,
+                query::query_delete,
+                query::query_run,
+                query::query_columnar,
+            ],
+        )
+        .mount(
+            "/api/0/jobs",
+            routes![job::job_submit, job::job_get, job::job_delete],
+        )
+        .mount(
+            "/api/0/schedules",
+            routes![
+                schedule::schedules_list,
+                schedule::schedule_get,
+                schedule::schedule_set,
+                schedule::schedule_delete,
+                schedule::schedule_results,
+                schedule::schedule_run,
+            ],
+        )
+        .mount(
+            "/api/0/budgets",
+            routes![
+                budget::budgets_list,
+                budget::budget_get,
+                budget::budget_set,
+                budget::budget_delete,
+                budget::budget_progress,
+            ],
+        )
+        .mount(
+            "/api/0/alerts",
+            routes![
+                alert::alerts_list,
+                alert::alert_get,
+                alert::alert_set,
+                alert::alert_delete,
+                alert::alert_state,
+                alert::alerts_check,
+            ],
+        )
+        .mount(
+            "/api/0/stats",
+            routes![
+                stats::stats_active,
+                stats::stats_heatmap,
+                stats::stats_working_hours
+            ],
+        )
+        .mount(
+            "/api/0/reports",
+            routes![
+                report::reports_list,
+                report::report_get,
+                report::report_set,
+                report::report_delete,
+                report::report_run,
+            ],
+        )
+        .mount(
+            "/api/0/integrations",
+            routes![
+                integration::homeassistant_state,
+                integration::homeassistant_discovery,
+            ],
+        )
+        .mount(
+            "/api/0/timeentries",
+            routes![
+                timeentry::entries_get,
+                timeentry::entry_create,
+                timeentry::entry_delete,
+            ],
+        )
+        .mount("/api/0/ws", routes![stream::event_stream])
+        .mount(
+            "/debug",
+            routes![
+                debug::debug_stats,
+                recorder::recording_get,
+                recorder::recording_clear,
+                chaos::chaos_get,
+                chaos::chaos_set,
+                chaos::chaos_clear,
+            ],
+        )
+        .mount(
+            "/api/0/apikeys",
+            routes![
+                apikey::apikey_create,
+                apikey::apikey_list,
+                apikey::apikey_delete,
+            ],
+        )
+        .mount(
+            "/api/0/settings",
+            routes![
+                settings::settings_list_get,
+                settings::setting_get,
+                settings::setting_set,
+                settings::setting_delete,
+            ],
+        )
+        .manage(server_state)
+        .manage(config)
+}
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use aw_datastore::QUERY_CACHE_PREFIX;
+use aw_models::Query;
+use aw_models::TimeInterval;
+use aw_query::DataType;
+
+use crate::config::AWConfig;
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Saved queries are stored in the key_value table, prefixed with `query.`
+static QUERY_PREFIX: &str = "query.";
+
+fn parse_name(name: &str) -> Result<String, HttpErrorJson> {
+    let namespace = QUERY_PREFIX.to_string();
+    if name.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long query name".to_string(),
+        ));
+    }
+    Ok(namespace + name)
+}
+
+/// A named query-language query. Occurrences of `{{param}}` in the query are
+/// replaced with the JSON-serialized parameter value when the query is run.
+#[derive(Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub query: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct QueryRunRequest {
+    timeperiods: Vec<TimeInterval>,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+}
+
+/// Runs a query for one timeperiod, optionally through the server-side
+/// result cache. Only closed timeperiods (fully in the past) are cached,
+/// keyed by a hash of the query code and the timeperiod. The datastore
+/// worker drops entries whose timeperiod overlaps modified events, so a
+/// cache hit is always current.
+fn run_query_cached(
+    datastore: &aw_datastore::Datastore,
+    code: &str,
+    interval: &TimeInterval,
+    limits: &aw_query::QueryLimits,
+    use_cache: bool,
+) -> Result<Value, HttpErrorJson> {
+    let cacheable = use_cache && *interval.end() < Utc::now();
+    let key = format!(
+        "{QUERY_CACHE_PREFIX}{}",
+        hex::encode(Sha256::digest(format!("{code}\n{interval}")))
+    );
+    if cacheable {
+        if let Ok(kv) = datastore.get_key_value(&key) {
+            if let Ok(entry) = serde_json::from_str::<Value>(&kv.value) {
+                if let Some(result) = entry.get("result") {
+                    return Ok(result.clone());
+                }
+            }
+        }
+    }
+    let result = aw_query::query_with_limits(code, interval, datastore, limits)
+        .map_err(|err| {
+            HttpErrorJson::new(Status::BadRequest, format!("Query error: {err}"))
+        })?;
+    let value = serde_json::to_value(&result).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to serialize query result: {err}"),
+        )
+    })?;
+    if cacheable {
+        let entry = json!({
+            "start": interval.start().to_rfc3339(),
+            "end": interval.end().to_rfc3339(),
+            "result": value,
+        });
+        if let Err(err) = datastore.insert_key_value(&key, &entry.to_string()) {
+            warn!("Failed to cache query result: {err}");
+        }
+    }
+    Ok(value)
+}
+
+/// Evaluates an ad-hoc query-language query for each of the given
+/// timeperiods, without saving it first. This is the endpoint aw-webui
+/// uses for its timeline and category views. Pass `cache=true` to serve
+/// closed timeperiods from the query result cache.
+#[post("/?<cache>", data = "<message>", format = "application/json")]
+pub fn query(
+    cache: Option<bool>,
+    message: Json<Query>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    // Queries read whatever buckets they name, so bucket-restricted
+    // keys may not run them
+    auth.require_all_buckets(Scope::Read)?;
+    let request = message.into_inner();
+    if request.query.is_empty() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Empty query".to_string(),
+        ));
+    }
+    let code = request.query.join("\n");
+    let datastore = endpoints_get_lock!(state.datastore);
+    let limits = config.query_limits();
+    let use_cache = cache.unwrap_or(false);
+    let mut results = Vec::new();
+    for interval in &request.timeperiods {
+        results.push(run_query_cached(
+            &datastore, &code, interval, &limits, use_cache,
+        )?);
+    }
+    Ok(Json(results))
+}
+
+#[derive(Deserialize)]
+pub struct ValidateRequest {
+    query: Vec<String>,
+}
+
+/// Statically checks a query without executing it: syntax errors,
+/// unknown functions, undefined variables and references to buckets
+/// that don't exist (or aren't visible to the presented key). Gives
+/// query editors instant feedback without the cost of a real run.
+#[post("/validate", data = "<message>", format = "application/json")]
+pub fn query_validate(
+    message: Json<ValidateRequest>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let code = message.into_inner().query.join("\n");
+    let mut analysis = aw_query::validate(&code);
+    let datastore = endpoints_get_lock!(state.datastore);
+    let buckets = datastore.get_buckets()?;
+    for bucket_id in &analysis.bucket_ids {
+        if !buckets.contains_key(bucket_id) || !auth.bucket_visible(bucket_id) {
+            analysis
+                .warnings
+                .push(format!("Bucket '{bucket_id}' does not exist"));
+        }
+    }
+    Ok(Json(json!({
+        "valid": analysis.errors.is_empty(),
+        "errors": analysis.errors,
+        "warnings": analysis.warnings,
+        "buckets": analysis.bucket_ids,
+    })))
+}
+
+#[get("/")]
+pub fn queries_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{QUERY_PREFIX}%"))?;
+    let names = keys
+        .into_iter()
+        .map(|key| key[QUERY_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(names))
+}
+
+#[get("/<name>")]
+pub fn query_get(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<SavedQuery>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let saved: SavedQuery = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse saved query: {err}"),
+        )
+    })?;
+    Ok(Json(saved))
+}
+
+#[post("/<name>", data = "<message>", format = "application/json")]
+pub fn query_set(
+    name: &str,
+    message: Json<SavedQuery>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Status, HttpErrorJson> {
+    // Saved queries are later executed by schedules, alerts and budgets
+    // with access to all buckets, so defining them requires the same
+    // unrestricted key that running them does
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let saved = message.into_inner();
+    if saved.query.is_empty() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Empty query".to_string(),
+        ));
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.insert_key_value(&key, &serde_json::to_string(&saved).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<name>")]
+pub fn query_delete(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.delete_key_value(&key) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Runs a saved query for each of the given timeperiods, returning one
+/// result per timeperiod. Dashboards and scheduled reports can this way
+/// reference one canonical query definition. Pass `cache=true` to serve
+/// closed timeperiods from the query result cache.
+#[post("/<name>/run?<cache>", data = "<message>", format = "application/json")]
+pub fn query_run(
+    name: &str,
+    cache: Option<bool>,
+    message: Json<QueryRunRequest>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let key = parse_name(name)?;
+    let request = message.into_inner();
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let saved: SavedQuery = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse saved query: {err}"),
+        )
+    })?;
+
+    let mut code = saved.query.join("\n");
+    for (param, value) in &request.params {
+        code = code.replace(&format!("{{{{{param}}}}}"), &value.to_string());
+    }
+
+    let limits = config.query_limits();
+    let use_cache = cache.unwrap_or(false);
+    let mut results = Vec::new();
+    for interval in &request.timeperiods {
+        results.push(run_query_cached(
+            &datastore, &code, interval, &limits, use_cache,
+        )?);
+    }
+    Ok(Json(results))
+}
+
+/// Runs a saved query like `query_run`, but returns the resulting events
+/// in a columnar layout (one array per field, with explicit dtypes) that
+/// pandas parses much faster than row-oriented JSON for large analyses.
+/// The query must return a list of events for each timeperiod; the
+/// `timeperiod` column holds the index of the timeperiod each row came
+/// from.
+#[post("/<name>/columnar", data = "<message>", format = "application/json")]
+pub fn query_columnar(
+    name: &str,
+    message: Json<QueryRunRequest>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    config: &State<AWConfig>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let key = parse_name(name)?;
+    let request = message.into_inner();
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let saved: SavedQuery = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse saved query: {err}"),
+        )
+    })?;
+
+    let mut code = saved.query.join("\n");
+    for (param, value) in &request.params {
+        code = code.replace(&format!("{{{{{param}}}}}"), &value.to_string());
+    }
+
+    let limits = config.query_limits();
+    let mut events = Vec::new();
+    for (index, interval) in request.timeperiods.iter().enumerate() {
+        let result = aw_query::query_with_limits(&code, interval, &datastore, &limits)
+            .map_err(|err| {
+                HttpErrorJson::new(Status::BadRequest, format!("Query error: {err}"))
+            })?;
+        let items = match result {
+            DataType::List(items) => items,
+            other => {
+                return Err(HttpErrorJson::new(
+                    Status::BadRequest,
+                    format!("Query must return a list of events, got {other:?}"),
+                ))
+            }
+        };
+        for item in items {
+            match item {
+                DataType::Event(event) => events.push((index, event)),
+                other => {
+                    return Err(HttpErrorJson::new(
+                        Status::BadRequest,
+                        format!("Query returned a non-event item: {other:?}"),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(Json(columnar_events(&events)))
+}
+
+/// Builds the columnar representation: fixed columns for timeperiod,
+/// timestamp and duration, plus one `data.<key>` column per data key seen
+/// across the events, null-padded where missing
+fn columnar_events(events: &[(usize, aw_models::Event)]) -> Value {
+    let mut data_keys: Vec<String> = Vec::new();
+    for (_, event) in events {
+        for key in event.data.keys() {
+            if !data_keys.contains(key) {
+                data_keys.push(key.clone());
+            }
+        }
+    }
+    data_keys.sort();
+
+    let mut columns = serde_json::Map::new();
+    columns.insert(
+        "timeperiod".to_string(),
+        column(
+            "int64",
+            events.iter().map(|(index, _)| Value::from(*index)).collect(),
+        ),
+    );
+    columns.insert(
+        "timestamp".to_string(),
+        column(
+            "datetime64[ns, UTC]",
+            events
+                .iter()
+                .map(|(_, event)| Value::from(event.timestamp.to_rfc3339()))
+                .collect(),
+        ),
+    );
+    columns.insert(
+        "duration".to_string(),
+        column(
+            "float64",
+            events
+                .iter()
+                .map(|(_, event)| {
+                    Value::from(event.duration.num_milliseconds() as f64 / 1000.0)
+                })
+                .collect(),
+        ),
+    );
+    for key in data_keys {
+        let values: Vec<Value> = events
+            .iter()
+            .map(|(_, event)| event.data.get(&key).cloned().unwrap_or(Value::Null))
+            .collect();
+        let dtype = infer_dtype(&values);
+        columns.insert(format!("data.{key}"), column(dtype, values));
+    }
+
+    let mut result = serde_json::Map::new();
+    result.insert("length".to_string(), Value::from(events.len()));
+    result.insert("columns".to_string(), Value::Object(columns));
+    Value::Object(result)
+}
+
+fn column(dtype: &str, values: Vec<Value>) -> Value {
+    let mut column = serde_json::Map::new();
+    column.insert("dtype".to_string(), Value::from(dtype));
+    column.insert("values".to_string(), Value::Array(values));
+    Value::Object(column)
+}
+
+/// "float64" if every non-null value is a number, "bool" if every
+/// non-null value is a bool, "object" otherwise
+fn infer_dtype(values: &[Value]) -> &'static str {
+    let mut non_null = values.iter().filter(|value| !value.is_null()).peekable();
+    if non_null.peek().is_none() {
+        return "object";
+    }
+    if non_null.clone().all(|value| value.is_number()) {
+        return "float64";
+    }
+    if non_null.all(|value| value.is_boolean()) {
+        return "bool";
+    }
+    "object"
+}
@@ -0,0 +1,128 @@
+//! Request/response recorder for debugging: keeps a ring buffer of
+//! recent API traffic (method, URI, sanitized headers, a body prefix
+//! and the response status) that can be dumped via an endpoint, so a
+//! client-reported bug can be reproduced without packet captures. Only
+//! attached in testing mode, like the per-request DB metrics headers.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::Utc;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{Data, Request, Response, State};
+use serde_json::{json, Value};
+
+use crate::config::AWConfig;
+use crate::endpoints::util::HttpErrorJson;
+
+/// How many exchanges to keep; older ones are dropped
+const RING_CAPACITY: usize = 512;
+
+/// How much of a request body to capture. Rocket only buffers this much
+/// for peeking anyway, and a prefix is enough to identify a payload.
+const BODY_PEEK_BYTES: usize = 512;
+
+/// Headers whose values are credentials and must never appear in a dump
+const REDACTED_HEADERS: [&str; 2] = ["x-api-key", "authorization"];
+
+static RING: Mutex<VecDeque<Value>> = Mutex::new(VecDeque::new());
+
+#[derive(Default)]
+struct RequestCapture {
+    started: Option<Instant>,
+    body: Option<String>,
+}
+
+pub struct Recorder;
+
+#[rocket::async_trait]
+impl Fairing for Recorder {
+    fn info(&self) -> Info {
+        Info {
+            name: "Recorder",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        if request.uri().path().starts_with("/debug") {
+            return;
+        }
+        let started = Instant::now();
+        let body = match request.content_type() {
+            Some(ct) if ct.is_json() => {
+                let peeked = data.peek(BODY_PEEK_BYTES).await;
+                Some(String::from_utf8_lossy(peeked).into_owned())
+            }
+            _ => None,
+        };
+        request.local_cache(|| RequestCapture {
+            started: Some(started),
+            body,
+        });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let capture = request.local_cache(RequestCapture::default);
+        let Some(started) = capture.started else {
+            // Recording of this request was skipped in on_request
+            return;
+        };
+        let headers: Vec<Value> = request
+            .headers()
+            .iter()
+            .map(|header| {
+                let value = if REDACTED_HEADERS.contains(&header.name().as_str().to_lowercase().as_str()) {
+                    "[redacted]"
+                } else {
+                    header.value()
+                };
+                json!([header.name().as_str(), value])
+            })
+            .collect();
+        let record = json!({
+            "timestamp": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "method": request.method().as_str(),
+            "uri": request.uri().to_string(),
+            "headers": headers,
+            "body": capture.body,
+            "status": response.status().code,
+            "duration_ms": started.elapsed().as_secs_f64() * 1000.0,
+        });
+        let mut ring = RING.lock().expect("Recorder ring poisoned");
+        if ring.len() >= RING_CAPACITY {
+            ring.pop_front();
+        }
+        ring.push_back(record);
+    }
+}
+
+/// Dumps the recorded exchanges, oldest first
+#[get("/recording")]
+pub fn recording_get(config: &State<AWConfig>) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    if !config.testing {
+        return Err(HttpErrorJson::new(
+            Status::Forbidden,
+            "The recorder is only available in testing mode".to_string(),
+        ));
+    }
+    let ring = RING.lock().expect("Recorder ring poisoned");
+    Ok(Json(ring.iter().cloned().collect()))
+}
+
+/// Clears the ring buffer, e.g. right before reproducing a bug so the
+/// dump afterwards only contains the interesting traffic
+#[delete("/recording")]
+pub fn recording_clear(config: &State<AWConfig>) -> Result<(), HttpErrorJson> {
+    if !config.testing {
+        return Err(HttpErrorJson::new(
+            Status::Forbidden,
+            "The recorder is only available in testing mode".to_string(),
+        ));
+    }
+    RING.lock().expect("Recorder ring poisoned").clear();
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+use crate::reports;
+use crate::reports::ReportDef;
+
+fn parse_name(name: &str) -> Result<String, HttpErrorJson> {
+    let namespace = reports::REPORT_PREFIX.to_string();
+    if name.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long report name".to_string(),
+        ));
+    }
+    Ok(namespace + name)
+}
+
+#[get("/")]
+pub fn reports_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{}%", reports::REPORT_PREFIX))?;
+    let names = keys
+        .into_iter()
+        .map(|key| key[reports::REPORT_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(names))
+}
+
+#[get("/<name>")]
+pub fn report_get(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<ReportDef>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let def: ReportDef = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse report: {err}"),
+        )
+    })?;
+    Ok(Json(def))
+}
+
+#[post("/<name>", data = "<message>", format = "application/json")]
+pub fn report_set(
+    name: &str,
+    message: Json<ReportDef>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Status, HttpErrorJson> {
+    // A report posts activity summaries to whatever webhook URL its
+    // definition names, so changing definitions is admin territory:
+    // anything less would let a Write key exfiltrate data on schedule
+    auth.require_all_buckets(Scope::Admin)?;
+    let key = parse_name(name)?;
+    let def = message.into_inner();
+    if let Err(err) = def.cron.parse::<cron::Schedule>() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            format!("Invalid cron expression: {err}"),
+        ));
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.insert_key_value(&key, &serde_json::to_string(&def).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<name>")]
+pub fn report_delete(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Admin)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    datastore.delete_key_value(&key)?;
+    datastore.delete_key_value(&format!("{}{name}", reports::STATE_PREFIX))?;
+    Ok(())
+}
+
+/// Posts the report immediately, regardless of its cron expression, and
+/// returns the composed message for inspection
+#[post("/<name>/run")]
+pub fn report_run(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    // Running a report composes (and returns) an activity summary over
+    // all buckets, like direct query execution
+    auth.require_all_buckets(Scope::Read)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let def: ReportDef = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse report: {err}"),
+        )
+    })?;
+    let message = reports::run_report(&datastore, &def, Utc::now())
+        .map_err(|err| HttpErrorJson::new(Status::BadRequest, err))?;
+    Ok(Json(json!({ "message": message })))
+}
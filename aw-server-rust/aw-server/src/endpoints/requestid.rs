@@ -0,0 +1,61 @@
+//! Request-ID middleware: every request gets an id, taken from an
+//! incoming `X-Request-Id` header or generated, set on the response and
+//! logged with the request's outcome, so a client-reported error can be
+//! correlated to the matching server log lines.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use uuid::Uuid;
+
+/// Incoming ids longer than this are ignored and replaced, so a client
+/// can't stuff arbitrary payloads into the logs
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+#[derive(Clone, Default)]
+struct RequestIdValue(String);
+
+pub struct RequestId;
+
+#[rocket::async_trait]
+impl Fairing for RequestId {
+    fn info(&self) -> Info {
+        Info {
+            name: "RequestId",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let id = request
+            .headers()
+            .get_one("X-Request-Id")
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LEN
+                    && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        request.local_cache(|| RequestIdValue(id));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let RequestIdValue(id) = request.local_cache(RequestIdValue::default);
+        response.set_header(Header::new("X-Request-Id", id.clone()));
+        let status = response.status();
+        if status.code >= 400 {
+            warn!(
+                "[{id}] {} {} -> {status}",
+                request.method(),
+                request.uri()
+            );
+        } else {
+            debug!(
+                "[{id}] {} {} -> {status}",
+                request.method(),
+                request.uri()
+            );
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Endpoints for the retention policy engine: a dry-run report of what
+//! the current rules would delete, and a way to force an enforcement
+//! pass without waiting for the background interval. The rules
+//! themselves are ordinary settings under the `retention` key.
+
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::Value;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+use crate::retention;
+
+/// What an enforcement pass right now would delete, per bucket with a
+/// matching rule; nothing is deleted
+#[get("/report")]
+pub fn retention_report(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    Ok(Json(retention::report(&datastore, Utc::now())))
+}
+
+/// Runs an enforcement pass immediately, regardless of the interval.
+/// Deleting data across buckets is admin territory.
+#[post("/run")]
+pub fn retention_run(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Admin)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    retention::enforce(&datastore, Utc::now());
+    Ok(())
+}
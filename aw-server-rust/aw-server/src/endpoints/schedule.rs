@@ -0,0 +1,141 @@
+use chrono::Utc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::Value;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+use crate::scheduler;
+use crate::scheduler::ScheduledQuery;
+
+fn parse_name(name: &str) -> Result<String, HttpErrorJson> {
+    let namespace = scheduler::SCHEDULE_PREFIX.to_string();
+    if name.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long schedule name".to_string(),
+        ));
+    }
+    Ok(namespace + name)
+}
+
+#[get("/")]
+pub fn schedules_list(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{}%", scheduler::SCHEDULE_PREFIX))?;
+    let names = keys
+        .into_iter()
+        .map(|key| key[scheduler::SCHEDULE_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(names))
+}
+
+#[get("/<name>")]
+pub fn schedule_get(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<ScheduledQuery>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let def: ScheduledQuery = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse schedule: {err}"),
+        )
+    })?;
+    Ok(Json(def))
+}
+
+#[post("/<name>", data = "<message>", format = "application/json")]
+pub fn schedule_set(
+    name: &str,
+    message: Json<ScheduledQuery>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Status, HttpErrorJson> {
+    // Schedules run queries against all buckets in the background, so
+    // defining them requires an unrestricted Write key
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let def = message.into_inner();
+    if let Err(err) = def.cron.parse::<cron::Schedule>() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            format!("Invalid cron expression: {err}"),
+        ));
+    }
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.insert_key_value(&key, &serde_json::to_string(&def).unwrap()) {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<name>")]
+pub fn schedule_delete(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Write)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    datastore.delete_key_value(&key)?;
+    datastore.delete_key_value(&format!("{}{name}", scheduler::STATE_PREFIX))?;
+    Ok(())
+}
+
+/// Returns the materialized results for a schedule, oldest first
+#[get("/<name>/results")]
+pub fn schedule_results(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Value>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let mut keys =
+        datastore.get_keys_starting(&format!("{}{name}.%", scheduler::RESULT_PREFIX))?;
+    // rfc3339 timestamps with a fixed offset sort chronologically
+    keys.sort();
+    let mut results = Vec::new();
+    for key in keys {
+        let kv = datastore.get_key_value(&key)?;
+        match serde_json::from_str(&kv.value) {
+            Ok(value) => results.push(value),
+            Err(err) => warn!("Failed to parse materialized result '{key}': {err}"),
+        }
+    }
+    Ok(Json(results))
+}
+
+/// Runs the schedule immediately, regardless of its cron expression
+#[post("/<name>/run")]
+pub fn schedule_run(
+    name: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let key = parse_name(name)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&key)?;
+    let def: ScheduledQuery = serde_json::from_str(&kv.value).map_err(|err| {
+        HttpErrorJson::new(
+            Status::InternalServerError,
+            format!("Failed to parse schedule: {err}"),
+        )
+    })?;
+    scheduler::run_schedule(&datastore, name, &def, Utc::now())
+        .map_err(|err| HttpErrorJson::new(Status::BadRequest, err))
+}
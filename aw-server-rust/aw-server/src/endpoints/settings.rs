@@ -0,0 +1,89 @@
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::Value;
+
+use rocket::http::Status;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Settings are stored in the key_value table, prefixed with `settings.`
+static SETTINGS_PREFIX: &str = "settings.";
+
+fn parse_key(key: &str) -> Result<String, HttpErrorJson> {
+    let namespace = SETTINGS_PREFIX.to_string();
+    if key.len() >= 128 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Too long key".to_string(),
+        ));
+    }
+    Ok(namespace + key)
+}
+
+#[get("/")]
+pub fn settings_list_get(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<String>>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let keys = datastore.get_keys_starting(&format!("{SETTINGS_PREFIX}%"))?;
+    let keys = keys
+        .into_iter()
+        .map(|key| key[SETTINGS_PREFIX.len()..].to_string())
+        .collect();
+    Ok(Json(keys))
+}
+
+#[get("/<key>")]
+pub fn setting_get(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    key: &str,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let setting_key = parse_key(key)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let kv = datastore.get_key_value(&setting_key)?;
+    let value = serde_json::from_str(&kv.value).unwrap_or(Value::String(kv.value));
+    Ok(Json(value))
+}
+
+#[post("/<key>", data = "<value>", format = "application/json")]
+pub fn setting_set(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    key: &str,
+    value: Json<Value>,
+) -> Result<Status, HttpErrorJson> {
+    // Settings are server-wide configuration, so changing them is
+    // admin-level — a write scope only covers event data
+    auth.require(Scope::Admin, None)?;
+    let setting_key = parse_key(key)?;
+    let value_str = value.into_inner().to_string();
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let result = datastore.insert_key_value(&setting_key, &value_str);
+    match result {
+        Ok(_) => Ok(Status::Created),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[delete("/<key>")]
+pub fn setting_delete(
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+    key: &str,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Admin, None)?;
+    let setting_key = parse_key(key)?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let result = datastore.delete_key_value(&setting_key);
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::DurationRound;
+use chrono::Timelike;
+use chrono::Utc;
+use chrono::Weekday;
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::value::Value;
+
+use aw_datastore::Datastore;
+use aw_models::Event;
+use aw_transform::filter_keyvals;
+use aw_transform::filter_period_intersect;
+use aw_transform::find_bucket;
+use aw_transform::infer_afk;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::{parse_rfc3339_param, HttpErrorJson};
+use crate::endpoints::ServerState;
+
+/// The timezone used for day/week boundaries in aggregations, stored as the
+/// `timezone` setting (an IANA name, e.g. "Europe/Berlin"). Defaults to UTC
+/// when unset or unparsable.
+pub fn get_timezone(datastore: &Datastore) -> Tz {
+    let kv = match datastore.get_key_value("settings.timezone") {
+        Ok(kv) => kv,
+        Err(_) => return Tz::UTC,
+    };
+    let tz_str: String = serde_json::from_str(&kv.value).unwrap_or(kv.value);
+    match tz_str.parse() {
+        Ok(tz) => tz,
+        Err(_) => {
+            warn!("Failed to parse timezone setting '{tz_str}', falling back to UTC");
+            Tz::UTC
+        }
+    }
+}
+
+/// The first day of the week for weekly aggregations, stored as the
+/// `week_start` setting ("monday" or "sunday"). Defaults to Monday when
+/// unset or unrecognized.
+pub fn get_week_start(datastore: &Datastore) -> Weekday {
+    let kv = match datastore.get_key_value("settings.week_start") {
+        Ok(kv) => kv,
+        Err(_) => return Weekday::Mon,
+    };
+    let day_str: String = serde_json::from_str(&kv.value).unwrap_or(kv.value);
+    match day_str.to_lowercase().as_str() {
+        "monday" => Weekday::Mon,
+        "sunday" => Weekday::Sun,
+        other => {
+            warn!("Unrecognized week_start setting '{other}', falling back to Monday");
+            Weekday::Mon
+        }
+    }
+}
+
+/// Days elapsed since the configured week start, 0 on the week start
+/// day itself
+pub fn days_into_week(weekday: Weekday, week_start: Weekday) -> u32 {
+    (weekday.num_days_from_monday() + 7 - week_start.num_days_from_monday()) % 7
+}
+
+/// The working-hours window for aggregations, stored as the
+/// `working_hours` setting (`{"start": 9, "end": 17}`, local hours).
+/// None when unset or invalid, in which case working-hours breakdowns
+/// are simply omitted.
+pub fn get_working_hours(datastore: &Datastore) -> Option<(u32, u32)> {
+    let kv = datastore.get_key_value("settings.working_hours").ok()?;
+    let value: Value = serde_json::from_str(&kv.value).ok()?;
+    let start = value.get("start")?.as_u64()?;
+    let end = value.get("end")?.as_u64()?;
+    if start >= end || end > 24 {
+        warn!("Invalid working_hours setting ({start}-{end}), ignoring");
+        return None;
+    }
+    Some((start as u32, end as u32))
+}
+
+/// Seconds of the given events falling within the local-hour window,
+/// walked hour by hour so segments spanning boundaries split correctly
+pub fn seconds_within_hours(events: &[Event], tz: &Tz, hours: (u32, u32)) -> f64 {
+    let mut seconds = 0.0;
+    for event in events {
+        let mut t = event.timestamp;
+        let event_end = event.calculate_endtime();
+        while t < event_end {
+            let next_hour = t.duration_trunc(Duration::hours(1)).unwrap() + Duration::hours(1);
+            let segment_end = std::cmp::min(event_end, next_hour);
+            let hour = t.with_timezone(tz).hour();
+            if hours.0 <= hour && hour < hours.1 {
+                seconds += (segment_end - t).num_milliseconds() as f64 / 1000.0;
+            }
+            t = segment_end;
+        }
+    }
+    seconds
+}
+
+/// How large a gap between window heartbeats is taken to mean the user
+/// was away, when AFK periods have to be inferred
+const INFERRED_AFK_MAX_GAP_SECONDS: i64 = 180;
+
+/// Window events intersected with the non-AFK periods, the basis for all
+/// stats endpoints. When no AFK bucket exists (a partial install with only
+/// a window watcher), AFK periods are inferred from gaps in the window
+/// heartbeats instead, so the numbers stay usable rather than 404ing.
+pub fn active_events(
+    datastore: &Datastore,
+    starttime: Option<DateTime<Utc>>,
+    endtime: Option<DateTime<Utc>>,
+) -> Result<Vec<Event>, HttpErrorJson> {
+    let buckets = datastore.get_buckets()?;
+    let window_bucket =
+        find_bucket("aw-watcher-window", &None, buckets.values()).ok_or_else(|| {
+            HttpErrorJson::new(Status::NotFound, "No window bucket found".to_string())
+        })?;
+    let window_events = datastore.get_events(&window_bucket, starttime, endtime, None)?;
+
+    let afk_events = match find_bucket("aw-watcher-afk", &None, buckets.values()) {
+        Some(afk_bucket) => datastore.get_events(&afk_bucket, starttime, endtime, None)?,
+        None => {
+            debug!("No AFK bucket found, inferring AFK periods from window event gaps");
+            infer_afk(
+                window_events.clone(),
+                Duration::seconds(INFERRED_AFK_MAX_GAP_SECONDS),
+            )
+        }
+    };
+    let not_afk = filter_keyvals(
+        afk_events,
+        "status",
+        &[Value::String("not-afk".to_string())],
+    );
+    Ok(filter_period_intersect(&window_events, &not_afk))
+}
+
+/// Returns active seconds per day within the queried period, computed by
+/// intersecting window events with the non-AFK periods. Gives simple clients
+/// the headline number with a single call, without needing the query language.
+#[get("/active?<start>&<end>")]
+pub fn stats_active(
+    start: &str,
+    end: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<BTreeMap<String, f64>>, HttpErrorJson> {
+    // Stats read the window and AFK buckets regardless of what the key
+    // names, so bucket-restricted keys may not use them
+    auth.require_all_buckets(Scope::Read)?;
+    let starttime = parse_rfc3339_param(Some(start), "start")?;
+    let endtime = parse_rfc3339_param(Some(end), "end")?;
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let tz = get_timezone(&datastore);
+    let active = active_events(&datastore, starttime, endtime)?;
+
+    let mut seconds_per_day: BTreeMap<String, f64> = BTreeMap::new();
+    for event in active {
+        let day = event.timestamp.with_timezone(&tz).format("%Y-%m-%d").to_string();
+        *seconds_per_day.entry(day).or_insert(0.0) +=
+            event.duration.num_milliseconds() as f64 / 1000.0;
+    }
+    Ok(Json(seconds_per_day))
+}
+
+/// Per-day split of active seconds into in-hours and out-of-hours totals
+type WorkingHoursSplit = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Returns active seconds per day within the queried period, split into
+/// in-hours and out-of-hours totals according to the working_hours
+/// setting. 400 when the setting is missing, since there is nothing
+/// meaningful to split by.
+#[get("/working_hours?<start>&<end>")]
+pub fn stats_working_hours(
+    start: &str,
+    end: &str,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<WorkingHoursSplit>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let starttime = parse_rfc3339_param(Some(start), "start")?;
+    let endtime = parse_rfc3339_param(Some(end), "end")?;
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let tz = get_timezone(&datastore);
+    let working_hours = get_working_hours(&datastore).ok_or_else(|| {
+        HttpErrorJson::new(
+            Status::BadRequest,
+            "The working_hours setting is not set".to_string(),
+        )
+    })?;
+    let active = active_events(&datastore, starttime, endtime)?;
+
+    let mut per_day = WorkingHoursSplit::new();
+    for event in active {
+        // Walk hour by hour like the heatmap, so events spanning the
+        // working-hours boundary split correctly
+        let mut t = event.timestamp;
+        let event_end = event.calculate_endtime();
+        while t < event_end {
+            let next_hour = t.duration_trunc(Duration::hours(1)).unwrap() + Duration::hours(1);
+            let segment_end = std::cmp::min(event_end, next_hour);
+            let local = t.with_timezone(&tz);
+            let day = local.format("%Y-%m-%d").to_string();
+            let hour = local.hour();
+            let key = if working_hours.0 <= hour && hour < working_hours.1 {
+                "in_hours"
+            } else {
+                "out_of_hours"
+            };
+            let entry = per_day.entry(day).or_insert_with(|| {
+                BTreeMap::from([("in_hours".to_string(), 0.0), ("out_of_hours".to_string(), 0.0)])
+            });
+            *entry.get_mut(key).unwrap() += (segment_end - t).num_milliseconds() as f64 / 1000.0;
+            t = segment_end;
+        }
+    }
+    Ok(Json(per_day))
+}
+
+/// Returns a 7x24 matrix (weekday, starting on the configured week start
+/// day, by hour of day) of active seconds within the queried period,
+/// optionally restricted to a single app. Used for rendering activity
+/// heatmaps without shipping the raw events to the client.
+#[get("/heatmap?<start>&<end>&<app>")]
+pub fn stats_heatmap(
+    start: &str,
+    end: &str,
+    app: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Vec<f64>>>, HttpErrorJson> {
+    auth.require_all_buckets(Scope::Read)?;
+    let starttime = parse_rfc3339_param(Some(start), "start")?;
+    let endtime = parse_rfc3339_param(Some(end), "end")?;
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    let tz = get_timezone(&datastore);
+    let week_start = get_week_start(&datastore);
+    let mut active = active_events(&datastore, starttime, endtime)?;
+    if let Some(app) = app {
+        active = filter_keyvals(active, "app", &[Value::String(app.to_string())]);
+    }
+
+    let mut matrix = vec![vec![0.0f64; 24]; 7];
+    for event in active {
+        // Walk the event hour by hour, so events spanning hour boundaries
+        // are attributed to the correct cells. The walk happens on UTC
+        // instants (which is safe across DST transitions), only the cell
+        // labels are computed in the configured timezone.
+        let mut t = event.timestamp;
+        let event_end = event.calculate_endtime();
+        while t < event_end {
+            let next_hour = t.duration_trunc(Duration::hours(1)).unwrap() + Duration::hours(1);
+            let segment_end = std::cmp::min(event_end, next_hour);
+            let local = t.with_timezone(&tz);
+            let weekday = days_into_week(local.weekday(), week_start) as usize;
+            let hour = local.hour() as usize;
+            matrix[weekday][hour] += (segment_end - t).num_milliseconds() as f64 / 1000.0;
+            t = segment_end;
+        }
+    }
+    Ok(Json(matrix))
+}
@@ -0,0 +1,64 @@
+//! Live event streaming over server-sent events (SSE), so watchers and
+//! dashboards can follow inserts and heartbeats without polling.
+
+use rocket::http::Status;
+use rocket::response::stream::{Event as SseEvent, EventStream};
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::State;
+use serde_json::json;
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::HttpErrorJson;
+use crate::endpoints::ServerState;
+
+/// Streams newly inserted events and merged heartbeats as SSE messages of
+/// type `event`, with a JSON body `{"bucket_id": ..., "event": ...}`.
+/// `buckets` optionally restricts the stream to a comma-separated list of
+/// bucket ids. Subscribers that fall too far behind receive a `lagged`
+/// message with the number of dropped notifications.
+#[get("/?<buckets>")]
+pub fn event_stream(
+    buckets: Option<String>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<EventStream![], HttpErrorJson> {
+    let filter: Option<Vec<String>> =
+        buckets.map(|buckets| buckets.split(',').map(|id| id.trim().to_string()).collect());
+    // A filtered stream only needs Read on the named buckets; an
+    // unfiltered one delivers events from every bucket
+    match &filter {
+        Some(bucket_ids) => {
+            for bucket_id in bucket_ids {
+                auth.require(Scope::Read, Some(bucket_id))?;
+            }
+        }
+        None => auth.require_all_buckets(Scope::Read)?,
+    }
+    let mut receiver = {
+        let datastore = endpoints_get_lock!(state.datastore);
+        datastore.subscribe()
+    };
+
+    Ok(EventStream! {
+        loop {
+            match receiver.recv().await {
+                Ok(notification) => {
+                    if let Some(filter) = &filter {
+                        if !filter.contains(&notification.bucket_id) {
+                            continue;
+                        }
+                    }
+                    yield SseEvent::json(&json!({
+                        "bucket_id": notification.bucket_id,
+                        "event": notification.event,
+                    }))
+                    .event("event");
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    yield SseEvent::data(skipped.to_string()).event("lagged");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
@@ -0,0 +1,50 @@
+//! Fast per-hour activity summary across buckets, computed in SQL so a
+//! dashboard timeline over months of data doesn't ship any events over
+//! the wire. Each hour bucket carries the event count and the summed
+//! duration of events starting in it; events spanning hour boundaries
+//! count wholly towards their start hour.
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::{json, Value};
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::{parse_rfc3339_param, HttpErrorJson};
+use crate::endpoints::ServerState;
+
+/// Returns `{bucket_id: {hour: {count, duration}}}` for every bucket
+/// visible to the caller, with hours as RFC3339 timestamps and durations
+/// in seconds. `start` and `end` clip the summarized range.
+#[get("/?<start>&<end>")]
+pub fn summary_get(
+    start: Option<&str>,
+    end: Option<&str>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Value>, HttpErrorJson> {
+    auth.require(Scope::Read, None)?;
+    let starttime = parse_rfc3339_param(start, "starttime")?;
+    let endtime = parse_rfc3339_param(end, "endtime")?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    let buckets = datastore.get_buckets()?;
+    let mut summary = serde_json::Map::new();
+    for bucket_id in buckets.keys() {
+        if !auth.bucket_visible(bucket_id) {
+            continue;
+        }
+        let hours = datastore.get_events_hourly_summary(bucket_id, starttime, endtime)?;
+        let mut per_hour = serde_json::Map::new();
+        for (hour, (count, duration_ns)) in hours {
+            per_hour.insert(
+                hour,
+                json!({
+                    "count": count,
+                    "duration": duration_ns as f64 / 1_000_000_000.0,
+                }),
+            );
+        }
+        summary.insert(bucket_id.clone(), Value::Object(per_hour));
+    }
+    Ok(Json(Value::Object(summary)))
+}
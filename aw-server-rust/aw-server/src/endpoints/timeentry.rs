@@ -0,0 +1,187 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use gethostname::gethostname;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+
+use aw_datastore::{Datastore, DatastoreError};
+use aw_models::{Bucket, BucketMetadata, Event, TryVec};
+
+use crate::endpoints::apikey::{ApiKeyAuth, Scope};
+use crate::endpoints::util::{parse_rfc3339_param, HttpErrorJson};
+use crate::endpoints::ServerState;
+
+/// The dedicated bucket manual time entries are logged into
+static BUCKET_ID: &str = "aw-manual";
+
+/// A manually logged block of time, for time-tracking workflows alongside
+/// the automatic watchers
+#[derive(Deserialize)]
+pub struct ManualEntry {
+    pub timestamp: DateTime<Utc>,
+    /// Duration in seconds
+    pub duration: f64,
+    pub project: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Creates the manual entry bucket if it doesn't exist yet
+fn ensure_bucket(datastore: &Datastore) -> Result<(), HttpErrorJson> {
+    match datastore.get_bucket(BUCKET_ID) {
+        Ok(_) => Ok(()),
+        Err(DatastoreError::NoSuchBucket(_)) => {
+            let bucket = Bucket {
+                bid: None,
+                id: BUCKET_ID.to_string(),
+                _type: "manualentry".to_string(),
+                client: "aw-server".to_string(),
+                hostname: gethostname()
+                    .into_string()
+                    .unwrap_or_else(|_| "unknown".to_string()),
+                created: None,
+                last_updated: None,
+                event_count: None,
+                total_duration: None,
+                data: Map::new(),
+                events: TryVec::new_empty(),
+                metadata: BucketMetadata::default(),
+            };
+            match datastore.create_bucket(&bucket) {
+                Ok(_) | Err(DatastoreError::BucketAlreadyExists(_)) => Ok(()),
+                Err(err) => Err(err.into()),
+            }
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[get("/?<start>&<end>&<limit>")]
+pub fn entries_get(
+    start: Option<&str>,
+    end: Option<&str>,
+    limit: Option<u64>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Vec<Event>>, HttpErrorJson> {
+    auth.require(Scope::Read, Some(BUCKET_ID))?;
+    let starttime = parse_rfc3339_param(start, "start")?;
+    let endtime = parse_rfc3339_param(end, "end")?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.get_events(BUCKET_ID, starttime, endtime, limit) {
+        Ok(events) => Ok(Json(events)),
+        // No entries logged yet
+        Err(DatastoreError::NoSuchBucket(_)) => Ok(Json(Vec::new())),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Logs a manual time entry. Entries may not overlap: an entry overlapping
+/// existing ones is rejected with 409, unless `trim=true` is passed, in
+/// which case it is trimmed to the first free window within its span (and
+/// only rejected when no free time remains).
+#[post("/?<trim>", data = "<message>", format = "application/json")]
+pub fn entry_create(
+    trim: Option<bool>,
+    message: Json<ManualEntry>,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<Json<Event>, HttpErrorJson> {
+    auth.require(Scope::Write, Some(BUCKET_ID))?;
+    let entry = message.into_inner();
+    // NaN fails both comparisons, so check finiteness explicitly
+    if !entry.duration.is_finite() || entry.duration <= 0.0 {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Entry duration must be a positive number".to_string(),
+        ));
+    }
+    if entry.project.is_empty() {
+        return Err(HttpErrorJson::new(
+            Status::BadRequest,
+            "Entry project must not be empty".to_string(),
+        ));
+    }
+    let trim = trim.unwrap_or(false);
+
+    let mut start = entry.timestamp;
+    // The cast saturates for huge durations and adding that would
+    // overflow the timestamp range; reject instead of panicking
+    let mut end = start
+        .checked_add_signed(Duration::milliseconds((entry.duration * 1000.0) as i64))
+        .ok_or_else(|| {
+            HttpErrorJson::new(
+                Status::BadRequest,
+                "Entry duration is too large".to_string(),
+            )
+        })?;
+
+    let datastore = endpoints_get_lock!(state.datastore);
+    auth.charge_quota(&datastore, 1)?;
+    ensure_bucket(&datastore)?;
+
+    // Existing entries overlapping the new one, oldest first. get_events
+    // clamps events to the queried range, so boundary touches show up with
+    // zero duration and don't count as overlaps.
+    let mut existing = datastore.get_events(BUCKET_ID, Some(start), Some(end), None)?;
+    existing.retain(|event| !event.duration.is_zero());
+    existing.reverse();
+
+    if !existing.is_empty() {
+        if !trim {
+            return Err(HttpErrorJson::new(
+                Status::Conflict,
+                "Entry overlaps existing manual entries".to_string(),
+            ));
+        }
+        for event in &existing {
+            if event.timestamp <= start {
+                // Overlap at the front, push the start past it
+                start = event.calculate_endtime();
+            } else {
+                // Overlap further in, keep the free window before it
+                end = event.timestamp;
+                break;
+            }
+        }
+        if start >= end {
+            return Err(HttpErrorJson::new(
+                Status::Conflict,
+                "Entry is fully covered by existing manual entries".to_string(),
+            ));
+        }
+    }
+
+    let mut data = Map::new();
+    data.insert("project".to_string(), Value::String(entry.project));
+    if let Some(description) = entry.description {
+        data.insert("description".to_string(), Value::String(description));
+    }
+    let event = Event {
+        id: None,
+        timestamp: start,
+        duration: end - start,
+        data,
+    };
+    let inserted = datastore.insert_events(BUCKET_ID, &[event])?;
+    Ok(Json(inserted.into_iter().next().unwrap()))
+}
+
+#[delete("/<entry_id>")]
+pub fn entry_delete(
+    entry_id: i64,
+    auth: ApiKeyAuth,
+    state: &State<ServerState>,
+) -> Result<(), HttpErrorJson> {
+    auth.require(Scope::Write, Some(BUCKET_ID))?;
+    let datastore = endpoints_get_lock!(state.datastore);
+    match datastore.delete_events_by_id(BUCKET_ID, vec![entry_id]) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::DateTime;
+use chrono::Utc;
+use rocket::http::ContentType;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use std::io::Cursor;
+
+use aw_datastore::DatastoreError;
+
+/// Parse an optional rfc3339 query parameter, turning parse failures into a
+/// 400 response mentioning the parameter by name
+pub fn parse_rfc3339_param(
+    param: Option<&str>,
+    name: &str,
+) -> Result<Option<DateTime<Utc>>, HttpErrorJson> {
+    match param {
+        Some(dt_str) => match DateTime::parse_from_rfc3339(dt_str) {
+            Ok(dt) => Ok(Some(dt.with_timezone(&Utc))),
+            Err(e) => {
+                let err_msg =
+                    format!("Failed to parse {name}, datetime needs to be in rfc3339 format: {e}");
+                warn!("{err_msg}");
+                Err(HttpErrorJson::new(Status::BadRequest, err_msg))
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// The User-Agent of the request, if any; the closest thing the v0
+/// protocol has to a client identifier. Recorded as ingest provenance.
+pub struct ClientInfo(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(ClientInfo(
+            request
+                .headers()
+                .get_one("User-Agent")
+                .map(|agent| agent.to_string()),
+        ))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct HttpErrorJson {
+    #[serde(skip_serializing)]
+    status: Status,
+    message: String,
+}
+
+impl HttpErrorJson {
+    pub fn new(status: Status, err: String) -> HttpErrorJson {
+        HttpErrorJson {
+            status,
+            message: err,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for HttpErrorJson {
+    fn respond_to(self, _: &Request) -> response::Result<'static> {
+        let body = serde_json::to_string(&self).unwrap();
+        Response::build()
+            .status(self.status)
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+impl From<DatastoreError> for HttpErrorJson {
+    fn from(err: DatastoreError) -> HttpErrorJson {
+        match err {
+            DatastoreError::NoSuchBucket(bucket_id) => HttpErrorJson::new(
+                Status::NotFound,
+                format!("The requested bucket '{bucket_id}' does not exist"),
+            ),
+            DatastoreError::BucketAlreadyExists(bucket_id) => HttpErrorJson::new(
+                Status::Conflict,
+                format!("Bucket '{bucket_id}' already exists"),
+            ),
+            DatastoreError::NoSuchKey(key) => HttpErrorJson::new(
+                Status::NotFound,
+                format!("The requested key '{key}' does not exist"),
+            ),
+            // The worker thread is gone, so no request can succeed until
+            // a restart; 503 tells clients to back off and retry
+            DatastoreError::MpscError => HttpErrorJson::new(
+                Status::ServiceUnavailable,
+                "Datastore worker is not responding".to_string(),
+            ),
+            // Busy/locked is transient, so this is also a retry case
+            DatastoreError::DatabaseBusy(msg) => {
+                HttpErrorJson::new(Status::ServiceUnavailable, msg)
+            }
+            DatastoreError::InternalError(msg) => {
+                HttpErrorJson::new(Status::InternalServerError, msg)
+            }
+            // When the DB is unitialized, we cannot handle any requests
+            DatastoreError::Uninitialized(msg) => {
+                HttpErrorJson::new(Status::ServiceUnavailable, msg)
+            }
+            DatastoreError::OldDbVersion(msg) => HttpErrorJson::new(
+                Status::InternalServerError,
+                format!("Database is of an older version: {msg}"),
+            ),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use aw_datastore::Datastore;
+use aw_models::Query;
+use aw_query::QueryLimits;
+
+/// Query jobs are stored in the key_value table, prefixed with `job.`, so
+/// their state survives a server restart.
+pub static JOB_PREFIX: &str = "job.";
+
+/// How many query jobs may execute at the same time
+const MAX_CONCURRENT_JOBS: usize = 2;
+
+/// How often the dispatcher looks for pending jobs
+const POLL_INTERVAL_MS: u64 = 250;
+
+/// Cancellation flags for currently running jobs, so deleting a job also
+/// aborts its query mid-execution instead of letting it keep burning the
+/// datastore
+static RUNNING_JOBS: LazyLock<Mutex<HashMap<String, Arc<AtomicBool>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Requests cancellation of a running job. A no-op if the job is not
+/// currently executing.
+pub fn cancel(job_id: &str) {
+    if let Some(cancelled) = RUNNING_JOBS.lock().unwrap().get(job_id) {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Error,
+}
+
+/// An asynchronously executed query, for queries too large to run within an
+/// HTTP request: submit, poll status, fetch results when done
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueryJob {
+    pub id: String,
+    pub status: JobStatus,
+    pub created: DateTime<Utc>,
+    pub query: Query,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl QueryJob {
+    pub fn new(query: Query) -> QueryJob {
+        QueryJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            status: JobStatus::Pending,
+            created: Utc::now(),
+            query,
+            result: None,
+            error: None,
+        }
+    }
+}
+
+pub fn save_job(datastore: &Datastore, job: &QueryJob) {
+    if let Err(err) = datastore.insert_key_value(
+        &format!("{JOB_PREFIX}{}", job.id),
+        &serde_json::to_string(job).unwrap(),
+    ) {
+        warn!("Failed to save query job {}: {err}", job.id);
+    }
+}
+
+/// Spawns the job dispatcher thread, which executes pending query jobs with
+/// at most MAX_CONCURRENT_JOBS running at a time
+pub fn start(datastore: Datastore, limits: QueryLimits) {
+    let running = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || loop {
+        dispatch_pending(&datastore, &running, &limits);
+        thread::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS));
+    });
+}
+
+fn dispatch_pending(datastore: &Datastore, running: &Arc<AtomicUsize>, limits: &QueryLimits) {
+    let keys = match datastore.get_keys_starting(&format!("{JOB_PREFIX}%")) {
+        Ok(keys) => keys,
+        Err(err) => {
+            warn!("Job dispatcher failed to list jobs: {err}");
+            return;
+        }
+    };
+    let mut jobs: Vec<QueryJob> = keys
+        .iter()
+        .filter_map(|key| datastore.get_key_value(key).ok())
+        .filter_map(|kv| serde_json::from_str::<QueryJob>(&kv.value).ok())
+        .collect();
+
+    // A job that was running when the process died is still marked Running
+    // in the datastore but has no cancellation flag registered; without
+    // this it would stay Running forever and clients would poll
+    // indefinitely. Re-queue such orphans so they eventually complete.
+    {
+        let running_jobs = RUNNING_JOBS.lock().unwrap();
+        for job in jobs.iter_mut() {
+            if job.status == JobStatus::Running && !running_jobs.contains_key(&job.id) {
+                info!("Re-queueing orphaned query job {}", job.id);
+                job.status = JobStatus::Pending;
+                save_job(datastore, job);
+            }
+        }
+    }
+
+    let mut pending: Vec<QueryJob> = jobs
+        .into_iter()
+        .filter(|job| job.status == JobStatus::Pending)
+        .collect();
+    pending.sort_by_key(|job| job.created);
+
+    for mut job in pending {
+        if running.load(Ordering::SeqCst) >= MAX_CONCURRENT_JOBS {
+            break;
+        }
+        // Only the dispatcher moves jobs from pending to running, so this
+        // cannot double-claim a job
+        job.status = JobStatus::Running;
+        save_job(datastore, &job);
+        running.fetch_add(1, Ordering::SeqCst);
+
+        let datastore = datastore.clone();
+        let running = Arc::clone(running);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        RUNNING_JOBS
+            .lock()
+            .unwrap()
+            .insert(job.id.clone(), Arc::clone(&cancelled));
+        let mut limits = limits.clone();
+        limits.cancelled = Some(cancelled);
+        thread::spawn(move || {
+            let job_id = job.id.clone();
+            execute_job(&datastore, job, &limits);
+            RUNNING_JOBS.lock().unwrap().remove(&job_id);
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+fn execute_job(datastore: &Datastore, mut job: QueryJob, limits: &QueryLimits) {
+    let code = job.query.query.join("\n");
+    let mut results = Vec::new();
+    for interval in &job.query.timeperiods {
+        match aw_query::query_with_limits(&code, interval, datastore, limits) {
+            Ok(result) => results.push(serde_json::to_value(&result).unwrap_or(Value::Null)),
+            // A cancelled job has been deleted; saving it would re-create
+            // its key in the datastore
+            Err(aw_query::QueryError::Cancelled()) => {
+                info!("Query job {} was cancelled", job.id);
+                return;
+            }
+            Err(err) => {
+                job.status = JobStatus::Error;
+                job.error = Some(format!("{err}"));
+                save_job(datastore, &job);
+                return;
+            }
+        }
+    }
+    job.status = JobStatus::Done;
+    job.result = Some(results);
+    save_job(datastore, &job);
+}
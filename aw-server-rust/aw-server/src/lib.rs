@@ -0,0 +1,24 @@
+#[macro_use]
+extern crate rocket;
+
+#[macro_use]
+pub mod macros;
+pub mod admin;
+pub mod alerts;
+pub mod anomaly;
+pub mod caldav;
+pub mod config;
+pub mod device_id;
+pub mod dirs;
+pub mod diskguard;
+pub mod endpoints;
+pub mod jobs;
+pub mod logging;
+pub mod prometheus;
+pub mod ratelimit;
+pub mod replay;
+pub mod reports;
+pub mod retention;
+pub mod scheduler;
+#[cfg(unix)]
+pub mod unix_socket;
@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use fern::colors::{Color, ColoredLevelConfig};
+use log::LevelFilter;
+
+use crate::dirs;
+
+/// Rotate the logfile when it grows past this size
+const MAX_LOG_SIZE_BYTES: u64 = 50 * 1024 * 1024;
+
+/// How many rotated logfiles (`.1`, `.2`, ...) are kept around
+const ROTATED_LOGS: usize = 2;
+
+/// Output format of log lines, selectable with `--log-format`
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable `[time][level][target]: message` lines
+    #[default]
+    Plain,
+    /// One JSON object per line with `timestamp`, `level`, `target` and
+    /// `message` fields, for shipping to Loki/ELK from containers
+    Json,
+}
+
+/// A logfile writer with size-based rotation: when the file grows past
+/// [`MAX_LOG_SIZE_BYTES`] it is renamed to `<name>.1` (shifting older
+/// rotations up, dropping the oldest) and a fresh file is started, so
+/// long-running deployments can't fill the disk.
+struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl RotatingLogFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingLogFile {
+            path,
+            file,
+            written,
+        })
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let rotated = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+        let _ = std::fs::remove_file(rotated(ROTATED_LOGS));
+        for n in (1..ROTATED_LOGS).rev() {
+            let _ = std::fs::rename(rotated(n), rotated(n + 1));
+        }
+        let _ = std::fs::rename(&self.path, rotated(1));
+        *self = RotatingLogFile::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.written > MAX_LOG_SIZE_BYTES {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// One JSON object per log line, parseable by any log shipper
+fn json_line(message: &std::fmt::Arguments, record: &log::Record) -> String {
+    serde_json::json!({
+        "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": message.to_string(),
+    })
+    .to_string()
+}
+
+pub fn setup_logger(
+    module: &str,
+    testing: bool,
+    verbose: bool,
+    format: LogFormat,
+    logfile_override: Option<PathBuf>,
+) -> Result<(), fern::InitError> {
+    let logfile_path: PathBuf = match logfile_override {
+        Some(path) => path,
+        None => {
+            let mut path =
+                dirs::get_log_dir(module).expect("Unable to get log dir to store logs in");
+            std::fs::create_dir_all(path.clone()).expect("Unable to create log dir");
+            let filename = format!(
+                "{}_{}.log",
+                module,
+                chrono::Local::now().format("%Y-%m-%dT%H-%M-%S")
+            );
+            path.push(filename);
+            path
+        }
+    };
+
+    let colors = ColoredLevelConfig::new()
+        .debug(Color::White)
+        .info(Color::Green)
+        .warn(Color::Yellow)
+        .error(Color::Red);
+
+    let default_log_level = if testing || verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+
+    let stdout_dispatch = match format {
+        LogFormat::Plain => fern::Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}][{}]: {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                colors.color(record.level()),
+                record.target(),
+                message
+            ))
+        }),
+        LogFormat::Json => fern::Dispatch::new().format(|out, message, record| {
+            out.finish(format_args!("{}", json_line(message, record)))
+        }),
+    };
+
+    let logfile: Box<dyn Write + Send> = Box::new(RotatingLogFile::open(logfile_path)?);
+    let file_dispatch = match format {
+        LogFormat::Plain => fern::Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}][{}]: {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.target(),
+                message
+            ))
+        }),
+        LogFormat::Json => fern::Dispatch::new().format(|out, message, record| {
+            out.finish(format_args!("{}", json_line(message, record)))
+        }),
+    };
+
+    fern::Dispatch::new()
+        .level(default_log_level)
+        .level_for("rocket", LevelFilter::Warn)
+        .level_for("_", LevelFilter::Warn) // Rocket requests
+        .chain(stdout_dispatch.chain(std::io::stdout()))
+        .chain(file_dispatch.chain(logfile))
+        .apply()?;
+    Ok(())
+}
@@ -0,0 +1,15 @@
+#[macro_export]
+macro_rules! endpoints_get_lock {
+    ($lock:expr) => {
+        match $lock.lock() {
+            Ok(lock) => lock,
+            Err(err) => {
+                warn!("Taking datastore lock failed, returning 504: {err}");
+                return Err(HttpErrorJson::new(
+                    Status::ServiceUnavailable,
+                    "Takin datastore lock failed".to_string(),
+                ));
+            }
+        }
+    };
+}
@@ -0,0 +1,360 @@
+#[macro_use]
+extern crate log;
+
+use std::sync::Mutex;
+
+use clap::Parser;
+
+use aw_server::*;
+
+/// ActivityWatch server, in Rust. Stores your events, and gives clients a
+/// nice API to work with them.
+#[derive(Parser)]
+#[command(version = env!("CARGO_PKG_VERSION"), about, long_about = None)]
+struct Opts {
+    /// Run in testing mode (on a separate port, with a separate datastore)
+    #[arg(long)]
+    testing: bool,
+    /// Verbose output
+    #[arg(long)]
+    verbose: bool,
+    /// Address to listen to
+    #[arg(long)]
+    host: Option<String>,
+    /// Port to listen on
+    #[arg(long)]
+    port: Option<u16>,
+    /// Path to database override
+    #[arg(long)]
+    dbpath: Option<String>,
+    /// Import buckets and events from a legacy aw-server-python SQLite
+    /// database at the given path, then continue starting up
+    #[arg(long)]
+    import_sqlite: Option<String>,
+    /// Keep all data in memory and never touch disk; everything is lost
+    /// when the server exits. Useful for demos and testing.
+    #[arg(long)]
+    ephemeral: bool,
+    /// Log output format
+    #[arg(long, value_enum, default_value = "plain")]
+    log_format: logging::LogFormat,
+    /// Path to logfile override; rotated in place when it grows too
+    /// large. By default a new timestamped file in the log dir per run.
+    #[arg(long)]
+    log_file: Option<std::path::PathBuf>,
+    /// Seconds to wait for the database to become available before
+    /// giving up and exiting
+    #[arg(long, default_value = "0")]
+    wait_for_db: u64,
+    /// Additional allowed CORS origin, on top of those in the config
+    /// file (can be given multiple times; `*` allows any origin)
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+    /// Path to a PEM certificate chain; together with --tls-key the
+    /// server listens over HTTPS directly, without a reverse proxy
+    #[arg(long = "tls-cert")]
+    tls_cert: Option<std::path::PathBuf>,
+    /// Path to the PEM private key matching --tls-cert
+    #[arg(long = "tls-key")]
+    tls_key: Option<std::path::PathBuf>,
+    /// Also listen on a Unix domain socket at the given path, for
+    /// local-only deployments where filesystem permissions control
+    /// access. A socket passed by systemd socket activation (LISTEN_FDS)
+    /// is picked up automatically and takes precedence.
+    #[cfg(unix)]
+    #[arg(long)]
+    socket: Option<std::path::PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run the server (the default when no subcommand is given)
+    Serve,
+    /// Open the database, run any pending schema migrations and exit
+    Migrate,
+    /// Export all buckets and events to a JSON file
+    Export {
+        /// Path to write the export to
+        file: std::path::PathBuf,
+    },
+    /// Import buckets and events from a JSON export file
+    Import {
+        /// Path to the export file
+        file: std::path::PathBuf,
+    },
+    /// Manage API keys directly in the database, without a running server
+    Apikey {
+        #[command(subcommand)]
+        action: ApikeyAction,
+    },
+    /// Replay a traffic file recorded via GET /debug/recording against
+    /// a running server, respecting the recorded relative timing
+    Replay {
+        /// Path to the recorded traffic file (JSON)
+        file: std::path::PathBuf,
+        /// Base URL of the server to replay against
+        #[arg(long, default_value = "http://localhost:5666")]
+        url: String,
+        /// Send requests back to back instead of respecting the
+        /// recorded inter-request gaps
+        #[arg(long)]
+        no_timing: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ApikeyAction {
+    /// Create a key; the plaintext key is printed exactly once
+    Create {
+        /// Human-readable name for the key
+        name: String,
+        /// Scopes to grant (read, write, admin)
+        #[arg(long, value_delimiter = ',', default_value = "read,write")]
+        scopes: Vec<String>,
+        /// Bucket-id pattern the key is restricted to (exact id or a `*`
+        /// suffix for prefix matches, repeatable); none means all buckets
+        #[arg(long = "bucket")]
+        buckets: Vec<String>,
+        /// Max events the key may ingest per UTC day; unset is unlimited
+        #[arg(long)]
+        max_events_per_day: Option<u64>,
+        /// Max events stored across the key's buckets; unset is unlimited
+        #[arg(long)]
+        max_total_events: Option<u64>,
+        /// Canonical hostname stamped onto buckets created with the key,
+        /// overriding the client-supplied one
+        #[arg(long)]
+        hostname: Option<String>,
+    },
+    /// List keys by id (hash) with their permissions
+    List,
+    /// Revoke a key by id
+    Revoke {
+        /// The key id (hash) from `apikey list` or creation
+        id: String,
+    },
+}
+
+/// Exit code for invalid configuration: restarting won't help, a human
+/// has to fix the config file
+const EXIT_CONFIG_ERROR: i32 = 2;
+
+/// Exit code for a database that didn't become available in time, e.g.
+/// locked by another process; restarting may well succeed
+const EXIT_DB_UNAVAILABLE: i32 = 3;
+
+#[rocket::main]
+#[allow(clippy::result_large_err)]
+async fn main() -> Result<(), rocket::Error> {
+    let mut opts: Opts = Opts::parse();
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    static LOGGING_INITIALIZED: AtomicBool = AtomicBool::new(false);
+    if !LOGGING_INITIALIZED.swap(true, Ordering::SeqCst) {
+        logging::setup_logger(
+            "aw-server-rust",
+            opts.testing,
+            opts.verbose,
+            opts.log_format,
+            opts.log_file.clone(),
+        )
+        .expect("Failed to setup logging");
+    }
+
+    match opts.command.take() {
+        None | Some(Command::Serve) => (),
+        Some(Command::Replay {
+            file,
+            url,
+            no_timing,
+        }) => {
+            if let Err(err) = replay::replay(&file, &url, no_timing) {
+                error!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        // Admin commands work on the database directly, no server needed
+        Some(command) => {
+            let db_path = match &opts.dbpath {
+                Some(dbpath) => dbpath.clone(),
+                None => dirs::db_path(opts.testing)
+                    .expect("Failed to get db path")
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            };
+            let datastore = aw_datastore::Datastore::new(db_path, false);
+            let result = match command {
+                Command::Migrate => admin::migrate(&datastore),
+                Command::Export { file } => admin::export(&datastore, &file),
+                Command::Import { file } => admin::import(&datastore, &file),
+                Command::Apikey { action } => match action {
+                    ApikeyAction::Create {
+                        name,
+                        scopes,
+                        buckets,
+                        max_events_per_day,
+                        max_total_events,
+                        hostname,
+                    } => admin::apikey_create(
+                        &datastore,
+                        &name,
+                        &scopes,
+                        buckets,
+                        max_events_per_day,
+                        max_total_events,
+                        hostname,
+                    ),
+                    ApikeyAction::List => admin::apikey_list(&datastore),
+                    ApikeyAction::Revoke { id } => admin::apikey_revoke(&datastore, &id),
+                },
+                Command::Serve | Command::Replay { .. } => unreachable!(),
+            };
+            datastore.close();
+            if let Err(err) = result {
+                error!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    }
+
+    let mut config = match config::create_config(opts.testing) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err}");
+            std::process::exit(EXIT_CONFIG_ERROR);
+        }
+    };
+    if let Some(host) = opts.host {
+        config.address = host;
+    }
+    if let Some(port) = opts.port {
+        config.port = port;
+    }
+    config.verbose = opts.verbose;
+    config.cors.extend(opts.cors_origins);
+    if let Some(cert) = opts.tls_cert {
+        config.tls_cert = Some(cert);
+    }
+    if let Some(key) = opts.tls_key {
+        config.tls_key = Some(key);
+    }
+    if config.tls_cert.is_some() != config.tls_key.is_some() {
+        error!("TLS requires both a certificate and a key, only one was given");
+        std::process::exit(EXIT_CONFIG_ERROR);
+    }
+
+    #[cfg(unix)]
+    {
+        let listener = match unix_socket::activation_listener() {
+            Some(listener) => {
+                info!("Using socket passed by systemd socket activation");
+                Some(listener)
+            }
+            None => match &opts.socket {
+                Some(path) => match unix_socket::bind(path) {
+                    Ok(listener) => {
+                        info!("Listening on unix socket at {path:?}");
+                        Some(listener)
+                    }
+                    Err(err) => {
+                        error!("Failed to bind unix socket at {path:?}: {err}");
+                        std::process::exit(EXIT_CONFIG_ERROR);
+                    }
+                },
+                None => None,
+            },
+        };
+        if let Some(listener) = listener {
+            unix_socket::start_forwarder(listener, config.address.clone(), config.port);
+        }
+    }
+
+    let legacy_import = false;
+    let device_id = device_id::get_device_id();
+
+    let datastore = if opts.ephemeral {
+        info!("Using ephemeral in-memory datastore, nothing will be persisted");
+        aw_datastore::Datastore::new_ephemeral(legacy_import)
+    } else {
+        let db_path = match opts.dbpath {
+            Some(dbpath) => dbpath,
+            None => dirs::db_path(opts.testing)
+                .expect("Failed to get db path")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        };
+        info!("Using DB at path {db_path:?}");
+        // The quota only makes sense for a file-backed database, so the
+        // checker isn't started for ephemeral datastores
+        if let Some(quota_mb) = config.db_quota_mb {
+            diskguard::start(db_path.clone(), quota_mb);
+        }
+        aw_datastore::Datastore::new(db_path, legacy_import)
+    };
+    // Check that the datastore actually answers before mounting anything
+    // on it, retrying for up to --wait-for-db seconds. A distinct exit
+    // code lets orchestrators restart on transient unavailability without
+    // also restart-looping on config errors.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(opts.wait_for_db);
+    loop {
+        match datastore.get_buckets() {
+            Ok(_) => break,
+            Err(err) if std::time::Instant::now() < deadline => {
+                warn!("Database not available yet ({err}), retrying");
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+            Err(err) => {
+                error!("Database did not become available: {err}");
+                std::process::exit(EXIT_DB_UNAVAILABLE);
+            }
+        }
+    }
+
+    if let Some(path) = &opts.import_sqlite {
+        info!("Importing legacy database at {path:?}");
+        let data = aw_datastore::legacy_import::read_legacy_db(path)
+            .expect("Failed to read legacy database");
+        info!("Importing {} buckets from legacy database", data.len());
+        // The transactional import rolls back on any error, so a failed
+        // import leaves the datastore untouched
+        let batch = datastore
+            .import(data)
+            .expect("Failed to import legacy database");
+        info!("Legacy import finished as batch {batch}");
+    }
+    scheduler::start(datastore.clone());
+    alerts::start(datastore.clone(), config.notification_channels.clone());
+    reports::start(datastore.clone());
+    retention::start(datastore.clone());
+    anomaly::start(datastore.clone());
+    if let Some(url) = &config.prometheus_remote_write_url {
+        prometheus::start(datastore.clone(), url.clone());
+    }
+    if let Some(caldav_config) = &config.caldav {
+        caldav::start(datastore.clone(), caldav_config.clone());
+    }
+
+    let server_state = endpoints::ServerState {
+        datastore: Mutex::new(datastore.clone()),
+        device_id,
+    };
+
+    // Rocket handles SIGINT/SIGTERM itself and returns from launch() once
+    // in-flight requests have drained; only then is it safe to close the
+    // datastore, which commits any uncommitted events before the worker
+    // thread exits.
+    let _rocket = endpoints::build_rocket(server_state, config)
+        .launch()
+        .await?;
+    info!("Shutting down, flushing datastore");
+    #[cfg(unix)]
+    unix_socket::notify_stopping();
+    datastore.close();
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::thread;
+
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::Tz;
+
+use aw_datastore::Datastore;
+
+use crate::endpoints::stats::{active_events, get_timezone};
+
+/// How often metrics are pushed to the remote-write endpoint
+const PUSH_INTERVAL_SECONDS: u64 = 60;
+
+/// Spawns the exporter thread, which pushes active-seconds gauges to a
+/// Prometheus remote-write endpoint every minute. Started only when
+/// `prometheus_remote_write_url` is configured.
+pub fn start(datastore: Datastore, url: String) {
+    thread::spawn(move || loop {
+        push_metrics(&datastore, &url, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(PUSH_INTERVAL_SECONDS));
+    });
+}
+
+fn push_metrics(datastore: &Datastore, url: &str, now: DateTime<Utc>) {
+    let metrics = match collect_metrics(datastore, now) {
+        Ok(metrics) => metrics,
+        Err(err) => {
+            warn!("Prometheus exporter failed to collect metrics: {err}");
+            return;
+        }
+    };
+    let body = encode_write_request(&metrics, now.timestamp_millis());
+    let compressed = match snap::raw::Encoder::new().compress_vec(&body) {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            warn!("Prometheus exporter failed to compress payload: {err}");
+            return;
+        }
+    };
+    if let Err(err) = ureq::post(url)
+        .timeout(std::time::Duration::from_secs(10))
+        .set("Content-Type", "application/x-protobuf")
+        .set("Content-Encoding", "snappy")
+        .set("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .send_bytes(&compressed)
+    {
+        warn!("Prometheus exporter failed to push metrics: {err}");
+    }
+}
+
+/// A gauge as a label set (including __name__) and a value
+type Metric = (Vec<(String, String)>, f64);
+
+/// Today's active seconds, total and per app, in the configured timezone
+fn collect_metrics(datastore: &Datastore, now: DateTime<Utc>) -> Result<Vec<Metric>, String> {
+    let tz = get_timezone(datastore);
+    let start = local_midnight(&tz, now);
+    let active =
+        active_events(datastore, Some(start), Some(now)).map_err(|err| format!("{err:?}"))?;
+
+    let mut total = 0.0;
+    let mut per_app: BTreeMap<String, f64> = BTreeMap::new();
+    for event in active {
+        let seconds = event.duration.num_milliseconds() as f64 / 1000.0;
+        total += seconds;
+        if let Some(app) = event.data.get("app").and_then(|value| value.as_str()) {
+            *per_app.entry(app.to_string()).or_insert(0.0) += seconds;
+        }
+    }
+
+    let mut metrics = vec![(
+        vec![("__name__".to_string(), "aw_active_seconds".to_string())],
+        total,
+    )];
+    for (app, seconds) in per_app {
+        metrics.push((
+            vec![
+                ("__name__".to_string(), "aw_app_active_seconds".to_string()),
+                ("app".to_string(), app),
+            ],
+            seconds,
+        ));
+    }
+    let dropped = crate::ratelimit::dropped_total();
+    if dropped > 0 {
+        metrics.push((
+            vec![(
+                "__name__".to_string(),
+                "aw_events_dropped_total".to_string(),
+            )],
+            dropped as f64,
+        ));
+    }
+    if let Some(size) = crate::diskguard::db_size_bytes() {
+        metrics.push((
+            vec![("__name__".to_string(), "aw_db_size_bytes".to_string())],
+            size as f64,
+        ));
+    }
+    Ok(metrics)
+}
+
+fn local_midnight(tz: &Tz, now: DateTime<Utc>) -> DateTime<Utc> {
+    let local = now.with_timezone(tz);
+    let midnight = local.date_naive().and_hms_opt(0, 0, 0).unwrap();
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        None => DateTime::from_naive_utc_and_offset(midnight, Utc),
+    }
+}
+
+/* Hand-rolled protobuf encoding of the remote-write WriteRequest message.
+ * The schema is tiny and frozen (prometheus/prompb/remote.proto), so
+ * encoding it directly beats pulling in a protobuf toolchain:
+ *
+ *   WriteRequest { repeated TimeSeries timeseries = 1; }
+ *   TimeSeries   { repeated Label labels = 1; repeated Sample samples = 2; }
+ *   Label        { string name = 1; string value = 2; }
+ *   Sample       { double value = 1; int64 timestamp = 2; }
+ */
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+/// Encodes a length-delimited field (wire type 2)
+fn encode_bytes_field(field: u64, bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_varint(field << 3 | 2, buf);
+    encode_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_label(name: &str, value: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_bytes_field(1, name.as_bytes(), &mut buf);
+    encode_bytes_field(2, value.as_bytes(), &mut buf);
+    buf
+}
+
+fn encode_sample(value: f64, timestamp_ms: i64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    // field 1, wire type 1 (fixed64)
+    encode_varint(1 << 3 | 1, &mut buf);
+    buf.extend_from_slice(&value.to_le_bytes());
+    encode_varint(2 << 3, &mut buf);
+    encode_varint(timestamp_ms as u64, &mut buf);
+    buf
+}
+
+fn encode_write_request(metrics: &[Metric], timestamp_ms: i64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (labels, value) in metrics {
+        let mut series = Vec::new();
+        for (name, label_value) in labels {
+            encode_bytes_field(1, &encode_label(name, label_value), &mut series);
+        }
+        encode_bytes_field(2, &encode_sample(*value, timestamp_ms), &mut series);
+        encode_bytes_field(1, &series, &mut buf);
+    }
+    buf
+}
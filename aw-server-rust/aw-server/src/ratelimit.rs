@@ -0,0 +1,61 @@
+//! Per-bucket ingest rate limiting, for buggy watchers that emit tens
+//! of events per second. Rather than rejecting (the watcher would just
+//! retry) or melting the database, events beyond the configured
+//! events-per-minute rate are dropped, and the drops are counted so
+//! the situation shows up in metrics instead of passing silently.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+/// Events accepted in the current one-minute window of a bucket
+struct Window {
+    started: Instant,
+    accepted: u64,
+}
+
+static WINDOWS: LazyLock<Mutex<HashMap<String, Window>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Total events dropped by rate limiting since startup
+pub fn dropped_total() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Charges `count` incoming events against the bucket's
+/// events-per-minute limit and returns how many of them may be kept.
+/// The rest are counted as dropped; the first drop in each window is
+/// logged so the misbehaving watcher can be found.
+pub fn check(bucket_id: &str, limit: u64, count: u64) -> u64 {
+    let mut windows = match WINDOWS.lock() {
+        Ok(windows) => windows,
+        // A poisoned lock shouldn't take ingest down with it
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let window = windows.entry(bucket_id.to_string()).or_insert(Window {
+        started: Instant::now(),
+        accepted: 0,
+    });
+    if window.started.elapsed().as_secs() >= 60 {
+        window.started = Instant::now();
+        window.accepted = 0;
+    }
+    let allowed = count.min(limit.saturating_sub(window.accepted));
+    let dropped = count - allowed;
+    if dropped > 0 {
+        // Only the request that crosses the limit logs, so a watcher
+        // stuck in a loop doesn't also flood the log
+        if window.accepted < limit {
+            warn!(
+                "Bucket '{bucket_id}' exceeded its rate limit of {limit} events/min, \
+                 dropping excess events"
+            );
+        }
+        DROPPED.fetch_add(dropped, Ordering::Relaxed);
+    }
+    window.accepted += allowed;
+    allowed
+}
@@ -0,0 +1,99 @@
+//! Replays a traffic file recorded by the testing-mode recorder
+//! (`GET /debug/recording`) against a server, respecting the relative
+//! timing of the recorded requests. Useful for load characterization
+//! and for reproducing bugs that only show up under a particular
+//! request interleaving.
+
+use std::path::Path;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+/// One recorded exchange, as dumped by the recorder. Fields the replay
+/// doesn't need (duration) are simply not deserialized.
+#[derive(Deserialize)]
+struct Record {
+    timestamp: DateTime<Utc>,
+    method: String,
+    uri: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    status: u16,
+}
+
+/// Headers that must not be replayed verbatim: tied to the original
+/// connection, set by the HTTP client itself, or redacted by the
+/// recorder
+const SKIPPED_HEADERS: [&str; 5] = [
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "accept-encoding",
+];
+
+/// Replays the recorded requests against `base_url` in order. With
+/// `no_timing` the original inter-request gaps are skipped and requests
+/// are sent back to back. Returns an error only if the file itself is
+/// unusable; responses differing from the recording are logged and
+/// counted, since that divergence is often exactly what's being hunted.
+pub fn replay(path: &Path, base_url: &str, no_timing: bool) -> Result<(), String> {
+    let data =
+        std::fs::read_to_string(path).map_err(|err| format!("Failed to read {path:?}: {err}"))?;
+    let records: Vec<Record> =
+        serde_json::from_str(&data).map_err(|err| format!("Failed to parse {path:?}: {err}"))?;
+    let Some(first) = records.first() else {
+        info!("Nothing to replay, {path:?} contains no requests");
+        return Ok(());
+    };
+    let base = base_url.trim_end_matches('/');
+    let first_timestamp = first.timestamp;
+    let started = Instant::now();
+    let mut divergences = 0;
+    for record in &records {
+        if !no_timing {
+            let offset = (record.timestamp - first_timestamp)
+                .to_std()
+                .unwrap_or_default();
+            if let Some(wait) = offset.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        let mut request = ureq::request(&record.method, &format!("{base}{}", record.uri));
+        for (name, value) in &record.headers {
+            if SKIPPED_HEADERS.contains(&name.to_lowercase().as_str()) || value == "[redacted]" {
+                continue;
+            }
+            request = request.set(name, value);
+        }
+        let status = match match &record.body {
+            Some(body) => request.send_string(body),
+            None => request.call(),
+        } {
+            Ok(response) => response.status(),
+            // 4xx/5xx responses are still responses; the recording may
+            // well contain them on purpose
+            Err(ureq::Error::Status(code, _)) => code,
+            Err(err) => {
+                warn!("{} {} failed: {err}", record.method, record.uri);
+                divergences += 1;
+                continue;
+            }
+        };
+        if status != record.status {
+            warn!(
+                "{} {} -> {status}, was {} when recorded",
+                record.method, record.uri, record.status
+            );
+            divergences += 1;
+        }
+    }
+    info!(
+        "Replayed {} requests in {:.1}s, {divergences} diverged from the recording",
+        records.len(),
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
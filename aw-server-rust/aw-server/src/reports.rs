@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::thread;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use aw_datastore::Datastore;
+
+use crate::endpoints::budget::{compute_progress, Budget, BUDGET_PREFIX};
+use crate::endpoints::stats::{active_events, get_timezone, get_working_hours, seconds_within_hours};
+
+/// Report definitions are stored in the key_value table, prefixed with
+/// `report.`; last-run state uses its own prefix.
+pub static REPORT_PREFIX: &str = "report.";
+pub static STATE_PREFIX: &str = "report_state.";
+
+/// How often the reporter thread checks for due reports
+const CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// How many apps the summary lists
+const TOP_APPS: usize = 5;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    /// Slack incoming webhook: payload is `{"text": ...}`
+    Slack,
+    /// Discord webhook: payload is `{"content": ...}`
+    Discord,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    Day,
+    Week,
+}
+
+/// A scheduled activity summary posted to a Slack or Discord webhook. The
+/// cron expression (six-field format with seconds) decides when it is
+/// posted; the period decides how far back the summary looks.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReportDef {
+    pub webhook: String,
+    pub format: ReportFormat,
+    pub period: ReportPeriod,
+    pub cron: String,
+}
+
+/// Spawns the reporter thread, which posts due summaries
+pub fn start(datastore: Datastore) {
+    thread::spawn(move || loop {
+        check_reports(&datastore, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+/// Posts every report whose cron expression has triggered since its last
+/// run, mirroring the scheduler's bookkeeping
+pub fn check_reports(datastore: &Datastore, now: DateTime<Utc>) {
+    let keys = match datastore.get_keys_starting(&format!("{REPORT_PREFIX}%")) {
+        Ok(keys) => keys,
+        Err(err) => {
+            warn!("Reporter failed to list reports: {err}");
+            return;
+        }
+    };
+    for key in keys {
+        let name = &key[REPORT_PREFIX.len()..];
+        let def = match datastore
+            .get_key_value(&key)
+            .map_err(|err| err.to_string())
+            .and_then(|kv| {
+                serde_json::from_str::<ReportDef>(&kv.value).map_err(|err| err.to_string())
+            }) {
+            Ok(def) => def,
+            Err(err) => {
+                warn!("Reporter failed to parse report '{name}': {err}");
+                continue;
+            }
+        };
+        let schedule: cron::Schedule = match def.cron.parse() {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                warn!("Reporter failed to parse cron expression for '{name}': {err}");
+                continue;
+            }
+        };
+
+        let state_key = format!("{STATE_PREFIX}{name}");
+        let last_run: Option<DateTime<Utc>> = datastore
+            .get_key_value(&state_key)
+            .ok()
+            .and_then(|kv| serde_json::from_str(&kv.value).ok());
+
+        let due = match last_run {
+            Some(last_run) => matches!(
+                schedule.after(&last_run).next(),
+                Some(next) if next <= now
+            ),
+            // First sighting: record the clock and wait for the next
+            // trigger, don't post retroactively
+            None => false,
+        };
+        if due {
+            if let Err(err) = run_report(datastore, &def, now) {
+                warn!("Reporter failed to post report '{name}': {err}");
+            }
+        }
+        if due || last_run.is_none() {
+            let now_str = serde_json::to_string(&now).unwrap();
+            if let Err(err) = datastore.insert_key_value(&state_key, &now_str) {
+                warn!("Reporter failed to save state for '{name}': {err}");
+            }
+        }
+    }
+}
+
+/// Composes and posts the summary, returning the message text. Delivery
+/// failures are logged but don't fail the run, so a flaky webhook doesn't
+/// make the reporter retry-spam on every tick.
+pub fn run_report(
+    datastore: &Datastore,
+    def: &ReportDef,
+    now: DateTime<Utc>,
+) -> Result<String, String> {
+    let message = compose_summary(datastore, &def.period, now)?;
+    let payload = match def.format {
+        ReportFormat::Slack => json!({ "text": message }),
+        ReportFormat::Discord => json!({ "content": message }),
+    };
+    if let Err(err) = ureq::post(&def.webhook)
+        .timeout(std::time::Duration::from_secs(10))
+        .send_json(payload)
+    {
+        warn!("Failed to deliver report to {}: {err}", def.webhook);
+    }
+    Ok(message)
+}
+
+fn format_hours(seconds: f64) -> String {
+    format!("{:.1}h", seconds / 3600.0)
+}
+
+/// The summary text: active time, top apps and budget progress over the
+/// trailing period
+fn compose_summary(
+    datastore: &Datastore,
+    period: &ReportPeriod,
+    now: DateTime<Utc>,
+) -> Result<String, String> {
+    let (label, start) = match period {
+        ReportPeriod::Day => ("today", now - Duration::hours(24)),
+        ReportPeriod::Week => ("this week", now - Duration::days(7)),
+    };
+
+    let active = active_events(datastore, Some(start), Some(now))
+        .map_err(|err| format!("failed to compute active time: {err:?}"))?;
+    let mut total = 0.0;
+    let mut per_app: HashMap<String, f64> = HashMap::new();
+    for event in &active {
+        let seconds = event.duration.num_milliseconds() as f64 / 1000.0;
+        total += seconds;
+        if let Some(app) = event.data.get("app").and_then(|value| value.as_str()) {
+            *per_app.entry(app.to_string()).or_insert(0.0) += seconds;
+        }
+    }
+    let mut top: Vec<(String, f64)> = per_app.into_iter().collect();
+    top.sort_by(|a, b| b.1.total_cmp(&a.1));
+    top.truncate(TOP_APPS);
+
+    let mut lines = vec![format!("Active time {label}: {}", format_hours(total))];
+    if let Some(working_hours) = get_working_hours(datastore) {
+        let tz = get_timezone(datastore);
+        let within = seconds_within_hours(&active, &tz, working_hours);
+        lines.push(format!(
+            "Within working hours ({:02}-{:02}): {}",
+            working_hours.0,
+            working_hours.1,
+            format_hours(within)
+        ));
+    }
+    if !top.is_empty() {
+        lines.push("Top apps:".to_string());
+        for (app, seconds) in top {
+            lines.push(format!("- {app}: {}", format_hours(seconds)));
+        }
+    }
+
+    let budget_keys = datastore
+        .get_keys_starting(&format!("{BUDGET_PREFIX}%"))
+        .map_err(|err| format!("failed to list budgets: {err}"))?;
+    let mut budget_lines = Vec::new();
+    for key in budget_keys {
+        let name = &key[BUDGET_PREFIX.len()..];
+        let Ok(kv) = datastore.get_key_value(&key) else {
+            continue;
+        };
+        let Ok(budget) = serde_json::from_str::<Budget>(&kv.value) else {
+            warn!("Reporter failed to parse budget '{name}', skipping");
+            continue;
+        };
+        match compute_progress(datastore, &budget, now) {
+            Ok((_start, spent, budgeted)) => budget_lines.push(format!(
+                "- {name}: {} / {}",
+                format_hours(spent),
+                format_hours(budgeted)
+            )),
+            Err(err) => warn!("Reporter failed to compute budget '{name}': {err:?}"),
+        }
+    }
+    if !budget_lines.is_empty() {
+        lines.push("Goals:".to_string());
+        lines.extend(budget_lines);
+    }
+
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,221 @@
+//! Retention policy engine: deletes events past a configurable age so
+//! the database doesn't grow forever on always-on machines.
+//!
+//! Rules live in the settings store under the `settings.retention` key
+//! as a JSON object mapping a bucket matcher to a maximum age in days,
+//! e.g. `{"web.tab.current": 90, "aw-watcher-afk_*": 30}`. A matcher is
+//! compared against the bucket type (exact) and the bucket id (exact or
+//! a `*` suffix for prefix matches), the same pattern language API keys
+//! use for bucket restrictions. When several rules match a bucket the
+//! longest retention wins — ambiguity should keep data, not delete it.
+//!
+//! Compaction is the gentler alternative: rules under the
+//! `settings.compaction` key use the same matchers and map to either a
+//! number of days or `{"days": N, "pulsetime": S}`. Events older than
+//! that are not deleted but merged — runs of identical-data events with
+//! gaps of at most the pulsetime (default 0) collapse into single rows,
+//! a retroactive heartbeat pass that shrinks the events table while
+//! preserving tracked durations.
+//!
+//! A background thread enforces the rules periodically; expired events
+//! are purged for good, including their provenance and any history
+//! pre-images. The report endpoint shows what a pass would remove
+//! without removing anything.
+
+use std::collections::HashMap;
+use std::thread;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+use aw_datastore::Datastore;
+
+/// Settings key (including the `settings.` prefix) holding the rules
+pub static RETENTION_SETTINGS_KEY: &str = "settings.retention";
+
+/// Settings key holding the compaction rules
+pub static COMPACTION_SETTINGS_KEY: &str = "settings.compaction";
+
+/// How often the retention thread enforces the rules
+const CHECK_INTERVAL_SECONDS: u64 = 3600;
+
+/// Spawns the retention thread, which periodically enforces the rules
+pub fn start(datastore: Datastore) {
+    thread::spawn(move || loop {
+        enforce(&datastore, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+/// Loads the retention rules, mapping matcher to max age in days.
+/// Missing or unparseable settings mean no rules — retention never
+/// deletes anything on a bad config.
+fn load_rules(datastore: &Datastore) -> HashMap<String, f64> {
+    let Ok(kv) = datastore.get_key_value(RETENTION_SETTINGS_KEY) else {
+        return HashMap::new();
+    };
+    match serde_json::from_str::<HashMap<String, f64>>(&kv.value) {
+        Ok(rules) => rules.into_iter().filter(|(_, days)| *days > 0.0).collect(),
+        Err(err) => {
+            warn!("Retention rules are not an object of positive day counts, ignoring: {err}");
+            HashMap::new()
+        }
+    }
+}
+
+/// A compaction rule value: a bare day count, or an object also naming
+/// the pulsetime used when merging
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum CompactionRule {
+    Days(f64),
+    Full {
+        days: f64,
+        #[serde(default)]
+        pulsetime: f64,
+    },
+}
+
+impl CompactionRule {
+    fn days(&self) -> f64 {
+        match self {
+            CompactionRule::Days(days) | CompactionRule::Full { days, .. } => *days,
+        }
+    }
+
+    fn pulsetime(&self) -> f64 {
+        match self {
+            CompactionRule::Days(_) => 0.0,
+            CompactionRule::Full { pulsetime, .. } => *pulsetime,
+        }
+    }
+}
+
+/// Loads the compaction rules; same failure mode as `load_rules`
+fn load_compaction_rules(datastore: &Datastore) -> HashMap<String, CompactionRule> {
+    let Ok(kv) = datastore.get_key_value(COMPACTION_SETTINGS_KEY) else {
+        return HashMap::new();
+    };
+    match serde_json::from_str::<HashMap<String, CompactionRule>>(&kv.value) {
+        Ok(rules) => rules
+            .into_iter()
+            .filter(|(_, rule)| rule.days() > 0.0)
+            .collect(),
+        Err(err) => {
+            warn!("Compaction rules are malformed, ignoring: {err}");
+            HashMap::new()
+        }
+    }
+}
+
+fn matches(pattern: &str, bucket_id: &str, bucket_type: &str) -> bool {
+    if pattern == bucket_type {
+        return true;
+    }
+    match pattern.strip_suffix('*') {
+        Some(prefix) => bucket_id.starts_with(prefix),
+        None => pattern == bucket_id,
+    }
+}
+
+/// The cutoff per bucket id implied by the rules; buckets no rule
+/// matches are not listed and never touched
+pub fn cutoffs(datastore: &Datastore, now: DateTime<Utc>) -> HashMap<String, DateTime<Utc>> {
+    let rules = load_rules(datastore);
+    if rules.is_empty() {
+        return HashMap::new();
+    }
+    let buckets = datastore.get_buckets().unwrap_or_default();
+    let mut cutoffs = HashMap::new();
+    for (bucket_id, bucket) in buckets {
+        let days = rules
+            .iter()
+            .filter(|(pattern, _)| matches(pattern, &bucket_id, &bucket._type))
+            .map(|(_, days)| *days)
+            .fold(None, |longest: Option<f64>, days| {
+                Some(longest.map_or(days, |longest| longest.max(days)))
+            });
+        if let Some(days) = days {
+            let age = Duration::try_milliseconds((days * 86_400_000.0) as i64)
+                .unwrap_or_else(Duration::zero);
+            cutoffs.insert(bucket_id, now - age);
+        }
+    }
+    cutoffs
+}
+
+/// The compaction cutoff and pulsetime per bucket id implied by the
+/// compaction rules. Like retention cutoffs, the longest `days` wins
+/// when several rules match.
+fn compaction_cutoffs(
+    datastore: &Datastore,
+    now: DateTime<Utc>,
+) -> HashMap<String, (DateTime<Utc>, f64)> {
+    let rules = load_compaction_rules(datastore);
+    if rules.is_empty() {
+        return HashMap::new();
+    }
+    let buckets = datastore.get_buckets().unwrap_or_default();
+    let mut cutoffs = HashMap::new();
+    for (bucket_id, bucket) in buckets {
+        let rule = rules
+            .iter()
+            .filter(|(pattern, _)| matches(pattern, &bucket_id, &bucket._type))
+            .map(|(_, rule)| *rule)
+            .fold(None, |longest: Option<CompactionRule>, rule| {
+                Some(match longest {
+                    Some(longest) if longest.days() >= rule.days() => longest,
+                    _ => rule,
+                })
+            });
+        if let Some(rule) = rule {
+            let age = Duration::try_milliseconds((rule.days() * 86_400_000.0) as i64)
+                .unwrap_or_else(Duration::zero);
+            cutoffs.insert(bucket_id, (now - age, rule.pulsetime()));
+        }
+    }
+    cutoffs
+}
+
+/// Deletes expired events and compacts old ones in every bucket with a
+/// matching rule. Public (with an injectable clock) so it can be tested
+/// and force-triggered over the API.
+pub fn enforce(datastore: &Datastore, now: DateTime<Utc>) {
+    for (bucket_id, cutoff) in cutoffs(datastore, now) {
+        match datastore.delete_events_before(&bucket_id, cutoff) {
+            Ok(0) => (),
+            Ok(deleted) => info!("Retention deleted {deleted} expired events from {bucket_id}"),
+            Err(err) => warn!("Retention failed to delete events from {bucket_id}: {err}"),
+        }
+    }
+    for (bucket_id, (cutoff, pulsetime)) in compaction_cutoffs(datastore, now) {
+        match datastore.compact_events_before(&bucket_id, cutoff, pulsetime) {
+            Ok(0) => (),
+            Ok(merged) => info!("Compaction merged away {merged} events in {bucket_id}"),
+            Err(err) => warn!("Compaction failed in {bucket_id}: {err}"),
+        }
+    }
+}
+
+/// What an enforcement pass at `now` would delete, per bucket with a
+/// matching rule, without deleting anything
+pub fn report(datastore: &Datastore, now: DateTime<Utc>) -> Vec<serde_json::Value> {
+    let mut entries = Vec::new();
+    for (bucket_id, cutoff) in cutoffs(datastore, now) {
+        let expired = match datastore.count_events_before(&bucket_id, cutoff) {
+            Ok(count) => count,
+            Err(err) => {
+                warn!("Retention failed to count events in {bucket_id}: {err}");
+                continue;
+            }
+        };
+        entries.push(json!({
+            "bucket": bucket_id,
+            "cutoff": cutoff.to_rfc3339(),
+            "expired_events": expired,
+        }));
+    }
+    entries.sort_by(|a, b| a["bucket"].as_str().cmp(&b["bucket"].as_str()));
+    entries
+}
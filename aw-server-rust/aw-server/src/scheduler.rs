@@ -0,0 +1,142 @@
+use std::thread;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+
+use aw_datastore::Datastore;
+use aw_models::TimeInterval;
+
+use crate::endpoints::query::SavedQuery;
+
+/// Schedule definitions are stored in the key_value table, prefixed with
+/// `schedule.`; last-run state and materialized results use their own
+/// prefixes so they don't show up as definitions.
+pub static SCHEDULE_PREFIX: &str = "schedule.";
+pub static STATE_PREFIX: &str = "schedule_state.";
+pub static RESULT_PREFIX: &str = "schedule_result.";
+
+/// How often the scheduler thread checks for due schedules
+const CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// A saved query scheduled for periodic materialization. The cron expression
+/// uses the six-field format with seconds (e.g. `0 0 8 * * Mon` for mondays
+/// at 08:00 UTC); each run queries the trailing `period_hours` of data.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ScheduledQuery {
+    pub query: String,
+    pub cron: String,
+    #[serde(default = "default_period_hours")]
+    pub period_hours: f64,
+}
+
+fn default_period_hours() -> f64 {
+    24.0
+}
+
+/// Spawns the scheduler thread, which periodically materializes the results
+/// of scheduled queries so expensive summaries are precomputed.
+pub fn start(datastore: Datastore) {
+    thread::spawn(move || loop {
+        check_schedules(&datastore, Utc::now());
+        thread::sleep(std::time::Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+/// Runs every schedule whose cron expression has triggered since its last
+/// run. Public (with an injectable clock) so it can be tested.
+pub fn check_schedules(datastore: &Datastore, now: DateTime<Utc>) {
+    let keys = match datastore.get_keys_starting(&format!("{SCHEDULE_PREFIX}%")) {
+        Ok(keys) => keys,
+        Err(err) => {
+            warn!("Scheduler failed to list schedules: {err}");
+            return;
+        }
+    };
+    for key in keys {
+        let name = &key[SCHEDULE_PREFIX.len()..];
+        let def = match datastore
+            .get_key_value(&key)
+            .map_err(|err| err.to_string())
+            .and_then(|kv| {
+                serde_json::from_str::<ScheduledQuery>(&kv.value).map_err(|err| err.to_string())
+            }) {
+            Ok(def) => def,
+            Err(err) => {
+                warn!("Scheduler failed to parse schedule '{name}': {err}");
+                continue;
+            }
+        };
+        let schedule: cron::Schedule = match def.cron.parse() {
+            Ok(schedule) => schedule,
+            Err(err) => {
+                warn!("Scheduler failed to parse cron expression for '{name}': {err}");
+                continue;
+            }
+        };
+
+        let state_key = format!("{STATE_PREFIX}{name}");
+        let last_run: Option<DateTime<Utc>> = datastore
+            .get_key_value(&state_key)
+            .ok()
+            .and_then(|kv| serde_json::from_str(&kv.value).ok());
+        let last_run = match last_run {
+            Some(last_run) => last_run,
+            None => {
+                // First time we see this schedule, start counting from now
+                // instead of running immediately
+                let _ = datastore
+                    .insert_key_value(&state_key, &serde_json::to_string(&now).unwrap());
+                continue;
+            }
+        };
+
+        let due = schedule
+            .after(&last_run)
+            .next()
+            .map(|next| next <= now)
+            .unwrap_or(false);
+        if !due {
+            continue;
+        }
+        match run_schedule(datastore, name, &def, now) {
+            Ok(_) => info!("Scheduler materialized query '{}' for '{name}'", def.query),
+            Err(err) => warn!("Scheduler failed to run '{name}': {err}"),
+        }
+        let _ = datastore.insert_key_value(&state_key, &serde_json::to_string(&now).unwrap());
+    }
+}
+
+/// Runs the saved query behind a schedule over its trailing period and
+/// stores the result in the history
+pub fn run_schedule(
+    datastore: &Datastore,
+    name: &str,
+    def: &ScheduledQuery,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let kv = datastore
+        .get_key_value(&format!("query.{}", def.query))
+        .map_err(|err| format!("failed to fetch saved query '{}': {err}", def.query))?;
+    let saved: SavedQuery =
+        serde_json::from_str(&kv.value).map_err(|err| format!("invalid saved query: {err}"))?;
+    let code = saved.query.join("\n");
+
+    let start = now - Duration::milliseconds((def.period_hours * 3_600_000.0) as i64);
+    let interval = TimeInterval::new(start, now);
+    let result = aw_query::query(&code, &interval, datastore)
+        .map_err(|err| format!("query error: {err}"))?;
+
+    let entry = serde_json::json!({
+        "timestamp": now,
+        "timeperiod": interval,
+        "result": result,
+    });
+    datastore
+        .insert_key_value(
+            &format!("{RESULT_PREFIX}{name}.{}", now.to_rfc3339()),
+            &entry.to_string(),
+        )
+        .map_err(|err| format!("failed to store result: {err}"))?;
+    Ok(())
+}
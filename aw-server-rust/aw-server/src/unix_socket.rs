@@ -0,0 +1,99 @@
+//! Unix domain socket support for local-only deployments. Rocket's
+//! listener only binds TCP, so a small forwarder accepts connections on
+//! the socket — either bound from `--socket` or inherited from systemd
+//! socket activation (LISTEN_FDS) — and proxies them to the TCP
+//! listener. Filesystem permissions on the socket then control who can
+//! reach the API.
+
+use std::io;
+use std::net::TcpStream;
+use std::os::fd::FromRawFd;
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+
+/// The first fd systemd passes with socket activation, per the protocol
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// A listener inherited via systemd socket activation, if any. Only the
+/// first passed fd is used; LISTEN_PID must match so sockets meant for
+/// a parent process are never stolen.
+pub fn activation_listener() -> Option<UnixListener> {
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if fds < 1 {
+        return None;
+    }
+    if fds > 1 {
+        warn!("systemd passed {fds} sockets, only the first is used");
+    }
+    // SAFETY: when LISTEN_PID matches, systemd guarantees fd 3 is a
+    // listening socket it opened for this process
+    Some(unsafe { UnixListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+/// Tells systemd the service has begun shutting down (`STOPPING=1`), so
+/// it knows the stop is deliberate and extends the stop timeout while
+/// the datastore flushes. A no-op without `NOTIFY_SOCKET`; abstract
+/// socket names are not supported by std and are skipped.
+pub fn notify_stopping() {
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if path.starts_with('@') {
+        debug!("Abstract NOTIFY_SOCKET is not supported, skipping notification");
+        return;
+    }
+    let result = UnixDatagram::unbound().and_then(|sock| sock.send_to(b"STOPPING=1", &path));
+    if let Err(err) = result {
+        warn!("Failed to notify systemd of shutdown: {err}");
+    }
+}
+
+/// Binds the socket path, removing a stale socket left by a previous run
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Spawns the accept loop: each connection on the unix socket is proxied
+/// to the TCP listener at `address:port` on its own thread
+pub fn start_forwarder(listener: UnixListener, address: String, port: u16) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let address = address.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = forward(stream, &address, port) {
+                            debug!("Unix socket connection ended: {err}");
+                        }
+                    });
+                }
+                Err(err) => warn!("Unix socket accept failed: {err}"),
+            }
+        }
+    });
+}
+
+/// Copies bytes in both directions until either side closes
+fn forward(client: UnixStream, address: &str, port: u16) -> io::Result<()> {
+    let upstream = TcpStream::connect((address, port))?;
+    let mut client_read = client.try_clone()?;
+    let mut upstream_write = upstream.try_clone()?;
+    let to_server = thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(std::net::Shutdown::Write);
+    });
+    let mut upstream_read = upstream;
+    let mut client_write = client;
+    let _ = io::copy(&mut upstream_read, &mut client_write);
+    let _ = client_write.shutdown(std::net::Shutdown::Write);
+    let _ = to_server.join();
+    Ok(())
+}
@@ -0,0 +1,4295 @@
+#[cfg(test)]
+mod api_tests {
+    use std::sync::Mutex;
+
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    use aw_server::config::AWConfig;
+    use aw_server::endpoints;
+
+    fn setup_testserver() -> Client {
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        Client::tracked(server).expect("valid instance")
+    }
+
+    #[test]
+    fn test_db_metrics_headers() {
+        // The metrics fairing is only attached in testing mode
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            testing: true,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let queries: u64 = res
+            .headers()
+            .get_one("X-DB-Queries")
+            .expect("X-DB-Queries header missing")
+            .parse()
+            .unwrap();
+        assert!(queries >= 1);
+        assert!(res.headers().get_one("X-DB-Time-Ms").is_some());
+
+        // Not attached outside testing mode
+        let client = setup_testserver();
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert!(res.headers().get_one("X-DB-Queries").is_none());
+    }
+
+    #[test]
+    fn test_debug_stats() {
+        // Only available in testing mode
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            testing: true,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        let res = client.get("/debug/stats").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let stats: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert!(stats["db_commands_handled"].as_u64().is_some());
+        assert_eq!(stats["buckets"], 0);
+        #[cfg(target_os = "linux")]
+        assert!(stats["memory"]["resident_kib"].as_u64().is_some());
+
+        let client = setup_testserver();
+        let res = client.get("/debug/stats").dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_recorder() {
+        use rocket::http::Header;
+
+        // Only attached in testing mode
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            testing: true,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        // The ring buffer is global, so pick a URI no other test uses
+        let res = client
+            .post("/api/0/buckets/recorder-test-bucket")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", "aw_secret"))
+            .body(
+                r#"{
+                    "id": "recorder-test-bucket",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client.get("/debug/recording").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let records: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let record = records
+            .iter()
+            .find(|r| r["uri"] == "/api/0/buckets/recorder-test-bucket")
+            .expect("request not recorded");
+        assert_eq!(record["method"], "POST");
+        assert_eq!(record["status"], 200);
+        assert!(record["body"]
+            .as_str()
+            .unwrap()
+            .contains("recorder-test-bucket"));
+        // Credentials never appear in the dump
+        let headers = record["headers"].as_array().unwrap();
+        assert!(headers
+            .iter()
+            .any(|h| h[0] == "X-API-Key" && h[1] == "[redacted]"));
+
+        // Clearing empties the buffer
+        let res = client.delete("/debug/recording").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/debug/recording").dispatch();
+        let records: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert!(!records
+            .iter()
+            .any(|r| r["uri"] == "/api/0/buckets/recorder-test-bucket"));
+
+        // Outside testing mode the endpoints are forbidden
+        let client = setup_testserver();
+        let res = client.get("/debug/recording").dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_chaos() {
+        // Only attached in testing mode
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            testing: true,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        // Rules are global, so match a path no other test requests:
+        // fail every 2nd request to it with a 503
+        let res = client
+            .put("/debug/chaos")
+            .header(ContentType::JSON)
+            .body(r#"[{"path_prefix": "/api/0/buckets/chaos-test", "status": 503, "every": 2}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client.get("/api/0/buckets/chaos-test").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client.get("/api/0/buckets/chaos-test").dispatch();
+        assert_eq!(res.status(), Status::ServiceUnavailable);
+        assert_eq!(res.into_string().unwrap_or_default(), "");
+        let res = client.get("/api/0/buckets/chaos-test").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        // Clearing the rules restores normal behavior
+        let res = client.delete("/debug/chaos").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/chaos-test").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        // Outside testing mode the endpoints are forbidden
+        let client = setup_testserver();
+        let res = client.get("/debug/chaos").dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_server_info() {
+        let client = setup_testserver();
+        let res = client.get("/api/0/info").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        assert!(body.contains("device_id"));
+    }
+
+    #[test]
+    fn test_request_id() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // Generated when absent
+        let res = client.get("/api/0/info").dispatch();
+        let id = res
+            .headers()
+            .get_one("X-Request-Id")
+            .expect("X-Request-Id header missing")
+            .to_string();
+        assert!(!id.is_empty());
+
+        // Echoed back when the client provides one
+        let res = client
+            .get("/api/0/info")
+            .header(Header::new("X-Request-Id", "my-request-1"))
+            .dispatch();
+        assert_eq!(res.headers().get_one("X-Request-Id"), Some("my-request-1"));
+
+        // Garbage ids are replaced, not echoed
+        let res = client
+            .get("/api/0/info")
+            .header(Header::new("X-Request-Id", "bad id\nwith newline"))
+            .dispatch();
+        assert_ne!(
+            res.headers().get_one("X-Request-Id"),
+            Some("bad id\nwith newline")
+        );
+    }
+
+    #[test]
+    fn test_health_probes() {
+        let client = setup_testserver();
+        let res = client.get("/healthz").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "ok");
+
+        let res = client.get("/readyz").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_buckets() {
+        let client = setup_testserver();
+
+        // Create bucket
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Creating the same bucket again answers 304, which aw-client's
+        // startup bucket POST tolerates
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::NotModified);
+
+        // Get bucket
+        let res = client.get("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Get non-existent bucket
+        let res = client.get("/api/0/buckets/invalid_bucket").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        // Get list of buckets
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Delete bucket
+        let res = client.delete("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_events() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Insert a single event
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 1.0,
+                    "data": {}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Get events
+        let res = client.get("/api/0/buckets/id/events").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Get event count
+        let res = client.get("/api/0/buckets/id/events/count").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "1");
+
+        // Heartbeat
+        let res = client
+            .post("/api/0/buckets/id/heartbeat?pulsetime=2")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T01:01:02Z",
+                    "duration": 1.0,
+                    "data": {}
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_heartbeat_batch() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // A replayed queue: three contiguous heartbeats with the same
+        // data merge into one event, the fourth differs and stands alone
+        let res = client
+            .post("/api/0/buckets/id/heartbeat/batch?pulsetime=2")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:00Z",
+                    "duration": 1.0,
+                    "data": {"app": "firefox"}
+                }, {
+                    "timestamp": "2018-01-01T01:01:02Z",
+                    "duration": 1.0,
+                    "data": {"app": "firefox"}
+                }, {
+                    "timestamp": "2018-01-01T01:01:04Z",
+                    "duration": 1.0,
+                    "data": {"app": "firefox"}
+                }, {
+                    "timestamp": "2018-01-01T01:01:06Z",
+                    "duration": 1.0,
+                    "data": {"app": "editor"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let written: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(written.len(), 4);
+        // Every replayed heartbeat resolves to the merged event's state
+        assert_eq!(written[2]["duration"], 5.0);
+
+        let res = client.get("/api/0/buckets/id/events").dispatch();
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["data"]["app"], "editor");
+        assert_eq!(events[1]["data"]["app"], "firefox");
+        assert_eq!(events[1]["duration"], 5.0);
+
+        // An empty batch is a no-op, not an error
+        let res = client
+            .post("/api/0/buckets/id/heartbeat/batch?pulsetime=2")
+            .header(ContentType::JSON)
+            .body("[]")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_events_explain() {
+        // Only available in testing mode
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            testing: true,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .get("/api/0/buckets/id/events/explain?limit=10")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let plan: Vec<String> = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert!(!plan.is_empty());
+        // The events query should use the index, not scan the table
+        assert!(plan[0].contains("USING INDEX"), "{plan:?}");
+
+        let client = setup_testserver();
+        let res = client
+            .get("/api/0/buckets/id/events/explain?limit=10")
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_field_limits() {
+        use std::collections::HashMap;
+
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let mut limits = HashMap::new();
+        limits.insert(
+            "web.tab.current".to_string(),
+            HashMap::from([("title".to_string(), 5)]),
+        );
+        let aw_config = AWConfig {
+            port: 8000,
+            field_limits: limits,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "web.tab.current",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Too long titles are trimmed with a marker, other fields and
+        // short values pass through untouched
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 1.0,
+                    "data": {"title": "a much too long title", "url": "http://example.com"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        assert!(body.contains(r#""title":"a muc…""#), "{body}");
+        assert!(body.contains(r#""url":"http://example.com""#), "{body}");
+
+        // Heartbeats are trimmed the same way, so they still merge with
+        // the trimmed stored event
+        let res = client
+            .post("/api/0/buckets/id/heartbeat?pulsetime=2")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T01:01:02Z",
+                    "duration": 1.0,
+                    "data": {"title": "a much too long title", "url": "http://example.com"}
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/id/events/count").dispatch();
+        assert_eq!(res.into_string().unwrap(), "1");
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        use std::collections::HashMap;
+
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            rate_limits: HashMap::from([("test.rate".to_string(), 2)]),
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+
+        let res = client
+            .post("/api/0/buckets/rated")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "rated",
+                    "type": "test.rate",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Three events against a limit of two per minute: the excess is
+        // dropped, not rejected
+        let res = client
+            .post("/api/0/buckets/rated/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}},
+                    {"timestamp": "2018-01-01T01:01:02Z", "duration": 1.0, "data": {}},
+                    {"timestamp": "2018-01-01T01:01:03Z", "duration": 1.0, "data": {}}]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let inserted: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(inserted.as_array().unwrap().len(), 2);
+        let res = client.get("/api/0/buckets/rated/events/count").dispatch();
+        assert_eq!(res.into_string().unwrap(), "2");
+
+        // A heartbeat over the rate is acknowledged but sampled out
+        let res = client
+            .post("/api/0/buckets/rated/heartbeat?pulsetime=1")
+            .header(ContentType::JSON)
+            .body(r#"{"timestamp": "2018-01-01T01:01:04Z", "duration": 1.0, "data": {}}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/rated/events/count").dispatch();
+        assert_eq!(res.into_string().unwrap(), "2");
+    }
+
+    #[test]
+    fn test_buckets_updated_since() {
+        let client = setup_testserver();
+
+        let mkbucket = |id: &str| {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "type",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        };
+        mkbucket("old");
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let since = chrono::Utc::now().to_rfc3339();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        mkbucket("fresh");
+
+        let res = client
+            .get(format!("/api/0/buckets/?updated_since={}", urlencode(&since)))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        assert!(body.contains("fresh"), "{body}");
+        assert!(!body.contains("old"), "{body}");
+
+        // Writing to the old bucket brings it back into the window
+        let res = client
+            .post("/api/0/buckets/old/events")
+            .header(ContentType::JSON)
+            .body(r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .get(format!("/api/0/buckets/?updated_since={}", urlencode(&since)))
+            .dispatch();
+        let body = res.into_string().unwrap();
+        assert!(body.contains("old"), "{body}");
+
+        // Garbage timestamps are rejected
+        let res = client
+            .get("/api/0/buckets/?updated_since=yesterday")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    fn urlencode(value: &str) -> String {
+        value.replace('+', "%2B").replace(':', "%3A")
+    }
+
+    #[test]
+    fn test_bucket_events_stream() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/streamed")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "streamed",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/streamed/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {"n": 1}},
+                    {"timestamp": "2018-01-01T01:01:03Z", "duration": 1.0, "data": {"n": 2}},
+                    {"timestamp": "2018-01-01T01:01:05Z", "duration": 1.0, "data": {"n": 3}}]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // One JSON object per line, newest first
+        let res = client.get("/api/0/buckets/streamed/events/stream").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        let events: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["data"]["n"], 3);
+        assert_eq!(events[2]["data"]["n"], 1);
+
+        // Time range filters apply like on /events
+        let res = client
+            .get("/api/0/buckets/streamed/events/stream?end=2018-01-01T01%3A01%3A04Z")
+            .dispatch();
+        let body = res.into_string().unwrap();
+        assert_eq!(body.lines().count(), 2);
+
+        // Unknown buckets get a clean 404, not a truncated stream
+        let res = client.get("/api/0/buckets/nosuch/events/stream").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_bucket_archive() {
+        let client = setup_testserver();
+
+        for id in ["current", "retired"] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "type",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+        let res = client
+            .post("/api/0/buckets/retired/events")
+            .header(ContentType::JSON)
+            .body(r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Archiving hides the bucket from the default listing...
+        let res = client
+            .post("/api/0/buckets/retired/archive?archived=true")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = client
+            .get("/api/0/buckets/")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        assert!(body.contains("current"), "{body}");
+        assert!(!body.contains("retired"), "{body}");
+
+        // ...but not from the opt-in listing, and its data stays
+        // queryable by id
+        let body = client
+            .get("/api/0/buckets/?include_archived=true")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        assert!(body.contains("retired"), "{body}");
+        let res = client.get("/api/0/buckets/retired/events").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Unarchiving brings it back
+        let res = client
+            .post("/api/0/buckets/retired/archive?archived=false")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = client
+            .get("/api/0/buckets/")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        assert!(body.contains("retired"), "{body}");
+    }
+
+    #[test]
+    fn test_event_provenance() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/prov")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "prov",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/prov/events")
+            .header(ContentType::JSON)
+            .body(r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Provenance is only included when asked for
+        let res = client.get("/api/0/buckets/prov/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events[0].get("provenance"), None);
+        let res = client
+            .get("/api/0/buckets/prov/events?include_provenance=true")
+            .dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let provenance = &events[0]["provenance"];
+        assert!(provenance["ingested"].is_string(), "{provenance}");
+        // A live insert belongs to no import batch
+        assert_eq!(provenance.get("batch"), None);
+
+        // Imports return a batch id, recorded on every imported event
+        let res = client
+            .post("/api/0/import/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"buckets": {"prov-import": {
+                    "id": "prov-import",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname",
+                    "events": [
+                        {"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}},
+                        {"timestamp": "2018-01-01T02:01:01Z", "duration": 1.0, "data": {}}
+                    ]
+                }}}"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let batch = body["batch"].as_str().unwrap().to_string();
+
+        let res = client
+            .get("/api/0/buckets/prov-import/events?include_provenance=true")
+            .dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 2);
+        assert_eq!(events[0]["provenance"]["batch"], batch.as_str());
+
+        // Rolling back the batch deletes exactly its events
+        let res = client.delete(format!("/api/0/import/{batch}")).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(body["events_deleted"], 2);
+        // A second rollback (or a mistyped batch id) is a 404, not a
+        // silent no-op
+        let res = client.delete(format!("/api/0/import/{batch}")).dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client.get("/api/0/buckets/prov-import/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 0);
+        let res = client.get("/api/0/buckets/prov/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_events_data_filter() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/filtered")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "filtered",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/filtered/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[
+                    {"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0,
+                     "data": {"app": "Firefox", "afk": false}},
+                    {"timestamp": "2018-01-01T02:01:01Z", "duration": 1.0,
+                     "data": {"app": "Firefox", "afk": true}},
+                    {"timestamp": "2018-01-01T03:01:01Z", "duration": 1.0,
+                     "data": {"app": "vim", "afk": false}}
+                ]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Single filter, string valued
+        let res = client
+            .get("/api/0/buckets/filtered/events?data.app=Firefox")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 2);
+
+        // Filters combine, and booleans are matched as booleans
+        let res = client
+            .get("/api/0/buckets/filtered/events?data.app=Firefox&data.afk=false")
+            .dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 1);
+        assert_eq!(events[0]["data"]["afk"], false);
+
+        // Unknown parameters are rejected rather than silently ignored
+        let res = client
+            .get("/api/0/buckets/filtered/events?bogus.field=1")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_events_as_of() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/timetravel")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "timetravel",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/timetravel/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[
+                    {"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {"n": 1}},
+                    {"timestamp": "2018-01-01T02:01:01Z", "duration": 1.0, "data": {"n": 2}}
+                ]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let inserted: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let doomed_id = inserted[0]["id"].as_i64().unwrap();
+
+        // Z suffix, so the timestamp survives URL query decoding (a "+00:00"
+        // offset would have its '+' decoded as a space)
+        let before_delete = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+
+        // Delete one event and insert another after the snapshot point
+        let res = client
+            .delete(format!("/api/0/buckets/timetravel/events/{doomed_id}"))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/timetravel/events")
+            .header(ContentType::JSON)
+            .body(r#"[{"timestamp": "2018-01-01T03:01:01Z", "duration": 1.0, "data": {"n": 3}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // The present shows the surviving and the new event
+        let res = client.get("/api/0/buckets/timetravel/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 2);
+
+        // The as_of read shows the bucket as it was: the deleted event is
+        // back, the later insert is hidden
+        let res = client
+            .get(format!(
+                "/api/0/buckets/timetravel/events?as_of={before_delete}"
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let ns: Vec<i64> = events
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["data"]["n"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ns, vec![2, 1]);
+
+        // as_of and data filters don't combine
+        let res = client
+            .get(format!(
+                "/api/0/buckets/timetravel/events?as_of={before_delete}&data.n=1"
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_events_aggregate() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/aggregated")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "aggregated",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aggregated/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[
+                    {"timestamp": "2018-01-01T01:00:00Z", "duration": 10.0,
+                     "data": {"app": "Firefox"}},
+                    {"timestamp": "2018-01-01T02:00:00Z", "duration": 5.0,
+                     "data": {"app": "Firefox"}},
+                    {"timestamp": "2018-01-01T03:00:00Z", "duration": 2.0,
+                     "data": {"app": "vim"}},
+                    {"timestamp": "2018-01-01T04:00:00Z", "duration": 1.0,
+                     "data": {}}
+                ]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Totals per app; the event without the field is excluded
+        let res = client
+            .get("/api/0/buckets/aggregated/events/aggregate?group_by=data.app")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let totals: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(totals["Firefox"]["count"], 2);
+        assert_eq!(totals["Firefox"]["duration"], 15.0);
+        assert_eq!(totals["vim"]["count"], 1);
+        assert_eq!(totals.as_object().unwrap().len(), 2);
+
+        // The range filter applies, clipping durations like /events does
+        let res = client
+            .get("/api/0/buckets/aggregated/events/aggregate?group_by=data.app&start=2018-01-01T01:00:05Z&end=2018-01-01T03:00:00Z")
+            .dispatch();
+        let totals: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(totals["Firefox"]["count"], 2);
+        assert_eq!(totals["Firefox"]["duration"], 10.0);
+
+        // group_by must name a data field
+        let res = client
+            .get("/api/0/buckets/aggregated/events/aggregate?group_by=timestamp")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_buckets_metrics() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/metrics-test-bucket")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "metrics-test-bucket",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/metrics-test-bucket/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[
+                    {"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}},
+                    {"timestamp": "2018-01-01T02:01:01Z", "duration": 1.0, "data": {}}
+                ]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Without metrics the rollup fields are absent
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let buckets: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let bucket = &buckets["metrics-test-bucket"];
+        assert_eq!(bucket.get("event_count"), None);
+        assert_eq!(bucket.get("total_duration"), None);
+
+        // With metrics each bucket reports count and total duration
+        let res = client.get("/api/0/buckets/?metrics=true").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let buckets: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let bucket = &buckets["metrics-test-bucket"];
+        assert_eq!(bucket["event_count"], 2);
+        assert_eq!(bucket["total_duration"], 2.0);
+    }
+
+    #[test]
+    fn test_buckets_include_last_events() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/preview-test-bucket")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "preview-test-bucket",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/preview-test-bucket/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[
+                    {"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {"n": 1}},
+                    {"timestamp": "2018-01-01T02:01:01Z", "duration": 1.0, "data": {"n": 2}},
+                    {"timestamp": "2018-01-01T03:01:01Z", "duration": 1.0, "data": {"n": 3}}
+                ]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Without the parameter the events field is absent
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let buckets: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(buckets["preview-test-bucket"].get("events"), None);
+
+        // With it each bucket carries its most recent events, newest first
+        let res = client
+            .get("/api/0/buckets/?include_last_events=2")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let buckets: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let events = buckets["preview-test-bucket"]["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["data"]["n"], 3);
+        assert_eq!(events[1]["data"]["n"], 2);
+    }
+
+    #[test]
+    fn test_cors() {
+        use rocket::http::Header;
+
+        // Default config: the bundled webui origin is allowed, others not
+        let client = setup_testserver();
+        let res = client
+            .get("/api/0/info")
+            .header(Header::new("Origin", "http://localhost:27180"))
+            .dispatch();
+        assert_eq!(
+            res.headers().get_one("Access-Control-Allow-Origin"),
+            Some("http://localhost:27180")
+        );
+        assert!(res
+            .headers()
+            .get_one("Access-Control-Allow-Headers")
+            .unwrap()
+            .contains("X-API-Key"));
+        assert_eq!(
+            res.headers().get_one("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        let res = client
+            .get("/api/0/info")
+            .header(Header::new("Origin", "http://evil.example.com"))
+            .dispatch();
+        assert!(res.headers().get_one("Access-Control-Allow-Origin").is_none());
+
+        // `*` allows any origin; credentials can be turned off
+        let state = endpoints::ServerState {
+            datastore: Mutex::new(aw_datastore::Datastore::new_in_memory(false)),
+            device_id: "test_device_id".to_string(),
+        };
+        let aw_config = AWConfig {
+            port: 8000,
+            cors: vec!["*".to_string()],
+            cors_allow_credentials: false,
+            ..Default::default()
+        };
+        let server = endpoints::build_rocket(state, aw_config);
+        let client = Client::tracked(server).expect("valid instance");
+        let res = client
+            .get("/api/0/info")
+            .header(Header::new("Origin", "http://anywhere.example.com"))
+            .dispatch();
+        assert_eq!(
+            res.headers().get_one("Access-Control-Allow-Origin"),
+            Some("http://anywhere.example.com")
+        );
+        assert!(res
+            .headers()
+            .get_one("Access-Control-Allow-Credentials")
+            .is_none());
+    }
+
+    #[test]
+    fn test_device() {
+        let client = setup_testserver();
+
+        let res = client.get("/api/0/device/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let device: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(device["device_id"], "test_device_id");
+        assert!(device["flavor"].as_str().unwrap().contains("rust"));
+        assert!(device["display_name"].is_null());
+
+        // Set, read back, clear
+        let res = client
+            .put("/api/0/device/")
+            .header(ContentType::JSON)
+            .body(r#"{"display_name": "Work laptop"}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/device/").dispatch();
+        let device: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(device["display_name"], "Work laptop");
+        let res = client
+            .put("/api/0/device/")
+            .header(ContentType::JSON)
+            .body(r#"{"display_name": ""}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/device/").dispatch();
+        let device: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert!(device["display_name"].is_null());
+    }
+
+    #[test]
+    fn test_apikey_scopes() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // With no keys configured everything is allowed
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Mint a write-only key restricted to buckets starting with "aw-"
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "watcher", "scopes": ["write"], "buckets": ["aw-*"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let key = created["key"].as_str().unwrap().to_string();
+        let id = created["id"].as_str().unwrap().to_string();
+
+        // Now requests without a key are rejected
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Unauthorized);
+        let res = client
+            .get("/api/0/buckets/")
+            .header(Header::new("X-API-Key", "aw_wrong"))
+            .dispatch();
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        // The key may create its bucket and post events...
+        let res = client
+            .post("/api/0/buckets/aw-test")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", key.clone()))
+            .body(
+                r#"{
+                    "id": "aw-test",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-test/events")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", key.clone()))
+            .body(r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // ...but not read them back, write elsewhere, or delete buckets
+        let res = client
+            .get("/api/0/buckets/aw-test/events")
+            .header(Header::new("X-API-Key", key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+        let res = client
+            .post("/api/0/buckets/other")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", key.clone()))
+            .body(
+                r#"{
+                    "id": "other",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+        let res = client
+            .delete("/api/0/buckets/aw-test")
+            .header(Header::new("X-API-Key", key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+
+        // Settings are enforced too: reading needs the read scope and
+        // changing them is admin-only
+        let res = client
+            .get("/api/0/settings/")
+            .header(Header::new("X-API-Key", key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+        let res = client
+            .post("/api/0/settings/somekey")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", key.clone()))
+            .body(r#""somevalue""#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+
+        // Revoking the key re-enables open access (no keys left)
+        let res = client.delete(format!("/api/0/apikeys/{id}")).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_apikey_isolation() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // Two buckets, only one matching the key's pattern
+        for id in ["aw-mine", "other"] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "type",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "viewer", "scopes": ["read"], "buckets": ["aw-*"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let key = created["key"].as_str().unwrap().to_string();
+
+        // Listing and exporting only show the key's buckets
+        let res = client
+            .get("/api/0/buckets/")
+            .header(Header::new("X-API-Key", key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        assert!(body.contains("aw-mine"), "{body}");
+        assert!(!body.contains("other"), "{body}");
+        let res = client
+            .get("/api/0/export/")
+            .header(Header::new("X-API-Key", key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        assert!(body.contains("aw-mine"), "{body}");
+        assert!(!body.contains("other"), "{body}");
+
+        // The query engine reads arbitrary buckets, so bucket-restricted
+        // keys may not use it
+        let res = client
+            .post("/api/0/query/")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", key))
+            .body(r#"{"timeperiods": ["2000-01-01T00:00:00Z/2000-01-02T00:00:00Z"], "query": ["RETURN = 1;"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_apikey_admin_management() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // Bootstrap: management is open while no admin key exists
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "root", "scopes": ["admin"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let admin_key = created["key"].as_str().unwrap().to_string();
+        let admin_id = created["id"].as_str().unwrap().to_string();
+
+        // From now on management requires admin
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "sneaky", "scopes": ["admin"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Unauthorized);
+        let res = client.get("/api/0/apikeys/").dispatch();
+        assert_eq!(res.status(), Status::Unauthorized);
+
+        // The admin key can mint a limited key, which cannot manage keys
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", admin_key.clone()))
+            .body(r#"{"name": "reader", "scopes": ["read"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let reader_key = created["key"].as_str().unwrap().to_string();
+        let reader_id = created["id"].as_str().unwrap().to_string();
+        let res = client
+            .get("/api/0/apikeys/")
+            .header(Header::new("X-API-Key", reader_key))
+            .dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+
+        // Clean up with the admin key; removing the admin key last
+        // reopens management (nothing left to authenticate with)
+        let res = client
+            .delete(format!("/api/0/apikeys/{reader_id}"))
+            .header(Header::new("X-API-Key", admin_key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .delete(format!("/api/0/apikeys/{admin_id}"))
+            .header(Header::new("X-API-Key", admin_key))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/apikeys/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_apikey_revocation_is_immediate() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // An admin key, so revoking the key under test doesn't reopen
+        // access by emptying the key store
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "root", "scopes": ["admin"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let admin_key = created["key"].as_str().unwrap().to_string();
+
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", admin_key.clone()))
+            .body(r#"{"name": "stolen", "scopes": ["read"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let stolen_key = created["key"].as_str().unwrap().to_string();
+        let stolen_id = created["id"].as_str().unwrap().to_string();
+
+        let res = client
+            .get("/api/0/buckets/")
+            .header(Header::new("X-API-Key", stolen_key.clone()))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Keys are looked up on every request, so revocation takes
+        // effect immediately — no token lifetime for a stolen key to
+        // ride out
+        let res = client
+            .delete(format!("/api/0/apikeys/{stolen_id}"))
+            .header(Header::new("X-API-Key", admin_key))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .get("/api/0/buckets/")
+            .header(Header::new("X-API-Key", stolen_key))
+            .dispatch();
+        assert_eq!(res.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn test_apikey_quota() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // Bucket created while no keys exist, so without auth
+        let res = client
+            .post("/api/0/buckets/aw-quota")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "aw-quota",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "daily", "scopes": ["read", "write"], "max_events_per_day": 2}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(created["max_events_per_day"], 2);
+        let daily_key = created["key"].as_str().unwrap().to_string();
+
+        // Two events fit in the daily quota, the third is rejected
+        let res = client
+            .post("/api/0/buckets/aw-quota/events")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", daily_key.clone()))
+            .body(
+                r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {}},
+                    {"timestamp": "2018-01-01T01:01:02Z", "duration": 1.0, "data": {}}]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-quota/events")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", daily_key.clone()))
+            .body(r#"[{"timestamp": "2018-01-01T01:01:03Z", "duration": 1.0, "data": {}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::TooManyRequests);
+        // Heartbeats count against the daily quota too
+        let res = client
+            .post("/api/0/buckets/aw-quota/heartbeat?pulsetime=1.0")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", daily_key))
+            .body(r#"{"timestamp": "2018-01-01T01:01:04Z", "duration": 1.0, "data": {}}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::TooManyRequests);
+
+        // The total quota counts events actually stored: the bucket
+        // already holds two, so one more exceeds a limit of two
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "total", "scopes": ["read", "write"], "max_total_events": 2}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let total_key = created["key"].as_str().unwrap().to_string();
+        let res = client
+            .post("/api/0/buckets/aw-quota/events")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", total_key))
+            .body(r#"[{"timestamp": "2018-01-01T01:01:05Z", "duration": 1.0, "data": {}}]"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::TooManyRequests);
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let client = setup_testserver();
+
+        // No watcher buckets: an empty feed, not an error
+        let res = client.get("/api/0/anomalies").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let feed: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(feed["anomalies"], serde_json::json!([]));
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // A week of ~2h editor days as a baseline (slightly varied so
+        // the standard deviation is not zero), then a day with 10h on a
+        // never-before-seen app, 2h of it in the middle of the night
+        let day = |days_ago: i64| {
+            (chrono::Utc::now() - chrono::Duration::days(days_ago))
+                .format("%Y-%m-%d")
+                .to_string()
+        };
+        let mut window_events = Vec::new();
+        let mut afk_events = Vec::new();
+        for (offset, days_ago) in (4..=10).enumerate() {
+            let duration = 7000.0 + 200.0 * (offset % 3) as f64;
+            let timestamp = format!("{}T12:00:00Z", day(days_ago));
+            window_events.push(format!(
+                r#"{{"timestamp": "{timestamp}", "duration": {duration},
+                     "data": {{"app": "editor", "title": "code"}}}}"#,
+            ));
+            afk_events.push(format!(
+                r#"{{"timestamp": "{timestamp}", "duration": {duration},
+                     "data": {{"status": "not-afk"}}}}"#,
+            ));
+        }
+        for (hour, duration) in [("02", 7200.0), ("12", 28800.0)] {
+            let timestamp = format!("{}T{hour}:00:00Z", day(2));
+            window_events.push(format!(
+                r#"{{"timestamp": "{timestamp}", "duration": {duration},
+                     "data": {{"app": "game", "title": "fun"}}}}"#,
+            ));
+            afk_events.push(format!(
+                r#"{{"timestamp": "{timestamp}", "duration": {duration},
+                     "data": {{"status": "not-afk"}}}}"#,
+            ));
+        }
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(format!("[{}]", window_events.join(",")))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(format!("[{}]", afk_events.join(",")))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Force a recompute and read the stored feed
+        let res = client.post("/api/0/anomalies/check").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/anomalies").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let feed: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let anomalies = feed["anomalies"].as_array().unwrap();
+        let kinds: Vec<&str> = anomalies
+            .iter()
+            .filter(|anomaly| anomaly["date"] == day(2))
+            .map(|anomaly| anomaly["kind"].as_str().unwrap())
+            .collect();
+        assert!(kinds.contains(&"total_time"), "kinds: {kinds:?}");
+        assert!(kinds.contains(&"new_dominant_app"), "kinds: {kinds:?}");
+        assert!(kinds.contains(&"odd_hours"), "kinds: {kinds:?}");
+        // The baseline days are unremarkable
+        assert!(anomalies.iter().all(|anomaly| anomaly["date"] == day(2)));
+    }
+
+    #[test]
+    fn test_events_suggest() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/suggesting")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "suggesting",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/suggesting/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {"app": "Firefox"}},
+                    {"timestamp": "2018-01-01T01:01:02Z", "duration": 1.0, "data": {"app": "Firefox"}},
+                    {"timestamp": "2018-01-01T01:01:03Z", "duration": 1.0, "data": {"app": "Files"}},
+                    {"timestamp": "2018-01-01T01:01:04Z", "duration": 1.0, "data": {"app": "Emacs"}}]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Case-insensitive prefix match, most frequent first
+        let res = client
+            .get("/api/0/buckets/suggesting/events/suggest?key=data.app&prefix=fi")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let suggestions: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let suggestions = suggestions.as_array().unwrap();
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(suggestions[0]["value"], "Firefox");
+        assert_eq!(suggestions[0]["count"], 2);
+        assert_eq!(suggestions[1]["value"], "Files");
+
+        // No prefix lists everything, the limit caps the list
+        let res = client
+            .get("/api/0/buckets/suggesting/events/suggest?key=data.app&limit=1")
+            .dispatch();
+        let suggestions: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(suggestions.as_array().unwrap().len(), 1);
+        assert_eq!(suggestions[0]["value"], "Firefox");
+
+        // The key must name a data field
+        let res = client
+            .get("/api/0/buckets/suggesting/events/suggest?key=timestamp")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_compaction() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/compactable")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "compactable",
+                    "type": "currentwindow",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Three contiguous events with identical data, one with other
+        // data in between runs, and one recent event
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let res = client
+            .post("/api/0/buckets/compactable/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{"timestamp": "2018-01-01T12:00:00Z", "duration": 10.0, "data": {{"app": "a"}}}},
+                    {{"timestamp": "2018-01-01T12:00:10Z", "duration": 10.0, "data": {{"app": "a"}}}},
+                    {{"timestamp": "2018-01-01T12:00:20Z", "duration": 10.0, "data": {{"app": "a"}}}},
+                    {{"timestamp": "2018-01-01T12:00:30Z", "duration": 5.0, "data": {{"app": "b"}}}},
+                    {{"timestamp": "{now}", "duration": 1.0, "data": {{"app": "a"}}}}]"#,
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .post("/api/0/settings/compaction")
+            .header(ContentType::JSON)
+            .body(r#"{"currentwindow": {"days": 30, "pulsetime": 1.0}}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.post("/api/0/retention/run").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // The identical run collapsed into one event preserving the
+        // total duration; the other-data and recent events are untouched
+        let res = client.get("/api/0/buckets/compactable/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let events = events.as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2]["timestamp"], "2018-01-01T12:00:00Z");
+        assert_eq!(events[2]["duration"], 30.0);
+        assert_eq!(events[2]["data"]["app"], "a");
+        assert_eq!(events[1]["duration"], 5.0);
+    }
+
+    #[test]
+    fn test_dashboard_categories_import_export() {
+        let client = setup_testserver();
+
+        // Import into a dashboard that doesn't exist yet creates it;
+        // the webui's local category ids are dropped
+        let res = client
+            .post("/api/0/dashboards/roaming/categories/import")
+            .header(ContentType::JSON)
+            .body(
+                r##"{"categories": [
+                    {"name": ["Work"], "rule": {"type": "none"}, "id": 0,
+                     "data": {"color": "#0F0"}},
+                    {"name": ["Work", "Email"], "id": 1,
+                     "rule": {"type": "regex", "regex": "Gmail|Thunderbird",
+                              "ignore_case": true}}
+                ]}"##,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client
+            .get("/api/0/dashboards/roaming/categories/export")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let export: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let categories = export["categories"].as_array().unwrap();
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories[0]["data"]["color"], "#0F0");
+        assert_eq!(categories[1]["rule"]["regex"], "Gmail|Thunderbird");
+        assert_eq!(categories[1]["rule"]["ignore_case"], true);
+        // Export numbers the ids sequentially, like the webui does
+        assert_eq!(categories[0]["id"], 0);
+        assert_eq!(categories[1]["id"], 1);
+
+        // Trees with regexes that don't compile are rejected whole
+        let res = client
+            .post("/api/0/dashboards/roaming/categories/import")
+            .header(ContentType::JSON)
+            .body(r#"{"categories": [{"name": ["X"], "rule": {"type": "regex", "regex": "("}}]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_retention() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/aw-watcher-web_host")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "aw-watcher-web_host",
+                    "type": "web.tab.current",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // One ancient event, one recent
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-web_host/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{"timestamp": "2018-01-01T01:01:01Z", "duration": 1.0, "data": {{}}}},
+                    {{"timestamp": "{now}", "duration": 1.0, "data": {{}}}}]"#,
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // No rules yet: nothing to report, running deletes nothing
+        let res = client.get("/api/0/retention/report").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "[]");
+
+        // A 90 day rule on the bucket type catches the ancient event
+        let res = client
+            .post("/api/0/settings/retention")
+            .header(ContentType::JSON)
+            .body(r#"{"web.tab.current": 90}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/api/0/retention/report").dispatch();
+        let report: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(report[0]["bucket"], "aw-watcher-web_host");
+        assert_eq!(report[0]["expired_events"], 1);
+
+        // The report didn't delete anything; a run does
+        let res = client.get("/api/0/buckets/aw-watcher-web_host/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 2);
+        let res = client.post("/api/0/retention/run").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/aw-watcher-web_host/events").dispatch();
+        let events: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 1);
+        let res = client.get("/api/0/retention/report").dispatch();
+        let report: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(report[0]["expired_events"], 0);
+    }
+
+    #[test]
+    fn test_dashboards() {
+        let client = setup_testserver();
+
+        let res = client.get("/api/0/dashboards/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "[]");
+
+        let res = client
+            .post("/api/0/dashboards/main")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "name": "Main",
+                    "categories": [
+                        {"name": ["Work"], "rule": {"type": "none"}},
+                        {"name": ["Work", "Programming"],
+                         "rule": {"type": "regex", "regex": "vim|emacs"}}
+                    ],
+                    "pinned_views": ["timeline", "top-apps"]
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client.get("/api/0/dashboards/main").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let dashboard: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(dashboard["name"], "Main");
+        assert_eq!(dashboard["categories"][1]["rule"]["regex"], "vim|emacs");
+        assert_eq!(dashboard["pinned_views"][0], "timeline");
+
+        let res = client.get("/api/0/dashboards/").dispatch();
+        assert_eq!(res.into_string().unwrap(), r#"["main"]"#);
+
+        // Invalid configs are rejected instead of stored
+        let res = client
+            .post("/api/0/dashboards/bad")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "Bad", "categories": [{"name": ["X"], "rule": {"type": "regex"}}]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .post("/api/0/dashboards/bad")
+            .header(ContentType::JSON)
+            .body(r#"{"name": "Bad", "categories": [{"name": [], "rule": {"type": "none"}}]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client.delete("/api/0/dashboards/main").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/dashboards/main").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_summary() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/summarized")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "summarized",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Two events in one hour, one in the next
+        let res = client
+            .post("/api/0/buckets/summarized/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{"timestamp": "2018-01-01T12:00:00Z", "duration": 10.0, "data": {}},
+                    {"timestamp": "2018-01-01T12:30:00Z", "duration": 5.0, "data": {}},
+                    {"timestamp": "2018-01-01T13:15:00Z", "duration": 2.0, "data": {}}]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client.get("/api/0/summary").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let summary: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let hours = &summary["summarized"];
+        assert_eq!(hours["2018-01-01T12:00:00+00:00"]["count"], 2);
+        assert_eq!(hours["2018-01-01T12:00:00+00:00"]["duration"], 15.0);
+        assert_eq!(hours["2018-01-01T13:00:00+00:00"]["count"], 1);
+        assert_eq!(hours["2018-01-01T13:00:00+00:00"]["duration"], 2.0);
+
+        // The range excludes events outside it entirely: only the
+        // 12:30 event overlaps, so one event and five seconds remain
+        let res = client
+            .get("/api/0/summary?start=2018-01-01T12:05:00Z&end=2018-01-01T13:00:00Z")
+            .dispatch();
+        let summary: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let hours = &summary["summarized"];
+        assert_eq!(hours["2018-01-01T12:00:00+00:00"]["count"], 1);
+        assert_eq!(hours["2018-01-01T12:00:00+00:00"]["duration"], 5.0);
+        assert!(hours.get("2018-01-01T13:00:00+00:00").is_none());
+    }
+
+    #[test]
+    fn test_apikey_canonical_hostname() {
+        use rocket::http::Header;
+
+        let client = setup_testserver();
+
+        // A key carrying a canonical hostname for its watcher
+        let res = client
+            .post("/api/0/apikeys/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"name": "gateway", "scopes": ["read", "write"],
+                    "hostname": "workstation.example.org"}"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let created: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(created["hostname"], "workstation.example.org");
+        let key = created["key"].as_str().unwrap().to_string();
+
+        // The client-supplied hostname is overridden at creation
+        let res = client
+            .post("/api/0/buckets/aw-gw")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-Key", key.clone()))
+            .body(
+                r#"{
+                    "id": "aw-gw",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "ephemeral-container-1234"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .get("/api/0/buckets/aw-gw")
+            .header(Header::new("X-API-Key", key))
+            .dispatch();
+        let bucket: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(bucket["hostname"], "workstation.example.org");
+    }
+
+    #[test]
+    fn test_bucket_trash() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Default delete moves the bucket to the trash
+        let res = client.delete("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client.get("/api/0/trash/").dispatch();
+        assert!(res.into_string().unwrap().contains("id"));
+
+        // The name stays reserved while in the trash
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::NotModified);
+
+        // Restore brings the bucket back
+        let res = client.post("/api/0/trash/id/restore").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Force delete bypasses the trash
+        let res = client.delete("/api/0/buckets/id?force=true").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/trash/").dispatch();
+        assert_eq!(res.into_string().unwrap(), "[]");
+
+        // Emptying the trash purges immediately
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.delete("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.delete("/api/0/trash/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "1");
+        let res = client.post("/api/0/trash/id/restore").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_bucket_copy() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 1.0,
+                    "data": {}
+                }, {
+                    "timestamp": "2018-01-02T01:01:01Z",
+                    "duration": 1.0,
+                    "data": {}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Copying a non-existent bucket fails
+        let res = client
+            .post("/api/0/buckets/invalid_bucket/copy?new_id=snapshot")
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        // Full copy duplicates all events
+        let res = client
+            .post("/api/0/buckets/id/copy?new_id=snapshot")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/snapshot/events/count").dispatch();
+        assert_eq!(res.into_string().unwrap(), "2");
+
+        // Copying to an already existing bucket fails
+        let res = client
+            .post("/api/0/buckets/id/copy?new_id=snapshot")
+            .dispatch();
+        assert_eq!(res.status(), Status::Conflict);
+
+        // Time-restricted copy only duplicates events in the range
+        let res = client
+            .post("/api/0/buckets/id/copy?new_id=snapshot2&end=2018-01-01T12:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .get("/api/0/buckets/snapshot2/events/count")
+            .dispatch();
+        assert_eq!(res.into_string().unwrap(), "1");
+
+        // Invalid timestamps are rejected
+        let res = client
+            .post("/api/0/buckets/id/copy?new_id=snapshot3&start=notadate")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Deleting the original leaves the snapshot intact
+        let res = client.delete("/api/0/buckets/id").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/snapshot").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_export_encrypted() {
+        use std::io::Read;
+
+        let client = setup_testserver();
+
+        // No recipient given and none configured
+        let res = client.get("/api/0/export/encrypted").dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Invalid recipient
+        let res = client
+            .get("/api/0/export/encrypted?recipient=notanagerecipient")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Encrypt with a fresh identity and verify we can decrypt it
+        let identity = age::x25519::Identity::generate();
+        let recipient = identity.to_public();
+        let res = client
+            .get(format!("/api/0/export/encrypted?recipient={recipient}"))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let encrypted = res.into_bytes().unwrap();
+
+        let decryptor = match age::Decryptor::new(&encrypted[..]).unwrap() {
+            age::Decryptor::Recipients(d) => d,
+            _ => panic!("Expected a recipients decryptor"),
+        };
+        let mut decrypted = Vec::new();
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap();
+        reader.read_to_end(&mut decrypted).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&decrypted).unwrap();
+        assert!(json.get("buckets").is_some());
+    }
+
+    #[test]
+    fn test_import_chunked() {
+        use sha2::{Digest, Sha256};
+
+        let client = setup_testserver();
+
+        let chunk = r#"{"buckets": {"id": {
+            "id": "id",
+            "type": "type",
+            "client": "client",
+            "hostname": "hostname",
+            "events": [{
+                "id": 1,
+                "timestamp": "2018-01-01T01:01:01Z",
+                "duration": 1.0,
+                "data": {}
+            }]
+        }}}"#;
+        let checksum = hex::encode(Sha256::digest(chunk.as_bytes()));
+
+        // A fresh session starts at chunk 0
+        let res = client.get("/api/0/import/chunked/session1").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_string().unwrap().contains("\"next_chunk\":0"));
+
+        // Invalid checksum is rejected
+        let res = client
+            .post("/api/0/import/chunked/session1?chunk=0&checksum=invalid")
+            .header(ContentType::JSON)
+            .body(chunk)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Valid chunk is imported
+        let res = client
+            .post(format!(
+                "/api/0/import/chunked/session1?chunk=0&checksum={checksum}"
+            ))
+            .header(ContentType::JSON)
+            .body(chunk)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Replaying the same chunk is accepted without duplicating events
+        let res = client
+            .post(format!(
+                "/api/0/import/chunked/session1?chunk=0&checksum={checksum}"
+            ))
+            .header(ContentType::JSON)
+            .body(chunk)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // A chunk from the future is rejected
+        let res = client
+            .post(format!(
+                "/api/0/import/chunked/session1?chunk=5&checksum={checksum}"
+            ))
+            .header(ContentType::JSON)
+            .body(chunk)
+            .dispatch();
+        assert_eq!(res.status(), Status::Conflict);
+
+        // The resume token has advanced
+        let res = client.get("/api/0/import/chunked/session1").dispatch();
+        assert!(res.into_string().unwrap().contains("\"next_chunk\":1"));
+
+        let res = client.get("/api/0/buckets/id/events/count").dispatch();
+        assert_eq!(res.into_string().unwrap(), "1");
+
+        // Finishing the session removes the resume state
+        let res = client.delete("/api/0/import/chunked/session1").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/import/chunked/session1").dispatch();
+        assert!(res.into_string().unwrap().contains("\"next_chunk\":0"));
+    }
+
+    #[test]
+    fn test_stats_active() {
+        let client = setup_testserver();
+
+        // No watcher buckets yet
+        let res = client
+            .get("/api/0/stats/active?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // 100s window event, but only 60s of it while not AFK
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 100.0,
+                    "data": {"app": "firefox", "title": "test"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 60.0,
+                    "data": {"status": "not-afk"}
+                }, {
+                    "timestamp": "2018-01-01T12:01:00Z",
+                    "duration": 40.0,
+                    "data": {"status": "afk"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .get("/api/0/stats/active?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["2018-01-01"], serde_json::json!(60.0));
+
+        // Invalid timestamps are rejected
+        let res = client
+            .get("/api/0/stats/active?start=notadate&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_stats_active_inferred_afk() {
+        let client = setup_testserver();
+
+        // Only a window watcher, no AFK bucket: a partial install
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "aw-watcher-window_test",
+                    "type": "currentwindow",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Two bursts of heartbeats with a long gap between them; the gap
+        // is inferred AFK, the bursts count as active in full
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 30.0,
+                    "data": {"app": "firefox", "title": "test"}
+                }, {
+                    "timestamp": "2018-01-01T12:00:40Z",
+                    "duration": 30.0,
+                    "data": {"app": "firefox", "title": "test"}
+                }, {
+                    "timestamp": "2018-01-01T14:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "editor", "title": "test"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .get("/api/0/stats/active?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["2018-01-01"], serde_json::json!(120.0));
+    }
+
+    #[test]
+    fn test_stats_heatmap() {
+        let client = setup_testserver();
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // 2018-01-01 is a Monday; the event spans the 12->13 hour boundary
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:59:00Z",
+                    "duration": 120.0,
+                    "data": {"app": "firefox", "title": "test"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:59:00Z",
+                    "duration": 120.0,
+                    "data": {"status": "not-afk"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .get("/api/0/stats/heatmap?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let matrix: Vec<Vec<f64>> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(matrix.len(), 7);
+        assert_eq!(matrix[0].len(), 24);
+        assert_eq!(matrix[0][12], 60.0);
+        assert_eq!(matrix[0][13], 60.0);
+
+        // Restricting to an app without events yields an empty heatmap
+        let res = client
+            .get("/api/0/stats/heatmap?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z&app=vim")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let matrix: Vec<Vec<f64>> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(matrix[0][12], 0.0);
+    }
+
+    #[test]
+    fn test_stats_week_start() {
+        let client = setup_testserver();
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // 2018-01-01 is a Monday
+        for bucket in ["aw-watcher-window_test", "aw-watcher-afk_test"] {
+            let data = if bucket.contains("afk") {
+                r#"{"status": "not-afk"}"#
+            } else {
+                r#"{"app": "firefox", "title": "test"}"#
+            };
+            let res = client
+                .post(format!("/api/0/buckets/{bucket}/events"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"[{{
+                        "timestamp": "2018-01-01T12:00:00Z",
+                        "duration": 60.0,
+                        "data": {data}
+                    }}]"#
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // Default week start is Monday, so the event lands in row 0
+        let res = client
+            .get("/api/0/stats/heatmap?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        let matrix: Vec<Vec<f64>> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(matrix[0][12], 60.0);
+
+        let res = client
+            .post("/api/0/settings/week_start")
+            .header(ContentType::JSON)
+            .body(r#""sunday""#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        // With the week starting on Sunday, Monday becomes row 1
+        let res = client
+            .get("/api/0/stats/heatmap?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        let matrix: Vec<Vec<f64>> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(matrix[0][12], 0.0);
+        assert_eq!(matrix[1][12], 60.0);
+    }
+
+    #[test]
+    fn test_stats_working_hours() {
+        let client = setup_testserver();
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // One minute at 10:00 (in hours) and one at 20:00 (out of hours)
+        for bucket in ["aw-watcher-window_test", "aw-watcher-afk_test"] {
+            let data = if bucket.contains("afk") {
+                r#"{"status": "not-afk"}"#
+            } else {
+                r#"{"app": "firefox", "title": "test"}"#
+            };
+            let res = client
+                .post(format!("/api/0/buckets/{bucket}/events"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"[
+                        {{"timestamp": "2018-01-01T10:00:00Z", "duration": 60.0, "data": {data}}},
+                        {{"timestamp": "2018-01-01T20:00:00Z", "duration": 60.0, "data": {data}}}
+                    ]"#
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // Without the setting there is nothing to split by
+        let res = client
+            .get("/api/0/stats/working_hours?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .post("/api/0/settings/working_hours")
+            .header(ContentType::JSON)
+            .body(r#"{"start": 9, "end": 17}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client
+            .get("/api/0/stats/working_hours?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["2018-01-01"]["in_hours"], serde_json::json!(60.0));
+        assert_eq!(json["2018-01-01"]["out_of_hours"], serde_json::json!(60.0));
+    }
+
+    #[test]
+    fn test_stats_timezone() {
+        let client = setup_testserver();
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // 2018-01-01T03:00Z is still 2017-12-31 (a Sunday) in New York
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T03:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "firefox", "title": "test"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T03:00:00Z",
+                    "duration": 60.0,
+                    "data": {"status": "not-afk"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Without a timezone setting, days are UTC days
+        let res = client
+            .get("/api/0/stats/active?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["2018-01-01"], serde_json::json!(60.0));
+
+        let res = client
+            .post("/api/0/settings/timezone")
+            .header(ContentType::JSON)
+            .body(r#""America/New_York""#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        // With the timezone set, the event lands on the previous (local) day
+        let res = client
+            .get("/api/0/stats/active?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["2017-12-31"], serde_json::json!(60.0));
+
+        // ... and in the heatmap on Sunday 22:00 local time
+        let res = client
+            .get("/api/0/stats/heatmap?start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        let matrix: Vec<Vec<f64>> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(matrix[6][22], 60.0);
+    }
+
+    #[test]
+    fn test_saved_queries() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 10.0,
+                    "data": {}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Empty queries are rejected
+        let res = client
+            .post("/api/0/queries/total")
+            .header(ContentType::JSON)
+            .body(r#"{"query": []}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Save a query with a parameter
+        let res = client
+            .post("/api/0/queries/total")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["events = query_bucket({{bucket}});", "RETURN sum_durations(events);"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        // It shows up in the list and can be fetched
+        let res = client.get("/api/0/queries/").dispatch();
+        assert!(res.into_string().unwrap().contains("total"));
+        let res = client.get("/api/0/queries/total").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Run it
+        let res = client
+            .post("/api/0/queries/total/run")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"],
+                    "params": {"bucket": "id"}
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "[10.0]");
+
+        // Running with a missing parameter is a query error
+        let res = client
+            .post("/api/0/queries/total/run")
+            .header(ContentType::JSON)
+            .body(r#"{"timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Delete it
+        let res = client.delete("/api/0/queries/total").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/queries/total").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_query_validate() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/validate-test-bucket")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "validate-test-bucket",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // A valid query referencing an existing bucket
+        let res = client
+            .post("/api/0/query/validate")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN query_bucket(\"validate-test-bucket\");"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["valid"], serde_json::json!(true));
+        assert_eq!(json["errors"], serde_json::json!([]));
+        assert_eq!(json["warnings"], serde_json::json!([]));
+        assert_eq!(json["buckets"], serde_json::json!(["validate-test-bucket"]));
+
+        // Unknown functions and undefined variables are errors
+        let res = client
+            .post("/api/0/query/validate")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN nonsense_function(undefined_var);"]}"#)
+            .dispatch();
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["valid"], serde_json::json!(false));
+        assert_eq!(json["errors"].as_array().unwrap().len(), 2);
+
+        // A missing bucket and a missing RETURN are warnings
+        let res = client
+            .post("/api/0/query/validate")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["events = query_bucket(\"no-such-bucket\");"]}"#)
+            .dispatch();
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["valid"], serde_json::json!(true));
+        assert_eq!(json["warnings"].as_array().unwrap().len(), 2);
+
+        // Syntax errors fail validation
+        let res = client
+            .post("/api/0/query/validate")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN ((("]}"#)
+            .dispatch();
+        let json: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(json["valid"], serde_json::json!(false));
+    }
+
+    #[test]
+    fn test_query() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:00:00Z",
+                    "duration": 10.0,
+                    "data": {"app": "firefox"}
+                }, {
+                    "timestamp": "2018-01-01T01:00:13Z",
+                    "duration": 10.0,
+                    "data": {"app": "firefox"}
+                }, {
+                    "timestamp": "2018-01-01T01:00:30Z",
+                    "duration": 10.0,
+                    "data": {"app": "emacs"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Run an ad-hoc query, one result per timeperiod
+        let res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z",
+                                    "2018-01-02T00:00:00Z/2018-01-03T00:00:00Z"],
+                    "query": ["events = flood(query_bucket(\"id\"));",
+                              "events = filter_keyvals(events, \"app\", [\"firefox\"]);",
+                              "events = merge_events_by_keys(events, [\"app\"]);",
+                              "RETURN sum_durations(events);"]
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        // flood bridges the 3s gap between the firefox events
+        assert_eq!(res.into_string().unwrap(), "[23.0,0.0]");
+
+        // Opt-in result caching for closed timeperiods
+        let cached_body = r#"{
+            "timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"],
+            "query": ["RETURN sum_durations(query_bucket(\"id\"));"]
+        }"#;
+        let res = client
+            .post("/api/0/query?cache=true")
+            .header(ContentType::JSON)
+            .body(cached_body)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "[30.0]");
+
+        // Inserting events into the period invalidates the cached result
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T02:00:00Z",
+                    "duration": 10.0,
+                    "data": {"app": "emacs"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/query?cache=true")
+            .header(ContentType::JSON)
+            .body(cached_body)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().unwrap(), "[40.0]");
+
+        // Empty queries are rejected
+        let res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(r#"{"timeperiods": [], "query": []}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Query errors surface as 400
+        let res = client
+            .post("/api/0/query")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"],
+                    "query": ["RETURN no_such_function();"]
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_scheduled_queries() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/queries/allevents")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN query_bucket(\"id\");"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        // Invalid cron expressions are rejected
+        let res = client
+            .post("/api/0/schedules/daily")
+            .header(ContentType::JSON)
+            .body(r#"{"query": "allevents", "cron": "not a cron"}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Create a schedule
+        let res = client
+            .post("/api/0/schedules/daily")
+            .header(ContentType::JSON)
+            .body(r#"{"query": "allevents", "cron": "0 0 0 * * *", "period_hours": 24.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/api/0/schedules/").dispatch();
+        assert!(res.into_string().unwrap().contains("daily"));
+        let res = client.get("/api/0/schedules/daily").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // No results yet
+        let res = client.get("/api/0/schedules/daily/results").dispatch();
+        assert_eq!(res.into_string().unwrap(), "[]");
+
+        // Force a run, which materializes one result
+        let res = client.post("/api/0/schedules/daily/run").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/schedules/daily/results").dispatch();
+        let results: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].get("timestamp").is_some());
+        assert!(results[0].get("result").is_some());
+
+        // Deleting removes the definition but keeps the history
+        let res = client.delete("/api/0/schedules/daily").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/schedules/daily").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client.get("/api/0/schedules/daily/results").dispatch();
+        let results: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_query_jobs() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 10.0,
+                    "data": {}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Empty queries are rejected
+        let res = client
+            .post("/api/0/jobs/")
+            .header(ContentType::JSON)
+            .body(r#"{"timeperiods": [], "query": []}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Submit a job and poll until the dispatcher has executed it
+        let res = client
+            .post("/api/0/jobs/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"],
+                    "query": ["RETURN sum_durations(query_bucket(\"id\"));"]
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let submitted: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let job_id = submitted["id"].as_str().unwrap().to_string();
+
+        let mut job = serde_json::Value::Null;
+        for _ in 0..50 {
+            let res = client.get(format!("/api/0/jobs/{job_id}")).dispatch();
+            assert_eq!(res.status(), Status::Ok);
+            job = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+            if job["status"] == "done" || job["status"] == "error" {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert_eq!(job["status"], "done");
+        assert_eq!(job["result"], serde_json::json!([10.0]));
+
+        // A failing query ends up in the error state
+        let res = client
+            .post("/api/0/jobs/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"],
+                    "query": ["RETURN query_bucket(\"no_such_bucket\");"]
+                }"#,
+            )
+            .dispatch();
+        let submitted: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let job_id = submitted["id"].as_str().unwrap().to_string();
+        let mut job = serde_json::Value::Null;
+        for _ in 0..50 {
+            let res = client.get(format!("/api/0/jobs/{job_id}")).dispatch();
+            job = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+            if job["status"] == "done" || job["status"] == "error" {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert_eq!(job["status"], "error");
+        assert!(job.get("error").is_some());
+
+        // Jobs can be deleted once consumed
+        let res = client.delete(format!("/api/0/jobs/{job_id}")).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get(format!("/api/0/jobs/{job_id}")).dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_alerts() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 10.0,
+                    "data": {}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Rules with a non-positive period are rejected
+        let res = client
+            .post("/api/0/alerts/stale")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "bucket_inactive", "bucket_id": "id", "inactive_hours": 0.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Rules referencing unknown notification channels are rejected
+        let res = client
+            .post("/api/0/alerts/stale")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"type": "bucket_inactive", "bucket_id": "id", "inactive_hours": 2.0,
+                    "channels": ["nonexistent"]}"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Create an inactivity rule on the bucket
+        let res = client
+            .post("/api/0/alerts/stale")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "bucket_inactive", "bucket_id": "id", "inactive_hours": 2.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/api/0/alerts/").dispatch();
+        assert!(res.into_string().unwrap().contains("stale"));
+
+        // Not firing before the first evaluation
+        let res = client.get("/api/0/alerts/stale/state").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let state: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(state["firing"], false);
+
+        // The only event is from 2018, so the rule fires on evaluation
+        let res = client.post("/api/0/alerts/check").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/alerts/stale/state").dispatch();
+        let state: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(state["firing"], true);
+        assert!(state.get("since").is_some());
+
+        // A recent event makes the rule recover
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 10.0,
+                    "data": {{}}
+                }}]"#,
+                chrono::Utc::now().to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.post("/api/0/alerts/check").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/alerts/stale/state").dispatch();
+        let state: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(state["firing"], false);
+
+        // Threshold rules compare a saved query result against a limit
+        let res = client
+            .post("/api/0/queries/ten")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN 10;"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client
+            .post("/api/0/alerts/overspent")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "query_threshold", "query": "ten", "threshold": 5.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.post("/api/0/alerts/check").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/alerts/overspent/state").dispatch();
+        let state: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(state["firing"], true);
+        assert_eq!(state["last_value"], 10.0);
+
+        // Deleting a rule removes its state as well
+        let res = client.delete("/api/0/alerts/stale").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/alerts/stale").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+        let res = client.get("/api/0/alerts/stale/state").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_budgets() {
+        let client = setup_testserver();
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // 2s of firefox just now, fully within a not-afk period
+        let now = chrono::Utc::now();
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 2.0,
+                    "data": {{"app": "firefox", "title": "test"}}
+                }}]"#,
+                (now - chrono::Duration::seconds(4)).to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 10.0,
+                    "data": {{"status": "not-afk"}}
+                }}]"#,
+                (now - chrono::Duration::seconds(10)).to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Non-positive budgets are rejected
+        let res = client
+            .post("/api/0/budgets/browsing")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "app", "app": "firefox", "period": "day", "hours": 0.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Daily app budget: 2s spent of the 1h budget
+        let res = client
+            .post("/api/0/budgets/browsing")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "app", "app": "firefox", "period": "day", "hours": 1.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/api/0/budgets/").dispatch();
+        assert!(res.into_string().unwrap().contains("browsing"));
+
+        let res = client.get("/api/0/budgets/browsing/progress").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let progress: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(progress["spent_seconds"], 2.0);
+        assert_eq!(progress["budget_seconds"], 3600.0);
+        assert!(progress.get("period_start").is_some());
+
+        // Query budgets take the spent seconds from a saved query
+        let res = client
+            .post("/api/0/queries/ten")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN 10;"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client
+            .post("/api/0/budgets/social")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "query", "query": "ten", "period": "week", "hours": 3.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/api/0/budgets/social/progress").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let progress: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(progress["spent_seconds"], 10.0);
+
+        // Delete a budget
+        let res = client.delete("/api/0/budgets/social").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/budgets/social/progress").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_manual_time_entries() {
+        let client = setup_testserver();
+
+        // Invalid entries are rejected
+        let res = client
+            .post("/api/0/timeentries/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 0.0,
+                    "project": "acme"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .post("/api/0/timeentries/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 3600.0,
+                    "project": ""
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        // A duration past the timestamp range is rejected, not panicked on
+        let res = client
+            .post("/api/0/timeentries/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 1e40,
+                    "project": "acme"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Log an hour on a project
+        let res = client
+            .post("/api/0/timeentries/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 3600.0,
+                    "project": "acme",
+                    "description": "writing reports"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let entry: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(entry["data"]["project"], "acme");
+
+        // Overlapping entries are rejected by default
+        let res = client
+            .post("/api/0/timeentries/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:30:00Z",
+                    "duration": 3600.0,
+                    "project": "other"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Conflict);
+
+        // With trim=true the entry is trimmed to the free window
+        let res = client
+            .post("/api/0/timeentries/?trim=true")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:30:00Z",
+                    "duration": 3600.0,
+                    "project": "other"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let entry: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(entry["timestamp"], "2018-01-01T13:00:00Z");
+        assert_eq!(entry["duration"], 1800.0);
+
+        // An entry fully covered by existing ones can't be trimmed
+        let res = client
+            .post("/api/0/timeentries/?trim=true")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:10:00Z",
+                    "duration": 600.0,
+                    "project": "other"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Conflict);
+
+        // Entries touching at the boundary are fine
+        let res = client
+            .post("/api/0/timeentries/")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T11:00:00Z",
+                    "duration": 3600.0,
+                    "project": "acme"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // List entries and delete one
+        let res = client.get("/api/0/timeentries/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(entries.len(), 3);
+        let entry_id = entries[0]["id"].as_i64().unwrap();
+        let res = client
+            .delete(format!("/api/0/timeentries/{entry_id}"))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/timeentries/").dispatch();
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn test_event_split() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/id")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "id",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/id/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 100.0,
+                    "data": {"app": "firefox"}
+                }]"#,
+            )
+            .dispatch();
+        let inserted: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let event_id = inserted[0]["id"].as_i64().unwrap();
+
+        // Splitting outside the event is rejected
+        let res = client
+            .post(format!(
+                "/api/0/buckets/id/events/{event_id}/split?at=2018-01-01T12:02:00Z"
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // Split 40s in
+        let res = client
+            .post(format!(
+                "/api/0/buckets/id/events/{event_id}/split?at=2018-01-01T12:00:40Z"
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let halves: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(halves.len(), 2);
+        assert_eq!(halves[0]["duration"], 40.0);
+        assert_eq!(halves[0]["id"].as_i64().unwrap(), event_id);
+        assert_eq!(halves[1]["timestamp"], "2018-01-01T12:00:40Z");
+        assert_eq!(halves[1]["duration"], 60.0);
+        assert_eq!(halves[1]["data"]["app"], "firefox");
+
+        // The bucket now holds exactly the two halves
+        let res = client.get("/api/0/buckets/id/events").dispatch();
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.len(), 2);
+
+        // Splitting a nonexistent event 404s
+        let res = client
+            .post("/api/0/buckets/id/events/12345/split?at=2018-01-01T12:00:20Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_events_update() {
+        let client = setup_testserver();
+
+        for id in ["mine", "other"] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "type",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+        let res = client
+            .post("/api/0/buckets/mine/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 100.0,
+                    "data": {"app": "firefox"}
+                }]"#,
+            )
+            .dispatch();
+        let inserted: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let event_id = inserted[0]["id"].as_i64().unwrap();
+
+        // Re-label the event and shorten it
+        let res = client
+            .put(format!("/api/0/buckets/mine/events/{event_id}"))
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "chromium"}
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let updated: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(updated["id"].as_i64().unwrap(), event_id);
+        assert_eq!(updated["duration"], 60.0);
+
+        let res = client.get("/api/0/buckets/mine/events").dispatch();
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["duration"], 60.0);
+        assert_eq!(events[0]["data"]["app"], "chromium");
+
+        // A bucket can only update events it owns
+        let res = client
+            .put(format!("/api/0/buckets/other/events/{event_id}"))
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "stolen"}
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+
+        // A nonexistent event id 404s
+        let res = client
+            .put("/api/0/buckets/mine/events/12345")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "ghost"}
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_import_toggl() {
+        let client = setup_testserver();
+
+        // Missing columns are rejected
+        let res = client
+            .post("/api/0/import/toggl")
+            .body("Foo,Bar\n1,2\n")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .post("/api/0/import/toggl")
+            .body(concat!(
+                "User,Email,Client,Project,Task,Description,Billable,",
+                "Start date,Start time,End date,End time,Duration,Tags\n",
+                "me,me@example.com,Acme,Website,,\"Fixing, testing\",No,",
+                "2018-01-01,09:00:00,2018-01-01,10:00:00,01:00:00,dev\n",
+                "me,me@example.com,,Internal,,,No,",
+                "2018-01-01,13:00:00,2018-01-01,13:30:00,00:30:00,\n",
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let summary: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(summary["events_imported"], 2);
+
+        let res = client.get("/api/0/buckets/toggl-import/events").dispatch();
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.len(), 2);
+        // Events come back newest first
+        assert_eq!(events[1]["timestamp"], "2018-01-01T09:00:00Z");
+        assert_eq!(events[1]["duration"], 3600.0);
+        assert_eq!(events[1]["data"]["project"], "Website");
+        assert_eq!(events[1]["data"]["description"], "Fixing, testing");
+        assert_eq!(events[1]["data"]["client"], "Acme");
+        assert_eq!(events[1]["data"]["tags"], "dev");
+        // Empty fields are left out
+        assert!(events[0]["data"].get("client").is_none());
+    }
+
+    #[test]
+    fn test_import_rescuetime() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/import/rescuetime")
+            .body(concat!(
+                "Date,Time Spent (seconds),Number of People,Activity,Category,Productivity\n",
+                "2018-01-01T09:00:00,300,1,firefox,Browsers,-1\n",
+                "2018-01-01T09:05:00,120,1,vim,Editors,2\n",
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let summary: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(summary["events_imported"], 2);
+
+        let res = client
+            .get("/api/0/buckets/rescuetime-import/events")
+            .dispatch();
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["timestamp"], "2018-01-01T09:00:00Z");
+        assert_eq!(events[1]["duration"], 300.0);
+        assert_eq!(events[1]["data"]["activity"], "firefox");
+        assert_eq!(events[1]["data"]["category"], "Browsers");
+        assert_eq!(events[1]["data"]["productivity"], -1);
+    }
+
+    #[test]
+    fn test_homeassistant_integration() {
+        let client = setup_testserver();
+
+        // Without watcher buckets everything is null
+        let res = client.get("/api/0/integrations/homeassistant").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let payload: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(payload["current_app"], serde_json::Value::Null);
+        assert_eq!(payload["afk"], serde_json::Value::Null);
+        assert_eq!(payload["active_seconds_today"], serde_json::Value::Null);
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+
+        // 2s of firefox just now, within a not-afk period
+        let now = chrono::Utc::now();
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 2.0,
+                    "data": {{"app": "firefox", "title": "test"}}
+                }}]"#,
+                (now - chrono::Duration::seconds(4)).to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 10.0,
+                    "data": {{"status": "not-afk"}}
+                }}]"#,
+                (now - chrono::Duration::seconds(10)).to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client.get("/api/0/integrations/homeassistant").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let payload: serde_json::Value =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(payload["current_app"], "firefox");
+        assert_eq!(payload["current_title"], "test");
+        assert_eq!(payload["afk"], false);
+        assert_eq!(payload["active_seconds_today"], 2.0);
+
+        // Discovery messages cover each sensor
+        let res = client
+            .get("/api/0/integrations/homeassistant/discovery")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let messages: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(messages.len(), 4);
+        assert!(messages[0]["topic"]
+            .as_str()
+            .unwrap()
+            .starts_with("homeassistant/sensor/activitywatch_"));
+        assert_eq!(
+            messages[0]["payload"]["state_topic"],
+            "activitywatch/state"
+        );
+    }
+
+    #[test]
+    fn test_events_downsampled() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/ds_test")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "ds_test",
+                    "type": "currentwindow",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/ds_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T00:00:00Z",
+                    "duration": 30.0,
+                    "data": {"app": "firefox"}
+                },
+                {
+                    "timestamp": "2018-01-01T00:00:30Z",
+                    "duration": 5.0,
+                    "data": {"app": "emacs"}
+                },
+                {
+                    "timestamp": "2018-01-01T00:00:35Z",
+                    "duration": 2.0,
+                    "data": {"app": "chrome"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // 100s range in 10 slots of 10s each
+        let res = client
+            .get("/api/0/buckets/ds_test/events/downsampled?start=2018-01-01T00:00:00Z&end=2018-01-01T00:01:40Z&points=10")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let slots: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        // Empty slots are omitted
+        assert_eq!(slots.len(), 4);
+        for slot in &slots[..3] {
+            assert_eq!(slot["data"]["app"], "firefox");
+            assert_eq!(slot["coverage"], 1.0);
+            assert_eq!(slot["duration"], 10.0);
+        }
+        // The fourth slot has 5s emacs and 2s chrome: emacs dominates,
+        // 7 of 10 seconds covered
+        assert_eq!(slots[3]["data"]["app"], "emacs");
+        assert!((slots[3]["coverage"].as_f64().unwrap() - 0.7).abs() < 1e-6);
+
+        // Invalid parameters are rejected
+        let res = client
+            .get("/api/0/buckets/ds_test/events/downsampled?start=2018-01-01T00:00:00Z&end=2018-01-01T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .get("/api/0/buckets/ds_test/events/downsampled?start=2018-01-01T00:00:00Z&end=2018-01-01T01:00:00Z&points=0")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_query_columnar() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/colbucket")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "colbucket",
+                    "type": "currentwindow",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/colbucket/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "firefox", "count": 1}
+                },
+                {
+                    "timestamp": "2018-01-01T13:00:00Z",
+                    "duration": 30.0,
+                    "data": {"app": "emacs"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .post("/api/0/queries/col")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN query_bucket(\"colbucket\");"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client
+            .post("/api/0/queries/col/columnar")
+            .header(ContentType::JSON)
+            .body(r#"{"timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(body["length"], 2);
+        let columns = &body["columns"];
+        assert_eq!(columns["duration"]["dtype"], "float64");
+        assert_eq!(columns["timestamp"]["dtype"], "datetime64[ns, UTC]");
+        assert_eq!(columns["data.app"]["dtype"], "object");
+        // Missing keys are null-padded, and all-number columns get float64
+        assert_eq!(columns["data.count"]["dtype"], "float64");
+        assert_eq!(columns["data.count"]["values"].as_array().unwrap().len(), 2);
+        assert!(columns["data.count"]["values"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|value| value.is_null()));
+        assert_eq!(columns["timeperiod"]["values"], serde_json::json!([0, 0]));
+
+        // Queries not returning a list of events are rejected
+        let res = client
+            .post("/api/0/queries/colnum")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN 10;"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client
+            .post("/api/0/queries/colnum/columnar")
+            .header(ContentType::JSON)
+            .body(r#"{"timeperiods": ["2018-01-01T00:00:00Z/2018-01-02T00:00:00Z"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_export_import_roundtrip() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/roundtrip")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "roundtrip",
+                    "type": "type",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/roundtrip/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T01:01:01Z",
+                    "duration": 1.0,
+                    "data": {"test": 1}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Export everything, then wipe the bucket
+        let res = client.get("/api/0/export/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let export = res.into_string().unwrap();
+        assert!(export.contains("roundtrip"));
+        let res = client.delete("/api/0/buckets/roundtrip?force=true").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // JSON import restores the bucket with its events
+        let res = client
+            .post("/api/0/import/")
+            .header(ContentType::JSON)
+            .body(&export)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/roundtrip/events").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let events: Vec<serde_json::Value> =
+            serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["data"]["test"], 1);
+
+        // Multipart form upload accepts the same format
+        let res = client.delete("/api/0/buckets/roundtrip?force=true").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let boundary = "----testboundary";
+        let body = format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"buckets.json\"; filename=\"buckets.json\"\r\n\
+             Content-Type: application/json\r\n\r\n\
+             {export}\r\n\
+             --{boundary}--\r\n"
+        );
+        let res = client
+            .post("/api/0/import/")
+            .header(ContentType::parse_flexible(&format!(
+                "multipart/form-data; boundary={boundary}"
+            ))
+            .unwrap())
+            .body(body)
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/buckets/roundtrip/events/count").dispatch();
+        assert_eq!(res.into_string().unwrap(), "1");
+    }
+
+    #[test]
+    fn test_export_timewarrior() {
+        let client = setup_testserver();
+
+        let res = client
+            .post("/api/0/buckets/tw_test")
+            .header(ContentType::JSON)
+            .body(
+                r#"{
+                    "id": "tw_test",
+                    "type": "currentwindow",
+                    "client": "client",
+                    "hostname": "hostname"
+                }"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/tw_test/events")
+            .header(ContentType::JSON)
+            .body(
+                r#"[{
+                    "timestamp": "2018-01-01T12:00:00Z",
+                    "duration": 60.0,
+                    "data": {"app": "firefox"}
+                },
+                {
+                    "timestamp": "2018-01-01T13:00:00Z",
+                    "duration": 30.0,
+                    "data": {"app": "Visual Studio"}
+                }]"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client
+            .post("/api/0/queries/twq")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN query_bucket(\"tw_test\");"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client
+            .get("/api/0/export/timewarrior?query=twq&start=2018-01-01T00:00:00Z&end=2018-01-02T00:00:00Z")
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        // Oldest first, tags with spaces quoted
+        assert_eq!(
+            body,
+            "inc 20180101T120000Z - 20180101T120100Z # firefox\n\
+             inc 20180101T130000Z - 20180101T130030Z # \"Visual Studio\"\n"
+        );
+
+        // Queries not returning a list of events are rejected
+        let res = client
+            .post("/api/0/queries/twnum")
+            .header(ContentType::JSON)
+            .body(r#"{"query": ["RETURN 10;"]}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let res = client
+            .get("/api/0/export/timewarrior?query=twnum")
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_reports() {
+        let client = setup_testserver();
+
+        for (id, _type) in [
+            ("aw-watcher-window_test", "currentwindow"),
+            ("aw-watcher-afk_test", "afkstatus"),
+        ] {
+            let res = client
+                .post(format!("/api/0/buckets/{id}"))
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{
+                        "id": "{id}",
+                        "type": "{_type}",
+                        "client": "client",
+                        "hostname": "hostname"
+                    }}"#,
+                ))
+                .dispatch();
+            assert_eq!(res.status(), Status::Ok);
+        }
+        let now = chrono::Utc::now();
+        let res = client
+            .post("/api/0/buckets/aw-watcher-window_test/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 2.0,
+                    "data": {{"app": "firefox", "title": "test"}}
+                }}]"#,
+                (now - chrono::Duration::seconds(4)).to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .post("/api/0/buckets/aw-watcher-afk_test/events")
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"[{{
+                    "timestamp": "{}",
+                    "duration": 10.0,
+                    "data": {{"status": "not-afk"}}
+                }}]"#,
+                (now - chrono::Duration::seconds(10)).to_rfc3339()
+            ))
+            .dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // A budget the summary should report progress on
+        let res = client
+            .post("/api/0/budgets/browsing")
+            .header(ContentType::JSON)
+            .body(r#"{"type": "app", "app": "firefox", "period": "day", "hours": 1.0}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        // Invalid cron expressions are rejected
+        let res = client
+            .post("/api/0/reports/daily")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"webhook": "http://127.0.0.1:9/", "format": "slack", "period": "day", "cron": "not a cron"}"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+
+        // The webhook points at a dead port; delivery failures are logged
+        // but don't fail the run
+        let res = client
+            .post("/api/0/reports/daily")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"webhook": "http://127.0.0.1:9/", "format": "slack", "period": "day", "cron": "0 0 18 * * *"}"#,
+            )
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client.get("/api/0/reports/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_string().unwrap().contains("daily"));
+        let res = client.get("/api/0/reports/daily").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // Force-running returns the composed summary
+        let res = client.post("/api/0/reports/daily/run").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&res.into_string().unwrap()).unwrap();
+        let message = body["message"].as_str().unwrap();
+        assert!(message.contains("Active time today"));
+        assert!(message.contains("firefox"));
+        assert!(message.contains("browsing"));
+
+        let res = client.delete("/api/0/reports/daily").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/reports/daily").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_settings() {
+        let client = setup_testserver();
+
+        // Set a setting
+        let res = client
+            .post("/api/0/settings/mykey")
+            .header(ContentType::JSON)
+            .body(r#"{"nested": "value"}"#)
+            .dispatch();
+        assert_eq!(res.status(), Status::Created);
+
+        // Get the setting
+        let res = client.get("/api/0/settings/mykey").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+
+        // List settings
+        let res = client.get("/api/0/settings/").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_string().unwrap().contains("mykey"));
+
+        // Delete the setting
+        let res = client.delete("/api/0/settings/mykey").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/api/0/settings/mykey").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,67 @@
+use aw_models::Event;
+
+/// Chunks together events with the same value at the specified key which are
+/// directly adjacent in time, similar to the aw-analysis transform of the same
+/// name.
+pub fn chunk_events_by_key(events: Vec<Event>, key: &str) -> Vec<Event> {
+    let mut chunked_events: Vec<Event> = Vec::new();
+    for event in events {
+        let event_value = match event.data.get(key) {
+            Some(v) => v.clone(),
+            None => continue,
+        };
+        match chunked_events.last_mut() {
+            Some(last_event) if last_event.data.get(key) == Some(&event_value) => {
+                last_event.duration += event.duration;
+            }
+            _ => {
+                let mut data = serde_json::Map::new();
+                data.insert(key.to_string(), event_value);
+                chunked_events.push(Event {
+                    id: None,
+                    timestamp: event.timestamp,
+                    duration: event.duration,
+                    data,
+                });
+            }
+        }
+    }
+    chunked_events
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_models::Event;
+
+    use super::chunk_events_by_key;
+
+    fn test_event(value: Value) -> Event {
+        let mut data = Map::new();
+        data.insert("test".to_string(), value);
+        Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_chunk_events_by_key() {
+        let events = vec![
+            test_event(json!(1)),
+            test_event(json!(1)),
+            test_event(json!(2)),
+            test_event(json!(1)),
+        ];
+        let res = chunk_events_by_key(events, "test");
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].duration, Duration::seconds(2));
+    }
+}
@@ -0,0 +1,153 @@
+//! Transforms for classifying (tagging and categorizing) events.
+//!
+//! Based on code in aw-research:
+//! https://github.com/ActivityWatch/aw-research/blob/master/aw_research/classify.py
+
+use fancy_regex::Regex;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+pub enum Rule {
+    None,
+    Regex(RegexRule),
+}
+
+pub struct RegexRule {
+    regex: Regex,
+}
+
+impl RegexRule {
+    pub fn new(regex_str: &str, ignore_case: bool) -> Result<RegexRule, Box<fancy_regex::Error>> {
+        let regex = if ignore_case {
+            Regex::new(&format!("(?i){regex_str}"))?
+        } else {
+            Regex::new(regex_str)?
+        };
+        Ok(RegexRule { regex })
+    }
+}
+
+impl RuleTrait for RegexRule {
+    fn matches(&self, event: &Event) -> bool {
+        event
+            .data
+            .values()
+            .filter(|val| val.is_string())
+            .any(|val| self.regex.is_match(val.as_str().unwrap()).unwrap_or(false))
+    }
+}
+
+trait RuleTrait {
+    fn matches(&self, event: &Event) -> bool;
+}
+
+impl RuleTrait for Rule {
+    fn matches(&self, event: &Event) -> bool {
+        match self {
+            Rule::None => false,
+            Rule::Regex(rule) => rule.matches(event),
+        }
+    }
+}
+
+/// Categorizes a list of events, where each event is assigned the deepest
+/// matching category (the last rule that matches wins within equal depth).
+pub fn categorize(mut events: Vec<Event>, rules: &[(Vec<String>, Rule)]) -> Vec<Event> {
+    let mut classified_events = Vec::new();
+    for event in events.drain(..) {
+        classified_events.push(categorize_one(event, rules));
+    }
+    classified_events
+}
+
+fn categorize_one(mut event: Event, rules: &[(Vec<String>, Rule)]) -> Event {
+    let mut category: Vec<String> = vec!["Uncategorized".into()];
+    for (cat, rule) in rules {
+        if rule.matches(&event) && cat.len() >= category.len() {
+            category = cat.clone();
+        }
+    }
+    event
+        .data
+        .insert("$category".into(), Value::from(category));
+    event
+}
+
+/// Tags a list of events, adding a `$tags` list to the event data containing
+/// all matching tags.
+pub fn tag(mut events: Vec<Event>, rules: &[(String, Rule)]) -> Vec<Event> {
+    let mut tagged_events = Vec::new();
+    for event in events.drain(..) {
+        tagged_events.push(tag_one(event, rules));
+    }
+    tagged_events
+}
+
+fn tag_one(mut event: Event, rules: &[(String, Rule)]) -> Event {
+    let mut tags: Vec<String> = Vec::new();
+    for (cls, rule) in rules {
+        if rule.matches(&event) {
+            tags.push(cls.clone());
+        }
+    }
+    tags.dedup();
+    event.data.insert("$tags".into(), Value::from(tags));
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+
+    use aw_models::Event;
+
+    use super::{categorize, tag, RegexRule, Rule};
+
+    fn test_event(app: &str) -> Event {
+        let mut data = Map::new();
+        data.insert("app".to_string(), json!(app));
+        Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_categorize() {
+        let rules = vec![
+            (
+                vec!["Work".to_string()],
+                Rule::Regex(RegexRule::new("Code", false).unwrap()),
+            ),
+            (
+                vec!["Work".to_string(), "Programming".to_string()],
+                Rule::Regex(RegexRule::new("Code", false).unwrap()),
+            ),
+        ];
+        let events = categorize(vec![test_event("Code"), test_event("Firefox")], &rules);
+        assert_eq!(
+            events[0].data["$category"],
+            json!(["Work", "Programming"])
+        );
+        assert_eq!(events[1].data["$category"], json!(["Uncategorized"]));
+    }
+
+    #[test]
+    fn test_tag() {
+        let rules = vec![
+            (
+                "coding".to_string(),
+                Rule::Regex(RegexRule::new("Code", false).unwrap()),
+            ),
+            ("never".to_string(), Rule::None),
+        ];
+        let events = tag(vec![test_event("Code")], &rules);
+        assert_eq!(events[0].data["$tags"], json!(["coding"]));
+    }
+}
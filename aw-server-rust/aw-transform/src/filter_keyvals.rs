@@ -0,0 +1,74 @@
+use fancy_regex::Regex;
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+/// Keeps only the events which have one of the specified values at the
+/// specified key.
+pub fn filter_keyvals(mut events: Vec<Event>, key: &str, vals: &[Value]) -> Vec<Event> {
+    events.retain(|event| match event.data.get(key) {
+        Some(v) => vals.contains(v),
+        None => false,
+    });
+    events
+}
+
+/// Keeps only the events where the value at `key` is a string matching the
+/// regex.
+pub fn filter_keyvals_regex(mut events: Vec<Event>, key: &str, regex: &Regex) -> Vec<Event> {
+    events.retain(|event| match event.data.get(key) {
+        Some(Value::String(s)) => regex.is_match(s).unwrap_or(false),
+        _ => false,
+    });
+    events
+}
+
+/// Removes the events which have one of the specified values at the specified
+/// key.
+pub fn exclude_keyvals(mut events: Vec<Event>, key: &str, vals: &[Value]) -> Vec<Event> {
+    events.retain(|event| match event.data.get(key) {
+        Some(v) => !vals.contains(v),
+        None => true,
+    });
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_models::Event;
+
+    use super::{exclude_keyvals, filter_keyvals};
+
+    fn test_event(value: Value) -> Event {
+        let mut data = Map::new();
+        data.insert("test".to_string(), value);
+        Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_filter_keyvals() {
+        let events = vec![test_event(json!(1)), test_event(json!(2))];
+        let res = filter_keyvals(events, "test", &[json!(1)]);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data["test"], json!(1));
+    }
+
+    #[test]
+    fn test_exclude_keyvals() {
+        let events = vec![test_event(json!(1)), test_event(json!(2))];
+        let res = exclude_keyvals(events, "test", &[json!(1)]);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data["test"], json!(2));
+    }
+}
@@ -0,0 +1,68 @@
+use aw_models::Event;
+
+use crate::sort_by_timestamp;
+
+/// Removes the parts of the events in `events` which do not intersect with any
+/// event in `filter_events`. Used for example to get the window events which
+/// occurred while the user was not AFK.
+pub fn filter_period_intersect(events: &[Event], filter_events: &[Event]) -> Vec<Event> {
+    let mut filtered_events = Vec::new();
+    let filter_events = sort_by_timestamp(filter_events.to_vec());
+    for filter in &filter_events {
+        let filter_endtime = filter.calculate_endtime();
+        for event in events {
+            if event.timestamp > filter_endtime {
+                continue;
+            }
+            let event_endtime = event.calculate_endtime();
+            if event_endtime < filter.timestamp {
+                continue;
+            }
+            let mut e = event.clone();
+            e.timestamp = std::cmp::max(e.timestamp, filter.timestamp);
+            let endtime = std::cmp::min(event_endtime, filter_endtime);
+            e.duration = endtime - e.timestamp;
+            filtered_events.push(e);
+        }
+    }
+    filtered_events
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+
+    use aw_models::Event;
+
+    use super::filter_period_intersect;
+
+    fn test_event(timestamp: &str, duration: i64) -> Event {
+        let mut data = Map::new();
+        data.insert("test".to_string(), json!(1));
+        Event {
+            id: None,
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            duration: Duration::seconds(duration),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_filter_period_intersect() {
+        let events = vec![
+            test_event("2000-01-01T00:00:00Z", 10),
+            test_event("2000-01-01T00:00:20Z", 10),
+        ];
+        let filter = vec![test_event("2000-01-01T00:00:05Z", 20)];
+        let res = filter_period_intersect(&events, &filter);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].duration, Duration::seconds(5));
+        assert_eq!(res[1].duration, Duration::seconds(5));
+    }
+}
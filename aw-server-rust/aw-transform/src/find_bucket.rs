@@ -0,0 +1,82 @@
+use aw_models::Bucket;
+
+/// Finds the first bucket which starts with the specified prefix, optionally
+/// restricted to a specific hostname. Archived buckets are never picked;
+/// they remain reachable by naming them explicitly.
+pub fn find_bucket<'a>(
+    bucket_filter: &str,
+    hostname_filter: &Option<String>,
+    buckets: impl IntoIterator<Item = &'a Bucket>,
+) -> Option<String> {
+    for bucket in buckets {
+        if bucket.is_archived() {
+            continue;
+        }
+        if bucket.id.starts_with(bucket_filter) {
+            if let Some(hostname) = hostname_filter {
+                if hostname != &bucket.hostname {
+                    continue;
+                }
+            }
+            return Some(bucket.id.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Map;
+
+    use aw_models::Bucket;
+    use aw_models::BucketMetadata;
+    use aw_models::TryVec;
+
+    use super::find_bucket;
+
+    fn test_bucket(id: &str, hostname: &str) -> Bucket {
+        Bucket {
+            bid: None,
+            id: id.to_string(),
+            _type: "type".to_string(),
+            client: "client".to_string(),
+            hostname: hostname.to_string(),
+            created: None,
+            last_updated: None,
+            event_count: None,
+            total_duration: None,
+            data: Map::new(),
+            events: TryVec::new_empty(),
+            metadata: BucketMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_find_bucket() {
+        let buckets = vec![
+            test_bucket("aw-watcher-afk_host1", "host1"),
+            test_bucket("aw-watcher-window_host1", "host1"),
+        ];
+        assert_eq!(
+            find_bucket("aw-watcher-window", &None, &buckets),
+            Some("aw-watcher-window_host1".to_string())
+        );
+        assert_eq!(
+            find_bucket("aw-watcher-window", &Some("host2".to_string()), &buckets),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_bucket_skips_archived() {
+        let mut archived = test_bucket("aw-watcher-window_host1", "host1");
+        archived
+            .data
+            .insert("archived".to_string(), serde_json::Value::Bool(true));
+        let buckets = vec![archived, test_bucket("aw-watcher-window_host2", "host2")];
+        assert_eq!(
+            find_bucket("aw-watcher-window", &None, &buckets),
+            Some("aw-watcher-window_host2".to_string())
+        );
+    }
+}
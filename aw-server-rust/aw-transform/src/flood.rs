@@ -0,0 +1,93 @@
+use aw_models::Event;
+use chrono::Duration;
+
+use crate::sort_by_timestamp;
+
+/// Floods event to the nearest neighbouring event if within the specified
+/// pulsetime, so that small gaps between events (typically caused by polling
+/// intervals) are filled.
+///
+/// Inspired by the aw-analysis implementation of the same name.
+pub fn flood(events: Vec<Event>, pulsetime: Duration) -> Vec<Event> {
+    let mut new_events = Vec::new();
+    let mut events_sorted = sort_by_timestamp(events);
+    let mut e1_iter = events_sorted.drain(..).peekable();
+    let mut gap_prev: Option<Duration> = None;
+    while let Some(mut e1) = e1_iter.next() {
+        if let Some(gap) = gap_prev {
+            e1.timestamp -= gap / 2;
+            e1.duration += gap / 2 ;
+            gap_prev = None;
+        }
+        let e2 = match e1_iter.peek() {
+            Some(e) => e,
+            None => {
+                new_events.push(e1.clone());
+                break;
+            }
+        };
+
+        let gap = e2.timestamp - e1.calculate_endtime();
+
+        if gap < pulsetime {
+            if e1.data == e2.data {
+                // Extend e1 to the start of e2, the next iteration will
+                // merge them if the datastore hasn't already.
+                e1.duration = e2.timestamp - e1.timestamp;
+            } else {
+                // Differing data, split the gap between the events
+                gap_prev = Some(gap);
+                e1.duration += gap / 2 ;
+            }
+        }
+        new_events.push(e1.clone());
+    }
+    new_events
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_models::Event;
+
+    use super::flood;
+
+    fn test_event(timestamp: &str, duration: i64, value: Value) -> Event {
+        let mut data = Map::new();
+        data.insert("test".to_string(), value);
+        Event {
+            id: None,
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            duration: Duration::seconds(duration),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_flood_same_data() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 1, json!(1));
+        let e2 = test_event("2000-01-01T00:00:02Z", 1, json!(1));
+        let res = flood(vec![e1, e2], Duration::seconds(5));
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].duration, Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_flood_different_data() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 1, json!(1));
+        let e2 = test_event("2000-01-01T00:00:02Z", 1, json!(2));
+        let res = flood(vec![e1, e2], Duration::seconds(5));
+        assert_eq!(res.len(), 2);
+        // The gap should be split equally between both events
+        assert_eq!(res[0].duration, Duration::milliseconds(1500));
+        assert_eq!(res[1].duration, Duration::milliseconds(1500));
+    }
+}
@@ -0,0 +1,257 @@
+use aw_models::Event;
+use chrono::Duration;
+use serde_json::{Map, Value};
+
+/// How event data is compared when deciding whether a heartbeat merges
+/// into the previous event. The default (no ignored keys, no fuzzy keys)
+/// is exact equality, matching the historical behavior.
+///
+/// `ignore_keys` are left out of the comparison entirely, for fields that
+/// change trivially without the activity changing (e.g. a clock embedded
+/// in a window title carried in its own field). `fuzzy_keys` must still
+/// be present in both events but their string values are compared
+/// case-insensitively with whitespace runs collapsed, absorbing cosmetic
+/// title churn. The merged event keeps the last event's data either way.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    pub ignore_keys: Vec<String>,
+    pub fuzzy_keys: Vec<String>,
+}
+
+impl MergePolicy {
+    /// True when the policy is plain exact equality, so callers can keep
+    /// using cheaper comparison paths
+    pub fn is_exact(&self) -> bool {
+        self.ignore_keys.is_empty() && self.fuzzy_keys.is_empty()
+    }
+
+    /// Whether two data objects are considered equal under this policy
+    pub fn data_matches(&self, a: &Map<String, Value>, b: &Map<String, Value>) -> bool {
+        if self.is_exact() {
+            return a == b;
+        }
+        let keys: std::collections::HashSet<&String> = a
+            .keys()
+            .chain(b.keys())
+            .filter(|key| !self.ignore_keys.contains(key))
+            .collect();
+        for key in keys {
+            let (Some(va), Some(vb)) = (a.get(key), b.get(key)) else {
+                return false;
+            };
+            let fuzzy_equal = match (self.fuzzy_keys.contains(key), va, vb) {
+                (true, Value::String(sa), Value::String(sb)) => {
+                    normalize_fuzzy(sa) == normalize_fuzzy(sb)
+                }
+                _ => va == vb,
+            };
+            if !fuzzy_equal {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Lowercases and collapses whitespace runs, so e.g. "Inbox  (2)" and
+/// "inbox (2)" compare equal under a fuzzy key
+fn normalize_fuzzy(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Returns a merged event if two events have the same data and are
+/// close enough in time, else None.
+///
+/// The pulsetime is the maximum amount of time (in seconds) which can be
+/// between the end of the last event and the start of the heartbeat and still
+/// have them merge.
+pub fn heartbeat(last_event: &Event, heartbeat: &Event, pulsetime: f64) -> Option<Event> {
+    heartbeat_with_policy(last_event, heartbeat, pulsetime, &MergePolicy::default())
+}
+
+/// Like [`heartbeat`], but with a configurable comparison policy instead
+/// of exact data equality.
+pub fn heartbeat_with_policy(
+    last_event: &Event,
+    heartbeat: &Event,
+    pulsetime: f64,
+    policy: &MergePolicy,
+) -> Option<Event> {
+    // Verify that data matches under the policy
+    if !policy.data_matches(&heartbeat.data, &last_event.data) {
+        return None;
+    }
+
+    let last_event_endtime = last_event.calculate_endtime();
+    let heartbeat_endtime = heartbeat.calculate_endtime();
+
+    // Verify that timestamps intersect (including pulsetime)
+    let pulsetime_ns: i64 = (pulsetime * 1_000_000_000.0).round() as i64;
+    let last_endtime_allowed = last_event_endtime + Duration::nanoseconds(pulsetime_ns);
+    if last_event.timestamp > heartbeat.timestamp {
+        return None;
+    }
+    if heartbeat.timestamp > last_endtime_allowed {
+        return None;
+    }
+
+    let starttime = if heartbeat.timestamp < last_event.timestamp {
+        &heartbeat.timestamp
+    } else {
+        &last_event.timestamp
+    };
+
+    let endtime = if heartbeat_endtime > last_event_endtime {
+        &heartbeat_endtime
+    } else {
+        &last_event_endtime
+    };
+
+    let duration = *endtime - *starttime;
+    if duration.num_nanoseconds().unwrap() < 0 {
+        debug!("Merging heartbeats would result in a negative duration, refusing to merge!");
+        return None;
+    }
+
+    // Success, return successful heartbeat last_event
+    Some(Event {
+        id: None,
+        timestamp: *starttime,
+        duration,
+        data: last_event.data.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_models::Event;
+
+    use super::heartbeat;
+    use super::heartbeat_with_policy;
+    use super::MergePolicy;
+
+    fn test_data(value: Value) -> Map<String, Value> {
+        let mut data = Map::new();
+        data.insert("test".to_string(), value);
+        data
+    }
+
+    #[test]
+    fn test_heartbeat_merge() {
+        let now = Utc::now();
+        let event1 = Event {
+            id: None,
+            timestamp: now,
+            duration: Duration::seconds(1),
+            data: test_data(json!(1)),
+        };
+        let heartbeat1 = Event {
+            id: None,
+            timestamp: now + Duration::seconds(2),
+            duration: Duration::seconds(1),
+            data: test_data(json!(1)),
+        };
+
+        // Merge result when pulsetime is long enough
+        let merged = heartbeat(&event1, &heartbeat1, 2.0).unwrap();
+        assert_eq!(merged.timestamp, now);
+        assert_eq!(merged.duration, Duration::seconds(3));
+
+        // No merge result when pulsetime is too short
+        assert!(heartbeat(&event1, &heartbeat1, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_heartbeat_data_mismatch() {
+        let now = Utc::now();
+        let event1 = Event {
+            id: None,
+            timestamp: now,
+            duration: Duration::seconds(1),
+            data: test_data(json!(1)),
+        };
+        let heartbeat1 = Event {
+            id: None,
+            timestamp: now + Duration::seconds(1),
+            duration: Duration::seconds(1),
+            data: test_data(json!(2)),
+        };
+        assert!(heartbeat(&event1, &heartbeat1, 10.0).is_none());
+    }
+
+    #[test]
+    fn test_heartbeat_policy_ignore_keys() {
+        let now = Utc::now();
+        let mut data1 = test_data(json!(1));
+        data1.insert("clock".to_string(), json!("12:00"));
+        let mut data2 = test_data(json!(1));
+        data2.insert("clock".to_string(), json!("12:01"));
+        let event1 = Event {
+            id: None,
+            timestamp: now,
+            duration: Duration::seconds(1),
+            data: data1,
+        };
+        let heartbeat1 = Event {
+            id: None,
+            timestamp: now + Duration::seconds(1),
+            duration: Duration::seconds(1),
+            data: data2,
+        };
+        // Exact comparison refuses, the policy merges
+        assert!(heartbeat(&event1, &heartbeat1, 10.0).is_none());
+        let policy = MergePolicy {
+            ignore_keys: vec!["clock".to_string()],
+            fuzzy_keys: vec![],
+        };
+        let merged = heartbeat_with_policy(&event1, &heartbeat1, 10.0, &policy).unwrap();
+        // The merged event keeps the last event's data
+        assert_eq!(merged.data["clock"], json!("12:00"));
+    }
+
+    #[test]
+    fn test_heartbeat_policy_fuzzy_keys() {
+        let now = Utc::now();
+        let mut data1 = Map::new();
+        data1.insert("title".to_string(), json!("Inbox  (2)"));
+        let mut data2 = Map::new();
+        data2.insert("title".to_string(), json!("inbox (2)"));
+        let event1 = Event {
+            id: None,
+            timestamp: now,
+            duration: Duration::seconds(1),
+            data: data1,
+        };
+        let heartbeat1 = Event {
+            id: None,
+            timestamp: now + Duration::seconds(1),
+            duration: Duration::seconds(1),
+            data: data2,
+        };
+        assert!(heartbeat(&event1, &heartbeat1, 10.0).is_none());
+        let policy = MergePolicy {
+            ignore_keys: vec![],
+            fuzzy_keys: vec!["title".to_string()],
+        };
+        assert!(heartbeat_with_policy(&event1, &heartbeat1, 10.0, &policy).is_some());
+        // A genuinely different title still refuses to merge
+        let mut data3 = Map::new();
+        data3.insert("title".to_string(), json!("Inbox (3)"));
+        let heartbeat2 = Event {
+            id: None,
+            timestamp: now + Duration::seconds(1),
+            duration: Duration::seconds(1),
+            data: data3,
+        };
+        assert!(heartbeat_with_policy(&event1, &heartbeat2, 10.0, &policy).is_none());
+    }
+}
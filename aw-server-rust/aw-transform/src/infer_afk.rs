@@ -0,0 +1,110 @@
+use aw_models::Event;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{Map, Value};
+
+use crate::sort_by_timestamp;
+
+fn status_event(start: DateTime<Utc>, end: DateTime<Utc>, status: &str) -> Event {
+    let mut data = Map::new();
+    data.insert("status".to_string(), Value::String(status.to_string()));
+    // Marked so consumers can tell these apart from real AFK watcher data
+    data.insert("inferred".to_string(), Value::Bool(true));
+    Event {
+        id: None,
+        timestamp: start,
+        duration: end - start,
+        data,
+    }
+}
+
+/// Infers AFK periods from gaps in window-event heartbeats, for hosts
+/// without an AFK watcher. Window watchers heartbeat while the machine is
+/// in use, so stretches of events with only small gaps between them become
+/// "not-afk" periods, and any gap larger than `max_gap` becomes an "afk"
+/// period. Every produced event carries `"inferred": true` in its data, so
+/// downstream consumers can tell it apart from real afkstatus data.
+///
+/// This is a heuristic: a user reading on screen without input keeps the
+/// heartbeats flowing (counted active, correctly), but a machine left on a
+/// video call with no window changes may be inferred AFK. Real watcher
+/// data should always be preferred when available.
+pub fn infer_afk(events: Vec<Event>, max_gap: Duration) -> Vec<Event> {
+    let events = sort_by_timestamp(events);
+    let mut result = Vec::new();
+    let mut iter = events.into_iter();
+    let first = match iter.next() {
+        Some(event) => event,
+        None => return result,
+    };
+    let mut run_start = first.timestamp;
+    let mut run_end = first.calculate_endtime();
+    for event in iter {
+        let endtime = event.calculate_endtime();
+        if event.timestamp - run_end <= max_gap {
+            run_end = std::cmp::max(run_end, endtime);
+        } else {
+            result.push(status_event(run_start, run_end, "not-afk"));
+            result.push(status_event(run_end, event.timestamp, "afk"));
+            run_start = event.timestamp;
+            run_end = endtime;
+        }
+    }
+    result.push(status_event(run_start, run_end, "not-afk"));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+
+    use aw_models::Event;
+
+    use super::infer_afk;
+
+    fn test_event(timestamp: &str, duration: i64) -> Event {
+        let mut data = Map::new();
+        data.insert("app".to_string(), json!("firefox"));
+        Event {
+            id: None,
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            duration: Duration::seconds(duration),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_infer_afk_empty() {
+        assert_eq!(infer_afk(vec![], Duration::seconds(180)).len(), 0);
+    }
+
+    #[test]
+    fn test_infer_afk_small_gaps_bridged() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 10);
+        let e2 = test_event("2000-01-01T00:00:20Z", 10);
+        let res = infer_afk(vec![e1, e2], Duration::seconds(180));
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data["status"], json!("not-afk"));
+        assert_eq!(res[0].data["inferred"], json!(true));
+        assert_eq!(res[0].duration, Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_infer_afk_large_gap_becomes_afk() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 10);
+        let e2 = test_event("2000-01-01T00:10:00Z", 10);
+        let res = infer_afk(vec![e1, e2], Duration::seconds(180));
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].data["status"], json!("not-afk"));
+        assert_eq!(res[0].duration, Duration::seconds(10));
+        assert_eq!(res[1].data["status"], json!("afk"));
+        assert_eq!(res[1].timestamp, res[0].calculate_endtime());
+        assert_eq!(res[1].duration, Duration::seconds(590));
+        assert_eq!(res[2].data["status"], json!("not-afk"));
+    }
+}
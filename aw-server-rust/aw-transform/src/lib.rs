@@ -0,0 +1,38 @@
+#[macro_use]
+extern crate log;
+
+mod chunk;
+mod classify;
+mod filter_keyvals;
+mod filter_period;
+mod find_bucket;
+mod flood;
+mod heartbeat;
+mod infer_afk;
+mod merge;
+mod period_union;
+mod sort;
+mod split_url;
+mod union_no_overlap;
+
+pub use chunk::chunk_events_by_key;
+pub use classify::categorize;
+pub use classify::tag;
+pub use classify::RegexRule;
+pub use classify::Rule;
+pub use filter_keyvals::exclude_keyvals;
+pub use filter_keyvals::filter_keyvals;
+pub use filter_keyvals::filter_keyvals_regex;
+pub use filter_period::filter_period_intersect;
+pub use find_bucket::find_bucket;
+pub use flood::flood;
+pub use heartbeat::heartbeat;
+pub use heartbeat::heartbeat_with_policy;
+pub use heartbeat::MergePolicy;
+pub use infer_afk::infer_afk;
+pub use merge::merge_events_by_keys;
+pub use period_union::period_union;
+pub use sort::sort_by_duration;
+pub use sort::sort_by_timestamp;
+pub use split_url::split_url_event;
+pub use union_no_overlap::union_no_overlap;
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+use crate::sort_by_timestamp;
+
+/// Merges events with the same values at the specified keys into a single
+/// event per unique combination, with the durations summed up.
+pub fn merge_events_by_keys(events: Vec<Event>, keys: Vec<String>) -> Vec<Event> {
+    if keys.is_empty() {
+        return vec![];
+    }
+    let mut merged_events_map: HashMap<String, Event> = HashMap::new();
+    'event: for event in events {
+        let mut key_values = Vec::new();
+        for key in &keys {
+            match event.data.get(key) {
+                Some(v) => key_values.push(v.to_string()),
+                None => continue 'event,
+            }
+        }
+        let summed_key = key_values.join(".");
+        match merged_events_map.get_mut(&summed_key) {
+            Some(merged_event) => {
+                merged_event.duration += event.duration;
+            }
+            None => {
+                let mut data = HashMap::new();
+                for key in &keys {
+                    data.insert(key.clone(), event.data.get(key).unwrap().clone());
+                }
+                let merged_event = Event {
+                    id: None,
+                    timestamp: event.timestamp,
+                    duration: event.duration,
+                    data: data
+                        .into_iter()
+                        .collect::<serde_json::Map<String, Value>>(),
+                };
+                merged_events_map.insert(summed_key, merged_event);
+            }
+        }
+    }
+    let merged_events_list: Vec<Event> = merged_events_map.into_values().collect();
+    sort_by_timestamp(merged_events_list)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_models::Event;
+
+    use super::merge_events_by_keys;
+
+    fn test_event(value: Value) -> Event {
+        let mut data = Map::new();
+        data.insert("test".to_string(), value);
+        Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_merge_events_by_keys() {
+        let events = vec![
+            test_event(json!(1)),
+            test_event(json!(1)),
+            test_event(json!(2)),
+        ];
+        let res = merge_events_by_keys(events, vec!["test".to_string()]);
+        assert_eq!(res.len(), 2);
+        let mut total = Duration::zero();
+        for e in &res {
+            total += e.duration;
+        }
+        assert_eq!(total, Duration::seconds(3));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+
+use aw_models::Event;
+
+use crate::sort_by_timestamp;
+
+/// Takes a list of two events and returns a new list of events covering the
+/// union of the periods of the two lists, with no overlapping events.
+///
+/// The data of the resulting events is empty, this transform only cares about
+/// the periods covered (used for example to compute "not-afk OR audible").
+pub fn period_union(events1: &[Event], events2: &[Event]) -> Vec<Event> {
+    let mut sorted_events: VecDeque<Event> = VecDeque::new();
+    sorted_events.extend(sort_by_timestamp([events1, events2].concat()));
+
+    let mut events_union = Vec::new();
+
+    if let Some(e) = sorted_events.pop_front() {
+        events_union.push(e)
+    }
+
+    for e in sorted_events {
+        let last_event = events_union.last_mut().unwrap();
+        let last_endtime = last_event.calculate_endtime();
+        if e.timestamp <= last_endtime {
+            // The events intersect, merge the periods
+            let endtime = std::cmp::max(e.calculate_endtime(), last_endtime);
+            last_event.duration = endtime - last_event.timestamp;
+        } else {
+            events_union.push(e);
+        }
+    }
+
+    // Clear data, only the periods are relevant
+    for e in &mut events_union {
+        e.data = serde_json::Map::new();
+        e.id = None;
+    }
+    events_union
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::Map;
+
+    use aw_models::Event;
+
+    use super::period_union;
+
+    fn test_event(timestamp: &str, duration: i64) -> Event {
+        Event {
+            id: None,
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            duration: Duration::seconds(duration),
+            data: Map::new(),
+        }
+    }
+
+    #[test]
+    fn test_period_union_merges_intersecting() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 10);
+        let e2 = test_event("2000-01-01T00:00:05Z", 10);
+        let res = period_union(&[e1], &[e2]);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].duration, Duration::seconds(15));
+    }
+
+    #[test]
+    fn test_period_union_keeps_disjoint() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 10);
+        let e2 = test_event("2000-01-01T00:01:00Z", 10);
+        let res = period_union(&[e1], &[e2]);
+        assert_eq!(res.len(), 2);
+    }
+}
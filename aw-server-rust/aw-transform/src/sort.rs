@@ -0,0 +1,52 @@
+use aw_models::Event;
+
+/// Sorts events by timestamp, ascending.
+pub fn sort_by_timestamp(mut events: Vec<Event>) -> Vec<Event> {
+    events.sort_by_key(|e1| e1.timestamp);
+    events
+}
+
+/// Sorts events by duration, descending.
+pub fn sort_by_duration(mut events: Vec<Event>) -> Vec<Event> {
+    events.sort_by_key(|e| std::cmp::Reverse(e.duration));
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::Map;
+
+    use aw_models::Event;
+
+    use super::{sort_by_duration, sort_by_timestamp};
+
+    fn test_event(timestamp: &str, duration: i64) -> Event {
+        Event {
+            id: None,
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            duration: Duration::seconds(duration),
+            data: Map::new(),
+        }
+    }
+
+    #[test]
+    fn test_sort_by_timestamp() {
+        let e1 = test_event("2000-01-01T00:00:01Z", 1);
+        let e2 = test_event("2000-01-01T00:00:00Z", 2);
+        let res = sort_by_timestamp(vec![e1, e2]);
+        assert_eq!(res[0].duration, Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_sort_by_duration() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 1);
+        let e2 = test_event("2000-01-01T00:00:01Z", 2);
+        let res = sort_by_duration(vec![e1, e2]);
+        assert_eq!(res[0].duration, Duration::seconds(2));
+    }
+}
@@ -0,0 +1,66 @@
+use serde_json::value::Value;
+
+use aw_models::Event;
+
+/// Adds `$protocol` and `$domain` entries to the data of an event with an
+/// `url` key, so browser events can be merged by domain.
+pub fn split_url_event(event: &mut Event) {
+    let url_str = match event.data.get("url") {
+        Some(Value::String(url)) => url.clone(),
+        _ => return,
+    };
+
+    // We don't want to depend on a full URL parser here, so do a simple split.
+    let mut rest = url_str.as_str();
+    let protocol = match rest.find("://") {
+        Some(idx) => {
+            let protocol = &rest[..idx];
+            rest = &rest[idx + 3..];
+            protocol
+        }
+        None => "",
+    };
+    let domain = match rest.find('/') {
+        Some(idx) => &rest[..idx],
+        None => rest,
+    };
+    let domain = domain.split('@').next_back().unwrap_or(domain);
+    let domain = domain.split(':').next().unwrap_or(domain);
+
+    event
+        .data
+        .insert("$protocol".to_string(), Value::String(protocol.to_string()));
+    event
+        .data
+        .insert("$domain".to_string(), Value::String(domain.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+
+    use aw_models::Event;
+
+    use super::split_url_event;
+
+    #[test]
+    fn test_split_url_event() {
+        let mut data = Map::new();
+        data.insert(
+            "url".to_string(),
+            json!("https://sub.example.com:443/path?query=1"),
+        );
+        let mut event = Event {
+            id: None,
+            timestamp: Utc::now(),
+            duration: Duration::seconds(1),
+            data,
+        };
+        split_url_event(&mut event);
+        assert_eq!(event.data["$protocol"], json!("https"));
+        assert_eq!(event.data["$domain"], json!("sub.example.com"));
+    }
+}
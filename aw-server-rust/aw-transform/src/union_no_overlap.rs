@@ -0,0 +1,150 @@
+use chrono::DateTime;
+use chrono::Utc;
+
+use aw_models::Event;
+
+/// Merges two sorted eventlists into one sorted eventlist covering both,
+/// where events from the first list take precedence when the lists overlap.
+///
+/// Used to combine window events with browser-tab events without double
+/// counting: the browser events only fill in the gaps left by the window
+/// events.
+///
+/// Example:
+///   events1 | xxx    xx     xxx   |
+///   events2 |  ----    ------   --|
+///   result  | xxx--  xx----xxx- --|
+pub fn union_no_overlap(events1: Vec<Event>, mut events2: Vec<Event>) -> Vec<Event> {
+    let mut events_union = Vec::new();
+
+    let mut e1_i = 0;
+    let mut e2_i = 0;
+    while e1_i < events1.len() && e2_i < events2.len() {
+        let e1 = &events1[e1_i];
+        let e2 = &events2[e2_i];
+
+        if intersects(e1, e2) {
+            if e1.timestamp <= e2.timestamp {
+                // e1 takes precedence, cut off the part of e2 it covers
+                match split_event(e2, e1.calculate_endtime()) {
+                    Some((_, e2_remainder)) => events2[e2_i] = e2_remainder,
+                    None => e2_i += 1,
+                }
+                events_union.push(e1.clone());
+                e1_i += 1;
+            } else {
+                // e2 started first, keep the part of it before e1 starts
+                match split_event(e2, e1.timestamp) {
+                    Some((e2_head, e2_remainder)) => {
+                        events_union.push(e2_head);
+                        events2[e2_i] = e2_remainder;
+                    }
+                    None => e2_i += 1,
+                }
+            }
+        } else if e1.timestamp <= e2.timestamp {
+            events_union.push(e1.clone());
+            e1_i += 1;
+        } else {
+            events_union.push(e2.clone());
+            e2_i += 1;
+        }
+    }
+    events_union.extend_from_slice(&events1[e1_i..]);
+    events_union.extend_from_slice(&events2[e2_i..]);
+    events_union
+}
+
+fn intersects(e1: &Event, e2: &Event) -> bool {
+    e1.timestamp < e2.calculate_endtime() && e2.timestamp < e1.calculate_endtime()
+}
+
+/// Split an event at the given timestamp, returning None if the timestamp
+/// does not fall strictly within the event
+fn split_event(e: &Event, timestamp: DateTime<Utc>) -> Option<(Event, Event)> {
+    if e.timestamp < timestamp && timestamp < e.calculate_endtime() {
+        let head = Event {
+            id: None,
+            timestamp: e.timestamp,
+            duration: timestamp - e.timestamp,
+            data: e.data.clone(),
+        };
+        let remainder = Event {
+            id: None,
+            timestamp,
+            duration: e.calculate_endtime() - timestamp,
+            data: e.data.clone(),
+        };
+        Some((head, remainder))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+    use serde_json::Map;
+    use serde_json::Value;
+
+    use aw_models::Event;
+
+    use super::union_no_overlap;
+
+    fn test_event(timestamp: &str, duration: i64, key: &str) -> Event {
+        let mut data = Map::new();
+        data.insert(key.to_string(), Value::Bool(true));
+        Event {
+            id: None,
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            duration: Duration::seconds(duration),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_union_no_overlap_disjoint() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 10, "window");
+        let e2 = test_event("2000-01-01T00:01:00Z", 10, "browser");
+        let res = union_no_overlap(vec![e1], vec![e2]);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].data, json!({"window": true}).as_object().unwrap().clone());
+        assert_eq!(res[1].data, json!({"browser": true}).as_object().unwrap().clone());
+    }
+
+    #[test]
+    fn test_union_no_overlap_precedence() {
+        // events1 covers the middle of a longer events2 event,
+        // which should be split around it
+        let e1 = test_event("2000-01-01T00:00:10Z", 10, "window");
+        let e2 = test_event("2000-01-01T00:00:00Z", 30, "browser");
+        let res = union_no_overlap(vec![e1], vec![e2]);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].duration, Duration::seconds(10));
+        assert_eq!(res[0].data["browser"], Value::Bool(true));
+        assert_eq!(res[1].duration, Duration::seconds(10));
+        assert_eq!(res[1].data["window"], Value::Bool(true));
+        assert_eq!(res[2].duration, Duration::seconds(10));
+        assert_eq!(res[2].data["browser"], Value::Bool(true));
+        // No time is counted twice
+        let total = res
+            .iter()
+            .fold(Duration::zero(), |acc, e| acc + e.duration);
+        assert_eq!(total, Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_union_no_overlap_same_start() {
+        let e1 = test_event("2000-01-01T00:00:00Z", 10, "window");
+        let e2 = test_event("2000-01-01T00:00:00Z", 30, "browser");
+        let res = union_no_overlap(vec![e1], vec![e2]);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].data["window"], Value::Bool(true));
+        assert_eq!(res[1].duration, Duration::seconds(20));
+    }
+}